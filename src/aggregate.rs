@@ -0,0 +1,123 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The accumulator of heartbeats received within the time window which
+//! summarizes every sender with one line.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+const CPU_FIELD: &str = "cpu_pct";
+const RSS_FIELD: &str = "rss_bytes";
+const BYTES_IN_MB: f64 = 1024.0 * 1024.0;
+
+///
+/// The sum and the maximum of the numeric field.
+///
+#[derive(Clone, Copy)]
+struct FieldStats {
+    sum: f64,
+    max: f64,
+    count: u64,
+}
+
+///
+/// The statistics of heartbeats of the sender within the window.
+///
+#[derive(Default)]
+pub struct WindowStats {
+    count: u64,
+    fields: HashMap<String, FieldStats>,
+    states: BTreeMap<String, u64>,
+}
+
+impl WindowStats {
+    ///
+    /// Add the heartbeat in `state` with numeric `values` to the statistics.
+    ///
+    pub fn add(&mut self, state: &str, values: &[(String, f64)]) {
+        self.count += 1;
+        *self.states.entry(state.to_owned()).or_insert(0) += 1;
+        for (key, value) in values {
+            let field = self.fields.entry(key.clone()).or_insert(FieldStats {
+                sum: 0.0,
+                max: *value,
+                count: 0,
+            });
+            field.sum += value;
+            field.max = field.max.max(*value);
+            field.count += 1;
+        }
+    }
+
+    ///
+    /// Make the summary line of the sender `name` where states are shortened
+    /// with `letter`, e.g. `name=rsync count=60 cpu_avg=12.3 cpu_max=45.1
+    /// rss_avg=102MB rss_max=150MB states=S:58/R:2`.
+    ///
+    pub fn summary<F: Fn(&str) -> String>(&self, name: &str, letter: F) -> String {
+        let mut line = format!("name={} count={}", name, self.count);
+        if let Some(cpu) = self.fields.get(CPU_FIELD) {
+            let _ = write!(
+                line,
+                " cpu_avg={:.1} cpu_max={:.1}",
+                cpu.sum / cpu.count as f64,
+                cpu.max
+            );
+        }
+        if let Some(rss) = self.fields.get(RSS_FIELD) {
+            let _ = write!(
+                line,
+                " rss_avg={:.0}MB rss_max={:.0}MB",
+                rss.sum / rss.count as f64 / BYTES_IN_MB,
+                rss.max / BYTES_IN_MB
+            );
+        }
+        let states: Vec<String> = self
+            .states
+            .iter()
+            .map(|(state, count)| format!("{}:{}", letter(state), count))
+            .collect();
+        let _ = write!(line, " states={}", states.join("/"));
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeats_are_summarized() {
+        let mut stats = WindowStats::default();
+        for i in 0..10 {
+            let state = if i < 8 { "Sleeping" } else { "Running" };
+            let values = [
+                (CPU_FIELD.to_owned(), f64::from(i) * 10.0),
+                (RSS_FIELD.to_owned(), BYTES_IN_MB * f64::from(100 + i)),
+            ];
+            stats.add(state, &values);
+        }
+        let letter = |state: &str| state[..1].to_owned();
+        assert_eq!(
+            stats.summary("rsync", letter),
+            "name=rsync count=10 cpu_avg=45.0 cpu_max=90.0 rss_avg=104MB rss_max=109MB states=R:2/S:8"
+        );
+    }
+}
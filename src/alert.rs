@@ -0,0 +1,99 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Alerts raised when metrics cross thresholds given with `Alert*` options.
+//!
+
+const ABOVE: &str = "above";
+const BELOW: &str = "below";
+
+///
+/// The threshold the metric value is compared against.
+///
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    Above(f64),
+    Below(f64),
+}
+
+impl Threshold {
+    ///
+    /// Parse the threshold from `value` which has form of `above:N`, `below:N`,
+    /// or just `N` which is the same as `above:N`.
+    ///
+    pub fn parse(value: &str) -> Option<Threshold> {
+        let parts: Vec<&str> = value.splitn(2, ':').collect();
+        match parts.as_slice() {
+            [ABOVE, limit] => limit.trim().parse().ok().map(Threshold::Above),
+            [BELOW, limit] => limit.trim().parse().ok().map(Threshold::Below),
+            [limit] => limit.trim().parse().ok().map(Threshold::Above),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Test the `value` crosses the threshold.
+    ///
+    pub fn crossed(&self, value: f64) -> bool {
+        match *self {
+            Threshold::Above(limit) => value > limit,
+            Threshold::Below(limit) => value < limit,
+        }
+    }
+}
+
+///
+/// The alert on the metric which fires once when the metric crosses
+/// the threshold and rearms when the metric gets back.
+///
+#[derive(Clone, Debug)]
+pub struct Alert {
+    metric: &'static str,
+    threshold: Threshold,
+    active: bool,
+}
+
+impl Alert {
+    pub fn new(metric: &'static str, threshold: Threshold) -> Alert {
+        Alert {
+            metric,
+            threshold,
+            active: false,
+        }
+    }
+
+    ///
+    /// Check the metric `value` and return the alert text if the alert fires.
+    ///
+    pub fn check(&mut self, value: f64) -> Option<String> {
+        let crossed = self.threshold.crossed(value);
+        let fire = crossed && !self.active;
+        self.active = crossed;
+        if fire {
+            let (direction, limit) = match self.threshold {
+                Threshold::Above(limit) => (ABOVE, limit),
+                Threshold::Below(limit) => (BELOW, limit),
+            };
+            Some(format!(
+                "{} {:.2} is {} {}",
+                self.metric, value, direction, limit
+            ))
+        } else {
+            None
+        }
+    }
+}
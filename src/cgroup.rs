@@ -0,0 +1,131 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The cgroup v2 the tool creates for the command to limit resources it uses.
+//! The cgroup is removed when the tool is done with it.
+//!
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+///
+/// The cgroup created under the base cgroup.
+///
+pub struct Cgroup {
+    dir: PathBuf,
+}
+
+impl Cgroup {
+    ///
+    /// Create the cgroup `owl-<name>.scope` under the `base` cgroup and limit
+    /// its memory to `memory_max` bytes. The cgroup is removed if the limit
+    /// cannot be set, e.g. the memory controller is not enabled in `base`.
+    ///
+    pub fn create(base: &Path, name: &str, memory_max: u64) -> io::Result<Cgroup> {
+        if !base.join("cgroup.controllers").is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "not a cgroup v2 hierarchy",
+            ));
+        }
+
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let dir = base.join(format!("owl-{}.scope", name));
+        if !dir.is_dir() {
+            fs::create_dir(&dir)?;
+        }
+        let cgroup = Cgroup { dir };
+        fs::write(cgroup.dir.join("memory.max"), format!("{}\n", memory_max))?;
+
+        Ok(cgroup)
+    }
+
+    ///
+    /// The directory of the cgroup.
+    ///
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    ///
+    /// Move the process with `pid` into the cgroup.
+    ///
+    pub fn add(&self, pid: u32) -> io::Result<()> {
+        fs::write(self.dir.join("cgroup.procs"), format!("{}\n", pid))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Only the empty cgroup can be removed, processes left keep it
+        if let Err(err) = fs::remove_dir(&self.dir) {
+            eprintln!(
+                "warning: failed to remove cgroup '{}': {}",
+                self.dir.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_is_made_with_memory_limit() {
+        let base =
+            std::env::temp_dir().join(format!("owl-test-{}-cgroup-base", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(&base).unwrap();
+
+        // Not the unified hierarchy
+        assert!(Cgroup::create(&base, "job", 1 << 20).is_err());
+
+        fs::write(base.join("cgroup.controllers"), "memory\n").unwrap();
+        let cgroup = Cgroup::create(&base, "nightly job/1", 1 << 20).unwrap();
+        assert_eq!(cgroup.path(), base.join("owl-nightly_job_1.scope"));
+        assert_eq!(
+            fs::read_to_string(cgroup.path().join("memory.max")).unwrap(),
+            "1048576\n"
+        );
+        cgroup.add(42).unwrap();
+        assert_eq!(
+            fs::read_to_string(cgroup.path().join("cgroup.procs")).unwrap(),
+            "42\n"
+        );
+
+        // Files of the real cgroup go away with it, files of the fake one do not
+        for file in ["memory.max", "cgroup.procs"] {
+            fs::remove_file(cgroup.path().join(file)).unwrap();
+        }
+        let dir = cgroup.path().to_path_buf();
+        drop(cgroup);
+        assert!(!dir.exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
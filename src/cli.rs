@@ -0,0 +1,234 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The command line parser which separates tool options from the command.
+//!
+//! Options are given before the command either as `+Name:Value` or as
+//! `--name value` and `--name=value` where the name is the option name
+//! in kebab case, e.g. `--max-rss-bytes 500M` is `+MaxRssBytes:500M`.
+//! Boolean options given as `--name` take no value. The first argument
+//! which is not the option or the `--` argument starts the command and
+//! the rest is passed to the command as-is.
+//!
+
+use crate::{OPTION_DELIMITER, OPTION_START};
+use std::ffi::OsString;
+
+const LONG_START: &str = "--";
+const LONG_DELIMITER: char = '=';
+const FLAG_HELP: &str = "help";
+const FLAG_HELP_SHORT: &str = "-h";
+const FLAG_TEST: &str = "test";
+const FLAG_JSON_SCHEMA: &str = "json-schema";
+
+///
+/// Options which are enabled with `--name` without value.
+///
+const BOOLEAN_OPTIONS: [&str; 24] = [
+    "Receive",
+    "HeartbeatOnChange",
+    "FsyncState",
+    "K8sLabels",
+    "Envelope",
+    "MemMaps",
+    "SignalGroup",
+    "AlertOrphan",
+    "InheritSigMask",
+    "PrintAccounting",
+    "WatchFifos",
+    "PushgatewayAutoDelete",
+    "CountOutputLines",
+    "StrictBinaryCheck",
+    "SkipBinaryCheck",
+    "AlertCwdChange",
+    "CpuBudgetPerRestart",
+    "SelfMetrics",
+    "DeliverAggregated",
+    "CpuSetInherit",
+    "AllowNested",
+    "Banner",
+    "BannerColor",
+    "DeduplicateSends",
+];
+
+///
+/// The usage printed on `--help`.
+///
+pub const USAGE: &str = "usage: owl [OPTS] command [ARGS]
+       owl [OPTS] --test
+       owl --json-schema
+       owl --help
+
+Options are given before the command as +Name:Value or --name value,
+e.g. +Host:127.0.0.1 or --host 127.0.0.1. Boolean options are enabled
+with +Name or --name. Arguments after the command or -- are passed
+to the command as-is.
+
+  --test           send one test message to the receiver and exit
+  --json-schema    print the JSON Schema of json messages and exit
+  --help           print this help and exit";
+
+///
+/// The command line split into tool options and the command.
+///
+#[derive(Debug, Default)]
+pub struct CommandLine {
+    /// Tool options in the order given.
+    pub opts: Vec<(String, String)>,
+
+    /// The command and its arguments.
+    pub command: Vec<OsString>,
+
+    /// The flag is raised by `--test`.
+    pub test: bool,
+
+    /// The flag is raised by `--json-schema`.
+    pub json_schema: bool,
+
+    /// The flag is raised by `--help` or `-h`.
+    pub help: bool,
+}
+
+///
+/// Parse command line `args` without the program name.
+///
+pub fn parse<I>(args: I) -> Result<CommandLine, String>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut line = CommandLine::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy().into_owned();
+        if let Some(opt) = text.strip_prefix(OPTION_START) {
+            let mut parts = opt.splitn(2, OPTION_DELIMITER);
+            let name = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+            line.opts.push((name.to_owned(), value.to_owned()));
+        } else if text == LONG_START {
+            break;
+        } else if text == FLAG_HELP_SHORT {
+            line.help = true;
+        } else if let Some(long) = text.strip_prefix(LONG_START) {
+            let (flag, value) = match long.split_once(LONG_DELIMITER) {
+                Some((flag, value)) => (flag, Some(value.to_owned())),
+                None => (long, None),
+            };
+            match flag {
+                FLAG_HELP => line.help = true,
+                FLAG_TEST => line.test = true,
+                FLAG_JSON_SCHEMA => line.json_schema = true,
+                _ => {
+                    let name = option_name(flag)?;
+                    let value = match value {
+                        Some(value) => value,
+                        None if BOOLEAN_OPTIONS.contains(&name.as_str()) => String::new(),
+                        None => args
+                            .next()
+                            .map(|v| v.to_string_lossy().into_owned())
+                            .ok_or_else(|| format!("--{} requires a value", flag))?,
+                    };
+                    line.opts.push((name, value));
+                }
+            }
+        } else {
+            line.command.push(arg);
+            break;
+        }
+    }
+    line.command.extend(args);
+
+    Ok(line)
+}
+
+///
+/// Convert the kebab case `flag` into the option name, e.g. `max-rss-bytes`
+/// into `MaxRssBytes`.
+///
+fn option_name(flag: &str) -> Result<String, String> {
+    let valid = flag
+        .split('-')
+        .all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !valid {
+        return Err(format!("--{} is not a valid option", flag));
+    }
+
+    Ok(flag
+        .split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn long_options_are_plus_options() {
+        let line = parse(args(&[
+            "+Host:127.0.0.1",
+            "--port",
+            "9000",
+            "--max-rss-bytes=500M",
+            "--receive",
+            "rsync",
+            "--port",
+            "+Host:x",
+        ]))
+        .unwrap();
+        assert_eq!(
+            line.opts,
+            vec![
+                ("Host".to_owned(), "127.0.0.1".to_owned()),
+                ("Port".to_owned(), "9000".to_owned()),
+                ("MaxRssBytes".to_owned(), "500M".to_owned()),
+                ("Receive".to_owned(), String::new()),
+            ]
+        );
+        assert_eq!(line.command, args(&["rsync", "--port", "+Host:x"]));
+    }
+
+    #[test]
+    fn double_dash_starts_command() {
+        let line = parse(args(&["--test", "--", "--help"])).unwrap();
+        assert!(line.test);
+        assert!(!line.help);
+        assert_eq!(line.command, args(&["--help"]));
+
+        let line = parse(args(&["-h", "--json-schema"])).unwrap();
+        assert!(line.help);
+        assert!(line.json_schema);
+        assert!(line.command.is_empty());
+    }
+
+    #[test]
+    fn invalid_long_options_are_errors() {
+        assert!(parse(args(&["--port"])).is_err());
+        assert!(parse(args(&["--max_rss", "1"])).is_err());
+        assert!(parse(args(&["--max--rss", "1"])).is_err());
+    }
+}
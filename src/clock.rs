@@ -0,0 +1,153 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The clock of the receiver which runs in real time or, while the log is
+//! replayed, in time of the log.
+//!
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // The time of the log the replay started at, the instant it started,
+    // and the speed multiplier.
+    static ref REPLAY: Mutex<Option<(SystemTime, Instant, f64)>> = Mutex::new(None);
+}
+
+///
+/// Get the current time which is the time of the log while it is replayed.
+///
+pub fn now() -> SystemTime {
+    match REPLAY.lock().ok().and_then(|replay| *replay) {
+        Some((at, started, speed)) => at + started.elapsed().mul_f64(speed),
+        None => SystemTime::now(),
+    }
+}
+
+///
+/// Start the replay at the time of the log `at` with the `speed` multiplier.
+///
+pub fn start_replay(at: SystemTime, speed: f64) {
+    if let Ok(mut replay) = REPLAY.lock() {
+        *replay = Some((at, Instant::now(), speed));
+    }
+}
+
+///
+/// Change the `speed` multiplier of the replay from the current time on,
+/// 0 stops the clock. Nothing changes if the replay is not started.
+///
+pub fn set_speed(speed: f64) {
+    let at = now();
+    if let Ok(mut replay) = REPLAY.lock() {
+        if replay.is_some() {
+            *replay = Some((at, Instant::now(), speed));
+        }
+    }
+}
+
+///
+/// Get the real time left until the clock reaches `at`.
+///
+pub fn real_time_until(at: SystemTime) -> Duration {
+    let left = at.duration_since(now()).unwrap_or_default();
+    match REPLAY.lock().ok().and_then(|replay| *replay) {
+        Some((_, _, speed)) => left.div_f64(speed),
+        None => left,
+    }
+}
+
+///
+/// Parse the time from `value` which is UTC in RFC 3339, e.g. `2019-11-05T14:03:12Z`
+/// or `2019-11-05T14:03:12.345Z`, or Unix time in seconds, e.g. `1572962592.345`.
+///
+pub fn parse_time(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return if secs.is_finite() && secs >= 0.0 {
+            Some(UNIX_EPOCH + Duration::from_secs_f64(secs))
+        } else {
+            None
+        };
+    }
+
+    let value = value.strip_suffix('Z')?;
+    let (date, time) = value.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, format!("0.{}", fraction).parse::<f64>().ok()?),
+        None => (time, 0.0),
+    };
+    let mut time = time.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Convert the civil date to days since the epoch
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(secs as u64) + Duration::from_secs_f64(fraction))
+}
+
+///
+/// Format the time `at` as UTC in RFC 3339, e.g. `2019-11-05T14:03:12Z`,
+/// with `millis` the milliseconds are included, e.g. `2019-11-05T14:03:12.345Z`.
+///
+pub fn format_time(at: SystemTime, millis: bool) -> String {
+    let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since the epoch to the civil date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let fraction = if millis {
+        format!(".{:03}", since.subsec_millis())
+    } else {
+        String::new()
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        fraction
+    )
+}
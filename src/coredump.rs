@@ -0,0 +1,234 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The lookup of core dumps the crashed command leaves behind.
+//!
+//! The dump is looked for where the kernel `core_pattern` places it or in
+//! the directory given. Pattern specifiers which cannot be known after
+//! the crash, e.g. `%t`, match any text.
+//!
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PIPE_PREFIX: char = '|';
+const ANY: char = '*';
+
+///
+/// The crashed process the core dump is looked for.
+///
+pub struct Crash {
+    /// The id of the process.
+    pub pid: u32,
+
+    /// The signal the process is killed with.
+    pub signal: i32,
+
+    /// The user id of the process.
+    pub uid: u32,
+
+    /// The group id of the process.
+    pub gid: u32,
+
+    /// The name of the process as the kernel keeps it.
+    pub comm: String,
+
+    /// The path of the executable file of the process.
+    pub exe: String,
+
+    /// The host name.
+    pub hostname: String,
+}
+
+///
+/// Find the core dump of the `crash` written by the kernel with the core `pattern`.
+/// The `.<pid>` suffix is added when `uses_pid` is set and the pattern has no `%p`.
+/// `None` is returned if the dump is piped to the program or is not found.
+///
+pub fn find_by_pattern(pattern: &str, uses_pid: bool, crash: &Crash) -> Option<PathBuf> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() || pattern.starts_with(PIPE_PREFIX) {
+        return None;
+    }
+
+    let mut path = expand_pattern(pattern, crash);
+    if uses_pid && !pattern.contains("%p") {
+        path.push_str(&format!(".{}", crash.pid));
+    }
+
+    // The kernel writes the relative path into the process working directory
+    // which is the tool one
+    let path = env::current_dir().ok()?.join(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path.file_name()?.to_str()?.to_owned();
+    if dir.to_string_lossy().contains(ANY) {
+        return None;
+    }
+    if !name.contains(ANY) {
+        return Some(path).filter(|path| path.is_file());
+    }
+
+    newest_file(&dir, |file| wildcard_match(&name, file))
+}
+
+///
+/// Find the newest core dump of the process `pid` in the `dir` which is
+/// the file with the process id in its name.
+///
+pub fn find_in_dir(dir: &Path, pid: u32) -> Option<PathBuf> {
+    let pid = pid.to_string();
+    newest_file(dir, |file| {
+        file.split(|c: char| !c.is_ascii_digit())
+            .any(|part| part == pid)
+    })
+}
+
+///
+/// Expand specifiers of the core `pattern` with values of the `crash`.
+/// Specifiers which values are unknown become wildcards.
+///
+fn expand_pattern(pattern: &str, crash: &Crash) -> String {
+    let mut path = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('%') => path.push('%'),
+            Some('p') | Some('P') | Some('i') | Some('I') => path.push_str(&crash.pid.to_string()),
+            Some('s') => path.push_str(&crash.signal.to_string()),
+            Some('u') => path.push_str(&crash.uid.to_string()),
+            Some('g') => path.push_str(&crash.gid.to_string()),
+            Some('e') => path.push_str(&crash.comm),
+            Some('E') => path.push_str(&crash.exe.replace('/', "!")),
+            Some('h') => path.push_str(&crash.hostname),
+            Some(_) => path.push(ANY),
+            None => {}
+        }
+    }
+
+    path
+}
+
+///
+/// Find the most recently modified file in the `dir` which name matches.
+///
+fn newest_file<F>(dir: &Path, matches: F) -> Option<PathBuf>
+where
+    F: Fn(&str) -> bool,
+{
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(&matches))
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            if meta.is_file() {
+                Some((meta.modified().ok()?, entry.path()))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+///
+/// Match the `text` against the `pattern` where `*` matches any text.
+///
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split(ANY);
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    parts.is_empty() && rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crash() -> Crash {
+        Crash {
+            pid: 1234,
+            signal: 6,
+            uid: 1000,
+            gid: 100,
+            comm: "job".to_owned(),
+            exe: "/usr/bin/job".to_owned(),
+            hostname: "host-1".to_owned(),
+        }
+    }
+
+    #[test]
+    fn pattern_specifiers_are_expanded() {
+        assert_eq!(
+            expand_pattern("/var/cores/%e.%p.%s.%u.%g.%h.%E.%t.%%", &crash()),
+            "/var/cores/job.1234.6.1000.100.host-1.!usr!bin!job.*.%"
+        );
+        assert!(wildcard_match("core.*.1234.*", "core.job.1234.1572962592"));
+        assert!(!wildcard_match("core.*.1234.*", "core.job.4321.1572962592"));
+        assert!(wildcard_match("core", "core"));
+    }
+
+    #[test]
+    fn dump_is_found_by_pattern_and_in_dir() {
+        let dir = env::temp_dir().join(format!("owl-test-{}-cores", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("core.job.1234.1572962592"), "").unwrap();
+        fs::write(dir.join("core.job.4321.1572962592"), "").unwrap();
+
+        let pattern = format!("{}/core.%e.%p.%t", dir.display());
+        assert_eq!(
+            find_by_pattern(&pattern, false, &crash()),
+            Some(dir.join("core.job.1234.1572962592"))
+        );
+        assert_eq!(
+            find_by_pattern("|/usr/lib/systemd/systemd-coredump %P", false, &crash()),
+            None
+        );
+        assert_eq!(
+            find_in_dir(&dir, 1234),
+            Some(dir.join("core.job.1234.1572962592"))
+        );
+        assert_eq!(find_in_dir(&dir, 123), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
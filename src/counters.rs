@@ -0,0 +1,104 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The counters of messages the receiver handles which are summarized when
+//! the receiver stops after `--duration`, e.g. to benchmark it with `--dry-run`.
+//!
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+///
+/// The numbers of messages, parse errors, and bytes received since the start.
+///
+pub struct Counters {
+    started: Instant,
+    messages: AtomicU64,
+    parse_errors: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counters {
+    pub fn new() -> Counters {
+        Counters {
+            started: Instant::now(),
+            messages: AtomicU64::new(0),
+            parse_errors: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    ///
+    /// Count the payload of `len` bytes received.
+    ///
+    pub fn received(&self, len: usize) {
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    ///
+    /// Count the message parsed, `ok` is false if the message is malformed.
+    ///
+    pub fn parsed(&self, ok: bool) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.parse_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    ///
+    /// Get the number of messages counted.
+    ///
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// Format the summary of counters with the throughput since the start,
+    /// e.g. `received 10000 messages (0 parse errors, 1830000 bytes) in 10.00s, 1000.0 msg/s`.
+    ///
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed().max(Duration::from_millis(1));
+        let messages = self.messages();
+        format!(
+            "received {} messages ({} parse errors, {} bytes) in {:.2}s, {:.1} msg/s",
+            messages,
+            self.parse_errors.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+            elapsed.as_secs_f64(),
+            messages as f64 / elapsed.as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_and_errors_are_summarized() {
+        let counters = Counters::new();
+        for ok in &[true, false, true] {
+            counters.received(10);
+            counters.parsed(*ok);
+        }
+
+        assert_eq!(counters.messages(), 3);
+        assert!(counters
+            .summary()
+            .starts_with("received 3 messages (1 parse errors, 30 bytes) in "));
+    }
+}
@@ -0,0 +1,291 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The SQLite database heartbeats received are stored in. The database is
+//! accessed with the system `libsqlite3` directly.
+//!
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int};
+use std::ptr;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS heartbeats (
+    ts INTEGER NOT NULL,
+    owl_pid INTEGER,
+    child_pid INTEGER,
+    name TEXT,
+    state TEXT,
+    cpu_pct REAL,
+    rss_bytes INTEGER,
+    threads INTEGER
+)";
+const INSERT_HEARTBEAT: &str = "INSERT INTO heartbeats
+    (ts, owl_pid, child_pid, name, state, cpu_pct, rss_bytes, threads)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+
+enum Sqlite3 {}
+enum Sqlite3Stmt {}
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut Sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+    fn sqlite3_prepare_v2(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        len: c_int,
+        stmt: *mut *mut Sqlite3Stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_step(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_finalize(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut Sqlite3Stmt, index: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_double(stmt: *mut Sqlite3Stmt, index: c_int, value: c_double) -> c_int;
+    fn sqlite3_bind_null(stmt: *mut Sqlite3Stmt, index: c_int) -> c_int;
+    fn sqlite3_bind_text(
+        stmt: *mut Sqlite3Stmt,
+        index: c_int,
+        value: *const c_char,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_column_count(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_column_name(stmt: *mut Sqlite3Stmt, index: c_int) -> *const c_char;
+    fn sqlite3_column_text(stmt: *mut Sqlite3Stmt, index: c_int) -> *const c_char;
+}
+
+/// The destructor which tells SQLite to copy bound values.
+const SQLITE_TRANSIENT: isize = -1;
+
+///
+/// The heartbeat stored as the row of the `heartbeats` table.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat {
+    pub ts: i64,
+    pub owl_pid: Option<i64>,
+    pub child_pid: Option<i64>,
+    pub name: String,
+    pub state: String,
+    pub cpu_pct: Option<f64>,
+    pub rss_bytes: Option<i64>,
+    pub threads: Option<i64>,
+}
+
+///
+/// The open database connection.
+///
+pub struct Database {
+    db: *mut Sqlite3,
+}
+
+// The connection is used by one thread at a time.
+unsafe impl Send for Database {}
+
+impl Database {
+    ///
+    /// Open or create the database at `path` and create the `heartbeats` table.
+    ///
+    pub fn open(path: &str) -> Result<Database, String> {
+        let filename = CString::new(path).map_err(|err| err.to_string())?;
+        let mut db: *mut Sqlite3 = ptr::null_mut();
+        let rc = unsafe {
+            sqlite3_open_v2(
+                filename.as_ptr(),
+                &mut db,
+                SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+                ptr::null(),
+            )
+        };
+        let database = Database { db };
+        if rc != SQLITE_OK {
+            return Err(database.error());
+        }
+
+        let stmt = database.prepare(CREATE_TABLE)?;
+        stmt.step_done().map_err(|_| database.error())?;
+        Ok(database)
+    }
+
+    ///
+    /// Insert the `heartbeat` into the `heartbeats` table.
+    ///
+    pub fn insert(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        let stmt = self.prepare(INSERT_HEARTBEAT)?;
+        let name = CString::new(heartbeat.name.as_str()).unwrap_or_default();
+        let state = CString::new(heartbeat.state.as_str()).unwrap_or_default();
+        unsafe {
+            sqlite3_bind_int64(stmt.stmt, 1, heartbeat.ts);
+            bind_int(stmt.stmt, 2, heartbeat.owl_pid);
+            bind_int(stmt.stmt, 3, heartbeat.child_pid);
+            sqlite3_bind_text(stmt.stmt, 4, name.as_ptr(), -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt.stmt, 5, state.as_ptr(), -1, SQLITE_TRANSIENT);
+            match heartbeat.cpu_pct {
+                Some(v) => sqlite3_bind_double(stmt.stmt, 6, v),
+                None => sqlite3_bind_null(stmt.stmt, 6),
+            };
+            bind_int(stmt.stmt, 7, heartbeat.rss_bytes);
+            bind_int(stmt.stmt, 8, heartbeat.threads);
+        }
+        stmt.step_done().map_err(|_| self.error())
+    }
+
+    ///
+    /// Run the `sql` query and return column names and rows with values
+    /// as text. `NULL` values are empty strings.
+    ///
+    pub fn query(&self, sql: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+        let stmt = self.prepare(sql)?;
+        let count = unsafe { sqlite3_column_count(stmt.stmt) };
+        let columns = (0..count)
+            .map(|i| text(unsafe { sqlite3_column_name(stmt.stmt, i) }))
+            .collect();
+
+        let mut rows = Vec::new();
+        loop {
+            match unsafe { sqlite3_step(stmt.stmt) } {
+                SQLITE_ROW => rows.push(
+                    (0..count)
+                        .map(|i| text(unsafe { sqlite3_column_text(stmt.stmt, i) }))
+                        .collect(),
+                ),
+                SQLITE_DONE => break,
+                _ => return Err(self.error()),
+            }
+        }
+
+        Ok((columns, rows))
+    }
+
+    fn prepare(&self, sql: &str) -> Result<Statement, String> {
+        let sql = CString::new(sql).map_err(|err| err.to_string())?;
+        let mut stmt: *mut Sqlite3Stmt = ptr::null_mut();
+        let rc =
+            unsafe { sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) };
+        if rc != SQLITE_OK || stmt.is_null() {
+            return Err(self.error());
+        }
+
+        Ok(Statement { stmt })
+    }
+
+    fn error(&self) -> String {
+        if self.db.is_null() {
+            "out of memory".to_owned()
+        } else {
+            text(unsafe { sqlite3_errmsg(self.db) })
+        }
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_close(self.db);
+        }
+    }
+}
+
+///
+/// The prepared statement which is finalized on drop.
+///
+struct Statement {
+    stmt: *mut Sqlite3Stmt,
+}
+
+impl Statement {
+    fn step_done(&self) -> Result<(), ()> {
+        let rc = unsafe { sqlite3_step(self.stmt) };
+        unsafe {
+            sqlite3_reset(self.stmt);
+        }
+        if rc == SQLITE_DONE || rc == SQLITE_ROW {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_finalize(self.stmt);
+        }
+    }
+}
+
+unsafe fn bind_int(stmt: *mut Sqlite3Stmt, index: c_int, value: Option<i64>) {
+    match value {
+        Some(v) => sqlite3_bind_int64(stmt, index, v),
+        None => sqlite3_bind_null(stmt, index),
+    };
+}
+
+fn text(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_heartbeats_are_counted() {
+        let path = std::env::temp_dir().join(format!("owl-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Database::open(&path.to_string_lossy()).unwrap();
+        for i in 0..100 {
+            db.insert(&Heartbeat {
+                ts: i,
+                child_pid: Some(1000 + i % 2),
+                name: "rsync".to_owned(),
+                state: "Running".to_owned(),
+                cpu_pct: Some(12.5),
+                ..Heartbeat::default()
+            })
+            .unwrap();
+        }
+
+        let (columns, rows) = db
+            .query("SELECT COUNT(*) AS total, COUNT(DISTINCT child_pid) FROM heartbeats")
+            .unwrap();
+        assert_eq!(columns[0], "total");
+        assert_eq!(rows, [["100", "2"]]);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The delivery metadata of records which receivers stamp and relays extend.
+//! The metadata is encoded by owl as `envelope.<field>=<value>` fields of SSDPD
+//! records and as the `envelope` object of JSON records.
+//!
+
+const SSDPD_DELIMITER: &str = "||";
+const SSDPD_SENT: &str = "envelope.sent_ts_ms=";
+const SSDPD_RELAY: &str = "envelope.relay=";
+const SSDPD_RECEIVED: &str = "envelope.received_ts_ms=";
+const JSON_SENT: &str = "\"sent_ts_ms\":";
+const JSON_RELAY_CHAIN: &str = "\"relay_chain\":[";
+
+///
+/// Get the time the `record` is sent at in milliseconds since the UNIX epoch.
+/// `None` is returned if the record has no envelope.
+///
+pub fn sent_ts_ms(record: &str) -> Option<u64> {
+    if is_json(record) {
+        let start = record.find(JSON_SENT)? + JSON_SENT.len();
+        let digits: String = record[start..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok()
+    } else {
+        record
+            .split(SSDPD_DELIMITER)
+            .find_map(|field| field.strip_prefix(SSDPD_SENT)?.parse().ok())
+    }
+}
+
+///
+/// Add the `relay` to the end of the relay chain of the `record`.
+/// The record without envelope is returned as-is.
+///
+pub fn append_relay(record: &str, relay: &str) -> String {
+    if sent_ts_ms(record).is_none() {
+        return record.to_owned();
+    }
+
+    if is_json(record) {
+        match relay_chain_bounds(record) {
+            Some((start, end)) => {
+                let delimiter = if end > start { "," } else { "" };
+                format!(
+                    "{}{}{}{}",
+                    &record[..end],
+                    delimiter,
+                    json_string(relay),
+                    &record[end..]
+                )
+            }
+            None => record.to_owned(),
+        }
+    } else {
+        format!("{}{}{}{}", record, SSDPD_DELIMITER, SSDPD_RELAY, relay)
+    }
+}
+
+///
+/// Add the time the `record` is received at to its envelope.
+/// The record without envelope is returned as-is.
+///
+pub fn stamp_received(record: &str, received_ts_ms: u64) -> String {
+    if sent_ts_ms(record).is_none() {
+        return record.to_owned();
+    }
+
+    if is_json(record) {
+        match relay_chain_bounds(record) {
+            Some((_, end)) => format!(
+                "{},\"received_ts_ms\":{}{}",
+                &record[..=end],
+                received_ts_ms,
+                &record[end + 1..]
+            ),
+            None => record.to_owned(),
+        }
+    } else {
+        format!(
+            "{}{}{}{}",
+            record, SSDPD_DELIMITER, SSDPD_RECEIVED, received_ts_ms
+        )
+    }
+}
+
+///
+/// Test the `record` is the JSON object.
+///
+fn is_json(record: &str) -> bool {
+    record.starts_with('{')
+}
+
+///
+/// Find the position after the opening bracket and the position of the closing
+/// bracket of the `relay_chain` array of the JSON `record`.
+///
+fn relay_chain_bounds(record: &str) -> Option<(usize, usize)> {
+    let start = record.find(JSON_RELAY_CHAIN)? + JSON_RELAY_CHAIN.len();
+    let (mut in_string, mut escaped) = (false, false);
+    for (pos, c) in record[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ']' if !in_string => return Some((start, start + pos)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+///
+/// Encode the string `s` as JSON string with quotes and escapes.
+///
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_are_appended_in_order() {
+        let record = r#"{"name":"job","envelope":{"sent_ts_ms":1572962592345,"relay_chain":[]}}"#;
+        let record = append_relay(&append_relay(record, "relay1"), "relay2");
+        assert!(record.contains(r#""relay_chain":["relay1","relay2"]"#));
+
+        let record = append_relay("1||2||job||S||envelope.sent_ts_ms=1", "relay1");
+        assert_eq!(
+            record,
+            "1||2||job||S||envelope.sent_ts_ms=1||envelope.relay=relay1"
+        );
+        assert_eq!(append_relay("1||2||job||S", "relay1"), "1||2||job||S");
+    }
+}
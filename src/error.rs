@@ -0,0 +1,93 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Errors which stop the tool before or while the command runs. Threads send
+//! their errors to the main thread which reports them and picks the exit code.
+//!
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum OwlError {
+    /// The command is not found in `PATH` or the file it runs is missing.
+    CommandNotFound(String),
+    /// The command is found but fails to start, e.g. its interpreter is missing.
+    CommandStartFailed(String, io::Error),
+    /// The listener of signals cannot be installed.
+    SignalSetupFailed(io::Error),
+    /// The socket cannot be bound on the address.
+    SocketBindFailed(String, io::Error),
+    /// The options or the configuration file are invalid.
+    ConfigParseError(String),
+    /// The transport the state is delivered with cannot be opened.
+    TransportSetupFailed(String),
+}
+
+impl fmt::Display for OwlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OwlError::CommandNotFound(name) => write!(f, "command '{}' not found in PATH", name),
+            OwlError::CommandStartFailed(name, err) => {
+                write!(f, "failed to execute command '{}': {}", name, err)
+            }
+            OwlError::SignalSetupFailed(err) => {
+                write!(f, "failed to set up signal listener: {}", err)
+            }
+            OwlError::SocketBindFailed(addr, err) => {
+                write!(f, "failed to listen on {}: {}", addr, err)
+            }
+            OwlError::ConfigParseError(err) => write!(f, "{}", err),
+            OwlError::TransportSetupFailed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for OwlError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OwlError::CommandStartFailed(_, err)
+            | OwlError::SignalSetupFailed(err)
+            | OwlError::SocketBindFailed(_, err) => Some(err),
+            OwlError::CommandNotFound(_)
+            | OwlError::ConfigParseError(_)
+            | OwlError::TransportSetupFailed(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_displayed_with_causes() {
+        let err = OwlError::SocketBindFailed(
+            "127.0.0.1:9090".to_owned(),
+            io::Error::from(io::ErrorKind::AddrInUse),
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to listen on 127.0.0.1:9090: address in use"
+        );
+        assert!(err.source().is_some());
+        let err = OwlError::CommandNotFound("rsync".to_owned());
+        assert_eq!(err.to_string(), "command 'rsync' not found in PATH");
+        assert!(err.source().is_none());
+    }
+}
@@ -0,0 +1,105 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The limit of the number of lines the command outputs either over its whole
+//! life or within the rolling time window.
+//!
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub struct LineLimit {
+    max: u64,
+    window: Option<Duration>,
+    total: u64,
+    times: VecDeque<Instant>,
+}
+
+impl LineLimit {
+    ///
+    /// Make the limit of `max` lines in total or within `window` if it's given.
+    ///
+    pub fn new(max: u64, window: Option<Duration>) -> LineLimit {
+        LineLimit {
+            max,
+            window,
+            total: 0,
+            times: VecDeque::new(),
+        }
+    }
+
+    ///
+    /// Get the number of lines allowed.
+    ///
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    ///
+    /// Count the line output at `now` and test the limit is exceeded. Within
+    /// the window no more than `max + 1` times are kept.
+    ///
+    pub fn add(&mut self, now: Instant) -> bool {
+        match self.window {
+            Some(window) => {
+                self.times.push_back(now);
+                while self
+                    .times
+                    .front()
+                    .is_some_and(|at| now.duration_since(*at) >= window)
+                {
+                    self.times.pop_front();
+                }
+                let exceeded = self.times.len() as u64 > self.max;
+                if exceeded {
+                    self.times.pop_front();
+                }
+                exceeded
+            }
+            None => {
+                self.total += 1;
+                self.total > self.max
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_lines_exceed_limit() {
+        let mut limit = LineLimit::new(3, None);
+        let now = Instant::now();
+        let exceeded: Vec<bool> = (0..5).map(|_| limit.add(now)).collect();
+        assert_eq!(exceeded, vec![false, false, false, true, true]);
+    }
+
+    #[test]
+    fn lines_exceed_limit_within_window() {
+        let mut limit = LineLimit::new(2, Some(Duration::from_secs(10)));
+        let start = Instant::now();
+        assert!(!limit.add(start));
+        assert!(!limit.add(start + Duration::from_secs(1)));
+        assert!(limit.add(start + Duration::from_secs(2)));
+        // The line output after the first second leaves the window
+        assert!(!limit.add(start + Duration::from_secs(11)));
+        assert!(limit.add(start + Duration::from_millis(11500)));
+        assert!(!limit.add(start + Duration::from_secs(30)));
+    }
+}
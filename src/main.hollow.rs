@@ -14,13 +14,1721 @@
  * limitations under the License.
  */
 
-use std::net::UdpSocket;
+///
+/// The receiver which prints the state delivered by owl.
+///
+/// The usage is `hollow [FLAGS]` where `[FLAGS]` have form of `--name value`.
+///
+/// Supported flags:
+///
+/// - `--watch` is the pattern of state files to watch for changes instead of
+///   receiving UDP messages, e.g. `--watch /var/run/owl-*.json`.
+/// - `--tcp-port` is the TCP port to accept connections on instead of receiving
+///   UDP messages, e.g. `--tcp-port 9090`. Messages are framed with 4-byte
+///   big-endian length.
+/// - `--shm` is the shared memory segment of the owl `shm` transport to read
+///   messages from instead of receiving UDP messages, e.g. `--shm rsync` or `--shm /owl-rsync`.
+/// - `--mq` is the POSIX message queue of the owl `posix_mq` transport to receive
+///   messages from instead of receiving UDP messages, e.g. `--mq rsync` or `--mq /owl-rsync`.
+/// - `--filter-state` is the comma separated list of states messages are printed in,
+///   e.g. `--filter-state R,D`. States are given with letters like in `ps` or names.
+/// - `--filter-name` is the substring the name in messages printed contains,
+///   e.g. `--filter-name rsync`.
+/// - `--invert` prints messages which do not match filters.
+/// - `--db` is the SQLite database every heartbeat received is stored in,
+///   e.g. `--db ./owl.db`. Requires the `sqlite` feature.
+/// - `--query` is the SQL query run against the database on start and printed,
+///   e.g. `--query "SELECT name, AVG(cpu_pct) FROM heartbeats GROUP BY name"`.
+/// - `--watchdog-timeout` is the time without heartbeats the sender is reported
+///   missing after, e.g. `--watchdog-timeout 30s`.
+/// - `--watchdog-recovery-notify` reports senders which resume heartbeats.
+/// - `--timestamps` prefixes every line printed with the time it is printed at.
+/// - `--replay` is the log of the receiver replayed as if messages arrived live,
+///   e.g. `--replay /var/log/owl.log`. Lines are timed with the leading timestamp
+///   or the `ts` field of JSON messages.
+/// - `--speed` is the multiplier of the replay speed, e.g. `--speed 2.0`.
+/// - `--replay-from` and `--replay-to` limit the time window replayed,
+///   e.g. `--replay-from 2019-11-05T14:00:00Z`.
+/// - `--metrics-port` is the TCP port the Prometheus `/metrics` endpoint is served
+///   on, e.g. `--metrics-port 9100`.
+/// - `--metrics-ttl` is the time gauges of the silent sender expire after,
+///   e.g. `--metrics-ttl 120s`.
+/// - `--route` is the pattern of names and the file messages with matching names
+///   are appended to, e.g. `--route "rsync*:/var/log/rsync.log"`. The flag can be
+///   given more than once and the first matching route is taken.
+/// - `--relay` is the receiver messages are passed to with the receiver added
+///   to their relay chain, e.g. `--relay 10.0.0.5:9090` or `--relay tcp://10.0.0.5:9090`.
+/// - `--tee` is the UDP receiver the copy of every message is sent to as-is,
+///   e.g. `--tee udp://10.0.0.2:9090`. The flag can be given more than once.
+/// - `--proxy-to` is the upstream UDP receiver every message is re-emitted to in order
+///   with the `relay_host` field added, e.g. `--proxy-to udp://central:9090`.
+/// - `--proxy-batch` is the number of messages sent to the upstream receiver in one
+///   datagram as the JSON array, e.g. `--proxy-batch 10`.
+/// - `--aggregate-window` is the time window heartbeats of every sender are summarized
+///   in and printed with one line instead of every heartbeat, e.g. `--aggregate-window 60s`.
+/// - `--zabbix-server` is the Zabbix trapper address messages of the `zabbix`
+///   format are relayed to, e.g. `--zabbix-server 192.168.0.10:10051`.
+/// - `--tail-log` is the number of last messages clients of the `/stream` endpoint
+///   of `--metrics-port` get on connect before live messages, e.g. `--tail-log 100`.
+/// - `--sse-port` is the TCP port the Server-Sent Events `/events` endpoint streams
+///   every message received on, e.g. `--sse-port 8081`.
+/// - `--dry-run` parses and counts messages received and outputs nothing, e.g. to benchmark
+///   the receiver with `--dry-run --duration 10s`.
+/// - `--duration` is the time the receiver stops after printing the summary of messages
+///   received, e.g. `--duration 10s`.
+/// - `--json-schema` prints the JSON Schema of messages of the `json` format and exits.
+///
+extern crate nix;
+extern crate signal_hook;
+
+#[macro_use]
+extern crate lazy_static;
+
+mod aggregate;
+mod clock;
+mod counters;
+#[cfg(feature = "sqlite")]
+mod db;
+mod envelope;
+mod metrics;
+mod proxy;
+mod replay;
+mod schema;
+mod sse;
+mod tail;
+mod versions;
+mod watchdog;
+
+use aggregate::WindowStats;
+use counters::Counters;
+use metrics::{Exposition, Metrics};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use proxy::Proxy;
+use replay::{Keyboard, Player};
+use sse::Events;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tail::Tail;
+use versions::{MESSAGE_VERSION, SHM_LAYOUT_VERSION};
+use watchdog::Watchdog;
+
+// Defaults and constants
+const FLAG_START: &str = "--";
+const FLAG_WATCH: &str = "watch";
+const FLAG_ZABBIX_SERVER: &str = "zabbix-server";
+const FLAG_TCP_PORT: &str = "tcp-port";
+const FLAG_SHM: &str = "shm";
+const FLAG_MQ: &str = "mq";
+const SHM_NAME_PREFIX: &str = "/owl-";
+const SHM_HEADER_SIZE: usize = 24;
+const SHM_MODE: libc::mode_t = 0o600;
+const FLAG_FILTER_STATE: &str = "filter-state";
+const FLAG_FILTER_NAME: &str = "filter-name";
+const FLAG_INVERT: &str = "invert";
+const FLAG_DB: &str = "db";
+const FLAG_WATCHDOG_TIMEOUT: &str = "watchdog-timeout";
+const FLAG_WATCHDOG_RECOVERY_NOTIFY: &str = "watchdog-recovery-notify";
+const WATCHDOG_POLL_SECS: u64 = 1;
+const FLAG_TIMESTAMPS: &str = "timestamps";
+const FLAG_REPLAY: &str = "replay";
+const FLAG_SPEED: &str = "speed";
+const FLAG_REPLAY_FROM: &str = "replay-from";
+const FLAG_REPLAY_TO: &str = "replay-to";
+const DEFAULT_SPEED: f64 = 1.0;
+const REPLAY_SRC: &str = "replay";
+const FLAG_METRICS_PORT: &str = "metrics-port";
+const FLAG_METRICS_TTL: &str = "metrics-ttl";
+const FLAG_ROUTE: &str = "route";
+const ROUTE_DELIMITER: char = ':';
+const FLAG_RELAY: &str = "relay";
+const RELAY_TCP_SCHEME: &str = "tcp://";
+const RELAY_BIND_ADDR: &str = "0.0.0.0:0";
+const RELAY_TIMEOUT_SECS: u64 = 2;
+const ENVELOPE_PREFIX: &str = "envelope.";
+const VERSION_FIELD: &str = "version";
+const FLAG_TEE: &str = "tee";
+const FLAG_PROXY_TO: &str = "proxy-to";
+const FLAG_PROXY_BATCH: &str = "proxy-batch";
+const DEFAULT_PROXY_BATCH: usize = 1;
+const PROXY_FLUSH_MILLIS: u64 = 1000;
+const FLAG_AGGREGATE_WINDOW: &str = "aggregate-window";
+const FLAG_JSON_SCHEMA: &str = "json-schema";
+const FLAG_TAIL_LOG: &str = "tail-log";
+const FLAG_SSE_PORT: &str = "sse-port";
+const FLAG_DRY_RUN: &str = "dry-run";
+const FLAG_DURATION: &str = "duration";
+const EVENTS_PATH: &str = "/events";
+const EVENTS_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n";
+const EVENTS_KEEP_ALIVE_SECS: u64 = 15;
+const TEE_UDP_SCHEME: &str = "udp://";
+const DEFAULT_METRICS_TTL_SECS: u64 = 120;
+const METRICS_PATH: &str = "/metrics";
+const STREAM_PATH: &str = "/stream";
+const STREAM_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const OPENMETRICS_MEDIA_TYPE: &str = "application/openmetrics-text";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const ACCEPT_HEADER: &str = "accept:";
+const ALERT_LINE_PREFIXES: [&str; 2] = ["[ALERT]", "[RECOVERED]"];
+#[cfg(feature = "sqlite")]
+const FLAG_QUERY: &str = "query";
+const STATE_LETTERS: [(&str, &str); 7] = [
+    ("R", "Running"),
+    ("S", "Sleeping"),
+    ("D", "Waiting"),
+    ("Z", "Zombie"),
+    ("T", "Stopped"),
+    ("t", "TraceStopped"),
+    ("X", "Dead"),
+];
+const DEFAULT_LISTEN_HOST: &str = "127.0.0.1";
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:9090";
+const POLL_MILLIS: u64 = 100;
+const RECEIVE_BUFFER_SIZE: usize = 65536;
+const ZABBIX_HEADER: &[u8] = b"ZBXD\x01";
+const ZABBIX_TIMEOUT_SECS: u64 = 5;
+#[cfg(feature = "sqlite")]
+const DB_QUEUE_SIZE: usize = 1024;
+const FAILURE: i32 = 1;
+const EVENT_TEST: &str = "test";
+const TEST_ACK: &[u8] = b"OK";
+
+lazy_static! {
+    // The collection of command line flags.
+    static ref FLAGS: HashMap<String, String> = collect_flags();
+
+    // The routes of messages to output files by the name.
+    static ref ROUTES: Vec<Route> = open_routes();
+
+    // The targets every message received is copied to.
+    static ref TEES: Vec<String> = flag_values(FLAG_TEE)
+        .into_iter()
+        .map(|v| v.strip_prefix(TEE_UDP_SCHEME).map(String::from).unwrap_or(v))
+        .collect();
+
+    // The socket messages are copied to tee targets with.
+    static ref TEE_SOCKET: Option<UdpSocket> = if TEES.is_empty() {
+        None
+    } else {
+        let socket = UdpSocket::bind(RELAY_BIND_ADDR).and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        });
+        match socket {
+            Ok(socket) => Some(socket),
+            Err(err) => {
+                eprintln!("error: failed to open tee socket: {}", err);
+                process::exit(FAILURE);
+            }
+        }
+    };
+
+    // The upstream receiver messages are re-emitted to in order.
+    static ref PROXY: Option<Mutex<Proxy>> = FLAGS.get(FLAG_PROXY_TO).map(|target| {
+        let batch = match FLAGS.get(FLAG_PROXY_BATCH) {
+            Some(v) => v.parse::<usize>().unwrap_or_else(|_| {
+                eprintln!("error: invalid proxy batch '{}'", v);
+                process::exit(FAILURE);
+            }),
+            None => DEFAULT_PROXY_BATCH,
+        };
+        let target = target.strip_prefix(TEE_UDP_SCHEME).unwrap_or(target);
+        match Proxy::new(target, batch, &RELAY_ID, RELAY_BIND_ADDR) {
+            Ok(proxy) => Mutex::new(proxy),
+            Err(err) => {
+                eprintln!("error: failed to open proxy to {}: {}", target, err);
+                process::exit(FAILURE);
+            }
+        }
+    });
+
+    // The connection records are relayed to the next receiver over.
+    static ref RELAY_LINK: Mutex<Option<RelayLink>> = Mutex::new(None);
+
+    // The name the receiver adds to the relay chain of messages it relays.
+    static ref RELAY_ID: String = {
+        let mut buf = [0u8; 256];
+        nix::unistd::gethostname(&mut buf)
+            .ok()
+            .and_then(|name| name.to_str().ok().map(String::from))
+            .unwrap_or_else(|| DEFAULT_LISTEN_HOST.to_owned())
+    };
+
+    // The flag is raised when messages newer than the receiver knows are reported.
+    static ref NEWER_VERSION_REPORTED: AtomicBool = AtomicBool::new(false);
+
+    // The flag is raised when messages are parsed and counted only.
+    static ref DRY_RUN: bool = FLAGS.contains_key(FLAG_DRY_RUN);
+
+    // The counters of messages received.
+    static ref COUNTERS: Counters = Counters::new();
+
+    // The flag is raised when the receiver is about to exit.
+    static ref SHUTDOWN: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
+    // The filter of messages printed.
+    static ref FILTER: Filter = Filter::from_flags();
+
+    // The gauges of senders exposed to Prometheus.
+    static ref METRICS: Option<Mutex<Metrics>> = FLAGS.get(FLAG_METRICS_PORT).map(|_| {
+        let ttl = match FLAGS.get(FLAG_METRICS_TTL) {
+            Some(v) => parse_duration(v).unwrap_or_else(|| {
+                eprintln!("error: invalid metrics TTL '{}'", v);
+                process::exit(FAILURE);
+            }),
+            None => Duration::from_secs(DEFAULT_METRICS_TTL_SECS),
+        };
+        Mutex::new(Metrics::new(ttl))
+    });
+
+    // The browser clients messages are pushed to as Server-Sent Events.
+    static ref EVENTS: Mutex<Events> = Mutex::new(Events::default());
+
+    // The last messages and clients they are streamed to.
+    static ref TAIL: Mutex<Tail> = Mutex::new(Tail::new(match FLAGS.get(FLAG_TAIL_LOG) {
+        Some(v) => v.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("error: invalid tail log size '{}'", v);
+            process::exit(FAILURE);
+        }),
+        None => 0,
+    }));
+
+    // The window and statistics of senders within the window when heartbeats are aggregated.
+    static ref AGGREGATE: Option<(Duration, Mutex<HashMap<String, WindowStats>>)> = FLAGS
+        .get(FLAG_AGGREGATE_WINDOW)
+        .map(|v| match parse_duration(v) {
+            Some(window) if window > Duration::from_secs(0) => (window, Mutex::new(HashMap::new())),
+            _ => {
+                eprintln!("error: invalid aggregate window '{}'", v);
+                process::exit(FAILURE);
+            }
+        });
+
+    // The watchdog of senders missing heartbeats.
+    static ref WATCHDOG: Option<Mutex<Watchdog>> = FLAGS
+        .get(FLAG_WATCHDOG_TIMEOUT)
+        .map(|v| match parse_duration(v) {
+            Some(timeout) => Mutex::new(Watchdog::new(timeout)),
+            None => {
+                eprintln!("error: invalid watchdog timeout '{}'", v);
+                process::exit(FAILURE);
+            }
+        });
+}
+
+#[cfg(feature = "sqlite")]
+lazy_static! {
+    // The queue of heartbeats the database writer stores.
+    static ref DB_QUEUE: Mutex<Option<mpsc::SyncSender<db::Heartbeat>>> = Mutex::new(None);
+}
+
+///
+/// The filter of messages by the state and the name of the process.
+/// Conditions given are combined with AND.
+///
+struct Filter {
+    states: Vec<String>,
+    name: Option<String>,
+    invert: bool,
+}
+
+impl Filter {
+    fn from_flags() -> Filter {
+        let states = FLAGS
+            .get(FLAG_FILTER_STATE)
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| {
+                        STATE_LETTERS
+                            .iter()
+                            .find(|(letter, _)| *letter == v)
+                            .map_or(v, |(_, name)| *name)
+                            .to_lowercase()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Filter {
+            states,
+            name: FLAGS.get(FLAG_FILTER_NAME).cloned(),
+            invert: FLAGS.contains_key(FLAG_INVERT),
+        }
+    }
+
+    ///
+    /// Test the message with `name` and `state` passes the filter.
+    ///
+    fn matches(&self, name: &str, state: &str) -> bool {
+        let state_ok = self.states.is_empty() || self.states.contains(&state.to_lowercase());
+        let name_ok = match self.name {
+            Some(ref v) => name.contains(v.as_str()),
+            None => true,
+        };
+        (state_ok && name_ok) != self.invert
+    }
+}
+
+///
+/// The route of messages which names match the pattern to the output file.
+///
+struct Route {
+    pattern: String,
+    output: Mutex<io::BufWriter<fs::File>>,
+}
 
 fn main() {
-    let socket = UdpSocket::bind("127.0.0.1:9090").unwrap();
+    if FLAGS.contains_key(FLAG_JSON_SCHEMA) {
+        println!("{}", schema::state_message_schema());
+        return;
+    }
+
+    lazy_static::initialize(&COUNTERS);
+    lazy_static::initialize(&ROUTES);
+    lazy_static::initialize(&TEE_SOCKET);
+    lazy_static::initialize(&PROXY);
+
+    if let Some(path) = FLAGS.get(FLAG_DB) {
+        if let Err(err) = open_database(path) {
+            eprintln!("error: failed to open database '{}': {}", path, err);
+            process::exit(FAILURE);
+        }
+    }
+
+    if WATCHDOG.is_some() {
+        thread::spawn(check_watchdog);
+    }
+
+    if AGGREGATE.is_some() {
+        thread::spawn(print_aggregates);
+    }
+
+    if PROXY.is_some() {
+        thread::spawn(flush_proxy);
+    }
+
+    if let Some(v) = FLAGS.get(FLAG_DURATION) {
+        match parse_duration(v) {
+            Some(duration) => {
+                thread::spawn(move || {
+                    thread::sleep(duration);
+                    println!("{}", COUNTERS.summary());
+                    process::exit(0);
+                });
+            }
+            None => {
+                eprintln!("error: invalid duration '{}'", v);
+                process::exit(FAILURE);
+            }
+        }
+    }
+
+    if let Some(port) = FLAGS.get(FLAG_METRICS_PORT) {
+        let listener = port
+            .parse::<u16>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+            .and_then(|port| TcpListener::bind((DEFAULT_LISTEN_HOST, port)));
+        match listener {
+            Ok(listener) => {
+                thread::spawn(move || serve_metrics(listener));
+            }
+            Err(err) => {
+                eprintln!(
+                    "error: failed to listen on metrics port '{}': {}",
+                    port, err
+                );
+                process::exit(FAILURE);
+            }
+        }
+    }
+
+    if let Some(port) = FLAGS.get(FLAG_SSE_PORT) {
+        let listener = port
+            .parse::<u16>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+            .and_then(|port| TcpListener::bind((DEFAULT_LISTEN_HOST, port)));
+        match listener {
+            Ok(listener) => {
+                thread::spawn(move || serve_events(listener));
+                thread::spawn(keep_events_alive);
+            }
+            Err(err) => {
+                eprintln!("error: failed to listen on SSE port '{}': {}", port, err);
+                process::exit(FAILURE);
+            }
+        }
+    }
+
+    if let Some(path) = FLAGS.get(FLAG_REPLAY) {
+        if let Err(err) = replay_log(path) {
+            eprintln!("error: failed to replay '{}': {}", path, err);
+            process::exit(FAILURE);
+        }
+        return;
+    }
+
+    if let Some(pattern) = FLAGS.get(FLAG_WATCH) {
+        if let Err(err) = watch_state_files(pattern) {
+            eprintln!("error: failed to watch '{}': {}", pattern, err);
+            process::exit(FAILURE);
+        }
+        return;
+    }
+
+    if let Some(port) = FLAGS.get(FLAG_TCP_PORT) {
+        if let Err(err) = receive_tcp(port) {
+            eprintln!("error: failed to listen on TCP port '{}': {}", port, err);
+            process::exit(FAILURE);
+        }
+        return;
+    }
+
+    if let Some(name) = FLAGS.get(FLAG_SHM) {
+        if let Err(err) = receive_shm(name) {
+            eprintln!("error: failed to read shared memory '{}': {}", name, err);
+            process::exit(FAILURE);
+        }
+        return;
+    }
+
+    if let Some(name) = FLAGS.get(FLAG_MQ) {
+        if let Err(err) = receive_mq(name) {
+            eprintln!(
+                "error: failed to receive from message queue '{}': {}",
+                name, err
+            );
+            process::exit(FAILURE);
+        }
+        return;
+    }
+
+    let socket = match UdpSocket::bind(DEFAULT_LISTEN_ADDR) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!(
+                "error: failed to listen on {}: {}",
+                DEFAULT_LISTEN_ADDR, err
+            );
+            process::exit(FAILURE);
+        }
+    };
+    let mut buf = [0; RECEIVE_BUFFER_SIZE];
     loop {
-        let mut buf = [0; 512];
-        let (_, src) = socket.recv_from(&mut buf).unwrap();
-        println!("{} -> {}", src,  String::from_utf8_lossy(&buf));
+        match socket.recv_from(&mut buf) {
+            Ok((len, src)) => handle_message(&src.to_string(), &buf[..len]),
+            Err(err) => eprintln!("error: failed to receive message: {}", err),
+        }
+    }
+}
+
+///
+/// Print the message `payload` received from `src`.
+///
+fn handle_message(src: &str, payload: &[u8]) {
+    COUNTERS.received(payload.len());
+    if !*DRY_RUN {
+        tee_message(payload);
+    }
+
+    if payload.starts_with(ZABBIX_HEADER) {
+        // Print the JSON body of Zabbix frames and relay them as-is
+        let body = payload.get(ZABBIX_HEADER.len() + 8..);
+        COUNTERS.parsed(body.is_some());
+        if *DRY_RUN {
+            return;
+        }
+        let body = body.unwrap_or_default();
+        print_line(&format!("{} -> {}", src, String::from_utf8_lossy(body)));
+        if let Some(server) = FLAGS.get(FLAG_ZABBIX_SERVER) {
+            match relay_zabbix(server, payload) {
+                Ok(response) => print_line(&format!("{} <- {}", server, response)),
+                Err(err) => eprintln!("error: failed to relay to {}: {}", server, err),
+            }
+        }
+    } else {
+        let text = String::from_utf8_lossy(payload);
+        for record in split_records(&text) {
+            let (name, state) = record_name_state(record);
+            COUNTERS.parsed(!name.is_empty() && !state.is_empty());
+            if *DRY_RUN {
+                // Parse fields the same as printing does and output nothing
+                std::hint::black_box(record_numbers(record));
+                std::hint::black_box(envelope::sent_ts_ms(record));
+                continue;
+            }
+
+            // Pass the record further along the relay chain
+            if let Some(target) = FLAGS.get(FLAG_RELAY) {
+                if let Err(err) = relay_record(target, record) {
+                    eprintln!("error: failed to relay to {}: {}", target, err);
+                }
+            }
+            if let Some(Ok(mut proxy)) = PROXY.as_ref().map(|proxy| proxy.lock()) {
+                if let Err(err) = proxy.push(record) {
+                    eprintln!("error: failed to proxy to {}: {}", proxy.target(), err);
+                }
+            }
+
+            check_version(src, record);
+
+            // Stamp the record with the time it is received at
+            let received_ts_ms = clock::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default();
+            let latency_ms =
+                envelope::sent_ts_ms(record).map(|sent| received_ts_ms.saturating_sub(sent) as f64);
+            let stamped = envelope::stamp_received(record, received_ts_ms);
+
+            if FILTER.matches(&name, &state) {
+                let mut values = record_numbers(record);
+                match AGGREGATE.as_ref() {
+                    Some((_, senders)) => {
+                        if let Ok(mut senders) = senders.lock() {
+                            senders
+                                .entry(name.clone())
+                                .or_default()
+                                .add(&state, &values);
+                        }
+                    }
+                    None => route_line(&name, &format!("{} -> {}", src, stamped)),
+                }
+                if let Ok(mut tail) = TAIL.lock() {
+                    tail.push(&format!("{} -> {}", src, stamped));
+                }
+                if let Ok(mut events) = EVENTS.lock() {
+                    events.send(&stamped);
+                }
+                let instance = record_number(record, "owl_pid").unwrap_or_default();
+                if let Some(metrics) = METRICS.as_ref() {
+                    if let Some(latency_ms) = latency_ms {
+                        values.push(("latency_ms".to_owned(), latency_ms));
+                    }
+                    if let Ok(mut metrics) = metrics.lock() {
+                        metrics.update(&name, &instance, &state, values, clock::now());
+                    }
+                }
+                if let Some(watchdog) = WATCHDOG.as_ref() {
+                    let notice = watchdog
+                        .lock()
+                        .ok()
+                        .and_then(|mut w| w.seen(&name, &instance, clock::now()));
+                    if let Some(notice) = notice {
+                        if FLAGS.contains_key(FLAG_WATCHDOG_RECOVERY_NOTIFY) {
+                            print_line(&notice);
+                        }
+                    }
+                }
+                #[cfg(feature = "sqlite")]
+                store_heartbeat(record, name, state);
+            }
+        }
+    }
+}
+
+///
+/// Open the SQLite database at `path`, run the query from the `--query` flag,
+/// and start the writer thread which stores heartbeats queued.
+///
+#[cfg(feature = "sqlite")]
+fn open_database(path: &str) -> Result<(), String> {
+    let database = db::Database::open(path)?;
+    if let Some(sql) = FLAGS.get(FLAG_QUERY) {
+        let (columns, rows) = database.query(sql)?;
+        println!("{}", columns.join("\t"));
+        for row in rows {
+            println!("{}", row.join("\t"));
+        }
+    }
+
+    // The receive loop does not wait for the database, heartbeats are dropped
+    // when the queue is full
+    let (sender, receiver) = mpsc::sync_channel::<db::Heartbeat>(DB_QUEUE_SIZE);
+    thread::spawn(move || {
+        for heartbeat in receiver {
+            if let Err(err) = database.insert(&heartbeat) {
+                eprintln!("error: failed to store heartbeat: {}", err);
+            }
+        }
+    });
+    if let Ok(mut queue) = DB_QUEUE.lock() {
+        *queue = Some(sender);
+    }
+
+    Ok(())
+}
+
+///
+/// The database is supported with the `sqlite` feature only.
+///
+#[cfg(not(feature = "sqlite"))]
+fn open_database(_path: &str) -> Result<(), String> {
+    Err("hollow is built without the sqlite feature".to_owned())
+}
+
+///
+/// Queue the heartbeat `record` of the process with `name` and `state`
+/// for storing in the database if it is open.
+///
+#[cfg(feature = "sqlite")]
+fn store_heartbeat(record: &str, name: String, state: String) {
+    let queue = match DB_QUEUE.lock() {
+        Ok(queue) => queue,
+        Err(_) => return,
+    };
+    if let Some(sender) = queue.as_ref() {
+        let int = |key: &str| record_number(record, key).and_then(|v| v.parse::<i64>().ok());
+        let heartbeat = db::Heartbeat {
+            ts: clock::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+            owl_pid: int("owl_pid"),
+            child_pid: int("pid"),
+            name,
+            state,
+            cpu_pct: record_number(record, "cpu_pct").and_then(|v| v.parse::<f64>().ok()),
+            rss_bytes: int("rss_bytes"),
+            threads: int("threads"),
+        };
+        if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(heartbeat) {
+            eprintln!("warning: database queue is full, heartbeat dropped");
+        }
+    }
+}
+
+///
+/// Find the numeric field `key` of the SSDPD or JSON `record`.
+/// The `owl_pid` and `pid` are the first two fields of SSDPD records.
+///
+fn record_number(record: &str, key: &str) -> Option<String> {
+    if record.starts_with('{') {
+        let pattern = format!("\"{}\":", key);
+        let start = record.find(&pattern)? + pattern.len();
+        let value: String = record[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+            .collect();
+        Some(value).filter(|v| !v.is_empty())
+    } else {
+        let mut fields = record.split("||");
+        match key {
+            "owl_pid" => fields.next().map(str::to_owned),
+            "pid" => fields.nth(1).map(str::to_owned),
+            _ => {
+                let prefix = format!("{}=", key);
+                fields.find_map(|field| field.strip_prefix(prefix.as_str()).map(str::to_owned))
+            }
+        }
+    }
+}
+
+///
+/// Report once the `record` from `src` has the message version newer than
+/// the receiver knows. Unknown fields of newer messages are skipped and fields
+/// missing in older messages are treated as absent, so all versions are handled.
+///
+fn check_version(src: &str, record: &str) {
+    let version = record_number(record, VERSION_FIELD).and_then(|v| v.parse::<u64>().ok());
+    if let Some(version) = version.filter(|v| *v > u64::from(MESSAGE_VERSION)) {
+        if !NEWER_VERSION_REPORTED.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "warning: {} sends messages of version {} newer than {}, unknown fields are skipped",
+                src, version, MESSAGE_VERSION
+            );
+        }
     }
 }
+
+///
+/// Collect numeric fields of the SSDPD or JSON `record` except process ids.
+/// Only top level fields of JSON objects are collected.
+///
+fn record_numbers(record: &str) -> Vec<(String, f64)> {
+    let mut numbers = Vec::new();
+    if !record.starts_with('{') {
+        for field in record.split("||").skip(4) {
+            if field.starts_with(ENVELOPE_PREFIX) {
+                continue;
+            }
+            if let Some((key, value)) = field
+                .split_once('=')
+                .filter(|(key, _)| *key != VERSION_FIELD)
+            {
+                if let Ok(value) = value.parse::<f64>() {
+                    numbers.push((key.to_owned(), value));
+                }
+            }
+        }
+        return numbers;
+    }
+
+    // Walk the object keeping track of nesting and strings
+    let chars: Vec<char> = record.chars().collect();
+    let (mut pos, mut depth) = (0, 0);
+    let mut key: Option<String> = None;
+    while pos < chars.len() {
+        match chars[pos] {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            '"' => {
+                let start = pos + 1;
+                pos += 1;
+                while pos < chars.len() && chars[pos] != '"' {
+                    if chars[pos] == '\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                let is_key = chars.get(pos + 1) == Some(&':');
+                if depth == 1 && is_key {
+                    key = Some(chars[start..pos.min(chars.len())].iter().collect());
+                }
+            }
+            c if depth == 1 && (c.is_ascii_digit() || c == '-') => {
+                let start = pos;
+                while pos + 1 < chars.len()
+                    && (chars[pos + 1].is_ascii_digit() || ".-+eE".contains(chars[pos + 1]))
+                {
+                    pos += 1;
+                }
+                let value: String = chars[start..=pos].iter().collect();
+                if let (Some(key), Ok(value)) = (key.take(), value.parse::<f64>()) {
+                    numbers.push((key, value));
+                }
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    numbers.retain(|(key, _)| key != "owl_pid" && key != "pid" && key != VERSION_FIELD);
+    numbers
+}
+
+///
+/// Serve the Server-Sent Events `/events` endpoint on the `listener` keeping
+/// connections of clients open to push messages to them.
+///
+fn serve_events(listener: TcpListener) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(ZABBIX_TIMEOUT_SECS)));
+
+        // Read the request line and skip headers
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        if reader.read_line(&mut request).is_err() {
+            continue;
+        }
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+            header.clear();
+        }
+
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        if path == EVENTS_PATH {
+            if stream.write_all(EVENTS_RESPONSE.as_bytes()).is_ok() {
+                if let Ok(mut events) = EVENTS.lock() {
+                    events.attach(stream);
+                }
+            }
+        } else {
+            let _ = stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+    }
+}
+
+///
+/// Send the keep-alive comment to Server-Sent Events clients periodically.
+///
+fn keep_events_alive() {
+    loop {
+        thread::sleep(Duration::from_secs(EVENTS_KEEP_ALIVE_SECS));
+        if let Ok(mut events) = EVENTS.lock() {
+            events.keep_alive();
+        }
+    }
+}
+
+///
+/// Serve the Prometheus `/metrics` endpoint on the `listener` answering
+/// every connection with gauges of senders. Gauges are rendered in the OpenMetrics
+/// format if the scraper accepts it and in the Prometheus text format otherwise.
+///
+fn serve_metrics(listener: TcpListener) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(ZABBIX_TIMEOUT_SECS)));
+
+        // Read the request line and headers the format is negotiated with
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        if reader.read_line(&mut request).is_err() {
+            continue;
+        }
+        let mut exposition = Exposition::Text;
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+            let accepts_openmetrics = header.to_ascii_lowercase().starts_with(ACCEPT_HEADER)
+                && header.contains(OPENMETRICS_MEDIA_TYPE);
+            if accepts_openmetrics {
+                exposition = Exposition::OpenMetrics;
+            }
+            header.clear();
+        }
+        let content_type = match exposition {
+            Exposition::Text => METRICS_CONTENT_TYPE,
+            Exposition::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+        };
+
+        // Streaming clients get the tail and live messages over the connection kept open
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        if path == STREAM_PATH {
+            if stream.write_all(STREAM_RESPONSE.as_bytes()).is_ok() {
+                if let Ok(mut tail) = TAIL.lock() {
+                    tail.attach(stream);
+                }
+            }
+            continue;
+        }
+        let response = if path == METRICS_PATH {
+            let body = METRICS
+                .as_ref()
+                .and_then(|metrics| {
+                    metrics
+                        .lock()
+                        .ok()
+                        .map(|mut m| m.render(clock::now(), exposition))
+                })
+                .unwrap_or_default();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        };
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
+
+///
+/// Print the `line` prefixed with the time when the `--timestamps` flag is set.
+/// Nothing is printed with the `--dry-run` flag.
+///
+fn print_line(line: &str) {
+    if !*DRY_RUN {
+        println!("{}", stamp_line(line));
+    }
+}
+
+///
+/// Prefix the `line` with the time when the `--timestamps` flag is set.
+///
+fn stamp_line(line: &str) -> String {
+    if FLAGS.contains_key(FLAG_TIMESTAMPS) {
+        format!("{} {}", clock::format_time(clock::now(), true), line)
+    } else {
+        line.to_owned()
+    }
+}
+
+///
+/// Write the `line` of the message with `name` to the output file of the first
+/// route which pattern matches the name. The line is printed if no route matches.
+///
+fn route_line(name: &str, line: &str) {
+    let route = ROUTES
+        .iter()
+        .find(|route| wildcard_match(&route.pattern, name));
+    match route {
+        Some(route) => {
+            if let Ok(mut output) = route.output.lock() {
+                let result = writeln!(output, "{}", stamp_line(line)).and_then(|_| output.flush());
+                if let Err(err) = result {
+                    eprintln!("error: failed to write route '{}': {}", route.pattern, err);
+                }
+            }
+        }
+        None => print_line(line),
+    }
+}
+
+///
+/// Open output files of routes from `--route` flags which have form
+/// of `pattern:path`. Files are opened for appending.
+///
+fn open_routes() -> Vec<Route> {
+    flag_values(FLAG_ROUTE)
+        .into_iter()
+        .map(|spec| {
+            let (pattern, path) = match spec.find(ROUTE_DELIMITER) {
+                Some(pos) if pos > 0 && pos + 1 < spec.len() => (&spec[..pos], &spec[pos + 1..]),
+                _ => {
+                    eprintln!("error: invalid route '{}'", spec);
+                    process::exit(FAILURE);
+                }
+            };
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|err| {
+                    eprintln!("error: failed to open route file '{}': {}", path, err);
+                    process::exit(FAILURE);
+                });
+            Route {
+                pattern: pattern.to_owned(),
+                output: Mutex::new(io::BufWriter::new(file)),
+            }
+        })
+        .collect()
+}
+
+///
+/// Replay the receiver log at `path` handling every line as the message
+/// received. Lines are `[<time>] <src> -> <message>` as printed by the receiver
+/// or just messages. The time is taken from the leading timestamp or the `ts`
+/// field of JSON messages and gaps between lines are kept divided by the speed
+/// from the `--speed` flag. Lines without time are handled immediately.
+/// The speed is changed from the keyboard while the log is replayed,
+/// see the `replay` module.
+///
+fn replay_log(path: &str) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let speed = match FLAGS.get(FLAG_SPEED) {
+        Some(v) => v
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| invalid(format!("invalid speed '{}'", v)))?,
+        None => DEFAULT_SPEED,
+    };
+    let window = |flag: &str| match FLAGS.get(flag) {
+        Some(v) => clock::parse_time(v)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("invalid time '{}'", v))),
+        None => Ok(None),
+    };
+    let (from, to) = (window(FLAG_REPLAY_FROM)?, window(FLAG_REPLAY_TO)?);
+
+    let (keys, commands) = mpsc::channel();
+    let _keyboard = Keyboard::open(keys);
+    let mut player = Player::new(speed, commands);
+
+    let mut started = false;
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        let (ts, rest) = match line.split_once(' ') {
+            Some((head, rest)) if !rest.is_empty() => match clock::parse_time(head) {
+                Some(ts) => (Some(ts), rest),
+                None => (None, line.as_str()),
+            },
+            _ => (None, line.as_str()),
+        };
+        if rest.trim().is_empty() || ALERT_LINE_PREFIXES.iter().any(|p| rest.starts_with(p)) {
+            continue;
+        }
+
+        let (src, payload) = rest.split_once(" -> ").unwrap_or((REPLAY_SRC, rest));
+        let ts = ts.or_else(|| record_number(payload, "ts").and_then(|v| clock::parse_time(&v)));
+
+        // Skip lines out of the time window and wait for the line time to come
+        match ts {
+            Some(ts) if from.is_some_and(|from| ts < from) || to.is_some_and(|to| ts > to) => {
+                continue
+            }
+            Some(ts) => {
+                if !started {
+                    clock::start_replay(ts, player.speed());
+                    started = true;
+                }
+                if !player.wait_until(ts) {
+                    break;
+                }
+            }
+            None if from.is_some() || to.is_some() => continue,
+            None => {}
+        }
+
+        handle_message(src, payload.as_bytes());
+    }
+
+    Ok(())
+}
+
+///
+/// Send messages waiting for the batch to fill to the upstream receiver periodically,
+/// so they are not held back when few messages arrive.
+///
+fn flush_proxy() {
+    while let Some(proxy) = PROXY.as_ref() {
+        thread::sleep(Duration::from_millis(PROXY_FLUSH_MILLIS));
+        let mut proxy = match proxy.lock() {
+            Ok(proxy) => proxy,
+            Err(_) => return,
+        };
+        if let Err(err) = proxy.flush() {
+            eprintln!("error: failed to proxy to {}: {}", proxy.target(), err);
+        }
+    }
+}
+
+///
+/// Check the watchdog every second and print alerts on senders which
+/// heartbeats are missing.
+///
+fn check_watchdog() {
+    while let Some(watchdog) = WATCHDOG.as_ref() {
+        thread::sleep(Duration::from_secs(WATCHDOG_POLL_SECS));
+        let alerts = match watchdog.lock() {
+            Ok(mut watchdog) => watchdog.check(clock::now()),
+            Err(_) => return,
+        };
+        for alert in alerts {
+            print_line(&alert);
+        }
+    }
+}
+
+///
+/// Print the summary of every sender at the end of each aggregate window
+/// and start the next window over.
+///
+fn print_aggregates() {
+    while let Some((window, senders)) = AGGREGATE.as_ref() {
+        thread::sleep(*window);
+        let senders = match senders.lock() {
+            Ok(mut senders) => std::mem::take(&mut *senders),
+            Err(_) => return,
+        };
+        let mut names: Vec<&String> = senders.keys().collect();
+        names.sort();
+        for name in names {
+            let summary = senders[name].summary(name, |state| {
+                STATE_LETTERS
+                    .iter()
+                    .find(|(_, v)| *v == state)
+                    .map_or(state, |(letter, _)| *letter)
+                    .to_owned()
+            });
+            route_line(name, &summary);
+        }
+    }
+}
+
+///
+/// Parse the duration from `value` which is the number optionally followed
+/// with one of `ms`, `s`, `m`, or `h` suffix, e.g. `30s`. The number without
+/// suffix is milliseconds like in owl options.
+///
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let pos = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let num = value[..pos].parse::<u64>().ok()?;
+    match value[pos..].trim() {
+        "" | "ms" => Some(Duration::from_millis(num)),
+        "s" => Some(Duration::from_secs(num)),
+        "m" => num.checked_mul(60).map(Duration::from_secs),
+        "h" => num.checked_mul(3600).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+///
+/// Split the message `text` into records of processes. The batch of SSDPD
+/// messages has one record per line and the batch of JSON messages is the array
+/// of objects.
+///
+fn split_records(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    if !text.starts_with('[') {
+        return text.lines().filter(|line| !line.is_empty()).collect();
+    }
+
+    // Split the array into top level objects skipping braces in strings
+    let mut records = Vec::new();
+    let (mut depth, mut start, mut in_string, mut escaped) = (0, 0, false, false);
+    for (pos, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => {
+                if depth == 0 {
+                    start = pos;
+                }
+                depth += 1;
+            }
+            '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    records.push(&text[start..=pos]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    records
+}
+
+///
+/// Get the name and the state of the process from the SSDPD or JSON `record`.
+///
+fn record_name_state(record: &str) -> (String, String) {
+    if record.starts_with('{') {
+        (
+            json_string_field(record, "name").unwrap_or_default(),
+            json_string_field(record, "state").unwrap_or_default(),
+        )
+    } else {
+        let mut fields = record.split("||").skip(2);
+        let name = fields.next().unwrap_or_default().to_owned();
+        let state = fields.next().unwrap_or_default().to_owned();
+        (name, state)
+    }
+}
+
+///
+/// Get the event of the SSDPD or JSON `record` if it is not the heartbeat.
+///
+fn record_event(record: &str) -> Option<String> {
+    if record.starts_with('{') {
+        json_string_field(record, "event")
+    } else {
+        record
+            .split("||")
+            .skip(4)
+            .find_map(|field| field.strip_prefix("event=").map(str::to_owned))
+    }
+}
+
+///
+/// Find the string field `key` of the top level JSON `object` encoded by owl
+/// and return its unescaped value.
+///
+fn json_string_field(object: &str, key: &str) -> Option<String> {
+    let pattern = format!("\"{}\":\"", key);
+    let start = object.find(&pattern)? + pattern.len();
+    let mut value = String::new();
+    let mut chars = object[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+///
+/// Accept TCP connections on the `port` and read messages from every connection
+/// in its own thread. On SIGINT the listener is closed and the receiver waits
+/// for connections to finish messages they are reading.
+///
+fn receive_tcp(port: &str) -> io::Result<()> {
+    let port = port
+        .parse::<u16>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    signal_hook::flag::register(signal_hook::SIGINT, Arc::clone(&SHUTDOWN))?;
+
+    let listener = TcpListener::bind((DEFAULT_LISTEN_HOST, port))?;
+    listener.set_nonblocking(true)?;
+
+    let mut connections: Vec<thread::JoinHandle<()>> = Vec::new();
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, src)) => {
+                stream.set_nonblocking(false)?;
+                connections.push(thread::spawn(move || {
+                    if let Err(err) = receive_connection(stream, &src.to_string()) {
+                        eprintln!("error: failed to read from {}: {}", src, err);
+                    }
+                }));
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(POLL_MILLIS));
+            }
+            Err(err) => eprintln!("error: failed to accept connection: {}", err),
+        }
+        connections.retain(|c| !c.is_finished());
+    }
+
+    drop(listener);
+    for connection in connections {
+        let _ = connection.join();
+    }
+
+    Ok(())
+}
+
+///
+/// Read messages from the shared memory segment `name` every time its semaphore
+/// is posted. The segment and the semaphore are created if the sender is not
+/// started yet. Messages already read are skipped when the semaphore is posted
+/// a few times while the message is read. The message is copied again when
+/// the sequence number of the header shows the sender changed it meanwhile.
+///
+fn receive_shm(name: &str) -> io::Result<()> {
+    let name = if name.starts_with('/') {
+        name.to_owned()
+    } else {
+        format!("{}{}", SHM_NAME_PREFIX, name)
+    };
+    let c_name = CString::new(name.as_str())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let size = SHM_HEADER_SIZE + RECEIVE_BUFFER_SIZE;
+
+    let (data, sem) = unsafe {
+        let fd = libc::shm_open(c_name.as_ptr(), libc::O_CREAT | libc::O_RDWR, SHM_MODE);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::ftruncate(fd, size as libc::off_t) != 0 {
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
+        }
+        let data = libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        libc::close(fd);
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let sem = libc::sem_open(
+            c_name.as_ptr(),
+            libc::O_CREAT,
+            libc::c_uint::from(SHM_MODE),
+            0 as libc::c_uint,
+        );
+        if sem == libc::SEM_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        (data as *const u8, sem)
+    };
+
+    let sequence = unsafe { &*(data.add(16) as *const AtomicU64) };
+    let mut last_seq = 0;
+    loop {
+        if unsafe { libc::sem_wait(sem) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        // The sequence number is odd while the sender writes the message
+        let (version, seq, payload) = loop {
+            let seq = sequence.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                thread::yield_now();
+                continue;
+            }
+            let (version, len) = unsafe {
+                (
+                    std::ptr::read_volatile(data as *const u32),
+                    std::ptr::read_volatile(data.add(4) as *const u32) as usize,
+                )
+            };
+            let mut payload = vec![0; len.min(RECEIVE_BUFFER_SIZE)];
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.add(SHM_HEADER_SIZE),
+                    payload.as_mut_ptr(),
+                    payload.len(),
+                );
+            }
+            fence(Ordering::Acquire);
+            if sequence.load(Ordering::Relaxed) == seq {
+                break (version, seq, payload);
+            }
+        };
+        if version != SHM_LAYOUT_VERSION {
+            eprintln!(
+                "warning: shared memory '{}' has unsupported layout version {}",
+                name, version
+            );
+            continue;
+        }
+        if seq == last_seq {
+            continue;
+        }
+        last_seq = seq;
+
+        handle_message(&name, &payload);
+    }
+}
+
+///
+/// Receive messages from the POSIX message queue `name` blocking until
+/// every next message arrives. The queue is created with the system defaults
+/// if the sender is not started yet. The sender removes the queue when
+/// it exits so the receiver is restarted together with the sender.
+///
+#[cfg(target_os = "linux")]
+fn receive_mq(name: &str) -> io::Result<()> {
+    use nix::mqueue::{mq_open, mq_receive, MQ_OFlag};
+    use nix::sys::stat::Mode;
+
+    let name = if name.starts_with('/') {
+        name.to_owned()
+    } else {
+        format!("{}{}", SHM_NAME_PREFIX, name)
+    };
+    let c_name = CString::new(name.as_str())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mqd = mq_open(
+        &c_name,
+        MQ_OFlag::O_CREAT | MQ_OFlag::O_RDONLY,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+        None,
+    )
+    .map_err(nix_error)?;
+
+    // The buffer must hold the largest message of the queue
+    let msg_size = unsafe {
+        let mut attr: libc::mq_attr = std::mem::zeroed();
+        if libc::mq_getattr(mqd, &mut attr) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        attr.mq_msgsize as usize
+    };
+
+    let mut buf = vec![0; msg_size];
+    let mut prio = 0;
+    loop {
+        match mq_receive(mqd, &mut buf, &mut prio) {
+            Ok(len) => handle_message(&name, &buf[..len]),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(err) => return Err(nix_error(err)),
+        }
+    }
+}
+
+///
+/// Message queues are not supported on the platform so the error is always returned.
+///
+#[cfg(not(target_os = "linux"))]
+fn receive_mq(_name: &str) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(target_os = "linux")]
+fn nix_error(err: nix::Error) -> io::Error {
+    match err {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        err => io::Error::new(io::ErrorKind::InvalidInput, err),
+    }
+}
+
+///
+/// Read messages from the `stream` until it is closed or the receiver shuts down.
+///
+fn receive_connection(mut stream: TcpStream, src: &str) -> io::Result<()> {
+    while let Some(payload) = read_frame(&mut stream)? {
+        handle_message(src, &payload);
+
+        // Acknowledge test messages so the sender knows they are received
+        let text = String::from_utf8_lossy(&payload);
+        let test = split_records(&text)
+            .iter()
+            .any(|record| record_event(record).as_deref() == Some(EVENT_TEST));
+        if test {
+            let mut frame = (TEST_ACK.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(TEST_ACK);
+            stream.write_all(&frame)?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Read the message framed with 4-byte big-endian length from the `stream`.
+/// The shutdown flag is checked while waiting for the message to start
+/// but once started the message is read to the end. `None` is returned
+/// when the stream is closed or the receiver shuts down.
+///
+fn read_frame(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    let mut read = 0;
+    stream.set_read_timeout(Some(Duration::from_millis(POLL_MILLIS)))?;
+    while read == 0 {
+        if SHUTDOWN.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        match stream.read(&mut len_buf) {
+            Ok(0) => return Ok(None),
+            Ok(n) => read = n,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    stream.set_read_timeout(None)?;
+    stream.read_exact(&mut len_buf[read..])?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > RECEIVE_BUFFER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too large", len),
+        ));
+    }
+
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+///
+/// Send the copy of the message `payload` to every tee target without waiting.
+/// Failures are reported and the message is not sent again.
+///
+fn tee_message(payload: &[u8]) {
+    if let Some(socket) = TEE_SOCKET.as_ref() {
+        for target in TEES.iter() {
+            if let Err(err) = socket.send_to(payload, target.as_str()) {
+                eprintln!("error: failed to tee to {}: {}", target, err);
+            }
+        }
+    }
+}
+
+///
+/// Send the `record` to the next receiver at `target` with the receiver added
+/// to its relay chain. The target is `host:port` of UDP receivers
+/// or `tcp://host:port` of TCP receivers. The connection is kept for next
+/// records and opened again once sending over it fails.
+///
+fn relay_record(target: &str, record: &str) -> io::Result<()> {
+    let record = envelope::append_relay(record, &RELAY_ID);
+    let mut link = RELAY_LINK
+        .lock()
+        .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
+    let mut relay = match link.take() {
+        Some(relay) => relay,
+        None => RelayLink::connect(target)?,
+    };
+    relay.send(record.as_bytes())?;
+    *link = Some(relay);
+
+    Ok(())
+}
+
+///
+/// The connection to the next receiver of the relay chain.
+///
+enum RelayLink {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl RelayLink {
+    ///
+    /// Connect to the receiver at `target` giving up after 2 seconds
+    /// so the unreachable receiver does not stall receiving for long.
+    ///
+    fn connect(target: &str) -> io::Result<RelayLink> {
+        let timeout = Duration::from_secs(RELAY_TIMEOUT_SECS);
+        match target.strip_prefix(RELAY_TCP_SCHEME) {
+            Some(addr) => {
+                let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+                for addr in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, timeout) {
+                        Ok(stream) => {
+                            stream.set_write_timeout(Some(timeout))?;
+                            return Ok(RelayLink::Tcp(stream));
+                        }
+                        Err(err) => last_err = err,
+                    }
+                }
+                Err(last_err)
+            }
+            None => {
+                let socket = UdpSocket::bind(RELAY_BIND_ADDR)?;
+                socket.connect(target)?;
+                Ok(RelayLink::Udp(socket))
+            }
+        }
+    }
+
+    ///
+    /// Send the `record` framed with 4-byte big-endian length over TCP
+    /// or as the datagram over UDP.
+    ///
+    fn send(&mut self, record: &[u8]) -> io::Result<()> {
+        match self {
+            RelayLink::Tcp(stream) => {
+                stream.write_all(&(record.len() as u32).to_be_bytes())?;
+                stream.write_all(record)
+            }
+            RelayLink::Udp(socket) => socket.send(record).map(|_| ()),
+        }
+    }
+}
+
+///
+/// Send the Zabbix `frame` to the Zabbix `server` trapper over TCP
+/// and return the body of the server response.
+///
+fn relay_zabbix(server: &str, frame: &[u8]) -> io::Result<String> {
+    let mut stream = TcpStream::connect(server)?;
+    stream.set_read_timeout(Some(Duration::from_secs(ZABBIX_TIMEOUT_SECS)))?;
+    stream.write_all(frame)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let body = response.get(ZABBIX_HEADER.len() + 8..).unwrap_or_default();
+    Ok(String::from_utf8_lossy(body).into_owned())
+}
+
+///
+/// Collect flags from the command line. Flags have form of `--name value`,
+/// `--name=value`, or `--name:value`. The flag without value, e.g. `--name`
+/// followed by another flag, has the empty value.
+///
+fn collect_flags() -> HashMap<String, String> {
+    parse_flags().into_iter().collect()
+}
+
+///
+/// Get values of the flag `name` which is given more than once in order.
+///
+fn flag_values(name: &str) -> Vec<String> {
+    parse_flags()
+        .into_iter()
+        .filter(|(flag, _)| flag == name)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+///
+/// Parse command line flags in order they are given.
+///
+fn parse_flags() -> Vec<(String, String)> {
+    let mut flags = Vec::new();
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with(FLAG_START) {
+            continue;
+        }
+
+        let flag = &arg[FLAG_START.len()..];
+        if let Some(pos) = flag.find(['=', ':']) {
+            flags.push((flag[..pos].to_owned(), flag[pos + 1..].to_owned()));
+        } else if args.peek().is_some_and(|v| !v.starts_with(FLAG_START)) {
+            flags.push((flag.to_owned(), args.next().unwrap_or_default()));
+        } else {
+            flags.push((flag.to_owned(), String::new()));
+        }
+    }
+
+    flags
+}
+
+///
+/// Watch the directory of the file `pattern` with inotify and print the content
+/// of every matching state file when it is replaced or written.
+///
+fn watch_state_files(pattern: &str) -> nix::Result<()> {
+    let path = Path::new(pattern);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    let name_pattern = path
+        .file_name()
+        .map(|v| v.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let inotify = Inotify::init(InitFlags::empty())?;
+    inotify.add_watch(
+        &dir,
+        AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+    )?;
+    loop {
+        for event in inotify.read_events()? {
+            if let Some(name) = event.name {
+                let name = name.to_string_lossy();
+                if wildcard_match(&name_pattern, &name) {
+                    let file = dir.join(name.as_ref());
+                    if let Ok(contents) = fs::read_to_string(&file) {
+                        print_line(&format!("{} -> {}", file.display(), contents.trim_end()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+///
+/// Test the `text` matches the `pattern` where `*` matches any sequence
+/// of characters and `?` matches any single character.
+///
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -20,9 +20,14 @@
 ///
 /// The usage is `owl [OPTS] command [ARGS]` where `[OPTS]` are tool options
 /// and `[ARGS]` are command arguments passed without any modification.
+/// Options are given as `+Name:Value` or `--name value`, see the `cli` module.
 ///
 /// E.g. `owl +Host:127.0.0.1 +Port:9090 rsync -avz /home/user root@192.168.56.102:/home`.
 ///
+/// The receiver is checked with `owl [OPTS] --test` which sends one test
+/// message and prints `OK` or `FAILED: <reason>`. `owl --json-schema` prints
+/// the JSON Schema of messages of the `json` format.
+///
 /// Shell scripts can be wrapped as well with modification of shebang, e.g.
 ///
 /// ```shell
@@ -30,17 +35,125 @@
 /// ...commands...
 /// ```
 ///
+/// Where the shebang takes one argument only options and the interpreter
+/// can be given in the comment which follows the shebang, e.g.
+///
+/// ```shell
+/// #!/usr/bin/env owl
+/// # owl: +Name:Awesome_Job +Host:127.0.0.1 bash
+/// ...commands...
+/// ```
+///
 /// The tool accepts options which have form of `+Name:value` where `Name` is the name
 /// of the option, case is sensitive, and `value` is the value.
 ///
 /// Supported options:
 ///
 /// - `Conf` is the location of the configuration file, e.g. `+Conf:/usr/local/owl.conf`.
+/// - `ProjectConf` is the project configuration file with the `[owl]` or `[tool.owl]` section, e.g. `+ProjectConf:Cargo.toml`.
 /// - `Host` is the host address to delivert state to, e.g. `+Host:192.168.0.90`.
-/// - `Port` is the port to deliver state to, e.g. `+Port:20304`.
-/// - `Heartbeat` is the delay between deliveries in milliseconds, e.g. `+Heartbeat:10000`.
+/// - `Port` is the port or the service name to deliver state to, e.g. `+Port:20304` or `+Port:syslog`.
+/// - `Heartbeat` is the delay between deliveries, e.g. `+Heartbeat:10000` or `+Heartbeat:10s`.
+/// - `HeartbeatCount` is the number of heartbeats delivered before the delivery stops, e.g. `+HeartbeatCount:10`.
+/// - `PreExec` is the shell command run before the command, e.g. `+PreExec:/usr/local/bin/setup.sh`.
+/// - `PostExec` is the shell command run after the command exits, e.g. `+PostExec:/usr/local/bin/cleanup.sh`.
+/// - `LogFile` is the file the command output is appended to, e.g. `+LogFile:/var/log/job.log`.
+/// - `OutputMode` is what happens to the command output, `pipe`, `silent`, `capture`, or `capture+forward`, e.g. `+OutputMode:capture`.
+/// - `CaptureMaxBytes` is the number of last bytes of the output `capture` keeps, e.g. `+CaptureMaxBytes:4K`.
+/// - `OnSighup` is what SIGHUP does, `forward`, `reload`, `restart`, or `ignore`, e.g. `+OnSighup:reload`.
+/// - `LogRotateSignal` is the signal which reopens the log file, e.g. `+LogRotateSignal:SIGHUP`.
+/// - `LogRotateSize` is the size the log file is rotated at, e.g. `+LogRotateSize:10MB`.
+/// - `Namespace` is the type of the namespace stats are read in, e.g. `+Namespace:pid`.
+/// - `NsPid` is the path to the namespace, e.g. `+NsPid:/proc/1234/ns/pid`.
+/// - `Transport` is the way the state is delivered with, `udp`, `tcp`, `file`, `shm`, `posix_mq`, or `pushgateway`, e.g. `+Transport:file`.
+/// - `MqName` is the POSIX message queue of the `posix_mq` transport, e.g. `+MqName:/owl-rsync`.
+/// - `MqMaxMsgs` is the number of messages the queue holds, e.g. `+MqMaxMsgs:10`.
+/// - `MqMsgSize` is the largest message the queue holds, e.g. `+MqMsgSize:4096`.
+/// - `PushgatewayUrl` is the group of the push gateway metrics are pushed to, e.g. `+PushgatewayUrl:http://localhost:9091/metrics/job/rsync`.
+/// - `PushgatewayInstance` is the instance label of the group, e.g. `+PushgatewayInstance:host1`.
+/// - `PushgatewayAutoDelete` deletes the group when the command exits, e.g. `+PushgatewayAutoDelete:true`.
+/// - `StateFile` is the file the state is written to, e.g. `+StateFile:/var/run/owl-rsync.json`.
+/// - `StateFileMode` is the permissions of the state file, e.g. `+StateFileMode:0640`.
+/// - `Receive` makes the tool print the state it delivers itself, e.g. `+Receive:true`.
+/// - `Label` is the list of labels attached to messages, e.g. `+Label:env=prod,team=infra`.
+/// - `K8sLabels` attaches the Kubernetes downward API labels to messages, e.g. `+K8sLabels:true`.
+/// - `PauseSignal` is the signal which pauses the command with SIGSTOP, e.g. `+PauseSignal:SIGUSR1`.
+/// - `ResumeSignal` is the signal which resumes the command with SIGCONT, e.g. `+ResumeSignal:SIGUSR2`.
+/// - `Format` is the format of UDP messages, `ssdpd`, `json`, or `zabbix`, e.g. `+Format:json`.
+/// - `ZabbixHost` is the Zabbix host name of the `zabbix` format, e.g. `+ZabbixHost:myserver`.
+/// - `ZabbixKey` is the Zabbix item key of the `zabbix` format, e.g. `+ZabbixKey:owl.state`.
+/// - `HeartbeatOnChange` skips heartbeats when stats do not change, e.g. `+HeartbeatOnChange:true`.
+/// - `ChangeThreshold` is the significant change of metrics in percents, e.g. `+ChangeThreshold:5%`.
+/// - `MaxSilence` is the longest time without heartbeats, e.g. `+MaxSilence:60s`.
+/// - `StatsInterval` is the delay between stats samples taken between heartbeats, e.g. `+StatsInterval:100ms`.
+/// - `DeliverAggregated` adds the smallest, the largest, and the average of samples to heartbeats, e.g. `+DeliverAggregated:true`.
+/// - `DotEnv` is the path to the .env file with `OWL_<NAME>=<VALUE>` options, e.g. `+DotEnv:deploy/.env`.
+/// - `Pids` is the comma separated list of existing processes to monitor instead of the command, e.g. `+Pids:1234,5678`.
+/// - `MaxRssBytes` is the RSS limit the command is terminated on, e.g. `+MaxRssBytes:500M`.
+/// - `CpuBudget` is the CPU time the command is terminated after, e.g. `+CpuBudget:3600s`.
+/// - `CpuBudgetPerRestart` starts the CPU budget over when the command restarts, e.g. `+CpuBudgetPerRestart:true`.
+/// - `MaxOutputLines` is the number of output lines the command is terminated after, e.g. `+MaxOutputLines:10000`.
+/// - `MaxOutputLinesWindow` is the rolling time window `MaxOutputLines` applies within, e.g. `+MaxOutputLinesWindow:60s`.
+/// - `ShutdownGrace` is the time after SIGTERM the command is killed with SIGKILL, e.g. `+ShutdownGrace:10s`.
+/// - `ChildStdinCmd` is the shell command which output the command reads STDIN from, e.g. `+ChildStdinCmd:get_token`.
+/// - `ChildStdinCmdTimeout` is the time the STDIN command is killed after, e.g. `+ChildStdinCmdTimeout:5s`.
+/// - `ChildStdinFile` is the file the command reads STDIN from, `-` is the tool STDIN, e.g. `+ChildStdinFile:/etc/secrets.txt`.
+/// - `FsyncState` flushes the state file to the storage on every write, e.g. `+FsyncState:true`.
+/// - `Environment` is the environment the command inherits, `inherit`, `clean`, or `minimal`, e.g. `+Environment:clean`.
+/// - `Env` is the comma separated list of `KEY=VALUE` variables set for the command, e.g. `+Env:LANG=C,TZ=UTC`.
+/// - `EnvSet` is the name of the `[watch.envsets.<NAME>]` set of variables set for the command, e.g. `+EnvSet:production`.
+/// - `SecretEnv` is the comma separated list of variables set for the command with values resolved from `file://`, `env://`, or `vault://` secrets, e.g. `+SecretEnv:DB_PASSWORD=vault://secret/data/myapp#password`.
+/// - `CgroupPath` is the cgroup which main process is monitored instead of the command, e.g. `+CgroupPath:/sys/fs/cgroup/system.slice/myapp.service`.
+/// - `CgroupMemoryLimit` is the memory limit of the cgroup the command is run in, e.g. `+CgroupMemoryLimit:512M`.
+/// - `CgroupBase` is the cgroup the command cgroup is created under, e.g. `+CgroupBase:/sys/fs/cgroup/user.slice`.
+/// - `CpuSet` is the comma separated list of CPUs or CPU ranges the command is pinned to, e.g. `+CpuSet:0,2-3`.
+/// - `CpuSetInherit` pins the tool to CPUs of `CpuSet` too, e.g. `+CpuSetInherit:true`.
+/// - `CgroupMainPid` is how the main process of the cgroup is picked, `first`, `last`, or `max_rss`, e.g. `+CgroupMainPid:max_rss`.
+/// - `MonitorByName` is the `comm` name of processes monitored instead of the command, e.g. `+MonitorByName:nginx`.
+/// - `MonitorByNamePick` is how the process of `MonitorByName` is picked, `lowest`, `youngest`, `oldest`, or `all`, e.g. `+MonitorByNamePick:all`.
+/// - `LockFile` is the file locked so only one instance runs the job, e.g. `+LockFile:/var/lock/owl-my_job.lock`.
+/// - `LockTimeout` is the time to wait for the lock held by another instance, e.g. `+LockTimeout:5s`.
+/// - `DumpFile` is the file stats are dumped to on `DumpSignal`, e.g. `+DumpFile:/tmp/owl.json`.
+/// - `DumpSignal` is the signal which dumps stats, SIGUSR1 if only `DumpFile` is given, e.g. `+DumpSignal:SIGUSR2`.
+/// - `ConnectRetry` is the number of times the TCP connection is retried on startup, e.g. `+ConnectRetry:10`.
+/// - `ConnectRetryDelay` is the delay between TCP connection attempts, e.g. `+ConnectRetryDelay:3s`.
+/// - `Envelope` attaches the delivery metadata to messages, e.g. `+Envelope:true`.
+/// - `TermPropagation` is how SIGTERM is forwarded, `immediate`, `none`, or `delay:<duration>`, e.g. `+TermPropagation:delay:5s`.
+/// - `SendBuffer` is the number of heartbeats the `tcp` transport buffers, e.g. `+SendBuffer:100`.
+/// - `StrictBinaryCheck` refuses to run the SUID or SGID command instead of warning, e.g. `+StrictBinaryCheck:true`.
+/// - `SkipBinaryCheck` runs the SUID or SGID command without the warning, e.g. `+SkipBinaryCheck:true`.
+/// - `CmdPrefix` is the comma separated command the command is run with, e.g. `+CmdPrefix:strace,-o,/tmp/trace.log`.
+/// - `CountOutputLines` adds the number of lines the command writes to messages, e.g. `+CountOutputLines:true`.
+/// - `MemMaps` adds the summary of memory mappings to messages, e.g. `+MemMaps:true`.
+/// - `WatchFifos` adds named pipes the process has open to messages, e.g. `+WatchFifos:true`.
+/// - `FifoPattern` is the pattern of named pipes watched, e.g. `+FifoPattern:/tmp/*.fifo`.
+/// - `ExecDelayStart` is the time heartbeats are sent before the command starts, e.g. `+ExecDelayStart:5s`.
+/// - `InheritSigMask` keeps signals blocked in the tool blocked in the command, e.g. `+InheritSigMask:true`.
+/// - `PrintAccounting` prints resources the command used to STDERR on exit, e.g. `+PrintAccounting:true`.
+/// - `CorePattern` is the file with the kernel core pattern dumps are looked for with, e.g. `+CorePattern:/proc/sys/kernel/core_pattern`.
+/// - `CoreDir` is the directory dumps are looked for in instead of the core pattern, e.g. `+CoreDir:/var/cores`.
+/// - `SparklineSize` is the number of latest CPU and RSS samples added to messages, e.g. `+SparklineSize:20`.
+/// - `AlertCpuEfficiency` is the threshold of the CPU efficiency alert, e.g. `+AlertCpuEfficiency:below:5`.
+/// - `SignalGroup` runs the command in its own process group signals are forwarded to, e.g. `+SignalGroup:true`.
+/// - `AlertOrphan` raises the alert when the command leaves its process group, e.g. `+AlertOrphan:true`.
+/// - `AlertLatency` is the send latency which raises the alert, e.g. `+AlertLatency:100ms`.
+/// - `SelfMetrics` adds the resource usage of the tool to messages, e.g. `+SelfMetrics:true`.
+/// - `DeduplicateSends` suppresses heartbeats identical to the last one sent, e.g. `+DeduplicateSends:true`.
+/// - `KeepAliveSends` is the number of heartbeats the identical one is sent every anyway, e.g. `+KeepAliveSends:10`.
+/// - `Banner` prints the summary of what the tool does to STDERR on start, e.g. `+Banner:true`.
+/// - `BannerColor` highlights the destination in the banner, e.g. `+BannerColor:true`.
+/// - `ActiveHours` is the comma separated list of local time windows the state is delivered within, e.g. `+ActiveHours:09:00-12:00,13:00-17:00`.
+/// - `AllowNested` delivers the state even when the tool runs under another instance of it, e.g. `+AllowNested:true`.
+/// - `Tee` is the comma separated list of addresses every message is copied to as SSDPD over UDP, e.g. `+Tee:audit.local:9191`.
+/// - `AlertCwdChange` raises the alert when the process changes the working directory, e.g. `+AlertCwdChange:true`.
+/// - `AlertFdSockets` is the number of open sockets which raises the alert, e.g. `+AlertFdSockets:50`.
+/// - `AlertRssHwm` is the peak RSS which raises the alert, e.g. `+AlertRssHwm:1G`.
+/// - `AlertRssGrowthRate` is the RSS growth rate which raises the alert, e.g. `+AlertRssGrowthRate:1M/s`.
+/// - `RssGrowthEma` is the smoothing factor of the RSS growth rate, e.g. `+RssGrowthEma:0.1`.
 ///
+extern crate libc;
 extern crate nix;
+#[cfg(target_os = "linux")]
 extern crate procinfo;
 extern crate signal_hook;
 extern crate toml;
@@ -48,365 +161,4336 @@ extern crate toml;
 #[macro_use]
 extern crate lazy_static;
 
-use nix::sys::signal::{self, Signal};
-use nix::unistd::Pid;
-use procinfo::pid::{stat, Stat};
-use std::collections::HashMap;
+mod alert;
+mod cgroup;
+mod cli;
+mod coredump;
+mod error;
+mod limiter;
+mod message;
+mod monitor;
+mod mqueue;
+mod platform;
+mod pushgateway;
+mod queue;
+mod sampler;
+mod schedule;
+mod schema;
+mod secrets;
+mod shm;
+mod tee;
+mod versions;
+
+use alert::{Alert, Threshold};
+use cgroup::Cgroup;
+use cli::CommandLine;
+use coredump::Crash;
+use error::OwlError;
+use limiter::LineLimit;
+use message::{json_string, stats_json, Envelope, StateMessage, Value};
+use monitor::{stats_changed, Monitor};
+use mqueue::MessageQueue;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{self, SigSet, SigmaskHow, Signal};
+use nix::unistd::{access, getgid, gethostname, getpgid, getppid, getuid, AccessFlags, Pid};
+use platform::{ProcessStats, ResourceUsage};
+use pushgateway::Pushgateway;
+use queue::SendQueue;
+use sampler::Samples;
+use schedule::ActiveHours;
+use shm::SharedMemory;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{CString, OsStr, OsString};
 use std::fs;
-use std::io::Read;
-use std::net::{SocketAddr, UdpSocket};
-use std::process::{self, Command};
-use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time;
+use tee::Tee;
 
 // Defaults and constants
 const EMPTY_STR: String = String::new();
 const OPTION_START: char = '+';
 const OPTION_DELIMITER: char = ':';
 const SECTION_WATCH: &str = "watch";
+const SECTION_OWL: &str = "owl";
+const SECTION_ENV_SETS: &str = "envsets";
+const SECTION_TOOL: &str = "tool";
 const OPT_CONF: &str = "Conf";
+const OPT_DOT_ENV: &str = "DotEnv";
 const OPT_HOST: &str = "Host";
 const OPT_PORT: &str = "Port";
 const OPT_NAME: &str = "Name";
 const OPT_HEARTBEAT: &str = "Heartbeat";
+const OPT_HEARTBEAT_ON_CHANGE: &str = "HeartbeatOnChange";
+const OPT_CHANGE_THRESHOLD: &str = "ChangeThreshold";
+const OPT_MAX_SILENCE: &str = "MaxSilence";
+const OPT_STATS_INTERVAL: &str = "StatsInterval";
+const OPT_DELIVER_AGGREGATED: &str = "DeliverAggregated";
+const OPT_PRE_EXEC: &str = "PreExec";
+const OPT_POST_EXEC: &str = "PostExec";
+const OPT_LOG_FILE: &str = "LogFile";
+const OPT_OUTPUT_MODE: &str = "OutputMode";
+const OPT_CAPTURE_MAX_BYTES: &str = "CaptureMaxBytes";
+const OUTPUT_MODE_PIPE: &str = "pipe";
+const OUTPUT_MODE_SILENT: &str = "silent";
+const OUTPUT_MODE_CAPTURE: &str = "capture";
+const OUTPUT_MODE_CAPTURE_FORWARD: &str = "capture+forward";
+const OPT_LOG_ROTATE_SIGNAL: &str = "LogRotateSignal";
+const OPT_LOG_ROTATE_SIZE: &str = "LogRotateSize";
+const OPT_NAMESPACE: &str = "Namespace";
+const OPT_NS_PID: &str = "NsPid";
+const OPT_TRANSPORT: &str = "Transport";
+const OPT_STATE_FILE: &str = "StateFile";
+const OPT_STATE_FILE_MODE: &str = "StateFileMode";
+const OPT_FSYNC_STATE: &str = "FsyncState";
+const OPT_RECEIVE: &str = "Receive";
+const OPT_LABEL: &str = "Label";
+const OPT_K8S_LABELS: &str = "K8sLabels";
+const OPT_PAUSE_SIGNAL: &str = "PauseSignal";
+const OPT_RESUME_SIGNAL: &str = "ResumeSignal";
+const OPT_FORMAT: &str = "Format";
+const OPT_ZABBIX_HOST: &str = "ZabbixHost";
+const OPT_ZABBIX_KEY: &str = "ZabbixKey";
+const OPT_PIDS: &str = "Pids";
+const OPT_CGROUP_PATH: &str = "CgroupPath";
+const OPT_CGROUP_MAIN_PID: &str = "CgroupMainPid";
+const OPT_CGROUP_MEMORY_LIMIT: &str = "CgroupMemoryLimit";
+const OPT_CGROUP_BASE: &str = "CgroupBase";
+const OPT_CPU_SET: &str = "CpuSet";
+const OPT_CPU_SET_INHERIT: &str = "CpuSetInherit";
+const DEFAULT_CGROUP_BASE: &str = "/sys/fs/cgroup";
+const OPT_MONITOR_BY_NAME: &str = "MonitorByName";
+const OPT_MONITOR_BY_NAME_PICK: &str = "MonitorByNamePick";
+const OPT_MAX_RSS_BYTES: &str = "MaxRssBytes";
+const OPT_SHUTDOWN_GRACE: &str = "ShutdownGrace";
+const OPT_CHILD_STDIN_FILE: &str = "ChildStdinFile";
+const OPT_CHILD_STDIN_CMD: &str = "ChildStdinCmd";
+const OPT_CHILD_STDIN_CMD_TIMEOUT: &str = "ChildStdinCmdTimeout";
+const OPT_ENVIRONMENT: &str = "Environment";
+const OPT_ENV: &str = "Env";
+const OPT_ENV_SET: &str = "EnvSet";
+const OPT_SECRET_ENV: &str = "SecretEnv";
+const OPT_TEE: &str = "Tee";
+const OPT_ALLOW_NESTED: &str = "AllowNested";
+const OPT_ACTIVE_HOURS: &str = "ActiveHours";
+const OPT_DEDUPLICATE_SENDS: &str = "DeduplicateSends";
+const OPT_KEEP_ALIVE_SENDS: &str = "KeepAliveSends";
+const OPT_BANNER: &str = "Banner";
+const OPT_ALERT_RSS_GROWTH_RATE: &str = "AlertRssGrowthRate";
+const OPT_RSS_GROWTH_EMA: &str = "RssGrowthEma";
+const OPT_BANNER_COLOR: &str = "BannerColor";
+const OPT_LOCK_FILE: &str = "LockFile";
+const OPT_LOCK_TIMEOUT: &str = "LockTimeout";
+const OPT_DUMP_FILE: &str = "DumpFile";
+const OPT_DUMP_SIGNAL: &str = "DumpSignal";
+const OPT_CONNECT_RETRY: &str = "ConnectRetry";
+const OPT_ENVELOPE: &str = "Envelope";
+const OPT_TERM_PROPAGATION: &str = "TermPropagation";
+const OPT_SEND_BUFFER: &str = "SendBuffer";
+const OPT_MEM_MAPS: &str = "MemMaps";
+const OPT_ALERT_LATENCY: &str = "AlertLatency";
+const OPT_SIGNAL_GROUP: &str = "SignalGroup";
+const OPT_CONNECT_RETRY_DELAY: &str = "ConnectRetryDelay";
+const OPT_EXEC_DELAY_START: &str = "ExecDelayStart";
+const OPT_INHERIT_SIG_MASK: &str = "InheritSigMask";
+const OPT_PRINT_ACCOUNTING: &str = "PrintAccounting";
+const OPT_CORE_PATTERN: &str = "CorePattern";
+const OPT_CORE_DIR: &str = "CoreDir";
+const OPT_WATCH_FIFOS: &str = "WatchFifos";
+const OPT_FIFO_PATTERN: &str = "FifoPattern";
+const OPT_COUNT_OUTPUT_LINES: &str = "CountOutputLines";
+const OPT_SELF_METRICS: &str = "SelfMetrics";
+const OPT_CMD_PREFIX: &str = "CmdPrefix";
+const OPT_HEARTBEAT_COUNT: &str = "HeartbeatCount";
+const OPT_PROJECT_CONF: &str = "ProjectConf";
+const OPT_ON_SIGHUP: &str = "OnSighup";
+const OPT_CPU_BUDGET: &str = "CpuBudget";
+const OPT_CPU_BUDGET_PER_RESTART: &str = "CpuBudgetPerRestart";
+const OPT_MAX_OUTPUT_LINES: &str = "MaxOutputLines";
+const OPT_MAX_OUTPUT_LINES_WINDOW: &str = "MaxOutputLinesWindow";
+const OPT_STRICT_BINARY_CHECK: &str = "StrictBinaryCheck";
+const OPT_SKIP_BINARY_CHECK: &str = "SkipBinaryCheck";
+const OPT_PUSHGATEWAY_URL: &str = "PushgatewayUrl";
+const OPT_PUSHGATEWAY_INSTANCE: &str = "PushgatewayInstance";
+const OPT_PUSHGATEWAY_AUTO_DELETE: &str = "PushgatewayAutoDelete";
+const OPT_MQ_NAME: &str = "MqName";
+const OPT_MQ_MAX_MSGS: &str = "MqMaxMsgs";
+const OPT_MQ_MSG_SIZE: &str = "MqMsgSize";
 const DEFAULT_REMOTE_HOST: &str = "0.0.0.0";
 const DEFAULT_REMOTE_PORT: &str = "39576";
-const DEFAULT_HEARTBEAT: &str = "1000";
 const DEFAULT_HEARTBEAT_MILLIS: u64 = 1000;
+const DEFAULT_CHANGE_THRESHOLD_PCT: f32 = 5.0;
+const DEFAULT_MAX_SILENCE_SECS: u64 = 60;
+const DEFAULT_STATE_FILE: &str = "/var/run/owl.json";
+const DEFAULT_STATE_FILE_MODE: u32 = 0o644;
+const DEFAULT_CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+const CORE_USES_PID: &str = "/proc/sys/kernel/core_uses_pid";
+const COMM_LEN: usize = 15;
+const DEFAULT_FIFO_PATTERN: &str = "/var/run/*.pipe";
+const MODE_SUID: u32 = 0o4000;
+const MODE_SGID: u32 = 0o2000;
+const ROOT_UID: u32 = 0;
+const DEFAULT_CONNECT_RETRY_DELAY_MILLIS: u64 = 1000;
+const DEFAULT_SEND_BUFFER: usize = 100;
+const DEFAULT_CAPTURE_MAX_BYTES: u64 = 65536;
+const DEFAULT_DUMP_FILE_DIR: &str = "/tmp";
+const DUMP_FILE_MODE: u32 = 0o600;
+const DEFAULT_DUMP_SIGNAL: i32 = signal_hook::SIGUSR1;
+const BANNER_HIGHLIGHT: &str = "\x1b[1;32m";
+const BANNER_RESET: &str = "\x1b[0m";
+const MASKED_VALUE: &str = "***";
+const DEFAULT_KEEP_ALIVE_SENDS: u64 = 10;
+const DEDUPLICATE_IGNORED_FIELDS: [&str; 3] =
+    ["send_latency_us", "avg_send_latency_us", "last_rtt_ms"];
+const SECRET_OPTION_MARKERS: [&str; 4] = ["Auth", "Password", "Secret", "Token"];
+const REPEATED_OPTIONS: [&str; 1] = [OPT_TEE];
+const STATE_FILE_TMP_SUFFIX: &str = ".tmp";
+const TRANSPORT_UDP: &str = "udp";
+const TRANSPORT_FILE: &str = "file";
+const TRANSPORT_TCP: &str = "tcp";
+const TRANSPORT_SHM: &str = "shm";
+const TRANSPORT_PUSHGATEWAY: &str = "pushgateway";
+const TRANSPORT_POSIX_MQ: &str = "posix_mq";
+const SHM_NAME_PREFIX: &str = "/owl-";
+const DEFAULT_MQ_MAX_MSGS: i64 = 10;
+const DEFAULT_MQ_MSG_SIZE: i64 = 4096;
+const SERVICE_PROTO: &str = "udp";
+const FORMAT_JSON: &str = "json";
+const FORMAT_ZABBIX: &str = "zabbix";
+const DEFAULT_ZABBIX_KEY: &str = "owl.state";
+const LOOPBACK_HOSTS: [&str; 2] = ["127.0.0.1", "::1"];
+const RECEIVE_TIMEOUT_MILLIS: u64 = 100;
+const RECEIVE_BUFFER_SIZE: usize = 65536;
+const OUTPUT_CHUNK_SIZE: usize = 8192;
+const OUTPUT_LINE_MAX_BYTES: usize = 65536;
+const K8S_PODINFO_LABELS: &str = "/etc/podinfo/labels";
+const K8S_ENV_LABELS: [(&str, &str); 4] = [
+    ("MY_POD_NAME", "pod_name"),
+    ("MY_POD_NAMESPACE", "pod_namespace"),
+    ("MY_NODE_NAME", "node_name"),
+    ("MY_POD_IP", "pod_ip"),
+];
+const PID_POLL_MILLIS: u64 = 10;
+const LOCK_POLL_MILLIS: u64 = 100;
+const SEND_RETRIES: u32 = 3;
+const SEND_RETRY_BACKOFF_MILLIS: u64 = 100;
+const SEND_LATENCY_SAMPLES: usize = 10;
 const CONF_LOCATION_CWD: &str = "owl.toml";
 const CONF_LOCATION_ETC: &str = "/etc/owl.toml";
 const CONF_LOCATION_ETC_OWL: &str = "/etc/owl/owl.toml";
+const DOT_ENV_LOCATION_CWD: &str = ".env";
+const DOT_ENV_PREFIX: &str = "OWL_";
+const DOT_ENV_EXPORT: &str = "export ";
+const SHEBANG: &str = "#!";
+const SCRIPT_OPTIONS_PREFIX: &str = "# owl:";
+const TOOL_NAME: &str = "owl";
+const PATH_VAR: &str = "PATH";
+const HOOK_SHELL: &str = "/bin/sh";
+const STDIN_PATH: &str = "-";
+const ENVIRONMENT_INHERIT: &str = "inherit";
+const ENVIRONMENT_CLEAN: &str = "clean";
+const ENVIRONMENT_MINIMAL: &str = "minimal";
+const MINIMAL_ENV_VARS: [&str; 8] = [
+    "PATH", "HOME", "USER", "LOGNAME", "TERM", "LANG", "LC_ALL", "TZ",
+];
+const LOG_ROTATED_SUFFIX: &str = ".1";
+const NAMESPACE_PID: &str = "pid";
+const STATE_ZOMBIE: &str = "Zombie";
+const STATE_PENDING: &str = "Pending";
+const STATE_EXITED: &str = "Exited";
+const STATE_VANISHED: &str = "Vanished";
+const EVENT_ACCOUNTING: &str = "accounting";
+const EVENT_TEST: &str = "test";
+const EVENT_PID_CHANGED: &str = "pid_changed";
+const EVENT_PID_VANISHED: &str = "pid_vanished";
+const STATE_TEST: &str = "Test";
+const TEST_ACK: &[u8] = b"OK";
+const TEST_TIMEOUT_MILLIS: u64 = 2000;
+const STATUS_PENDING: &str = "pending";
+const STATUS_RUNNING: &str = "running";
+const EXEC_DELAY_POLL_MILLIS: u64 = 100;
+const STDIN_CMD_POLL_MILLIS: u64 = 10;
+const STOP_SIGNALS: [i32; 3] = [
+    signal_hook::SIGINT,
+    signal_hook::SIGTERM,
+    signal_hook::SIGQUIT,
+];
+const TERM_PROPAGATION_IMMEDIATE: &str = "immediate";
+const TERM_PROPAGATION_NONE: &str = "none";
+const TERM_PROPAGATION_DELAY: &str = "delay:";
+const SIGHUP_FORWARD: &str = "forward";
+const SIGHUP_RELOAD: &str = "reload";
+const SIGHUP_RESTART: &str = "restart";
+const SIGHUP_IGNORE: &str = "ignore";
+const CGROUP_MAIN_PID_FIRST: &str = "first";
+const CGROUP_MAIN_PID_LAST: &str = "last";
+const CGROUP_MAIN_PID_MAX_RSS: &str = "max_rss";
+const NAME_PICK_LOWEST: &str = "lowest";
+const NAME_PICK_YOUNGEST: &str = "youngest";
+const NAME_PICK_OLDEST: &str = "oldest";
+const NAME_PICK_ALL: &str = "all";
 const UNIX_SIGNAL_EXIT_CODE: i32 = 128;
+const COMMAND_NOT_FOUND_EXIT_CODE: i32 = 127;
+const COMMAND_NOT_EXECUTABLE_EXIT_CODE: i32 = 126;
+const RSS_LIMIT_EXIT_CODE: i32 = 137;
+const LOCKED_EXIT_CODE: i32 = 75;
+const INVALID_OPTS_EXIT_CODE: i32 = 2;
 const SUCCESS: i32 = 0;
+#[cfg(target_os = "linux")]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(target_os = "linux"))]
+const SEND_FLAGS: libc::c_int = 0;
+const FAILURE: i32 = 1;
 
 lazy_static! {
     // The id of the process which run the command.
     static ref CHILD_PID: AtomicU32 = AtomicU32::new(0);
 
+    // The flag is raised when the tool runs under another instance of it
+    // which reports the same processes so the state is not delivered twice.
+    static ref NESTED: bool = !opt_enabled(OPT_ALLOW_NESTED) && parent_is_owl();
+
+    // The flag is raised when the tool is about to exit.
+    static ref SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+    // The flag is set while the command start is delayed with `ExecDelayStart`.
+    static ref EXEC_PENDING: AtomicBool = AtomicBool::new(OPT.contains_key(OPT_EXEC_DELAY_START));
+
+    // The flag is raised while the command is paused with the pause signal.
+    static ref PAUSED: AtomicBool = AtomicBool::new(false);
+
+    // The flag is raised when options should be read again on SIGHUP.
+    static ref RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    // The flag is raised when the command should be started again once it exits on SIGHUP.
+    static ref RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    // The ports of service names looked up in the services database.
+    static ref SERVICE_PORTS: Mutex<HashMap<String, Option<u16>>> = Mutex::new(HashMap::new());
+
+    // The flag is raised when the process is killed for exceeding the RSS limit.
+    static ref RSS_LIMIT_KILLED: AtomicBool = AtomicBool::new(false);
+
+    // The limit of lines the command outputs and the flag raised when it's exceeded.
+    static ref OUTPUT_LIMIT: Option<Mutex<LineLimit>> = OPT
+        .get(OPT_MAX_OUTPUT_LINES)
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|max| {
+            let window = OPT
+                .get(OPT_MAX_OUTPUT_LINES_WINDOW)
+                .and_then(|v| parse_duration(v))
+                .filter(|v| !v.is_zero());
+            Mutex::new(LineLimit::new(max, window))
+        });
+    static ref OUTPUT_LIMIT_EXCEEDED: AtomicBool = AtomicBool::new(false);
+
+    // The number of heartbeats failed to be delivered since the last successful one.
+    static ref SEND_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+    // The durations of the latest sends in microseconds, the last one is the latest.
+    static ref SEND_LATENCIES: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+
+    // The round-trip time of the TCP connection in microseconds, 0 when unknown.
+    static ref TCP_RTT_US: AtomicU64 = AtomicU64::new(0);
+
+    // The number of heartbeats dropped from the full send buffer since the last
+    // successful delivery.
+    static ref SEND_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+    // The number of lines the command wrote to STDOUT and STDERR.
+    static ref STDOUT_LINES: AtomicU64 = AtomicU64::new(0);
+    static ref STDERR_LINES: AtomicU64 = AtomicU64::new(0);
+
     // The last signal caught.
     static ref LAST_SIGNAL: AtomicI32 = AtomicI32::new(0);
 
     // The collection of tool options.
     static ref OPT: HashMap<String, String> = collect_opts();
 
+    // The command line split into tool options and the command.
+    static ref COMMAND_LINE: CommandLine = parse_command_line();
+
+    // The options and the interpreter from the header of the script run.
+    static ref SCRIPT_HEADER: Option<ScriptHeader> = read_script_header();
+
     // The collection of command line arguments of the command.
     static ref ARGS: Vec<OsString> = collect_command_args();
-}
 
-fn main() {
-    // Do nothing if no command line arguments are passed.
-    if env::args().len() < 2 {
-        return;
-    }
+    // The ids of existing processes monitored instead of the command.
+    static ref PIDS: Vec<u32> = collect_pids();
 
-    // Start up facilities
-    thread::spawn(listen_signals);
-    thread::spawn(deliver_state);
+    // The labels attached to every message.
+    static ref LABELS: Vec<(String, String)> = collect_labels();
+
+    // The log file the command output is written to.
+    static ref LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+    // The last bytes of the command output captured and the number of bytes kept.
+    static ref CAPTURED: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+    static ref CAPTURE_MAX_BYTES: usize = OPT
+        .get(OPT_CAPTURE_MAX_BYTES)
+        .and_then(|v| parse_size(v))
+        .unwrap_or(DEFAULT_CAPTURE_MAX_BYTES) as usize;
 
-    // Spawn the child process with command line arguments passed.
-    std::process::exit(execute_command());
+    // The lock file held while the tool runs.
+    static ref LOCK_FILE: Mutex<Option<fs::File>> = Mutex::new(None);
+
+    // The tee every message is copied to.
+    static ref TEE: Option<Tee> = OPT.get(OPT_TEE).and_then(|targets| match Tee::new(targets) {
+        Ok(tee) => Some(tee),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            None
+        }
+    });
 }
 
 ///
-/// Start the command executing.
-/// By default STDIN, STDOUT, and STDERR becomes standats inputs
-/// and outputs for the command process.
+/// The signal caught which is forwarded to processes monitored.
 ///
-fn execute_command() -> i32 {
-    if let Some(name) = command_name() {
-        let mut child = Command::new(name)
-            .args(command_args())
-            .spawn()
-            .expect("failed to execute command");
-        CHILD_PID.store(child.id(), Ordering::Relaxed);
-        child
-            .wait()
-            .expect("failed to retrieve command exit status")
-            .code()
-            .unwrap_or(UNIX_SIGNAL_EXIT_CODE + LAST_SIGNAL.load(Ordering::Relaxed))
-    } else {
-        SUCCESS
-    }
+struct SignalEvent {
+    signal: Signal,
+    pids: Vec<u32>,
 }
 
 ///
-/// Collect the tool options from the command line and from the configuration file
-/// if it exists.
-/// Options passed within the command line has the biggest priority
-/// and rewrite the similar options from the configuration file.
+/// The `# owl:` comment which follows the shebang of the script run.
 ///
-fn collect_opts() -> HashMap<String, String> {
-    let mut dict: HashMap<String, String> = HashMap::new();
-
-    // Collect options from command line arguments
-    let opts: Vec<String> = env::args_os()
-        .skip(1)
-        .map(|x| x.to_string_lossy().into())
-        .filter(|x: &String| x.starts_with(OPTION_START))
-        .collect();
-
-    for opt in opts {
-        let mut lossy: String = opt.to_string();
-        let _ = lossy.remove(0); // Strip the leading +
-
-        // Find the index of colon and split to name and value
-        let parts: Vec<&str> = lossy.splitn(2, OPTION_DELIMITER).collect();
-        if parts.len() == 2 {
-            dict.insert(parts[0].into(), parts[1].into());
-        } else {
-            dict.insert(parts[0].into(), EMPTY_STR);
-        }
-    }
-
-    // Collect options from configuration file
-    if let Some(conf) = read_config_content(dict.get(OPT_CONF)) {
-        if let Some(conf) = conf.get(SECTION_WATCH) {
-            if let Some(watch) = conf.as_table() {
-                for entry in watch.into_iter() {
-                    if dict.get(entry.0).is_none() {
-                        match entry.1 {
-                            toml::Value::String(v) => {
-                                dict.insert(entry.0.to_string(), v.to_string())
-                            }
-                            toml::Value::Integer(v) => {
-                                dict.insert(entry.0.to_string(), format!("{}", v))
-                            }
-                            toml::Value::Float(v) => {
-                                dict.insert(entry.0.to_string(), format!("{}", v))
-                            }
-                            toml::Value::Boolean(v) => {
-                                dict.insert(entry.0.to_string(), format!("{}", v))
-                            }
-                            toml::Value::Datetime(v) => {
-                                dict.insert(entry.0.to_string(), format!("{}", v))
-                            }
-                            _ => None,
-                        };
-                    }
-                }
-            }
-        }
-    }
+struct ScriptHeader {
+    opts: Vec<String>,
+    interpreter: Vec<OsString>,
+}
 
-    dict
+///
+/// The frame waiting in the send buffer with counters it reports.
+///
+struct Frame {
+    data: Vec<u8>,
+    failures: u64,
+    dropped: u64,
 }
 
 ///
-/// Strip all `+<Name>:<Value>` arguments and return others.
+/// The way SIGTERM caught is forwarded to processes monitored.
 ///
-fn collect_command_args() -> Vec<OsString> {
-    env::args_os()
-        .skip(1)
-        .filter(|x| !x.to_string_lossy().starts_with(OPTION_START))
-        .collect()
+#[derive(Clone, Copy)]
+enum TermPropagation {
+    Immediate,
+    Delay(time::Duration),
+    None,
 }
 
 ///
-/// Get the name of the command the child process run.
+/// What the tool does on SIGHUP caught.
 ///
-fn command_name() -> Option<OsString> {
-    ARGS.iter().take(1).cloned().nth(0)
+#[derive(Clone, Copy, PartialEq)]
+enum SighupAction {
+    Forward,
+    Reload,
+    Restart,
+    Ignore,
 }
 
 ///
-/// Get the list of command line arguments of the child process.
+/// The options of the delivery which can be changed on reload.
 ///
-fn command_args() -> Vec<OsString> {
-    ARGS.iter().skip(1).cloned().collect()
+struct DeliveryOpts {
+    delay: time::Duration,
+    on_change: bool,
+    change_threshold: f32,
+    max_silence: time::Duration,
+    max_rss: Option<u64>,
+    stats_interval: Option<time::Duration>,
+    aggregated: bool,
+}
+
+impl DeliveryOpts {
+    ///
+    /// Read delivery options from `opts` and use defaults on missing ones.
+    ///
+    fn from_opts(opts: &HashMap<String, String>) -> DeliveryOpts {
+        DeliveryOpts {
+            delay: opts
+                .get(OPT_HEARTBEAT)
+                .and_then(|v| parse_duration(v))
+                .unwrap_or_else(|| time::Duration::from_millis(DEFAULT_HEARTBEAT_MILLIS)),
+            on_change: opts
+                .get(OPT_HEARTBEAT_ON_CHANGE)
+                .is_some_and(|v| value_enabled(v)),
+            change_threshold: opts
+                .get(OPT_CHANGE_THRESHOLD)
+                .and_then(|v| v.trim().trim_end_matches('%').parse::<f32>().ok())
+                .unwrap_or(DEFAULT_CHANGE_THRESHOLD_PCT),
+            max_silence: opts
+                .get(OPT_MAX_SILENCE)
+                .and_then(|v| parse_duration(v))
+                .unwrap_or_else(|| time::Duration::from_secs(DEFAULT_MAX_SILENCE_SECS)),
+            max_rss: opts
+                .get(OPT_MAX_RSS_BYTES)
+                .and_then(|v| parse_size(v))
+                .filter(|v| *v > 0),
+            stats_interval: opts
+                .get(OPT_STATS_INTERVAL)
+                .and_then(|v| parse_duration(v))
+                .filter(|v| !v.is_zero()),
+            aggregated: opts
+                .get(OPT_DELIVER_AGGREGATED)
+                .is_some_and(|v| value_enabled(v)),
+        }
+    }
 }
 
 ///
-/// Listen for incoming OS signal in the infinite loop.
-/// All signal caught are redirected as-is to the child process.
+/// The log file opened for appending the command output.
 ///
-fn listen_signals() {
-    let signals = signal_hook::iterator::Signals::new(allowed_signals())
-        .expect("failed to setup signal listener");
-    for s in signals.forever() {
-        // Save the last signal caught
-        LAST_SIGNAL.store(s, Ordering::Relaxed);
+struct LogFile {
+    path: String,
+    file: fs::File,
+    size: u64,
+    max_size: u64,
+}
 
-        // Propagate the signal to the command process
-        let pid = CHILD_PID.load(Ordering::Relaxed);
-        if pid > 0 {
-            if let Some(sig) = cast_signal(s) {
-                let _ = signal::kill(Pid::from_raw(pid as i32), sig);
-            }
-        }
-    }
-}
-
-///
-/// Make the list of all possible allowed signals
-/// which the tool can subscribe for.
-///
-fn allowed_signals() -> Vec<i32> {
-    let all = vec![
-        signal_hook::SIGABRT,
-        signal_hook::SIGALRM,
-        signal_hook::SIGBUS,
-        signal_hook::SIGCHLD,
-        signal_hook::SIGCONT,
-        signal_hook::SIGFPE,
-        signal_hook::SIGHUP,
-        signal_hook::SIGILL,
-        signal_hook::SIGINT,
-        signal_hook::SIGIO,
-        signal_hook::SIGKILL,
-        signal_hook::SIGPIPE,
-        signal_hook::SIGPROF,
-        signal_hook::SIGQUIT,
-        signal_hook::SIGSEGV,
-        signal_hook::SIGSTOP,
-        signal_hook::SIGSYS,
-        signal_hook::SIGTERM,
-        signal_hook::SIGTRAP,
-        signal_hook::SIGUSR1,
-        signal_hook::SIGUSR2,
-        signal_hook::SIGWINCH,
-    ];
+fn main() {
+    // Do nothing if no command line arguments are passed.
+    if env::args().len() < 2 {
+        return;
+    }
+
+    if COMMAND_LINE.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    if COMMAND_LINE.json_schema {
+        println!("{}", schema::state_message_schema());
+        return;
+    }
+
+    // Stop before anything is started if options are invalid
+    if let Err(err) = validate_opts() {
+        std::process::exit(report_error(&OwlError::ConfigParseError(err)));
+    }
+
+    if opt_enabled(OPT_BANNER) {
+        print_banner();
+    }
 
-    let mut interest = Vec::new();
-    for s in all {
-        let mut found = false;
-        for f in signal_hook::FORBIDDEN {
-            if s == *f {
-                found = true;
-                break;
+    // Check the receiver is reachable instead of running the command
+    if COMMAND_LINE.test {
+        match test_delivery() {
+            Ok(_) => {
+                println!("OK");
+                std::process::exit(SUCCESS);
+            }
+            Err(err) => {
+                println!("FAILED: {}", err);
+                std::process::exit(FAILURE);
             }
         }
+    }
+
+    // Existing processes are monitored instead of the command
+    let cgroup = OPT.contains_key(OPT_CGROUP_PATH);
+    let by_name = OPT.contains_key(OPT_MONITOR_BY_NAME);
+    let exclusive = if !PIDS.is_empty() && !ARGS.is_empty() {
+        Some("+Pids and the command")
+    } else if cgroup && (!PIDS.is_empty() || !ARGS.is_empty()) {
+        Some("+CgroupPath, +Pids, and the command")
+    } else if by_name && (cgroup || !PIDS.is_empty() || !ARGS.is_empty()) {
+        Some("+MonitorByName, +CgroupPath, +Pids, and the command")
+    } else {
+        None
+    };
+    if let Some(exclusive) = exclusive {
+        let err = format!("{} are mutually exclusive", exclusive);
+        std::process::exit(report_error(&OwlError::ConfigParseError(err)));
+    }
 
-        if !found {
-            interest.push(s);
+    // Make sure only one instance runs the job
+    if let Some(path) = OPT.get(OPT_LOCK_FILE) {
+        let timeout = OPT.get(OPT_LOCK_TIMEOUT).and_then(|v| parse_duration(v));
+        match acquire_lock(path, timeout) {
+            Ok(Some(file)) => {
+                if let Ok(mut lock) = LOCK_FILE.lock() {
+                    *lock = Some(file);
+                }
+            }
+            Ok(None) => {
+                eprintln!("error: lock file '{}' is held by another instance", path);
+                std::process::exit(LOCKED_EXIT_CODE);
+            }
+            Err(err) => {
+                eprintln!("error: failed to lock file '{}': {}", path, err);
+                std::process::exit(FAILURE);
+            }
         }
     }
 
-    interest
-}
+    // Look up the service name of the port once before threads need it
+    let _ = remote_address();
 
-///
-/// Convert signal from the numeric representation `from` into `Signal` type
-/// if possible.
-///
-fn cast_signal(from: i32) -> Option<Signal> {
-    if let Ok(sig) = Signal::from_c_int(from) {
-        Some(sig)
+    // Start up facilities
+    if let Err(err) = start_signal_listener() {
+        std::process::exit(report_error(&err));
+    }
+    let receiver = if opt_enabled(OPT_RECEIVE) {
+        match start_receiver() {
+            Ok(receiver) => Some(receiver),
+            Err(err) => std::process::exit(report_error(&err)),
+        }
     } else {
         None
+    };
+    // The queue is created here so it is removed when the tool exits
+    let message_queue =
+        if !*NESTED && OPT.get(OPT_TRANSPORT).map(String::as_str) == Some(TRANSPORT_POSIX_MQ) {
+            let (name, max_msgs, msg_size) = mq_opts();
+            match MessageQueue::create(&name, max_msgs, msg_size) {
+                Ok(queue) => Some(queue),
+                Err(err) => {
+                    let err = format!("failed to open message queue '{}': {}", name, err);
+                    std::process::exit(report_error(&OwlError::TransportSetupFailed(err)));
+                }
+            }
+        } else {
+            None
+        };
+    if *NESTED {
+        eprintln!("warning: the tool runs under another instance, the state is not delivered");
+    } else if let Err(err) = start_delivery() {
+        drop(message_queue);
+        std::process::exit(report_error(&err));
     }
+
+    // Spawn the child process with command line arguments passed
+    // or wait for monitored processes to exit.
+    let code = if cgroup {
+        wait_cgroup()
+    } else if by_name {
+        wait_shutdown()
+    } else if PIDS.is_empty() {
+        execute_command().unwrap_or_else(|err| report_error(&err))
+    } else {
+        wait_pids()
+    };
+
+    // Shut down facilities which should finish cleanly
+    SHUTDOWN.store(true, Ordering::Relaxed);
+    if let Some(receiver) = receiver {
+        let _ = receiver.join();
+    }
+    drop(message_queue);
+
+    std::process::exit(code);
 }
 
 ///
-/// Deliver process stats periodically in the infinite loop.
+/// Print the `err` and get the code the tool exits with on it.
 ///
-fn deliver_state() {
-    // Read delivery configuration and use defaults on missing options.
-    let mut remote_host = OPT.get(OPT_HOST).unwrap_or(&EMPTY_STR).clone();
-    if remote_host.is_empty() {
-        remote_host = DEFAULT_REMOTE_HOST.to_owned();
+fn report_error(err: &OwlError) -> i32 {
+    eprintln!("error: {}", err);
+    match err {
+        OwlError::CommandNotFound(_) => COMMAND_NOT_FOUND_EXIT_CODE,
+        OwlError::CommandStartFailed(_, err) if err.kind() == io::ErrorKind::NotFound => {
+            COMMAND_NOT_FOUND_EXIT_CODE
+        }
+        OwlError::CommandStartFailed(..) => COMMAND_NOT_EXECUTABLE_EXIT_CODE,
+        OwlError::ConfigParseError(_) => INVALID_OPTS_EXIT_CODE,
+        OwlError::SignalSetupFailed(_)
+        | OwlError::SocketBindFailed(..)
+        | OwlError::TransportSetupFailed(_) => FAILURE,
     }
+}
 
-    let mut remote_port = OPT.get(OPT_PORT).unwrap_or(&EMPTY_STR).clone();
-    if remote_port.is_empty() {
-        remote_port = DEFAULT_REMOTE_PORT.to_owned();
+///
+/// Validate values of options which the tool cannot run without.
+///
+fn validate_opts() -> Result<(), String> {
+    if let Some(path) = OPT.get(OPT_CONF) {
+        check_config_file(path)?;
+    }
+    term_propagation()?;
+    on_sighup()?;
+    match OPT.get(OPT_OUTPUT_MODE).map(String::as_str) {
+        None | Some(OUTPUT_MODE_PIPE) => {}
+        Some(OUTPUT_MODE_SILENT)
+        | Some(OUTPUT_MODE_CAPTURE)
+        | Some(OUTPUT_MODE_CAPTURE_FORWARD)
+            if OPT.contains_key(OPT_LOG_FILE) =>
+        {
+            return Err(format!(
+                "+{} and +{} are mutually exclusive",
+                OPT_OUTPUT_MODE, OPT_LOG_FILE
+            ));
+        }
+        Some(OUTPUT_MODE_SILENT)
+        | Some(OUTPUT_MODE_CAPTURE)
+        | Some(OUTPUT_MODE_CAPTURE_FORWARD) => {}
+        Some(other) => return Err(format!("unsupported output mode '{}'", other)),
+    }
+    if let Some(targets) = OPT.get(OPT_TEE) {
+        tee::parse_targets(targets)?;
+    }
+    if let Some(value) = OPT.get(OPT_ACTIVE_HOURS) {
+        ActiveHours::parse(value)?;
+    }
+    if let Some(value) = OPT.get(OPT_DUMP_SIGNAL) {
+        if parse_signal(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a signal",
+                OPT_DUMP_SIGNAL, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_ALERT_RSS_GROWTH_RATE) {
+        if monitor::parse_growth_rate(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid rate",
+                OPT_ALERT_RSS_GROWTH_RATE, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_RSS_GROWTH_EMA) {
+        if value.parse::<f64>().map_or(true, |v| v <= 0.0 || v > 1.0) {
+            return Err(format!(
+                "+{} value '{}' is not within (0, 1]",
+                OPT_RSS_GROWTH_EMA, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_KEEP_ALIVE_SENDS) {
+        if value.parse::<u64>().map_or(true, |v| v == 0) {
+            return Err(format!(
+                "+{} value '{}' is not a positive number",
+                OPT_KEEP_ALIVE_SENDS, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_MAX_OUTPUT_LINES) {
+        if value.parse::<u64>().is_err() {
+            return Err(format!(
+                "+{} value '{}' is not a number of lines",
+                OPT_MAX_OUTPUT_LINES, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_MAX_OUTPUT_LINES_WINDOW) {
+        if parse_duration(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid duration",
+                OPT_MAX_OUTPUT_LINES_WINDOW, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_CPU_SET) {
+        if parse_cpu_set(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid list of CPUs",
+                OPT_CPU_SET, value
+            ));
+        }
+    } else if opt_enabled(OPT_CPU_SET_INHERIT) {
+        return Err(format!(
+            "+{} requires +{}",
+            OPT_CPU_SET_INHERIT, OPT_CPU_SET
+        ));
+    }
+    if let Some(value) = OPT.get(OPT_CGROUP_MEMORY_LIMIT) {
+        if parse_size(value).filter(|v| *v > 0).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid size",
+                OPT_CGROUP_MEMORY_LIMIT, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_CAPTURE_MAX_BYTES) {
+        if parse_size(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid size",
+                OPT_CAPTURE_MAX_BYTES, value
+            ));
+        }
+    }
+    if OPT.get(OPT_TRANSPORT).map(String::as_str) == Some(TRANSPORT_PUSHGATEWAY) {
+        pushgateway()?;
+    }
+    if let Some(value) = OPT.get(OPT_MQ_NAME) {
+        if !value.starts_with('/') || value[1..].contains('/') {
+            return Err(format!(
+                "+{} value '{}' is not a queue name like /owl-rsync",
+                OPT_MQ_NAME, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_MQ_MAX_MSGS) {
+        if value.parse::<i64>().map_or(true, |v| v <= 0) {
+            return Err(format!(
+                "+{} value '{}' is not a positive number",
+                OPT_MQ_MAX_MSGS, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_MQ_MSG_SIZE) {
+        if parse_size(value)
+            .filter(|&v| v > 0 && v <= i64::MAX as u64)
+            .is_none()
+        {
+            return Err(format!(
+                "+{} value '{}' is not a valid size",
+                OPT_MQ_MSG_SIZE, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_EXEC_DELAY_START) {
+        if parse_duration(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid duration",
+                OPT_EXEC_DELAY_START, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_STATS_INTERVAL) {
+        if parse_duration(value).filter(|v| !v.is_zero()).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid positive duration",
+                OPT_STATS_INTERVAL, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_CPU_BUDGET) {
+        if parse_duration(value).is_none() {
+            return Err(format!(
+                "+{} value '{}' is not a valid duration",
+                OPT_CPU_BUDGET, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_HEARTBEAT_COUNT) {
+        if value.parse::<u64>().is_err() {
+            return Err(format!(
+                "+{} value '{}' is not a valid number",
+                OPT_HEARTBEAT_COUNT, value
+            ));
+        }
+    }
+    if let Some(value) = OPT.get(OPT_HEARTBEAT) {
+        match parse_duration(value) {
+            Some(delay) if delay > time::Duration::from_millis(0) => {}
+            _ => {
+                return Err(format!(
+                    "+{} value '{}' is not a valid duration",
+                    OPT_HEARTBEAT, value
+                ))
+            }
+        }
     }
 
-    let delay = OPT
-        .get(OPT_HEARTBEAT)
-        .unwrap_or(&DEFAULT_HEARTBEAT.to_owned())
-        .parse::<u64>()
-        .unwrap_or(DEFAULT_HEARTBEAT_MILLIS);
+    Ok(())
+}
 
-    let remote_addr = format!("{}:{}", remote_host, remote_port);
+///
+/// Parse the `TermPropagation` option which is `immediate`, `none`, or `delay:<duration>`.
+///
+fn term_propagation() -> Result<TermPropagation, String> {
+    let value = match OPT.get(OPT_TERM_PROPAGATION) {
+        Some(value) => value,
+        None => return Ok(TermPropagation::Immediate),
+    };
 
-    // Start sending notifications periodically when child PID is defined
-    loop {
-        let pid = CHILD_PID.load(Ordering::Relaxed);
-        if pid > 0 {
-            if let Some(info) = read_process_info(pid) {
-                send_state(remote_addr.clone(), info);
-            }
+    match value.as_str() {
+        TERM_PROPAGATION_IMMEDIATE => Ok(TermPropagation::Immediate),
+        TERM_PROPAGATION_NONE => Ok(TermPropagation::None),
+        v => v
+            .strip_prefix(TERM_PROPAGATION_DELAY)
+            .and_then(parse_duration)
+            .map(TermPropagation::Delay)
+            .ok_or_else(|| {
+                format!(
+                    "+{} value '{}' is not one of immediate, none, or delay:<duration>",
+                    OPT_TERM_PROPAGATION, value
+                )
+            }),
+    }
+}
 
-            // Sleep a little before the next delivery
-            thread::sleep(time::Duration::from_millis(delay));
-        }
+///
+/// Parse the `OnSighup` option which is `forward`, `reload`, `restart`, or `ignore`.
+///
+fn on_sighup() -> Result<SighupAction, String> {
+    match OPT.get(OPT_ON_SIGHUP).map(String::as_str) {
+        None | Some(SIGHUP_FORWARD) => Ok(SighupAction::Forward),
+        Some(SIGHUP_RELOAD) => Ok(SighupAction::Reload),
+        Some(SIGHUP_RESTART) => Ok(SighupAction::Restart),
+        Some(SIGHUP_IGNORE) => Ok(SighupAction::Ignore),
+        Some(value) => Err(format!(
+            "+{} value '{}' is not one of forward, reload, restart, or ignore",
+            OPT_ON_SIGHUP, value
+        )),
     }
 }
 
 ///
-/// Send the stat of the process to the remote listener.
-/// The send is done over UDP socket which is created with a random
-/// port.
+/// Test the boolean option `name` is enabled. The option without value,
+/// e.g. `+Receive`, is enabled as well.
 ///
-fn send_state(remote_addr: String, stat: Stat) {
-    // Make temp UDP socket with OS assigned port and send message
-    let local_addr = SocketAddr::from(([0, 0, 0, 0], 0));
-    if let Ok(socket) = UdpSocket::bind(&local_addr) {
-        // Get command name from option or from command line
-        let cmd_name: String = if let Some(v) = OPT.get(OPT_NAME) {
-            v.clone()
-        } else {
-            stat.command
-        };
+fn opt_enabled(name: &str) -> bool {
+    OPT.get(name).is_some_and(|v| value_enabled(v))
+}
 
-        let msg = format!(
-            "{}||{}||{}||{:?}",
-            process::id(),
-            stat.pid,
-            cmd_name,
-            stat.state
+///
+/// Test the value of the boolean option enables it.
+///
+fn value_enabled(v: &str) -> bool {
+    v.is_empty() || v == "true" || v == "yes" || v == "1"
+}
+
+///
+/// Install the listener of signals the tool handles and start the thread
+/// which listens to them.
+///
+fn start_signal_listener() -> Result<(), OwlError> {
+    let signals = signal_hook::iterator::Signals::new(platform::allowed_signals())
+        .map_err(OwlError::SignalSetupFailed)?;
+    thread::spawn(move || listen_signals(signals));
+    Ok(())
+}
+
+///
+/// Start the thread which delivers the state and wait until it is set up.
+/// The error the thread fails to set up the delivery with is returned.
+///
+fn start_delivery() -> Result<(), OwlError> {
+    let (ready, setup) = mpsc::channel();
+    thread::spawn(move || deliver_state(ready));
+    // The thread which stops without the result has nothing to deliver
+    setup.recv().unwrap_or(Ok(()))
+}
+
+///
+/// Bind the UDP socket on the delivery address and start the thread which
+/// prints the state received until the tool shuts down.
+///
+fn start_receiver() -> Result<thread::JoinHandle<()>, OwlError> {
+    let addr = remote_address().map_err(OwlError::ConfigParseError)?;
+
+    let host = match OPT.get(OPT_HOST) {
+        Some(host) if !host.is_empty() => host.clone(),
+        _ => DEFAULT_REMOTE_HOST.to_owned(),
+    };
+    if !LOOPBACK_HOSTS.contains(&host.as_str()) {
+        eprintln!(
+            "warning: +{} is set but +{} '{}' is not the loopback address",
+            OPT_RECEIVE, OPT_HOST, host
         );
-        let _ = socket.send_to(msg.as_ref(), remote_addr);
     }
+
+    let socket = match UdpSocket::bind(&addr) {
+        Ok(socket) => socket,
+        Err(err) => return Err(OwlError::SocketBindFailed(addr, err)),
+    };
+
+    Ok(thread::spawn(move || receive_state(socket)))
 }
 
 ///
-/// Read stats of the process with `id` using `procinfo` crate.
-/// On success stats returned or `None` otherwise.
+/// Print messages received on the `socket` until the tool shuts down.
 ///
-fn read_process_info(id: u32) -> Option<Stat> {
-    match stat(id as i32) {
-        Ok(info) => Some(info),
-        Err(_) => None,
+fn receive_state(socket: UdpSocket) {
+    let _ = socket.set_read_timeout(Some(time::Duration::from_millis(RECEIVE_TIMEOUT_MILLIS)));
+    let mut buf = [0; RECEIVE_BUFFER_SIZE];
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        if let Ok((len, src)) = socket.recv_from(&mut buf) {
+            println!("{} -> {}", src, String::from_utf8_lossy(&buf[..len]));
+        }
     }
 }
 
 ///
-/// Read content of the file with `path` given. If the read is successful
-/// the content is treated as toml and parsed. On any error, from reading to parsing,
-/// `None` is returned.
+/// Start the command executing.
+/// By default STDIN, STDOUT, and STDERR becomes standats inputs
+/// and outputs for the command process.
 ///
-fn read_file_contents<S: Into<String>>(path: S) -> Option<toml::Value> {
-    match fs::File::open(path.into()) {
-        Ok(mut file) => {
-            let mut contents = String::new();
-            if file.read_to_string(&mut contents).is_ok() {
-                match contents.parse::<toml::Value>() {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
+fn execute_command() -> Result<i32, OwlError> {
+    if let Some(name) = command_name() {
+        let mut binaries = match resolve_command(&name) {
+            Some(path) => vec![path],
+            None => {
+                return Err(OwlError::CommandNotFound(
+                    name.to_string_lossy().into_owned(),
+                ))
+            }
+        };
+        let prefix = command_prefix();
+        if let Some(program) = prefix.first() {
+            match resolve_command(OsStr::new(program)) {
+                Some(path) => binaries.push(path),
+                None => return Err(OwlError::CommandNotFound(program.clone())),
+            }
+        }
+
+        // Binaries which change privileges are reported unless that is intended.
+        if !opt_enabled(OPT_SKIP_BINARY_CHECK) {
+            let strict = opt_enabled(OPT_STRICT_BINARY_CHECK);
+            for issue in binaries.iter().flat_map(|path| check_binary(path)) {
+                if strict {
+                    eprintln!("error: {}", issue);
+                    return Ok(FAILURE);
                 }
-            } else {
-                None
+                eprintln!("warning: {}", issue);
             }
         }
-        _ => None,
+
+        // Wait before the command starts while heartbeats report it pending.
+        if let Some(delay) = OPT
+            .get(OPT_EXEC_DELAY_START)
+            .and_then(|v| parse_duration(v))
+        {
+            let started = time::Instant::now();
+            while started.elapsed() < delay {
+                if SHUTDOWN.load(Ordering::Relaxed) {
+                    return Ok(UNIX_SIGNAL_EXIT_CODE + LAST_SIGNAL.load(Ordering::Relaxed));
+                }
+                thread::sleep(time::Duration::from_millis(EXEC_DELAY_POLL_MILLIS));
+            }
+        }
+
+        // Run the pre-exec hook and do not start the command if it fails.
+        if let Some(hook) = OPT.get(OPT_PRE_EXEC) {
+            let code = execute_hook(hook);
+            if code != SUCCESS {
+                return Ok(code);
+            }
+        }
+
+        // Run the post-exec hook regardless of how the command finishes.
+        let result = run_command(&name, &prefix);
+        if let Some(hook) = OPT.get(OPT_POST_EXEC) {
+            let _ = execute_hook(hook);
+        }
+
+        result
+    } else {
+        Ok(SUCCESS)
     }
 }
 
 ///
-/// Search for the configuration file and read it.
-/// If `explicit_path` is given then only that file is tried to be read.
-/// Otherwise the configuration file is searched in known locations.
+/// Spawn the command `name` run by the `prefix` and wait for it to exit,
+/// restarting it on SIGHUP if asked. The exit code of the command is returned
+/// or the error if the command fails to start.
 ///
-fn read_config_content(explicit_path: Option<&String>) -> Option<toml::Value> {
-    if let Some(path) = explicit_path {
-        read_file_contents(path)
-    } else {
-        read_file_contents(CONF_LOCATION_CWD)
-            .or_else(|| read_file_contents(CONF_LOCATION_ETC_OWL))
-            .or_else(|| read_file_contents(CONF_LOCATION_ETC))
+fn run_command(name: &OsStr, prefix: &[String]) -> Result<i32, OwlError> {
+    // Open the log file the command output is redirected to.
+    let log_path = OPT.get(OPT_LOG_FILE).cloned().unwrap_or_default();
+    if !log_path.is_empty() {
+        if let Err(err) = open_log_file(&log_path) {
+            eprintln!("error: failed to open log file '{}': {}", log_path, err);
+            return Ok(FAILURE);
+        }
+    }
+
+    // The prefix runs the command, e.g. under strace
+    let mut command = match prefix.split_first() {
+        Some((program, args)) => {
+            let mut command = Command::new(program);
+            command.args(args).arg(name);
+            command
+        }
+        None => Command::new(name),
+    };
+    command.args(command_args());
+    if let Err(err) = configure_environment(&mut command) {
+        eprintln!("error: {}", err);
+        return Ok(FAILURE);
+    }
+    // The output of the STDIN command wins over the STDIN file
+    let mut stdin_data = None;
+    if let Some(stdin_cmd) = OPT.get(OPT_CHILD_STDIN_CMD) {
+        let timeout = OPT
+            .get(OPT_CHILD_STDIN_CMD_TIMEOUT)
+            .and_then(|v| parse_duration(v));
+        match run_stdin_command(stdin_cmd, timeout) {
+            Ok(data) => stdin_data = Some(data),
+            Err(err) => {
+                eprintln!("error: stdin command '{}' {}", stdin_cmd, err);
+                return Ok(FAILURE);
+            }
+        }
+        command.stdin(Stdio::piped());
+    }
+    match OPT.get(OPT_CHILD_STDIN_FILE).map(String::as_str) {
+        _ if stdin_data.is_some() => {}
+        None | Some(STDIN_PATH) => {}
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => {
+                command.stdin(Stdio::from(file));
+            }
+            Err(err) => {
+                eprintln!("error: failed to open stdin file '{}': {}", path, err);
+                return Ok(FAILURE);
+            }
+        },
+    }
+    let output_mode = OPT.get(OPT_OUTPUT_MODE).map(String::as_str);
+    let forward = output_mode == Some(OUTPUT_MODE_CAPTURE_FORWARD);
+    let capture = forward || output_mode == Some(OUTPUT_MODE_CAPTURE);
+    if output_mode == Some(OUTPUT_MODE_SILENT) {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    } else if capture
+        || !log_path.is_empty()
+        || opt_enabled(OPT_COUNT_OUTPUT_LINES)
+        || OUTPUT_LIMIT.is_some()
+    {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    if opt_enabled(OPT_SIGNAL_GROUP) {
+        command.process_group(0);
+    }
+
+    // The command starts with no signals blocked unless it inherits the tool mask.
+    let sigmask = if opt_enabled(OPT_INHERIT_SIG_MASK) {
+        SigSet::thread_get_mask().unwrap_or_else(|_| SigSet::empty())
+    } else {
+        SigSet::empty()
+    };
+    unsafe {
+        command.pre_exec(move || {
+            signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&sigmask), None)
+                .map_err(|_| io::Error::last_os_error())
+        });
+    }
+
+    // The command is run in the cgroup with the memory limit if it can be created.
+    let cgroup = OPT
+        .get(OPT_CGROUP_MEMORY_LIMIT)
+        .and_then(|v| parse_size(v))
+        .and_then(|limit| {
+            let base = OPT
+                .get(OPT_CGROUP_BASE)
+                .map_or(DEFAULT_CGROUP_BASE, String::as_str);
+            match Cgroup::create(Path::new(base), &message_name(), limit) {
+                Ok(cgroup) => Some(cgroup),
+                Err(err) => {
+                    eprintln!(
+                        "warning: failed to create cgroup under '{}', the memory limit is skipped: {}",
+                        base, err
+                    );
+                    None
+                }
+            }
+        });
+
+    // The command is pinned to CPUs after it starts, the tool is pinned too if asked.
+    let cpu_set = OPT.get(OPT_CPU_SET).and_then(|v| parse_cpu_set(v));
+    if let Some(cpus) = cpu_set
+        .as_ref()
+        .filter(|_| opt_enabled(OPT_CPU_SET_INHERIT))
+    {
+        if let Err(err) = platform::set_own_cpu_affinity(cpus) {
+            eprintln!(
+                "warning: failed to pin the tool to CPUs '{}': {}",
+                OPT[OPT_CPU_SET], err
+            );
+        }
+    }
+
+    // Children waited for before are not accounted to the command.
+    let usage_before = platform::read_children_usage();
+    let to_log = !log_path.is_empty();
+    let (child, status, copiers) = loop {
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                EXEC_PENDING.store(false, Ordering::Relaxed);
+                let name = name.to_string_lossy().into_owned();
+                return Err(OwlError::CommandStartFailed(name, err));
+            }
+        };
+        CHILD_PID.store(child.id(), Ordering::Relaxed);
+        EXEC_PENDING.store(false, Ordering::Relaxed);
+        if let Some(cpus) = cpu_set.as_ref() {
+            if let Err(err) = platform::set_cpu_affinity(child.id(), cpus) {
+                eprintln!(
+                    "warning: failed to pin process {} to CPUs '{}': {}",
+                    child.id(),
+                    OPT[OPT_CPU_SET],
+                    err
+                );
+            }
+        }
+        if let Some(cgroup) = cgroup.as_ref() {
+            if let Err(err) = cgroup.add(child.id()) {
+                eprintln!(
+                    "warning: failed to move process {} into cgroup '{}': {}",
+                    child.id(),
+                    cgroup.path().display(),
+                    err
+                );
+            }
+        }
+        write_lock_info(child.id());
+
+        // Feed the STDIN command output to the command and close its STDIN.
+        if let (Some(data), Some(mut stdin)) = (stdin_data.clone(), child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&data);
+            });
+        }
+
+        // Copy the command output into the log file or the tool output
+        // until the command closes it.
+        let mut copiers = Vec::new();
+        if let Some(out) = child.stdout.take() {
+            copiers.push(thread::spawn(move || {
+                copy_output(
+                    out,
+                    &STDOUT_LINES,
+                    if forward {
+                        write_capture_stdout
+                    } else if capture {
+                        write_capture
+                    } else if to_log {
+                        write_log
+                    } else {
+                        write_stdout
+                    },
+                )
+            }));
+        }
+        if let Some(err) = child.stderr.take() {
+            copiers.push(thread::spawn(move || {
+                copy_output(
+                    err,
+                    &STDERR_LINES,
+                    if forward {
+                        write_capture_stderr
+                    } else if capture {
+                        write_capture
+                    } else if to_log {
+                        write_log
+                    } else {
+                        write_stderr
+                    },
+                )
+            }));
+        }
+
+        // The command killed with the signal exits with 128 plus the signal number
+        let status = match child.wait() {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!(
+                    "error: failed to retrieve exit status of process {}: {}",
+                    child.id(),
+                    err
+                );
+                let _ = child.kill();
+                return Ok(FAILURE);
+            }
+        };
+
+        // The command which exits on SIGHUP with restart requested is started again,
+        // the request is dropped when the command survives the signal and exits later
+        let restart = RESTART_REQUESTED.swap(false, Ordering::Relaxed)
+            && status.signal() == Some(libc::SIGHUP);
+        if restart && !SHUTDOWN.load(Ordering::Relaxed) {
+            for copier in copiers {
+                let _ = copier.join();
+            }
+            eprintln!(
+                "warning: process {} exited on SIGHUP, restarting",
+                child.id()
+            );
+            continue;
+        }
+        break (child, status, copiers);
+    };
+    let usage = platform::read_children_usage().since(&usage_before);
+    let core_dump = if status.core_dumped() {
+        find_core_dump(child.id(), status.signal().unwrap_or_default())
+    } else {
+        None
+    };
+    let exit_reason = status.signal().map(|sig| match cast_signal(sig) {
+        Some(name) => format!("killed by signal {} ({})", name, sig),
+        None => format!("killed by signal {}", sig),
+    });
+    if let Some(reason) = exit_reason.as_ref() {
+        eprintln!("error: process {} {}", child.id(), reason);
+    }
+    let code = status.code().unwrap_or_else(|| {
+        UNIX_SIGNAL_EXIT_CODE
+            + status
+                .signal()
+                .unwrap_or_else(|| LAST_SIGNAL.load(Ordering::Relaxed))
+    });
+    for copier in copiers {
+        let _ = copier.join();
+    }
+    let code = if RSS_LIMIT_KILLED.load(Ordering::Relaxed) {
+        RSS_LIMIT_EXIT_CODE
+    } else {
+        code
+    };
+
+    // Report resources the command used over its whole life.
+    if opt_enabled(OPT_PRINT_ACCOUNTING) {
+        print_accounting(&usage);
+    }
+    let mut msg = accounting_message(child.id(), code, &usage);
+    if let Some(reason) = exit_reason {
+        msg.fields.push(("exit_reason", Value::Text(reason)));
+    }
+    if OUTPUT_LIMIT_EXCEEDED.load(Ordering::Relaxed) {
+        msg.fields
+            .push(("output_limit_exceeded", Value::Bool(true)));
+    }
+    if capture {
+        let output = match CAPTURED.lock() {
+            Ok(captured) => {
+                String::from_utf8_lossy(&captured.iter().cloned().collect::<Vec<u8>>()).into_owned()
+            }
+            Err(_) => String::new(),
+        };
+        msg.fields.push(("last_output", Value::Text(output)));
+    }
+    if status.core_dumped() {
+        let alert = match core_dump {
+            Some(path) => {
+                let path = path.to_string_lossy().into_owned();
+                let alert = format!("process {} dumped core to {}", child.id(), path);
+                msg.fields.push(("coredump_path", Value::Text(path)));
+                alert
+            }
+            None => format!("process {} dumped core", child.id()),
+        };
+        eprintln!("alert: {}", alert);
+        msg.alerts.push(alert);
+    }
+    send_accounting(msg);
+
+    Ok(code)
+}
+
+///
+/// Test the parent process runs the same program the tool does, i.e. the tool
+/// is nested into another instance of it, comparing their command names.
+///
+fn parent_is_owl() -> bool {
+    let parent = platform::read_process_stats(getppid().as_raw() as u32);
+    let own = platform::read_process_stats(process::id());
+    match (parent, own) {
+        (Some(parent), Some(own)) => parent.command == own.command,
+        _ => false,
+    }
+}
+
+///
+/// Configure the environment of the `command` with the `Environment` option
+/// and add variables from the `Env` option and secrets from the `SecretEnv`
+/// option.
+///
+/// - `inherit` passes the whole tool environment.
+/// - `clean` passes variables from the `Env` option only.
+/// - `minimal` passes `PATH`, `HOME`, `USER`, `LOGNAME`, `TERM`, `LANG`,
+///   `LC_ALL`, and `TZ`.
+///
+fn configure_environment(command: &mut Command) -> Result<(), String> {
+    match OPT.get(OPT_ENVIRONMENT).map(String::as_str) {
+        None | Some(ENVIRONMENT_INHERIT) => {}
+        Some(ENVIRONMENT_CLEAN) => {
+            command.env_clear();
+        }
+        Some(ENVIRONMENT_MINIMAL) => {
+            command.env_clear();
+            for var in MINIMAL_ENV_VARS.iter() {
+                if let Some(value) = env::var_os(var) {
+                    command.env(var, value);
+                }
+            }
+        }
+        Some(other) => return Err(format!("unsupported environment '{}'", other)),
+    }
+
+    if let Some(opt) = OPT.get(OPT_ENV) {
+        for pair in opt.split(',') {
+            let parts: Vec<&str> = pair.splitn(2, '=').collect();
+            if parts.len() == 2 && !parts[0].trim().is_empty() {
+                command.env(parts[0].trim(), parts[1]);
+            }
+        }
+    }
+
+    if let Some(opt) = OPT.get(OPT_SECRET_ENV) {
+        for pair in opt.split(',') {
+            let parts: Vec<&str> = pair.splitn(2, '=').collect();
+            if parts.len() != 2 || parts[0].trim().is_empty() {
+                return Err(format!(
+                    "invalid secret variable '{}', expected NAME=REFERENCE",
+                    pair
+                ));
+            }
+            let name = parts[0].trim();
+            let value = secrets::resolve(parts[1].trim())
+                .map_err(|err| format!("failed to resolve secret variable '{}': {}", name, err))?;
+            command.env(name, value);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Wait until all processes from the `Pids` option exit. Processes monitored
+/// are not children of the tool so their exit codes are unknown and zombies
+/// are treated as exited since the tool cannot reap them.
+///
+fn wait_pids() -> i32 {
+    let running = |pid: &u32| match platform::try_read_process_stats(*pid) {
+        Ok(Some(stats)) => stats.state != STATE_ZOMBIE,
+        Ok(None) => false,
+        Err(_) => signal::kill(Pid::from_raw(*pid as i32), None).is_ok(),
+    };
+    while monitored_pids().iter().any(running) {
+        thread::sleep(time::Duration::from_millis(DEFAULT_HEARTBEAT_MILLIS));
+    }
+
+    if RSS_LIMIT_KILLED.load(Ordering::Relaxed) {
+        RSS_LIMIT_EXIT_CODE
+    } else {
+        SUCCESS
+    }
+}
+
+///
+/// Wait until the cgroup from the `CgroupPath` option is removed or the tool
+/// is stopped with SIGINT, SIGTERM, or SIGQUIT. The cgroup may have no processes
+/// for a while, e.g. when the container restarts.
+///
+fn wait_cgroup() -> i32 {
+    let path = OPT.get(OPT_CGROUP_PATH).cloned().unwrap_or_default();
+    while Path::new(&path).is_dir() && !SHUTDOWN.load(Ordering::Relaxed) {
+        thread::sleep(time::Duration::from_millis(DEFAULT_HEARTBEAT_MILLIS));
+    }
+
+    if RSS_LIMIT_KILLED.load(Ordering::Relaxed) {
+        RSS_LIMIT_EXIT_CODE
+    } else {
+        SUCCESS
+    }
+}
+
+///
+/// Wait until the tool is stopped with SIGINT, SIGTERM, or SIGQUIT while processes
+/// found by the `MonitorByName` option are monitored. Processes may be missing
+/// for a while, e.g. when they are restarted by the service manager.
+///
+fn wait_shutdown() -> i32 {
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        thread::sleep(time::Duration::from_millis(DEFAULT_HEARTBEAT_MILLIS));
+    }
+
+    if RSS_LIMIT_KILLED.load(Ordering::Relaxed) {
+        RSS_LIMIT_EXIT_CODE
+    } else {
+        SUCCESS
+    }
+}
+
+///
+/// Get ids of processes monitored, i.e. the main process of the cgroup from
+/// the `CgroupPath` option, processes named after the `MonitorByName` option,
+/// processes from the `Pids` option including ones which exited, or the command process
+/// when it is started.
+///
+fn monitored_pids() -> Vec<u32> {
+    if let Some(path) = OPT.get(OPT_CGROUP_PATH) {
+        cgroup_main_pid(Path::new(path)).into_iter().collect()
+    } else if let Some(name) = OPT.get(OPT_MONITOR_BY_NAME) {
+        named_pids(name)
+    } else if PIDS.is_empty() {
+        let pid = CHILD_PID.load(Ordering::Relaxed);
+        if pid > 0 {
+            vec![pid]
+        } else {
+            Vec::new()
+        }
+    } else {
+        PIDS.clone()
+    }
+}
+
+///
+/// Pick processes which `comm` is `name` with the `MonitorByNamePick` option
+/// which is `lowest` id, `youngest` or `oldest` by the start time, or `all`.
+/// The tool itself is never picked.
+///
+fn named_pids(name: &str) -> Vec<u32> {
+    let own_pid = process::id();
+    let processes: Vec<(u32, u64)> = platform::find_processes_by_name(name)
+        .into_iter()
+        .filter(|(pid, _)| *pid != own_pid)
+        .collect();
+    let pick = match OPT.get(OPT_MONITOR_BY_NAME_PICK).map(String::as_str) {
+        None | Some(NAME_PICK_LOWEST) => processes.first(),
+        Some(NAME_PICK_YOUNGEST) => processes.iter().max_by_key(|(_, started)| *started),
+        Some(NAME_PICK_OLDEST) => processes.iter().min_by_key(|(_, started)| *started),
+        Some(NAME_PICK_ALL) => return processes.into_iter().map(|(pid, _)| pid).collect(),
+        Some(other) => {
+            eprintln!("warning: unsupported process pick '{}'", other);
+            processes.first()
+        }
+    };
+
+    pick.map(|(pid, _)| *pid).into_iter().collect()
+}
+
+///
+/// Pick the main process of the cgroup at `dir` from processes listed in its
+/// `cgroup.procs` with the `CgroupMainPid` option which is `first`, `last`,
+/// or `max_rss`.
+///
+fn cgroup_main_pid(dir: &Path) -> Option<u32> {
+    let pids = platform::read_cgroup_procs(dir)?;
+    match OPT.get(OPT_CGROUP_MAIN_PID).map(String::as_str) {
+        None | Some(CGROUP_MAIN_PID_FIRST) => pids.first().cloned(),
+        Some(CGROUP_MAIN_PID_LAST) => pids.last().cloned(),
+        Some(CGROUP_MAIN_PID_MAX_RSS) => pids
+            .iter()
+            .filter_map(|pid| platform::read_process_stats(*pid))
+            .max_by_key(|stats| stats.rss_bytes.unwrap_or_default())
+            .map(|stats| stats.pid),
+        Some(other) => {
+            eprintln!("warning: unsupported cgroup main pid '{}'", other);
+            pids.first().cloned()
+        }
+    }
+}
+
+///
+/// Parse the comma separated list of process ids from the `Pids` option.
+///
+fn collect_pids() -> Vec<u32> {
+    let mut pids = Vec::new();
+    if let Some(list) = OPT.get(OPT_PIDS) {
+        for item in list.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match item.parse::<u32>() {
+                Ok(pid) if pid > 0 => pids.push(pid),
+                _ => eprintln!("warning: invalid process id '{}' in +Pids", item),
+            }
+        }
+    }
+
+    pids
+}
+
+///
+/// Create the lock file at `path` and lock it exclusively. If the file is locked
+/// by another instance the lock is retried until the `timeout` passes.
+/// The file locked is returned or `None` if the lock is not acquired in time.
+///
+fn acquire_lock(path: &str, timeout: Option<time::Duration>) -> io::Result<Option<fs::File>> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let deadline = time::Instant::now() + timeout.unwrap_or_default();
+    loop {
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(_) => break,
+            Err(nix::Error::Sys(Errno::EAGAIN)) if time::Instant::now() < deadline => {
+                thread::sleep(time::Duration::from_millis(LOCK_POLL_MILLIS));
+            }
+            Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(None),
+            Err(nix::Error::Sys(errno)) => return Err(io::Error::from_raw_os_error(errno as i32)),
+            Err(_) => return Err(io::Error::last_os_error()),
+        }
+    }
+
+    let mut lock = Some(file);
+    write_lock_content(&mut lock, 0)?;
+    Ok(lock)
+}
+
+///
+/// Write the id of the tool and the command process `child_pid` into the lock
+/// file if it is held.
+///
+fn write_lock_info(child_pid: u32) {
+    if let Ok(mut lock) = LOCK_FILE.lock() {
+        let _ = write_lock_content(&mut lock, child_pid);
+    }
+}
+
+fn write_lock_content(lock: &mut Option<fs::File>, child_pid: u32) -> io::Result<()> {
+    if let Some(file) = lock.as_mut() {
+        file.set_len(0)?;
+        file.seek(io::SeekFrom::Start(0))?;
+        writeln!(file, "owl_pid={}", process::id())?;
+        if child_pid > 0 {
+            writeln!(file, "child_pid={}", child_pid)?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Find the core dump of the command `pid` killed with the `signal`
+/// in the `CoreDir` directory or where the `CorePattern` places it.
+///
+fn find_core_dump(pid: u32, signal: i32) -> Option<PathBuf> {
+    if let Some(dir) = OPT.get(OPT_CORE_DIR) {
+        return coredump::find_in_dir(Path::new(dir), pid);
+    }
+
+    let pattern_file = OPT
+        .get(OPT_CORE_PATTERN)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_CORE_PATTERN);
+    let pattern = fs::read_to_string(pattern_file).ok()?;
+    let uses_pid = fs::read_to_string(CORE_USES_PID).is_ok_and(|v| v.trim() != "0");
+
+    let name = command_name()?;
+    let exe = resolve_command(&name)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut comm = Path::new(&name)
+        .file_name()
+        .map(|v| v.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    while comm.len() > COMM_LEN {
+        comm.pop();
+    }
+    let mut buf = [0u8; 256];
+    let hostname = gethostname(&mut buf)
+        .ok()
+        .and_then(|name| name.to_str().ok().map(String::from))
+        .unwrap_or_default();
+
+    let crash = Crash {
+        pid,
+        signal,
+        uid: getuid().as_raw(),
+        gid: getgid().as_raw(),
+        comm,
+        exe,
+        hostname,
+    };
+    coredump::find_by_pattern(&pattern, uses_pid, &crash)
+}
+
+///
+/// Run the `hook` command line with the shell and wait for it to finish.
+/// The hook has the environment the command has. The exit code of the hook
+/// is returned.
+///
+fn execute_hook(hook: &str) -> i32 {
+    let mut command = Command::new(HOOK_SHELL);
+    command.arg("-c").arg(hook);
+    if let Err(err) = configure_environment(&mut command) {
+        eprintln!("error: {}", err);
+        return FAILURE;
+    }
+    match command.status() {
+        Ok(status) => status
+            .code()
+            .unwrap_or_else(|| UNIX_SIGNAL_EXIT_CODE + status.signal().unwrap_or(0)),
+        Err(err) => {
+            eprintln!("error: failed to execute hook '{}': {}", hook, err);
+            COMMAND_NOT_FOUND_EXIT_CODE
+        }
+    }
+}
+
+///
+/// Run the `cmd` command line with the shell and collect its STDOUT. The command
+/// STDERR is the tool STDERR. The command is killed if it runs longer than
+/// the `timeout`. The reason is returned if the command fails.
+///
+fn run_stdin_command(cmd: &str, timeout: Option<time::Duration>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(HOOK_SHELL)
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to execute: {}", err))?;
+
+    // Read the output while waiting so the command does not block on the full pipe
+    let mut stdout = child.stdout.take().ok_or("has no STDOUT")?;
+    let reader = thread::spawn(move || {
+        let mut data = Vec::new();
+        stdout.read_to_end(&mut data).map(|_| data)
+    });
+
+    let started = time::Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(err) => return Err(format!("failed to wait: {}", err)),
+        }
+        if let Some(timeout) = timeout.filter(|t| started.elapsed() >= *t) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("timed out after {:?}", timeout));
+        }
+        thread::sleep(time::Duration::from_millis(STDIN_CMD_POLL_MILLIS));
+    };
+    if !status.success() {
+        return Err(match status.code() {
+            Some(code) => format!("exited with code {}", code),
+            None => format!("killed by signal {}", status.signal().unwrap_or_default()),
+        });
+    }
+
+    match reader.join() {
+        Ok(Ok(data)) => Ok(data),
+        Ok(Err(err)) => Err(format!("failed to read output: {}", err)),
+        Err(_) => Err("failed to read output".to_owned()),
+    }
+}
+
+///
+/// Open the log file at `path` in append mode and make it the destination
+/// of the command output.
+///
+fn open_log_file(path: &str) -> io::Result<()> {
+    let file = append_file(path)?;
+    let size = file.metadata()?.len();
+    let max_size = OPT
+        .get(OPT_LOG_ROTATE_SIZE)
+        .and_then(|v| parse_size(v))
+        .unwrap_or(0);
+    *LOG_FILE.lock().unwrap() = Some(LogFile {
+        path: path.to_owned(),
+        file,
+        size,
+        max_size,
+    });
+    Ok(())
+}
+
+///
+/// Open the file at `path` for appending, creating it if it does not exist.
+///
+fn append_file(path: &str) -> io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+///
+/// Read the command output from `source` chunk by chunk, count complete lines
+/// in `lines` and against `MaxOutputLines`, and pass every chunk to `write`
+/// as soon as it is read so partial lines, e.g. prompts, are not held back.
+/// Every `OUTPUT_LINE_MAX_BYTES` of the line without the newline count against
+/// `MaxOutputLines` as one more line so the endless line is stopped too.
+///
+fn copy_output<R: Read>(mut source: R, lines: &AtomicU64, write: fn(&[u8])) {
+    let mut buf = [0; OUTPUT_CHUNK_SIZE];
+    let mut line_bytes = 0;
+    loop {
+        let len = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+
+        // The output is forwarded up to the line which exceeds the limit
+        let exceeded = OUTPUT_LIMIT_EXCEEDED.load(Ordering::Relaxed);
+        let mut end = len;
+        let mut line_start = 0;
+        for (i, byte) in buf[..len].iter().enumerate() {
+            line_bytes += 1;
+            if *byte != b'\n' && line_bytes < OUTPUT_LINE_MAX_BYTES {
+                continue;
+            }
+            if *byte == b'\n' {
+                lines.fetch_add(1, Ordering::Relaxed);
+            }
+            line_bytes = 0;
+            if check_output_limit() && end == len {
+                end = line_start;
+            }
+            line_start = i + 1;
+        }
+
+        // The output over the limit is dropped until the command exits
+        if !exceeded && end > 0 {
+            write(&buf[..end]);
+        }
+    }
+}
+
+///
+/// Count the line the command outputs and terminate the command once
+/// it outputs more lines than `MaxOutputLines` allow. `true` is returned
+/// when the limit is exceeded.
+///
+fn check_output_limit() -> bool {
+    let (exceeded, max) = match OUTPUT_LIMIT.as_ref().and_then(|limit| limit.lock().ok()) {
+        Some(mut limit) => (limit.add(time::Instant::now()), limit.max()),
+        None => return false,
+    };
+    if exceeded && !OUTPUT_LIMIT_EXCEEDED.swap(true, Ordering::Relaxed) {
+        let pid = CHILD_PID.load(Ordering::Relaxed);
+        eprintln!(
+            "error: process {} output exceeds the limit of {} lines, terminating",
+            pid, max
+        );
+        let grace = OPT.get(OPT_SHUTDOWN_GRACE).and_then(|v| parse_duration(v));
+        terminate_process(pid, grace);
+    }
+
+    exceeded
+}
+
+///
+/// Write the `data` of the command output into the tool STDOUT.
+///
+fn write_stdout(data: &[u8]) {
+    let mut out = io::stdout().lock();
+    let _ = out.write_all(data).and_then(|_| out.flush());
+}
+
+///
+/// Write the `data` of the command output into the tool STDERR.
+///
+fn write_stderr(data: &[u8]) {
+    let _ = io::stderr().write_all(data);
+}
+
+///
+/// Keep the `data` of the command output in the capture buffer dropping
+/// the oldest bytes over `CaptureMaxBytes`.
+///
+fn write_capture(data: &[u8]) {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        captured.extend(data);
+        let excess = captured.len().saturating_sub(*CAPTURE_MAX_BYTES);
+        captured.drain(..excess);
+    }
+}
+
+///
+/// Keep the `data` of the command output in the capture buffer and write it
+/// into the tool STDOUT.
+///
+fn write_capture_stdout(data: &[u8]) {
+    write_capture(data);
+    write_stdout(data);
+}
+
+///
+/// Keep the `data` of the command output in the capture buffer and write it
+/// into the tool STDERR.
+///
+fn write_capture_stderr(data: &[u8]) {
+    write_capture(data);
+    write_stderr(data);
+}
+
+///
+/// Write the `data` into the log file and rotate the file if it has grown
+/// over the size limit.
+///
+fn write_log(data: &[u8]) {
+    if let Some(log) = LOG_FILE.lock().unwrap().as_mut() {
+        if log.file.write_all(data).is_ok() {
+            log.size += data.len() as u64;
+        }
+
+        if log.max_size > 0 && log.size >= log.max_size {
+            let _ = fs::rename(&log.path, format!("{}{}", log.path, LOG_ROTATED_SUFFIX));
+            let _ = reopen_log(log);
+        }
+    }
+}
+
+///
+/// Reopen the log file at the same path. The new file is opened before
+/// the old one is closed so no output is lost in between.
+///
+fn reopen_log(log: &mut LogFile) -> io::Result<()> {
+    let file = append_file(&log.path)?;
+    log.size = file.metadata()?.len();
+    log.file = file;
+    Ok(())
+}
+
+///
+/// Reopen the log file if it is open, e.g. after it was moved by `logrotate`.
+///
+fn rotate_log() {
+    if let Some(log) = LOG_FILE.lock().unwrap().as_mut() {
+        if let Err(err) = reopen_log(log) {
+            eprintln!("error: failed to reopen log file '{}': {}", log.path, err);
+        }
+    }
+}
+
+///
+/// Parse the size from `value` which is the number of bytes optionally
+/// followed with one of `K`, `M`, `G`, or `T` suffix and optional `B`,
+/// e.g. `512`, `64K`, or `10MB`.
+///
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value.trim_end_matches(['B', 'b']);
+    let (digits, multiplier) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 1u64 << 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 1u64 << 20),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 1u64 << 30),
+        Some('T') | Some('t') => (&value[..value.len() - 1], 1u64 << 40),
+        _ => (value, 1),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|v| v.checked_mul(multiplier))
+}
+
+///
+/// Parse the list of CPU numbers from `value` which is the comma separated
+/// list of CPUs or inclusive CPU ranges, e.g. `0,1` or `0-3,8`.
+///
+fn parse_cpu_set(value: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for item in value.split(',').map(str::trim) {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (
+                first.trim().parse::<usize>().ok()?,
+                last.trim().parse::<usize>().ok()?,
+            ),
+            None => {
+                let cpu = item.parse::<usize>().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+
+    Some(cpus)
+}
+
+///
+/// Parse the duration from `value` which is the number optionally followed
+/// with one of `ms`, `s`, `m`, or `h` suffix, e.g. `500ms` or `60s`.
+/// The number without suffix is milliseconds like in the `Heartbeat` option.
+///
+fn parse_duration(value: &str) -> Option<time::Duration> {
+    let value = value.trim();
+    let pos = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let num = value[..pos].parse::<u64>().ok()?;
+    match value[pos..].trim() {
+        "" | "ms" => Some(time::Duration::from_millis(num)),
+        "s" => Some(time::Duration::from_secs(num)),
+        "m" => num.checked_mul(60).map(time::Duration::from_secs),
+        "h" => num.checked_mul(3600).map(time::Duration::from_secs),
+        _ => None,
+    }
+}
+
+///
+/// Parse the signal from `value` which is either the signal name with or
+/// without `SIG` prefix, e.g. `SIGHUP` or `HUP`, or the signal number.
+///
+fn parse_signal(value: &str) -> Option<i32> {
+    let value = value.trim().to_uppercase();
+    if let Ok(num) = value.parse::<i32>() {
+        return cast_signal(num).map(|sig| sig as i32);
+    }
+
+    let name = if value.starts_with("SIG") {
+        value
+    } else {
+        format!("SIG{}", value)
+    };
+    Signal::from_str(&name).ok().map(|sig| sig as i32)
+}
+
+///
+/// Resolve the command `name` into the path of the executable file the same way
+/// `which` does. When `name` contains a slash it is checked as-is, otherwise
+/// every directory listed in `PATH` is tried in order.
+///
+fn resolve_command(name: &OsStr) -> Option<PathBuf> {
+    let name = Path::new(name);
+    if name.components().count() > 1 {
+        return if is_executable(name) {
+            Some(name.to_path_buf())
+        } else {
+            None
+        };
+    }
+
+    env::var_os(PATH_VAR).and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| is_executable(path))
+    })
+}
+
+///
+/// Check the binary at `path` does not change privileges of the process
+/// unexpectedly. Issues found are returned, i.e. the SUID or SGID bit is set,
+/// or the SUID binary is not owned by root.
+///
+fn check_binary(path: &Path) -> Vec<String> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut issues = Vec::new();
+    if meta.mode() & MODE_SUID != 0 {
+        issues.push(format!("binary '{}' has the SUID bit set", path.display()));
+        if meta.uid() != ROOT_UID {
+            issues.push(format!(
+                "SUID binary '{}' is owned by uid {}, not root",
+                path.display(),
+                meta.uid()
+            ));
+        }
+    }
+    if meta.mode() & MODE_SGID != 0 {
+        issues.push(format!("binary '{}' has the SGID bit set", path.display()));
+    }
+
+    issues
+}
+
+///
+/// Test the file at `path` exists, is a regular file and can be executed
+/// by the current user.
+///
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => meta.is_file() && access(path, AccessFlags::X_OK).is_ok(),
+        Err(_) => false,
+    }
+}
+
+///
+/// Collect the tool options from the command line and from the configuration file
+/// if it exists.
+/// Options passed within the command line has the biggest priority
+/// and rewrite the similar options from the configuration file.
+///
+fn collect_opts() -> HashMap<String, String> {
+    let mut dict: HashMap<String, String> = HashMap::new();
+
+    // Collect options from command line arguments, values of options which can
+    // be repeated are joined with commas
+    for (name, value) in COMMAND_LINE.opts.iter() {
+        match dict.get_mut(name) {
+            Some(joined) if REPEATED_OPTIONS.contains(&name.as_str()) => {
+                joined.push(',');
+                joined.push_str(value);
+            }
+            _ => {
+                dict.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    // Collect options from the script header which do not override command line options
+    if let Some(header) = SCRIPT_HEADER.as_ref() {
+        for opt in header.opts.iter() {
+            let mut parts = opt[1..].splitn(2, OPTION_DELIMITER);
+            let name = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+            dict.entry(name.to_owned())
+                .or_insert_with(|| value.to_owned());
+        }
+    }
+
+    // Collect options from .env file which do not override command line options
+    for (name, value) in read_dot_env(dict.get(OPT_DOT_ENV)) {
+        dict.entry(name).or_insert(value);
+    }
+
+    // Collect options from the project configuration file and then from configuration file
+    let project = dict
+        .get(OPT_PROJECT_CONF)
+        .and_then(|path| read_project_section(path));
+    let conf =
+        read_config_content(dict.get(OPT_CONF)).and_then(|conf| conf.get(SECTION_WATCH).cloned());
+    let confs: Vec<toml::Value> = project.into_iter().chain(conf).collect();
+    for conf in confs.iter() {
+        if let Some(watch) = conf.as_table() {
+            for entry in watch.into_iter() {
+                if !dict.contains_key(entry.0) {
+                    match entry.1 {
+                        toml::Value::String(v) => dict.insert(entry.0.to_string(), v.to_string()),
+                        toml::Value::Integer(v) => {
+                            dict.insert(entry.0.to_string(), format!("{}", v))
+                        }
+                        toml::Value::Float(v) => dict.insert(entry.0.to_string(), format!("{}", v)),
+                        toml::Value::Boolean(v) => {
+                            dict.insert(entry.0.to_string(), format!("{}", v))
+                        }
+                        toml::Value::Datetime(v) => {
+                            dict.insert(entry.0.to_string(), format!("{}", v))
+                        }
+                        _ => None,
+                    };
+                }
+            }
+        }
+    }
+
+    // Put variables of the environment set before explicit ones so explicit ones win
+    if let Some(name) = dict.get(OPT_ENV_SET).cloned() {
+        match confs.iter().find_map(|conf| read_env_set(conf, &name)) {
+            Some(vars) => {
+                let env = match dict.get(OPT_ENV) {
+                    Some(explicit) if !vars.is_empty() => format!("{},{}", vars, explicit),
+                    Some(explicit) => explicit.clone(),
+                    None => vars,
+                };
+                dict.insert(OPT_ENV.to_owned(), env);
+            }
+            None => eprintln!("warning: environment set '{}' is not configured", name),
+        }
+    }
+
+    dict
+}
+
+///
+/// Read the `envsets.<name>` table of the configuration section `conf` as
+/// the comma separated list of `KEY=VALUE` variables.
+///
+fn read_env_set(conf: &toml::Value, name: &str) -> Option<String> {
+    let set = conf.get(SECTION_ENV_SETS)?.get(name)?.as_table()?;
+    let vars: Vec<String> = set
+        .iter()
+        .filter_map(|(key, value)| match value {
+            toml::Value::String(v) => Some(format!("{}={}", key, v)),
+            toml::Value::Array(_) | toml::Value::Table(_) => None,
+            other => Some(format!("{}={}", key, other)),
+        })
+        .collect();
+    Some(vars.join(","))
+}
+
+///
+/// Read the `[owl]` or `[tool.owl]` section of the project configuration file
+/// at `path`, e.g. `Cargo.toml` or `pyproject.toml`.
+///
+fn read_project_section(path: &str) -> Option<toml::Value> {
+    let conf = match read_file_contents(path) {
+        Some(conf) => conf,
+        None => {
+            eprintln!(
+                "warning: failed to read project configuration file '{}'",
+                path
+            );
+            return None;
+        }
+    };
+    conf.get(SECTION_OWL)
+        .or_else(|| {
+            conf.get(SECTION_TOOL)
+                .and_then(|tool| tool.get(SECTION_OWL))
+        })
+        .cloned()
+}
+
+///
+/// Read options from the .env file. If `explicit_path` is given then that file
+/// is read, otherwise `.env` in the current directory is read if it exists.
+/// Only `OWL_<NAME>=<VALUE>` lines are taken, other variables, comments,
+/// and blank lines are skipped.
+///
+fn read_dot_env(explicit_path: Option<&String>) -> Vec<(String, String)> {
+    let path = explicit_path.map_or(DOT_ENV_LOCATION_CWD, |v| v.as_str());
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            if explicit_path.is_some() {
+                eprintln!("warning: failed to read .env file '{}': {}", path, err);
+            }
+            return Vec::new();
+        }
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix(DOT_ENV_EXPORT).unwrap_or(line);
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next()?.trim();
+            let value = parts.next()?.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            Some((env_option_name(key)?, value.to_owned()))
+        })
+        .collect()
+}
+
+///
+/// Map the variable name `OWL_<NAME>` to the option name, e.g. `OWL_LOG_FILE`
+/// is mapped to `LogFile`. `None` is returned for names without the prefix.
+///
+fn env_option_name(key: &str) -> Option<String> {
+    let name = key.strip_prefix(DOT_ENV_PREFIX)?;
+    let name: String = name
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let part = part.to_lowercase();
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+///
+/// Parse the command line of the tool and stop it if the command line is invalid.
+///
+fn parse_command_line() -> CommandLine {
+    match cli::parse(env::args_os().skip(1)) {
+        Ok(line) => line,
+        Err(err) => std::process::exit(report_error(&OwlError::ConfigParseError(err))),
+    }
+}
+
+///
+/// Take the command and its arguments which follow tool options.
+///
+fn collect_command_args() -> Vec<OsString> {
+    let args = COMMAND_LINE.command.clone();
+
+    // The script is run with the interpreter from its header
+    match SCRIPT_HEADER.as_ref() {
+        Some(header) => header.interpreter.iter().cloned().chain(args).collect(),
+        None => args,
+    }
+}
+
+///
+/// Read the header of the script the tool runs, which is the command. The header is the `# owl:` comment on the line
+/// which follows the shebang, e.g. `# owl: +Name:Awesome_Job +Host:127.0.0.1 bash`.
+/// Words of the comment which are not options make the interpreter the script
+/// is run with. If the shebang runs the tool itself the script is run with
+/// the shell by default. `None` is returned if the script has no header.
+///
+fn read_script_header() -> Option<ScriptHeader> {
+    let path = COMMAND_LINE.command.first()?;
+    let file = fs::File::open(path).ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+
+    let mut lines = BufReader::new(file).lines();
+    let shebang = lines.next()?.ok()?;
+    let shebang = shebang.strip_prefix(SHEBANG)?;
+    let comment = lines.next()?.ok()?;
+    let comment = comment.strip_prefix(SCRIPT_OPTIONS_PREFIX)?;
+
+    let (opts, words): (Vec<&str>, Vec<&str>) = comment
+        .split_whitespace()
+        .partition(|word| word.starts_with(OPTION_START));
+    let mut interpreter: Vec<OsString> = words.into_iter().map(OsString::from).collect();
+    let runs_tool = shebang
+        .split_whitespace()
+        .any(|word| Path::new(word).file_name() == Some(OsStr::new(TOOL_NAME)));
+    if interpreter.is_empty() && runs_tool {
+        interpreter.push(OsString::from(HOOK_SHELL));
+    }
+
+    Some(ScriptHeader {
+        opts: opts.into_iter().map(String::from).collect(),
+        interpreter,
+    })
+}
+
+///
+/// Get the name of the command the child process run.
+///
+fn command_name() -> Option<OsString> {
+    ARGS.first().cloned()
+}
+
+///
+/// Get the command the command is run with from the `CmdPrefix` option
+/// which is the comma separated list of the program and its arguments.
+/// The comma is escaped as `\,` in arguments.
+///
+fn command_prefix() -> Vec<String> {
+    OPT.get(OPT_CMD_PREFIX)
+        .map(|value| split_command_prefix(value))
+        .unwrap_or_default()
+}
+
+///
+/// Split the `value` of the `CmdPrefix` option on commas not escaped with `\`
+/// skipping empty arguments.
+///
+fn split_command_prefix(value: &str) -> Vec<String> {
+    let mut prefix = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(',') => prefix.last_mut().unwrap().push(','),
+                Some(next) => prefix.last_mut().unwrap().extend(['\\', next]),
+                None => prefix.last_mut().unwrap().push('\\'),
+            },
+            ',' => prefix.push(String::new()),
+            _ => prefix.last_mut().unwrap().push(c),
+        }
+    }
+    prefix.retain(|arg| !arg.is_empty());
+    prefix
+}
+
+///
+/// Get the list of command line arguments of the child process.
+///
+fn command_args() -> Vec<OsString> {
+    ARGS.iter().skip(1).cloned().collect()
+}
+
+///
+/// Listen for incoming OS signal from `signals` in the infinite loop.
+/// All signal caught are redirected as-is to the child process.
+///
+fn listen_signals(signals: signal_hook::iterator::Signals) {
+    let (dispatcher, events) = mpsc::channel::<SignalEvent>();
+    thread::spawn(move || dispatch_signals(events));
+    let rotate_signal = OPT.get(OPT_LOG_ROTATE_SIGNAL).and_then(|v| parse_signal(v));
+    let pause_signal = OPT.get(OPT_PAUSE_SIGNAL).and_then(|v| parse_signal(v));
+    let resume_signal = OPT.get(OPT_RESUME_SIGNAL).and_then(|v| parse_signal(v));
+    let dump_signal = dump_signal();
+    let term_propagation = term_propagation().unwrap_or(TermPropagation::Immediate);
+    let sighup_action = on_sighup().unwrap_or(SighupAction::Forward);
+    for s in signals.forever() {
+        // The log rotate signal is consumed by the tool
+        if Some(s) == rotate_signal {
+            rotate_log();
+            continue;
+        }
+
+        // SIGHUP reloads options or restarts the command instead of only being forwarded
+        if s == signal_hook::SIGHUP {
+            match sighup_action {
+                SighupAction::Forward => {}
+                SighupAction::Reload => {
+                    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+                    continue;
+                }
+                SighupAction::Restart => RESTART_REQUESTED.store(true, Ordering::Relaxed),
+                SighupAction::Ignore => continue,
+            }
+        }
+
+        // The pause and resume signals are replaced with SIGSTOP and SIGCONT
+        if Some(s) == pause_signal {
+            if signal_command(Signal::SIGSTOP) {
+                PAUSED.store(true, Ordering::Relaxed);
+            }
+            continue;
+        }
+        if Some(s) == resume_signal {
+            if signal_command(Signal::SIGCONT) {
+                PAUSED.store(false, Ordering::Relaxed);
+            }
+            continue;
+        }
+
+        // The dump signal is consumed by the tool
+        if Some(s) == dump_signal {
+            dump_stats();
+            continue;
+        }
+
+        // The tool only observes the cgroup or named processes so it stops instead of them
+        let observes = OPT.contains_key(OPT_CGROUP_PATH) || OPT.contains_key(OPT_MONITOR_BY_NAME);
+        if observes && STOP_SIGNALS.contains(&s) {
+            SHUTDOWN.store(true, Ordering::Relaxed);
+            continue;
+        }
+
+        // Save the last signal caught
+        LAST_SIGNAL.store(s, Ordering::Relaxed);
+
+        // The command which is not started yet is not started at all
+        if EXEC_PENDING.load(Ordering::Relaxed) && STOP_SIGNALS.contains(&s) {
+            SHUTDOWN.store(true, Ordering::Relaxed);
+            continue;
+        }
+
+        // SIGTERM is held back when the command is stopped by other means
+        if s == signal_hook::SIGTERM {
+            match term_propagation {
+                TermPropagation::Immediate => {}
+                TermPropagation::None => continue,
+                TermPropagation::Delay(delay) => {
+                    let dispatcher = dispatcher.clone();
+                    thread::spawn(move || {
+                        thread::sleep(delay);
+                        let _ = dispatcher.send(SignalEvent {
+                            signal: Signal::SIGTERM,
+                            pids: monitored_pids(),
+                        });
+                    });
+                    continue;
+                }
+            }
+        }
+
+        // Propagate the signal to processes monitored
+        if let Some(signal) = cast_signal(s) {
+            let _ = dispatcher.send(SignalEvent {
+                signal,
+                pids: monitored_pids(),
+            });
+        }
+    }
+}
+
+///
+/// Print the summary of what is monitored, how often, where the state is
+/// delivered to, the configuration file, and options given into STDERR.
+/// Options which hold secrets are masked.
+///
+fn print_banner() {
+    let monitored = if let Some(path) = OPT.get(OPT_CGROUP_PATH) {
+        format!("cgroup {}", path)
+    } else if let Some(name) = OPT.get(OPT_MONITOR_BY_NAME) {
+        format!("processes named {}", name)
+    } else if !PIDS.is_empty() {
+        let pids: Vec<String> = PIDS.iter().map(u32::to_string).collect();
+        format!("processes {}", pids.join(", "))
+    } else {
+        let args: Vec<String> = ARGS
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        args.join(" ")
+    };
+
+    let transport = OPT.get(OPT_TRANSPORT).map_or(TRANSPORT_UDP, String::as_str);
+    let destination = match transport {
+        TRANSPORT_FILE => state_file_opts().0,
+        TRANSPORT_SHM => shm_name(),
+        TRANSPORT_POSIX_MQ => mq_opts().0,
+        TRANSPORT_PUSHGATEWAY => OPT.get(OPT_PUSHGATEWAY_URL).cloned().unwrap_or_default(),
+        _ => remote_address().unwrap_or_default(),
+    };
+    let destination = if opt_enabled(OPT_BANNER_COLOR) {
+        format!("{}{}{}", BANNER_HIGHLIGHT, destination, BANNER_RESET)
+    } else {
+        destination
+    };
+
+    let conf = OPT.get(OPT_CONF).cloned().or_else(|| {
+        [CONF_LOCATION_CWD, CONF_LOCATION_ETC_OWL, CONF_LOCATION_ETC]
+            .iter()
+            .find(|path| Path::new(path).is_file())
+            .map(|path| path.to_string())
+    });
+
+    let options: BTreeMap<&String, &String> = OPT.iter().collect();
+    let options: Vec<String> = options
+        .into_iter()
+        .map(|(name, value)| {
+            if SECRET_OPTION_MARKERS.iter().any(|m| name.contains(m)) {
+                format!("{}:{}", name, MASKED_VALUE)
+            } else {
+                format!("{}:{}", name, value)
+            }
+        })
+        .collect();
+
+    let rows = [
+        ("monitoring", monitored),
+        (
+            "heartbeat",
+            format!("{}ms", DeliveryOpts::from_opts(&OPT).delay.as_millis()),
+        ),
+        ("transport", transport.to_owned()),
+        ("destination", destination),
+        ("config", conf.unwrap_or_else(|| "none".to_owned())),
+        ("options", options.join(" ")),
+    ];
+    let mut banner = format!(
+        "owl {} (pid {})\n",
+        env!("CARGO_PKG_VERSION"),
+        process::id()
+    );
+    for (name, value) in rows.iter() {
+        banner.push_str(&format!("  {:<12} {}\n", name, value));
+    }
+    let _ = io::stderr().write_all(banner.as_bytes());
+}
+
+///
+/// Get the signal which dumps stats from the `DumpSignal` option or SIGUSR1
+/// when only the `DumpFile` option is given. Stats are not dumped otherwise
+/// so SIGUSR1 is propagated to the command.
+///
+fn dump_signal() -> Option<i32> {
+    match OPT.get(OPT_DUMP_SIGNAL) {
+        Some(value) => parse_signal(value),
+        None if OPT.contains_key(OPT_DUMP_FILE) => Some(DEFAULT_DUMP_SIGNAL),
+        None => None,
+    }
+}
+
+///
+/// Write stats of processes monitored, the tool metadata, and options into
+/// the dump file. Options which hold secrets are not written.
+///
+fn dump_stats() {
+    let path = match OPT.get(OPT_DUMP_FILE) {
+        Some(path) => path.clone(),
+        None => format!("{}/owl-{}.json", DEFAULT_DUMP_FILE_DIR, process::id()),
+    };
+
+    let options: BTreeMap<&String, &String> = OPT
+        .iter()
+        .filter(|(name, _)| !SECRET_OPTION_MARKERS.iter().any(|m| name.contains(m)))
+        .collect();
+    let options: Vec<String> = options
+        .iter()
+        .map(|(name, value)| format!("{}:{}", json_string(name), json_string(value)))
+        .collect();
+    let processes: Vec<String> = collect_stats(&monitored_pids())
+        .iter()
+        .map(stats_json)
+        .collect();
+    let json = format!(
+        "{{\"owl_pid\":{},\"child_pid\":{},\"options\":{{{}}},\"processes\":[{}]}}\n",
+        process::id(),
+        CHILD_PID.load(Ordering::Relaxed),
+        options.join(","),
+        processes.join(",")
+    );
+
+    let tmp_path = PathBuf::from(format!(
+        "{}{}.{}",
+        path,
+        STATE_FILE_TMP_SUFFIX,
+        process::id()
+    ));
+    let result = write_file(&tmp_path, DUMP_FILE_MODE, false, json.as_bytes())
+        .and_then(|_| fs::rename(&tmp_path, &path));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        eprintln!("error: failed to write dump file '{}': {}", path, err);
+    }
+}
+
+///
+/// Forward signals of `events` to processes. The signal is sent to every
+/// process of the event in its own thread so processes receive it at once.
+///
+fn dispatch_signals(events: mpsc::Receiver<SignalEvent>) {
+    let group = opt_enabled(OPT_SIGNAL_GROUP);
+    let target = move |pid: u32| {
+        if group {
+            signal_target(pid)
+        } else {
+            Pid::from_raw(pid as i32)
+        }
+    };
+    for event in events {
+        let signal = event.signal;
+        match event.pids.as_slice() {
+            [] => {}
+            [pid] => {
+                let _ = signal::kill(target(*pid), signal);
+            }
+            pids => thread::scope(|scope| {
+                for pid in pids {
+                    scope.spawn(move || signal::kill(target(*pid), signal));
+                }
+            }),
+        }
+    }
+}
+
+///
+/// Send the signal `sig` to every process monitored. The signal is sent to
+/// the process group if the process leads its own group, or to the process
+/// otherwise so the tool itself is not signaled. Returns `true` if the signal
+/// is sent to any process.
+///
+fn signal_command(sig: Signal) -> bool {
+    let mut sent = false;
+    for pid in monitored_pids() {
+        sent |= signal::kill(signal_target(pid), sig).is_ok();
+    }
+
+    sent
+}
+
+///
+/// Get the target of signals sent to the process with `pid` which is the process
+/// group if the process leads its own group, or the process otherwise.
+///
+fn signal_target(pid: u32) -> Pid {
+    let pid = Pid::from_raw(pid as i32);
+    match getpgid(Some(pid)) {
+        Ok(pgrp) if pgrp == pid => Pid::from_raw(-pid.as_raw()),
+        _ => pid,
+    }
+}
+
+///
+/// Terminate the process with `pid` with SIGTERM and, if `grace` is given,
+/// kill it with SIGKILL when it is still alive after the grace period.
+///
+fn terminate_process(pid: u32, grace: Option<time::Duration>) {
+    let target = signal_target(pid);
+    let _ = signal::kill(target, Signal::SIGTERM);
+    if let Some(grace) = grace {
+        thread::spawn(move || {
+            thread::sleep(grace);
+            if signal::kill(Pid::from_raw(pid as i32), None).is_ok() {
+                let _ = signal::kill(target, Signal::SIGKILL);
+            }
+        });
+    }
+}
+
+///
+/// Convert signal from the numeric representation `from` into `Signal` type
+/// if possible.
+///
+fn cast_signal(from: i32) -> Option<Signal> {
+    Signal::from_c_int(from).ok()
+}
+
+///
+/// Deliver process stats periodically in the infinite loop.
+///
+fn deliver_state(ready: mpsc::Sender<Result<(), OwlError>>) {
+    // Read delivery configuration and use defaults on missing options.
+    let mut opts = DeliveryOpts::from_opts(&OPT);
+    let heartbeat_count = OPT
+        .get(OPT_HEARTBEAT_COUNT)
+        .and_then(|v| v.parse::<u64>().ok());
+    if heartbeat_count == Some(0) {
+        return;
+    }
+    let mut heartbeats = 0;
+
+    // The command runs without deliveries when the address is unknown
+    let mut remote_addr = match remote_address() {
+        Ok(addr) => addr,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return;
+        }
+    };
+
+    // Enter the namespace the process stats should be read in
+    if let Some(ns) = OPT.get(OPT_NAMESPACE) {
+        if let Err(err) = enter_stats_namespace(ns) {
+            eprintln!("error: failed to enter {} namespace: {}", ns, err);
+        }
+    }
+
+    // Heartbeats are delivered within active hours only
+    let active_hours = OPT
+        .get(OPT_ACTIVE_HOURS)
+        .and_then(|v| ActiveHours::parse(v).ok());
+
+    // Choose the transport the state is delivered with
+    let transport = OPT
+        .get(OPT_TRANSPORT)
+        .cloned()
+        .unwrap_or_else(|| TRANSPORT_UDP.to_owned());
+    let transports = [
+        TRANSPORT_UDP,
+        TRANSPORT_FILE,
+        TRANSPORT_TCP,
+        TRANSPORT_SHM,
+        TRANSPORT_POSIX_MQ,
+        TRANSPORT_PUSHGATEWAY,
+    ];
+    if !transports.contains(&transport.as_str()) {
+        let err = format!("unsupported transport '{}'", transport);
+        let _ = ready.send(Err(OwlError::ConfigParseError(err)));
+        return;
+    }
+
+    let (state_file, state_file_mode, fsync_state) = state_file_opts();
+
+    let shutdown_grace = OPT.get(OPT_SHUTDOWN_GRACE).and_then(|v| parse_duration(v));
+
+    // Read the CPU time processes are terminated after, the time spent is kept
+    // by the process over the session or the command incarnation
+    let cpu_budget = OPT.get(OPT_CPU_BUDGET).and_then(|v| parse_duration(v));
+    let budget_per_restart = opt_enabled(OPT_CPU_BUDGET_PER_RESTART);
+    let mut cpu_spent: HashMap<u32, u64> = HashMap::new();
+    let mut budget_child = 0;
+
+    let envelope = opt_enabled(OPT_ENVELOPE);
+    let count_lines = opt_enabled(OPT_COUNT_OUTPUT_LINES);
+    let self_metrics = opt_enabled(OPT_SELF_METRICS);
+    let deduplicate = opt_enabled(OPT_DEDUPLICATE_SENDS);
+    let keep_alive_sends = OPT
+        .get(OPT_KEEP_ALIVE_SENDS)
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_KEEP_ALIVE_SENDS);
+    let mut last_hash: Option<u64> = None;
+    let mut deduplicated = 0;
+    let mut self_sample: Option<(time::Instant, u64)> = None;
+    let mut reported_lines = (0, 0);
+    let mut latency_alert = OPT
+        .get(OPT_ALERT_LATENCY)
+        .and_then(|v| parse_duration(v))
+        .map(|limit| {
+            Alert::new(
+                "send_latency_ms",
+                Threshold::Above(limit.as_secs_f64() * 1000.0),
+            )
+        });
+
+    // Start sending notifications periodically when processes are monitored
+    let mut socket: Option<UdpSocket> = None;
+    if transport == TRANSPORT_UDP {
+        match bind_socket(&remote_addr) {
+            Ok(udp) => socket = Some(udp),
+            Err(err) => {
+                let _ = ready.send(Err(OwlError::SocketBindFailed(remote_addr, err)));
+                return;
+            }
+        }
+    }
+    let shared_memory = if transport == TRANSPORT_SHM {
+        match SharedMemory::open(&shm_name()) {
+            Ok(shm) => Some(shm),
+            Err(err) => {
+                let err = format!("failed to open shared memory '{}': {}", shm_name(), err);
+                let _ = ready.send(Err(OwlError::TransportSetupFailed(err)));
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let message_queue = if transport == TRANSPORT_POSIX_MQ {
+        let (name, max_msgs, msg_size) = mq_opts();
+        match MessageQueue::open(&name, max_msgs, msg_size) {
+            Ok(queue) => Some(queue),
+            Err(err) => {
+                let err = format!("failed to open message queue '{}': {}", name, err);
+                let _ = ready.send(Err(OwlError::TransportSetupFailed(err)));
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let gateway = if transport == TRANSPORT_PUSHGATEWAY {
+        match pushgateway() {
+            Ok(gateway) => Some(gateway),
+            Err(err) => {
+                let _ = ready.send(Err(OwlError::TransportSetupFailed(err)));
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let send_buffer: Option<Arc<SendQueue<Frame>>> = if transport == TRANSPORT_TCP {
+        let capacity = OPT
+            .get(OPT_SEND_BUFFER)
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_SEND_BUFFER);
+        let queue = Arc::new(SendQueue::new(capacity));
+        let frames = Arc::clone(&queue);
+        let addr = remote_addr.clone();
+        thread::spawn(move || write_frames(&addr, &frames));
+        Some(queue)
+    } else {
+        None
+    };
+    let track_named_pid = OPT.contains_key(OPT_MONITOR_BY_NAME)
+        && OPT.get(OPT_MONITOR_BY_NAME_PICK).map(String::as_str) != Some(NAME_PICK_ALL);
+    let mut named_pid: Option<u32> = None;
+    let mut monitors: HashMap<u32, Monitor> = HashMap::new();
+    let mut samples: HashMap<u32, Samples> = HashMap::new();
+    let mut last_delivered: Option<(time::Instant, HashMap<u32, ProcessStats>)> = None;
+    // Processes delivered twice at least, the first heartbeat may catch the command
+    // loading right after it is spawned so it is not compared to
+    let mut settled: Vec<u32> = Vec::new();
+    let mut terminated: Vec<u32> = Vec::new();
+    let mut vanished_pids: Vec<u32> = Vec::new();
+    let _ = ready.send(Ok(()));
+
+    loop {
+        // Apply options changed in configuration files since the start
+        if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            let reloaded = collect_opts();
+            opts = DeliveryOpts::from_opts(&reloaded);
+
+            // UDP heartbeats follow the new address, the TCP writer keeps its connection
+            if send_buffer.is_none() {
+                match remote_address_from(&reloaded) {
+                    Ok(addr) if addr != remote_addr => {
+                        remote_addr = addr;
+                        socket = None;
+                    }
+                    Ok(_) => {}
+                    Err(err) => eprintln!("error: {}", err),
+                }
+            }
+            eprintln!("warning: delivery options reloaded");
+        }
+
+        // Processes reported vanished once are not monitored anymore
+        let mut pids = monitored_pids();
+        pids.retain(|pid| !vanished_pids.contains(pid));
+        let pending = pids.is_empty() && EXEC_PENDING.load(Ordering::Relaxed);
+        if pids.is_empty() && !pending {
+            thread::sleep(time::Duration::from_millis(PID_POLL_MILLIS));
+            continue;
+        }
+
+        // Processes which vanished are reported right away except the command
+        // which the tool reaps and reports with the accounting message
+        let command_pid = CHILD_PID.load(Ordering::Relaxed);
+        let mut stats = Vec::with_capacity(pids.len());
+        let mut vanished = Vec::new();
+        for (pid, result) in read_stats(&pids) {
+            match result {
+                Ok(Some(info)) => stats.push(info),
+                Ok(None) if pid != command_pid => vanished.push(pid),
+                Ok(None) => {}
+                Err(err) => eprintln!("warning: failed to read stats of process {}: {}", pid, err),
+            }
+        }
+        monitors.retain(|pid, _| pids.contains(pid));
+        samples.retain(|pid, _| pids.contains(pid));
+        if opts.stats_interval.is_some() {
+            let now = time::Instant::now();
+            for info in stats.iter() {
+                samples.entry(info.pid).or_default().add(info, now);
+            }
+        }
+        terminated.retain(|pid| pids.contains(pid));
+
+        // Terminate processes which exceed the RSS limit
+        if let Some(max_rss) = opts.max_rss {
+            for info in stats.iter() {
+                let exceeded = info.rss_bytes.is_some_and(|rss| rss > max_rss);
+                if exceeded && !terminated.contains(&info.pid) {
+                    eprintln!(
+                        "error: process {} RSS {} bytes exceeds the limit of {} bytes, terminating",
+                        info.pid,
+                        info.rss_bytes.unwrap_or_default(),
+                        max_rss
+                    );
+                    RSS_LIMIT_KILLED.store(true, Ordering::Relaxed);
+                    terminate_process(info.pid, shutdown_grace);
+                    terminated.push(info.pid);
+                }
+            }
+        }
+
+        // Terminate processes once the CPU budget is spent
+        let budget_remaining = cpu_budget.map(|budget| {
+            let child_pid = CHILD_PID.load(Ordering::Relaxed);
+            if budget_per_restart && child_pid != budget_child {
+                cpu_spent.clear();
+                budget_child = child_pid;
+            }
+            for info in stats.iter() {
+                if let Some(cpu_ms) = info.cpu_time_ms {
+                    let spent = cpu_spent.entry(info.pid).or_default();
+                    *spent = (*spent).max(cpu_ms);
+                }
+            }
+            budget.as_secs_f64() - cpu_spent.values().sum::<u64>() as f64 / 1000.0
+        });
+        if let Some(remaining) = budget_remaining.filter(|v| *v <= 0.0) {
+            for info in stats.iter() {
+                if !terminated.contains(&info.pid) {
+                    eprintln!(
+                        "error: CPU budget is exceeded by {:.2} seconds, terminating process {}",
+                        -remaining, info.pid
+                    );
+                    terminate_process(info.pid, shutdown_grace);
+                    terminated.push(info.pid);
+                }
+            }
+        }
+        let mut msgs: Vec<StateMessage> = stats
+            .iter()
+            .map(|info| {
+                let mut msg = state_message(info);
+                monitors
+                    .entry(info.pid)
+                    .or_insert_with(Monitor::new)
+                    .observe(info.pid, info, &mut msg);
+                msg
+            })
+            .collect();
+        if opts.stats_interval.is_some() {
+            for msg in msgs.iter_mut() {
+                if let Some(samples) = samples.get(&msg.pid) {
+                    msg.fields.extend(samples.fields(opts.aggregated));
+                }
+            }
+        }
+        if pending {
+            msgs.push(pending_message());
+        }
+
+        // Report the named process picked is replaced with another one
+        if let (true, Some(info)) = (track_named_pid, stats.first()) {
+            if let Some(prev) = named_pid.filter(|prev| *prev != info.pid) {
+                msgs.push(pid_changed_message(info, prev));
+            }
+            named_pid = Some(info.pid);
+        }
+        for pid in vanished {
+            vanished_pids.push(pid);
+            let msg = pid_vanished_message(pid);
+            for alert in msg.alerts.iter() {
+                eprintln!("alert: {}", alert);
+            }
+            msgs.push(msg);
+        }
+
+        if let Some(remaining) = budget_remaining {
+            for msg in msgs.iter_mut() {
+                msg.fields.push((
+                    "cpu_budget_remaining_secs",
+                    Value::Float(remaining.max(0.0)),
+                ));
+            }
+        }
+
+        // Report how long previous deliveries took
+        if let Some((last_us, avg_us)) = send_latency() {
+            for msg in msgs.iter_mut() {
+                msg.fields.push(("send_latency_us", Value::UInt(last_us)));
+                msg.fields
+                    .push(("avg_send_latency_us", Value::UInt(avg_us)));
+            }
+            let alert = latency_alert
+                .as_mut()
+                .and_then(|alert| alert.check(last_us as f64 / 1000.0));
+            if let Some(alert) = alert {
+                eprintln!("alert: {}", alert);
+                for msg in msgs.iter_mut() {
+                    msg.alerts.push(alert.clone());
+                }
+            }
+        }
+        // Report output lines of the command since the last delivered heartbeat
+        let lines = (
+            STDOUT_LINES.load(Ordering::Relaxed),
+            STDERR_LINES.load(Ordering::Relaxed),
+        );
+        let child_pid = CHILD_PID.load(Ordering::Relaxed);
+        let child_msg = msgs.iter_mut().find(|msg| msg.pid == child_pid);
+        if let Some(msg) = child_msg.filter(|_| count_lines) {
+            msg.fields.push(("stdout_lines", Value::UInt(lines.0)));
+            msg.fields.push((
+                "stdout_lines_delta",
+                Value::UInt(lines.0 - reported_lines.0),
+            ));
+            msg.fields.push(("stderr_lines", Value::UInt(lines.1)));
+            msg.fields.push((
+                "stderr_lines_delta",
+                Value::UInt(lines.1 - reported_lines.1),
+            ));
+        }
+
+        // Report resources the tool itself uses
+        if self_metrics {
+            let metrics = read_self_metrics(&mut self_sample);
+            for msg in msgs.iter_mut() {
+                msg.self_metrics = metrics.clone();
+            }
+        }
+
+        let rtt_us = TCP_RTT_US.load(Ordering::Relaxed);
+        if rtt_us > 0 {
+            for msg in msgs.iter_mut() {
+                msg.fields
+                    .push(("last_rtt_ms", Value::Float(rtt_us as f64 / 1000.0)));
+            }
+        }
+
+        // The tee receives every heartbeat even ones skipped
+        if let Some(tee) = TEE.as_ref().filter(|_| !msgs.is_empty()) {
+            tee.send(&msgs);
+        }
+
+        // Skip the heartbeat when nothing changed since the last delivered one
+        // unless alerts are raised or the silence lasts too long
+        let skip = opts.on_change
+            && msgs.iter().all(|msg| msg.alerts.is_empty())
+            && last_delivered.as_ref().is_some_and(|(at, prev)| {
+                at.elapsed() < opts.max_silence
+                    && prev.len() == stats.len()
+                    && stats.iter().all(|info| {
+                        settled.contains(&info.pid)
+                            && prev.get(&info.pid).is_some_and(|prev| {
+                                !stats_changed(prev, info, opts.change_threshold)
+                            })
+                    })
+            });
+
+        // Outside active hours heartbeats are not delivered while samples keep
+        // accumulating so the first heartbeat in the window has the current state
+        let inactive = active_hours
+            .as_ref()
+            .is_some_and(|hours| !hours.is_active_now());
+
+        // Suppress heartbeats identical to the last one sent except every
+        // `KeepAliveSends` heartbeat which confirms the tool is alive
+        let duplicate = deduplicate && !skip && !inactive && {
+            let hash = messages_hash(&msgs);
+            let duplicate = last_hash == Some(hash) && deduplicated + 1 < keep_alive_sends;
+            last_hash = Some(hash);
+            duplicate
+        };
+        if duplicate {
+            deduplicated += 1;
+        }
+
+        if !skip && !inactive && !duplicate && !msgs.is_empty() {
+            if deduplicated > 0 {
+                for msg in msgs.iter_mut() {
+                    msg.fields
+                        .push(("deduplicated_count", Value::UInt(deduplicated)));
+                }
+                deduplicated = 0;
+            }
+            settled = stats
+                .iter()
+                .map(|info| info.pid)
+                .filter(|pid| {
+                    last_delivered
+                        .as_ref()
+                        .is_some_and(|(_, prev)| prev.contains_key(pid))
+                })
+                .collect();
+            let delivered = stats.iter().map(|info| (info.pid, info.clone())).collect();
+            last_delivered = Some((time::Instant::now(), delivered));
+            reported_lines = lines;
+            for samples in samples.values_mut() {
+                samples.reset();
+            }
+            if envelope {
+                for msg in msgs.iter_mut() {
+                    msg.envelope = Some(Envelope::now());
+                }
+            }
+
+            if transport == TRANSPORT_FILE {
+                if let Err(err) = write_state_file(&state_file, state_file_mode, fsync_state, &msgs)
+                {
+                    eprintln!(
+                        "error: failed to write state file '{}': {}",
+                        state_file, err
+                    );
+                }
+            } else if let Some(shm) = shared_memory.as_ref() {
+                let started = time::Instant::now();
+                match shm.write(&encode_messages(&msgs)) {
+                    Ok(_) => record_send_latency(started.elapsed()),
+                    Err(err) => eprintln!(
+                        "error: failed to write state to shared memory '{}': {}",
+                        shm.name(),
+                        err
+                    ),
+                }
+            } else if let Some(queue) = message_queue.as_ref() {
+                let started = time::Instant::now();
+                match queue.send(&encode_messages(&msgs)) {
+                    Ok(_) => record_send_latency(started.elapsed()),
+                    Err(err) => eprintln!(
+                        "error: failed to send state to message queue '{}': {}",
+                        queue.name(),
+                        err
+                    ),
+                }
+            } else if let Some(gateway) = gateway.as_ref() {
+                let started = time::Instant::now();
+                match gateway.push(&msgs) {
+                    Ok(_) => record_send_latency(started.elapsed()),
+                    Err(err) => eprintln!(
+                        "error: failed to push metrics to {}: {}",
+                        gateway.url(),
+                        err
+                    ),
+                }
+            } else {
+                // Report heartbeats which failed to be delivered before
+                let failures = SEND_FAILURES.load(Ordering::Relaxed);
+                if failures > 0 {
+                    for msg in msgs.iter_mut() {
+                        msg.fields.push(("send_failures", Value::UInt(failures)));
+                    }
+                }
+
+                // The TCP writer reports the result itself
+                if let Some(queue) = send_buffer.as_ref() {
+                    let dropped = SEND_DROPPED.load(Ordering::Relaxed);
+                    if dropped > 0 {
+                        for msg in msgs.iter_mut() {
+                            msg.fields.push(("send_dropped", Value::UInt(dropped)));
+                        }
+                    }
+                    let frame = Frame {
+                        data: encode_messages(&msgs),
+                        failures,
+                        dropped,
+                    };
+                    if queue.push(frame).is_some() {
+                        SEND_DROPPED.fetch_add(1, Ordering::Relaxed);
+                    }
+                } else {
+                    match send_state_with_retry(&mut socket, &remote_addr, &encode_messages(&msgs))
+                    {
+                        Ok(_) => {
+                            SEND_FAILURES.fetch_sub(failures, Ordering::Relaxed);
+                        }
+                        Err(err) => {
+                            SEND_FAILURES.fetch_add(1, Ordering::Relaxed);
+                            eprintln!("error: failed to send state to {}: {}", remote_addr, err);
+                        }
+                    }
+                }
+            }
+
+            // Stop delivering once enough heartbeats are sent, the command keeps running
+            heartbeats += 1;
+            if heartbeat_count == Some(heartbeats) {
+                return;
+            }
+        }
+
+        // Sleep a little before the next delivery sampling stats meanwhile
+        match opts
+            .stats_interval
+            .filter(|interval| *interval < opts.delay)
+        {
+            Some(interval) => {
+                let until = time::Instant::now() + opts.delay;
+                loop {
+                    thread::sleep(
+                        interval.min(until.saturating_duration_since(time::Instant::now())),
+                    );
+                    let now = time::Instant::now();
+                    if now >= until {
+                        break;
+                    }
+                    for info in collect_stats(&pids) {
+                        samples.entry(info.pid).or_default().add(&info, now);
+                    }
+                }
+            }
+            None => thread::sleep(opts.delay),
+        }
+    }
+}
+
+///
+/// Read the resource usage of the tool process the way stats of processes
+/// monitored are read. The CPU usage is computed since the `prev` sample
+/// which is replaced with the current one.
+///
+fn read_self_metrics(prev: &mut Option<(time::Instant, u64)>) -> Vec<(&'static str, Value)> {
+    let stats = match platform::read_process_stats(process::id()) {
+        Some(stats) => stats,
+        None => return Vec::new(),
+    };
+
+    let now = time::Instant::now();
+    let mut metrics = Vec::new();
+    if let (Some((at, prev_ms)), Some(cpu_ms)) = (*prev, stats.cpu_time_ms) {
+        let wall_ms = now.duration_since(at).as_secs_f64() * 1000.0;
+        if wall_ms > 0.0 && cpu_ms >= prev_ms {
+            let cpu_pct = (cpu_ms - prev_ms) as f64 / wall_ms * 100.0;
+            metrics.push(("owl_cpu_pct", Value::Float(cpu_pct)));
+        }
+    }
+    *prev = stats.cpu_time_ms.map(|ms| (now, ms));
+    if let Some(rss) = stats.rss_bytes {
+        metrics.push(("owl_rss_bytes", Value::UInt(rss)));
+    }
+    if let Some(threads) = stats.threads {
+        metrics.push(("owl_threads", Value::UInt(threads as u64)));
+    }
+
+    metrics
+}
+
+///
+/// Read stats of processes with `pids` concurrently. Processes which stats
+/// cannot be read are skipped.
+///
+fn collect_stats(pids: &[u32]) -> Vec<ProcessStats> {
+    read_stats(pids)
+        .into_iter()
+        .filter_map(|(_, stats)| stats.ok().flatten())
+        .collect()
+}
+
+///
+/// Read stats of processes with `pids` concurrently. Stats of the single process
+/// are read in the calling thread so the namespace it entered is kept. Every
+/// pid is paired with its stats, `None` if the process does not exist,
+/// or the error the read failed with.
+///
+fn read_stats(pids: &[u32]) -> Vec<(u32, io::Result<Option<ProcessStats>>)> {
+    let mem_maps = opt_enabled(OPT_MEM_MAPS);
+    let fifo_pattern = if opt_enabled(OPT_WATCH_FIFOS) {
+        Some(
+            OPT.get(OPT_FIFO_PATTERN)
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_FIFO_PATTERN),
+        )
+    } else {
+        None
+    };
+    let read = move |pid: u32| {
+        let mut stats = match platform::try_read_process_stats(pid) {
+            Ok(Some(stats)) => stats,
+            result => return (pid, result),
+        };
+        if mem_maps {
+            platform::read_memory_maps(pid, &mut stats);
+        }
+        if let Some(pattern) = fifo_pattern {
+            stats.monitored_fifos = platform::read_fifo_paths(pid).map(|paths| {
+                paths
+                    .into_iter()
+                    .filter(|path| coredump::wildcard_match(pattern, path))
+                    .collect()
+            });
+        }
+        (pid, Ok(Some(stats)))
+    };
+
+    if pids.len() == 1 {
+        return vec![read(pids[0])];
+    }
+
+    thread::scope(|scope| {
+        let readers: Vec<_> = pids
+            .iter()
+            .map(|pid| scope.spawn(move || read(*pid)))
+            .collect();
+        readers
+            .into_iter()
+            .filter_map(|reader| reader.join().ok())
+            .collect()
+    })
+}
+
+///
+/// Make the state message from the process `stats`.
+///
+fn state_message(stats: &ProcessStats) -> StateMessage {
+    // Get command name from option or from command line
+    // The process run with the prefix is named after the command
+    let cmd_name: String = if let Some(v) = OPT.get(OPT_NAME) {
+        v.clone()
+    } else if OPT.contains_key(OPT_CMD_PREFIX) && stats.pid == CHILD_PID.load(Ordering::Relaxed) {
+        command_name()
+            .map(|name| {
+                let path = Path::new(&name);
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_else(|| stats.command.clone())
+    } else {
+        stats.command.clone()
+    };
+
+    let mut msg = StateMessage::from_stats(process::id(), cmd_name, stats);
+    msg.labels = LABELS.clone();
+    if OPT.contains_key(OPT_PAUSE_SIGNAL) {
+        msg.fields
+            .push(("paused", Value::Bool(PAUSED.load(Ordering::Relaxed))));
+    }
+    if OPT.contains_key(OPT_EXEC_DELAY_START) {
+        msg.fields
+            .push(("status", Value::Text(STATUS_RUNNING.to_owned())));
+    }
+    msg
+}
+
+///
+/// Make the state message of the command which start is delayed.
+///
+fn pending_message() -> StateMessage {
+    StateMessage {
+        owl_pid: process::id(),
+        pid: 0,
+        name: message_name(),
+        state: STATE_PENDING.to_owned(),
+        fields: vec![("status", Value::Text(STATUS_PENDING.to_owned()))],
+        labels: LABELS.clone(),
+        alerts: Vec::new(),
+        self_metrics: Vec::new(),
+        envelope: None,
+    }
+}
+
+///
+/// Make the event message of the process `stats` picked by the name instead
+/// of the process `prev_pid`.
+///
+fn pid_changed_message(stats: &ProcessStats, prev_pid: u32) -> StateMessage {
+    let mut msg = state_message(stats);
+    msg.fields = vec![
+        ("event", Value::Text(EVENT_PID_CHANGED.to_owned())),
+        ("previous_pid", Value::UInt(prev_pid as u64)),
+    ];
+    msg
+}
+
+///
+/// Make the event message of the process `pid` which vanished while it was
+/// monitored, i.e. it exited and was reaped by another process.
+///
+fn pid_vanished_message(pid: u32) -> StateMessage {
+    StateMessage {
+        owl_pid: process::id(),
+        pid,
+        name: message_name(),
+        state: STATE_VANISHED.to_owned(),
+        fields: vec![("event", Value::Text(EVENT_PID_VANISHED.to_owned()))],
+        labels: LABELS.clone(),
+        alerts: vec![format!("process {} vanished", pid)],
+        self_metrics: Vec::new(),
+        envelope: None,
+    }
+}
+
+///
+/// Make the accounting message of the command `pid` which exited with `code`
+/// from its resource `usage`.
+///
+fn accounting_message(pid: u32, code: i32, usage: &ResourceUsage) -> StateMessage {
+    let fields = vec![
+        ("event", Value::Text(EVENT_ACCOUNTING.to_owned())),
+        ("exit_code", Value::UInt(code as u64)),
+        ("ru_utime_ms", Value::UInt(usage.utime_ms)),
+        ("ru_stime_ms", Value::UInt(usage.stime_ms)),
+        ("ru_maxrss_bytes", Value::UInt(usage.maxrss_bytes)),
+        ("ru_minflt", Value::UInt(usage.minflt)),
+        ("ru_majflt", Value::UInt(usage.majflt)),
+        ("ru_inblock", Value::UInt(usage.inblock)),
+        ("ru_oublock", Value::UInt(usage.oublock)),
+        ("ru_nvcsw", Value::UInt(usage.nvcsw)),
+        ("ru_nivcsw", Value::UInt(usage.nivcsw)),
+    ];
+
+    StateMessage {
+        owl_pid: process::id(),
+        pid,
+        name: message_name(),
+        state: STATE_EXITED.to_owned(),
+        fields,
+        labels: LABELS.clone(),
+        alerts: Vec::new(),
+        self_metrics: Vec::new(),
+        envelope: None,
+    }
+}
+
+///
+/// Print the resource `usage` of the command in human readable form.
+///
+fn print_accounting(usage: &ResourceUsage) {
+    eprintln!(
+        "accounting: user {:.3}s, system {:.3}s, max RSS {} bytes, page faults {} minor {} major, \
+         blocks {} in {} out, context switches {} voluntary {} involuntary",
+        usage.utime_ms as f64 / 1000.0,
+        usage.stime_ms as f64 / 1000.0,
+        usage.maxrss_bytes,
+        usage.minflt,
+        usage.majflt,
+        usage.inblock,
+        usage.oublock,
+        usage.nvcsw,
+        usage.nivcsw
+    );
+}
+
+///
+/// Get the message name from the `Name` option or from the command line.
+///
+fn message_name() -> String {
+    match OPT.get(OPT_NAME) {
+        Some(v) => v.clone(),
+        None => command_name()
+            .map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+///
+/// Collect labels attached to every message. The labels are taken from
+/// the Kubernetes downward API when `K8sLabels` is enabled and from the `Label`
+/// option which has form of `key=value,key=value`. Explicit labels win on conflict.
+///
+fn collect_labels() -> Vec<(String, String)> {
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+
+    if opt_enabled(OPT_K8S_LABELS) {
+        for (var, key) in K8S_ENV_LABELS.iter() {
+            if let Ok(value) = env::var(var) {
+                labels.insert((*key).to_owned(), value);
+            }
+        }
+
+        if let Ok(contents) = fs::read_to_string(K8S_PODINFO_LABELS) {
+            for line in contents.lines() {
+                let parts: Vec<&str> = line.splitn(2, '=').collect();
+                if parts.len() == 2 && !parts[0].trim().is_empty() {
+                    let value = parts[1].trim().trim_matches('"').replace("\\\"", "\"");
+                    labels.insert(parts[0].trim().to_owned(), value);
+                }
+            }
+        }
+    }
+
+    if let Some(opt) = OPT.get(OPT_LABEL) {
+        for pair in opt.split(',') {
+            let parts: Vec<&str> = pair.splitn(2, '=').collect();
+            if parts.len() == 2 && !parts[0].trim().is_empty() {
+                labels.insert(parts[0].trim().to_owned(), parts[1].trim().to_owned());
+            }
+        }
+    }
+
+    labels.into_iter().collect()
+}
+
+///
+/// Make the address of the remote listener from `Host` and `Port` options
+/// and use defaults on missing options.
+///
+fn remote_address() -> Result<String, String> {
+    remote_address_from(&OPT)
+}
+
+///
+/// Make the address of the remote listener from `Host` and `Port` options
+/// of `opts` like `remote_address` does.
+///
+fn remote_address_from(opts: &HashMap<String, String>) -> Result<String, String> {
+    let mut remote_host = opts.get(OPT_HOST).unwrap_or(&EMPTY_STR).clone();
+    if remote_host.is_empty() {
+        remote_host = DEFAULT_REMOTE_HOST.to_owned();
+    }
+
+    let mut remote_port = opts.get(OPT_PORT).unwrap_or(&EMPTY_STR).clone();
+    if remote_port.is_empty() {
+        remote_port = DEFAULT_REMOTE_PORT.to_owned();
+    }
+
+    match resolve_port(&remote_port) {
+        Some(port) if remote_host.contains(':') => Ok(format!("[{}]:{}", remote_host, port)),
+        Some(port) => Ok(format!("{}:{}", remote_host, port)),
+        None => Err(format!(
+            "+{} value '{}' is neither a port number nor a known service name",
+            OPT_PORT, remote_port
+        )),
+    }
+}
+
+///
+/// Resolve the `port` which is either the port number or the service name,
+/// e.g. `syslog`, looked up in the services database (`/etc/services`).
+/// Service names are looked up once and cached. The lookup is done under
+/// the lock because `getservbyname` returns the entry in the static buffer.
+///
+fn resolve_port(port: &str) -> Option<u16> {
+    if let Ok(num) = port.parse::<u16>() {
+        return Some(num);
+    }
+
+    let mut ports = SERVICE_PORTS.lock().ok()?;
+    if let Some(resolved) = ports.get(port) {
+        return *resolved;
+    }
+    let name = CString::new(port).ok()?;
+    let proto = CString::new(SERVICE_PROTO).ok()?;
+    let resolved = unsafe {
+        let entry = libc::getservbyname(name.as_ptr(), proto.as_ptr());
+        if entry.is_null() {
+            None
+        } else {
+            Some(u16::from_be((*entry).s_port as u16))
+        }
+    };
+    ports.insert(port.to_owned(), resolved);
+
+    resolved
+}
+
+///
+/// Enter the namespace of the type `ns` located at the `NsPid` option path.
+/// Only the thread which calls the function is moved to the namespace
+/// so the command process is still spawned in the tool namespace.
+///
+#[cfg(target_os = "linux")]
+fn enter_stats_namespace(ns: &str) -> Result<(), String> {
+    use nix::sched::CloneFlags;
+    use std::os::unix::io::AsRawFd;
+
+    let nstype = match ns {
+        NAMESPACE_PID => CloneFlags::CLONE_NEWPID,
+        _ => return Err(format!("unsupported namespace type '{}'", ns)),
+    };
+
+    let path = OPT
+        .get(OPT_NS_PID)
+        .ok_or_else(|| format!("option {} is required", OPT_NS_PID))?;
+    let file = fs::File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    platform::enter_namespace(file.as_raw_fd(), nstype).map_err(|err| err.to_string())
+}
+
+///
+/// Namespaces are supported on Linux only.
+///
+#[cfg(not(target_os = "linux"))]
+fn enter_stats_namespace(_ns: &str) -> Result<(), String> {
+    Err("namespaces are not supported on the platform".to_owned())
+}
+
+///
+/// Get the path, the permissions, and whether to flush the state file from
+/// the `StateFile`, `StateFileMode`, and `FsyncState` options.
+///
+fn state_file_opts() -> (String, u32, bool) {
+    let path = OPT
+        .get(OPT_STATE_FILE)
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_STATE_FILE.to_owned());
+    let mode = OPT
+        .get(OPT_STATE_FILE_MODE)
+        .and_then(|v| u32::from_str_radix(v, 8).ok())
+        .unwrap_or(DEFAULT_STATE_FILE_MODE);
+
+    (path, mode, opt_enabled(OPT_FSYNC_STATE))
+}
+
+///
+/// Deliver the test message with the transport configured. Over TCP
+/// the receiver must acknowledge the message within 2 seconds. UDP has
+/// no acknowledgment so only sending is checked.
+///
+fn test_delivery() -> io::Result<()> {
+    let msgs = [StateMessage {
+        owl_pid: process::id(),
+        pid: 0,
+        name: OPT
+            .get(OPT_NAME)
+            .cloned()
+            .unwrap_or_else(|| TOOL_NAME.to_owned()),
+        state: STATE_TEST.to_owned(),
+        fields: vec![("event", Value::Text(EVENT_TEST.to_owned()))],
+        labels: LABELS.clone(),
+        alerts: Vec::new(),
+        self_metrics: Vec::new(),
+        envelope: None,
+    }];
+
+    let transport = OPT.get(OPT_TRANSPORT).map(String::as_str);
+    if transport == Some(TRANSPORT_FILE) {
+        let (path, mode, fsync) = state_file_opts();
+        return write_state_file(&path, mode, fsync, &msgs);
+    }
+    if transport == Some(TRANSPORT_SHM) {
+        return SharedMemory::open(&shm_name()).and_then(|shm| shm.write(&encode_messages(&msgs)));
+    }
+    if transport == Some(TRANSPORT_POSIX_MQ) {
+        let (name, max_msgs, msg_size) = mq_opts();
+        return MessageQueue::open(&name, max_msgs, msg_size)
+            .and_then(|queue| queue.send(&encode_messages(&msgs)));
+    }
+    if transport == Some(TRANSPORT_PUSHGATEWAY) {
+        let gateway =
+            pushgateway().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        return gateway.push(&msgs);
+    }
+
+    let remote_addr =
+        remote_address().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    if transport == Some(TRANSPORT_TCP) {
+        let stream = connect_stream(&remote_addr)?;
+        stream.set_read_timeout(Some(time::Duration::from_millis(TEST_TIMEOUT_MILLIS)))?;
+        let mut stream = Some(stream);
+        send_frame(&mut stream, &remote_addr, &encode_messages(&msgs))?;
+        let mut stream = stream.ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf)?;
+        let mut ack = vec![0; u32::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut ack)?;
+        if ack != TEST_ACK {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the receiver did not acknowledge the message",
+            ));
+        }
+    } else {
+        let socket = bind_socket(&remote_addr)?;
+        send_state(&socket, &remote_addr, &encode_messages(&msgs))?;
+        eprintln!(
+            "warning: UDP delivery to {} cannot be confirmed",
+            remote_addr
+        );
+    }
+
+    Ok(())
+}
+
+///
+/// Make the push gateway client of the `pushgateway` transport from options.
+///
+fn pushgateway() -> Result<Pushgateway, String> {
+    let url = OPT
+        .get(OPT_PUSHGATEWAY_URL)
+        .ok_or_else(|| format!("+{} is required by the push gateway", OPT_PUSHGATEWAY_URL))?;
+    Pushgateway::new(url, OPT.get(OPT_PUSHGATEWAY_INSTANCE).map(String::as_str))
+}
+
+///
+/// Get the name of the shared memory segment of the `shm` transport
+/// which is `/owl-<name>` where the name is the message name.
+///
+fn shm_name() -> String {
+    format!("{}{}", SHM_NAME_PREFIX, message_name().replace('/', "_"))
+}
+
+///
+/// Get the name, the number of messages, and the message size of the queue
+/// of the `posix_mq` transport from the `MqName`, `MqMaxMsgs`, and `MqMsgSize`
+/// options. The name defaults to the one of the shared memory segment.
+///
+fn mq_opts() -> (String, i64, i64) {
+    let name = OPT.get(OPT_MQ_NAME).cloned().unwrap_or_else(shm_name);
+    let max_msgs = OPT
+        .get(OPT_MQ_MAX_MSGS)
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_MQ_MAX_MSGS);
+    let msg_size = OPT
+        .get(OPT_MQ_MSG_SIZE)
+        .and_then(|v| parse_size(v))
+        .map_or(DEFAULT_MQ_MSG_SIZE, |v| v as i64);
+
+    (name, max_msgs, msg_size)
+}
+
+///
+/// Deliver the accounting message `msg` with the transport configured.
+/// The message is sent once over the new connection or socket because
+/// the tool exits right after.
+///
+fn send_accounting(mut msg: StateMessage) {
+    if *NESTED {
+        return;
+    }
+    if opt_enabled(OPT_ENVELOPE) {
+        msg.envelope = Some(Envelope::now());
+    }
+    let msgs = [msg];
+
+    let result = match OPT.get(OPT_TRANSPORT).map(String::as_str) {
+        Some(TRANSPORT_FILE) => {
+            let (path, mode, fsync) = state_file_opts();
+            write_state_file(&path, mode, fsync, &msgs)
+        }
+        Some(TRANSPORT_SHM) => {
+            SharedMemory::open(&shm_name()).and_then(|shm| shm.write(&encode_messages(&msgs)))
+        }
+        Some(TRANSPORT_POSIX_MQ) => {
+            let (name, max_msgs, msg_size) = mq_opts();
+            MessageQueue::open(&name, max_msgs, msg_size)
+                .and_then(|queue| queue.send(&encode_messages(&msgs)))
+        }
+        Some(TRANSPORT_PUSHGATEWAY) => match pushgateway() {
+            // Metrics of the command which exited are not kept with auto delete
+            Ok(gateway) if opt_enabled(OPT_PUSHGATEWAY_AUTO_DELETE) => gateway.delete(),
+            Ok(gateway) => gateway.push(&msgs),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+        },
+        transport => {
+            let remote_addr = match remote_address() {
+                Ok(addr) => addr,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    return;
+                }
+            };
+            if transport == Some(TRANSPORT_TCP) {
+                send_frame(&mut None, &remote_addr, &encode_messages(&msgs))
+            } else {
+                send_state_with_retry(&mut None, &remote_addr, &encode_messages(&msgs))
+            }
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: failed to deliver accounting: {}", err);
+    }
+    if let Some(tee) = TEE.as_ref() {
+        tee.send(&msgs);
+        tee.finish();
+    }
+}
+
+///
+/// Send the state message to the remote listener and retry with exponential backoff
+/// on failure. The UDP socket is created on the first use and reused then, also
+/// by retries, it is recreated only if it fails to be bound or becomes unusable.
+///
+fn send_state_with_retry(
+    socket: &mut Option<UdpSocket>,
+    remote_addr: &str,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut backoff = SEND_RETRY_BACKOFF_MILLIS;
+    let mut retry = 0;
+    loop {
+        let result = match socket {
+            Some(socket) => send_state(socket, remote_addr, data),
+            None => bind_socket(remote_addr).and_then(|s| {
+                let result = send_state(&s, remote_addr, data);
+                *socket = Some(s);
+                result
+            }),
+        };
+
+        match result {
+            Err(ref err) if retry < SEND_RETRIES => {
+                if socket_unusable(err) {
+                    *socket = None;
+                }
+                thread::sleep(time::Duration::from_millis(backoff));
+                backoff *= 2;
+                retry += 1;
+            }
+            _ => return result,
+        }
+    }
+}
+
+///
+/// Connect to the remote listener over TCP and retry the connection up to
+/// the `ConnectRetry` times waiting `ConnectRetryDelay` between attempts.
+/// `None` is returned if all attempts failed.
+///
+fn connect_with_retry(remote_addr: &str) -> Option<TcpStream> {
+    let retries = OPT
+        .get(OPT_CONNECT_RETRY)
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(0);
+    let delay = OPT
+        .get(OPT_CONNECT_RETRY_DELAY)
+        .and_then(|v| parse_duration(v))
+        .unwrap_or_else(|| time::Duration::from_millis(DEFAULT_CONNECT_RETRY_DELAY_MILLIS));
+
+    for attempt in 1..=retries + 1 {
+        match connect_stream(remote_addr) {
+            Ok(stream) => return Some(stream),
+            Err(err) => eprintln!(
+                "warning: connection attempt {} of {} to {} failed: {}",
+                attempt,
+                retries + 1,
+                remote_addr,
+                err
+            ),
+        }
+        if attempt <= retries && !SHUTDOWN.load(Ordering::Relaxed) {
+            thread::sleep(delay);
+        }
+    }
+
+    None
+}
+
+///
+/// Connect to the remote listener over TCP.
+///
+fn connect_stream(remote_addr: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(remote_addr)?;
+    #[cfg(target_os = "macos")]
+    unsafe {
+        let on: libc::c_int = 1;
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NOSIGPIPE,
+            &on as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+
+    Ok(stream)
+}
+
+///
+/// Write frames of the send buffer `frames` to the remote listener over TCP
+/// in the infinite loop. Counters reported by frames delivered are reset.
+///
+fn write_frames(remote_addr: &str, frames: &SendQueue<Frame>) {
+    let mut stream = connect_with_retry(remote_addr);
+    loop {
+        let frame = frames.pop();
+        let started = time::Instant::now();
+        match send_frame(&mut stream, remote_addr, &frame.data) {
+            Ok(_) => {
+                record_send_latency(started.elapsed());
+                let rtt = stream
+                    .as_ref()
+                    .and_then(|s| platform::read_tcp_rtt(s.as_raw_fd()));
+                if let Some(rtt) = rtt {
+                    TCP_RTT_US.store(rtt.as_micros() as u64, Ordering::Relaxed);
+                }
+
+                // Frames buffered together report the same counters
+                let reset = |counter: &AtomicU64, reported: u64| {
+                    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                        Some(v.saturating_sub(reported))
+                    });
+                };
+                reset(&SEND_FAILURES, frame.failures);
+                reset(&SEND_DROPPED, frame.dropped);
+            }
+            Err(err) => {
+                SEND_FAILURES.fetch_add(1, Ordering::Relaxed);
+                eprintln!("error: failed to send state to {}: {}", remote_addr, err);
+            }
+        }
+    }
+}
+
+///
+/// Send the encoded `payload` to the remote listener over TCP framed with 4-byte
+/// big-endian length. The connection is made on the first use and reused then.
+/// If the connection is broken it is made again once.
+///
+fn send_frame(stream: &mut Option<TcpStream>, remote_addr: &str, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    let mut reconnect = stream.is_some();
+    loop {
+        let result = match stream {
+            Some(s) => send_all(s, &frame),
+            None => connect_stream(remote_addr).and_then(|s| {
+                let result = send_all(&s, &frame);
+                *stream = Some(s);
+                result
+            }),
+        };
+
+        match result {
+            Err(_) if reconnect => {
+                *stream = None;
+                reconnect = false;
+            }
+            Err(err) => {
+                *stream = None;
+                return Err(err);
+            }
+            Ok(_) => return Ok(()),
+        }
+    }
+}
+
+///
+/// Write all `data` into the `stream`. Broken connections do not raise SIGPIPE
+/// so it is not forwarded to the command.
+///
+fn send_all(stream: &TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let sent = unsafe {
+            libc::send(
+                stream.as_raw_fd(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+                SEND_FLAGS,
+            )
+        };
+        if sent < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        } else {
+            data = &data[sent as usize..];
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Test the socket which failed to send with `err` cannot be used anymore
+/// and should be created again. Other errors, e.g. the unreachable network,
+/// are transient and the socket is kept.
+///
+fn socket_unusable(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EBADF) | Some(libc::ENOTSOCK) | Some(libc::EAFNOSUPPORT)
+    )
+}
+
+///
+/// Make UDP socket with OS assigned port of the same address family
+/// as `remote_addr` has.
+///
+fn bind_socket(remote_addr: &str) -> io::Result<UdpSocket> {
+    let local_addr = if remote_addr.starts_with('[') {
+        SocketAddr::from(([0u16; 8], 0))
+    } else {
+        SocketAddr::from(([0, 0, 0, 0], 0))
+    };
+    UdpSocket::bind(local_addr)
+}
+
+///
+/// Send encoded state messages to the remote listener over UDP `socket` in one datagram.
+///
+fn send_state(socket: &UdpSocket, remote_addr: &str, data: &[u8]) -> io::Result<()> {
+    let started = time::Instant::now();
+    socket.send_to(data, remote_addr)?;
+    record_send_latency(started.elapsed());
+
+    Ok(())
+}
+
+///
+/// Keep the `latency` of the send among the latest ones.
+///
+fn record_send_latency(latency: time::Duration) {
+    if let Ok(mut latencies) = SEND_LATENCIES.lock() {
+        latencies.push_back(latency.as_micros() as u64);
+        while latencies.len() > SEND_LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+    }
+}
+
+///
+/// Get the latency of the last send and the average latency of the latest sends
+/// in microseconds. `None` is returned if nothing is sent yet.
+///
+fn send_latency() -> Option<(u64, u64)> {
+    let latencies = SEND_LATENCIES.lock().ok()?;
+    let last = *latencies.back()?;
+    let avg = latencies.iter().sum::<u64>() / latencies.len() as u64;
+    Some((last, avg))
+}
+
+///
+/// Hash state messages as they are encoded in SSDPD without the envelope,
+/// resources of the tool, and fields which change on every send.
+///
+fn messages_hash(msgs: &[StateMessage]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for msg in msgs.iter() {
+        let mut msg = msg.clone();
+        msg.envelope = None;
+        msg.self_metrics.clear();
+        msg.fields
+            .retain(|(name, _)| !DEDUPLICATE_IGNORED_FIELDS.contains(name));
+        msg.to_ssdpd().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+///
+/// Encode state messages with the format from the `Format` option. The single
+/// message is encoded as-is while the batch of messages is encoded as lines
+/// of SSDPD, the JSON array, or one Zabbix request with item keys suffixed
+/// with the process id, e.g. `owl.state.1234[rss_bytes]`.
+///
+fn encode_messages(msgs: &[StateMessage]) -> Vec<u8> {
+    match OPT.get(OPT_FORMAT).map(String::as_str) {
+        Some(FORMAT_JSON) if msgs.len() == 1 => msgs[0].to_json().into_bytes(),
+        Some(FORMAT_JSON) => {
+            let items: Vec<String> = msgs.iter().map(StateMessage::to_json).collect();
+            format!("[{}]", items.join(",")).into_bytes()
+        }
+        Some(FORMAT_ZABBIX) => {
+            let host = OPT
+                .get(OPT_ZABBIX_HOST)
+                .cloned()
+                .or_else(|| {
+                    let mut buf = [0u8; 256];
+                    gethostname(&mut buf)
+                        .ok()
+                        .and_then(|name| name.to_str().ok().map(String::from))
+                })
+                .unwrap_or_default();
+            let key = OPT
+                .get(OPT_ZABBIX_KEY)
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_ZABBIX_KEY);
+            if msgs.len() == 1 {
+                message::zabbix_frame(&msgs[0].zabbix_items(&host, key))
+            } else {
+                let items: Vec<String> = msgs
+                    .iter()
+                    .flat_map(|msg| msg.zabbix_items(&host, &format!("{}.{}", key, msg.pid)))
+                    .collect();
+                message::zabbix_frame(&items)
+            }
+        }
+        _ => {
+            let lines: Vec<String> = msgs.iter().map(StateMessage::to_ssdpd).collect();
+            lines.join("\n").into_bytes()
+        }
+    }
+}
+
+///
+/// Write state messages as JSON into the file at `path`. The single message is
+/// written as the object and the batch is written as the array. Messages are written
+/// into the temporary file `<path>.tmp.<pid>` first which then is renamed to `path`
+/// so readers never see partially written file. With `fsync` the data is flushed
+/// to the storage before the rename and the directory is flushed after it.
+///
+/// When `path` is the mount point itself, e.g. the file bind-mounted into
+/// the container, it cannot be replaced and it is overwritten in place.
+///
+fn write_state_file(path: &str, mode: u32, fsync: bool, msgs: &[StateMessage]) -> io::Result<()> {
+    let mut json = if msgs.len() == 1 {
+        msgs[0].to_json()
+    } else {
+        let items: Vec<String> = msgs.iter().map(StateMessage::to_json).collect();
+        format!("[{}]", items.join(","))
+    };
+    json.push('\n');
+
+    let dest = Path::new(path);
+    let dir = match dest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !same_filesystem(dir, dest) {
+        return write_file(dest, mode, fsync, json.as_bytes());
+    }
+
+    let tmp_path = PathBuf::from(format!(
+        "{}{}.{}",
+        path,
+        STATE_FILE_TMP_SUFFIX,
+        process::id()
+    ));
+    if let Err(err) = write_file(&tmp_path, mode, fsync, json.as_bytes()) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    fs::rename(&tmp_path, dest)?;
+    if fsync {
+        fs::File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+///
+/// Write `data` into the file at `path` created with `mode` and flush it
+/// to the storage with `fsync`.
+///
+fn write_file(path: &Path, mode: u32, fsync: bool, data: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    file.write_all(data)?;
+    if fsync {
+        file.sync_all()?;
+    }
+
+    Ok(())
+}
+
+///
+/// Test the file at `path` resides on the same filesystem as the directory `dir`.
+/// The file which does not exist yet is created on the filesystem of `dir`.
+///
+fn same_filesystem(dir: &Path, path: &Path) -> bool {
+    match (fs::metadata(dir), fs::metadata(path)) {
+        (Ok(dir), Ok(file)) => dir.dev() == file.dev(),
+        _ => true,
+    }
+}
+
+///
+/// Read content of the file with `path` given. If the read is successful
+/// the content is treated as toml and parsed. On any error, from reading to parsing,
+/// `None` is returned.
+///
+fn read_file_contents<S: Into<String>>(path: S) -> Option<toml::Value> {
+    match fs::File::open(path.into()) {
+        Ok(mut file) => {
+            let mut contents = String::new();
+            if file.read_to_string(&mut contents).is_ok() {
+                contents.parse::<toml::Value>().ok()
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+///
+/// Test the configuration file with `path` given explicitly is valid toml.
+/// The file which cannot be read is skipped as it is when options are collected.
+///
+fn check_config_file(path: &str) -> Result<(), String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .parse::<toml::Value>()
+            .map(|_| ())
+            .map_err(|err| format!("failed to parse configuration file '{}': {}", path, err)),
+        Err(_) => Ok(()),
+    }
+}
+
+///
+/// Search for the configuration file and read it.
+/// If `explicit_path` is given then only that file is tried to be read.
+/// Otherwise the configuration file is searched in known locations.
+///
+fn read_config_content(explicit_path: Option<&String>) -> Option<toml::Value> {
+    if let Some(path) = explicit_path {
+        read_file_contents(path)
+    } else {
+        read_file_contents(CONF_LOCATION_CWD)
+            .or_else(|| read_file_contents(CONF_LOCATION_ETC_OWL))
+            .or_else(|| read_file_contents(CONF_LOCATION_ETC))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_is_number_or_service_name() {
+        assert_eq!(resolve_port("39576"), Some(39576));
+        assert_eq!(resolve_port("ntp"), Some(123));
+        assert_eq!(resolve_port("owl-no-such-service"), None);
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(
+            parse_duration("250"),
+            Some(time::Duration::from_millis(250))
+        );
+        assert_eq!(
+            parse_duration("500ms"),
+            Some(time::Duration::from_millis(500))
+        );
+        assert_eq!(parse_duration(" 60s "), Some(time::Duration::from_secs(60)));
+        assert_eq!(parse_duration("5m"), Some(time::Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(time::Duration::from_secs(7200)));
+        assert_eq!(parse_duration("abc"), None);
+        assert_eq!(parse_duration("10d"), None);
+        assert_eq!(parse_duration("-5s"), None);
+    }
+
+    #[test]
+    fn dot_env_options_are_read() {
+        let path = env::temp_dir().join(format!("owl-test-{}.env", process::id()));
+        fs::write(
+            &path,
+            "# deploy\nOWL_PORT=12345\nexport OWL_LOG_FILE=\"/tmp/job.log\"\nPATH=/bin\n\n",
+        )
+        .unwrap();
+        let opts = read_dot_env(Some(&path.to_string_lossy().into_owned()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            opts,
+            [
+                ("Port".to_owned(), "12345".to_owned()),
+                ("LogFile".to_owned(), "/tmp/job.log".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn project_sections_are_read() {
+        let dir = env::temp_dir().join(format!("owl-test-{}-project", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cargo = dir.join("Cargo.toml");
+        fs::write(
+            &cargo,
+            "[package]\nname = \"job\"\n\n[owl]\nHost = \"10.0.0.1\"\nPort = 9000\n",
+        )
+        .unwrap();
+        let pyproject = dir.join("pyproject.toml");
+        fs::write(&pyproject, "[tool.owl]\nName = \"job\"\n").unwrap();
+        let unrelated = dir.join("other.toml");
+        fs::write(&unrelated, "[watch]\nName = \"job\"\n").unwrap();
+
+        let section = read_project_section(cargo.to_str().unwrap()).unwrap();
+        assert_eq!(
+            section.get("Host").and_then(|v| v.as_str()),
+            Some("10.0.0.1")
+        );
+        assert_eq!(section.get("Port").and_then(|v| v.as_integer()), Some(9000));
+        let section = read_project_section(pyproject.to_str().unwrap()).unwrap();
+        assert_eq!(section.get("Name").and_then(|v| v.as_str()), Some("job"));
+        assert!(read_project_section(unrelated.to_str().unwrap()).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stdin_command_output_is_captured() {
+        assert_eq!(run_stdin_command("echo hello", None).unwrap(), b"hello\n");
+        assert_eq!(
+            run_stdin_command("echo oops >&2; exit 3", None).unwrap_err(),
+            "exited with code 3"
+        );
+
+        let started = time::Instant::now();
+        let timeout = time::Duration::from_millis(100);
+        let err = run_stdin_command("sleep 5", Some(timeout)).unwrap_err();
+        assert!(err.starts_with("timed out"), "{}", err);
+        assert!(started.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn failed_send_is_retried_with_backoff() {
+        // Sending to the broadcast address without SO_BROADCAST is always refused
+        let mut socket = None;
+        let started = time::Instant::now();
+        let result = send_state_with_retry(&mut socket, "255.255.255.255:9", b"owl");
+        assert!(result.is_err());
+        assert!(started.elapsed() >= time::Duration::from_millis(100 + 200 + 400));
+        assert!(socket.is_some(), "the socket is usable and must be kept");
+    }
+
+    #[test]
+    fn only_broken_sockets_are_unusable() {
+        assert!(socket_unusable(&io::Error::from_raw_os_error(libc::EBADF)));
+        assert!(socket_unusable(&io::Error::from_raw_os_error(
+            libc::ENOTSOCK
+        )));
+        assert!(!socket_unusable(&io::Error::from_raw_os_error(
+            libc::EACCES
+        )));
+        assert!(!socket_unusable(&io::Error::from_raw_os_error(
+            libc::ECONNREFUSED
+        )));
+    }
+
+    #[test]
+    fn command_prefix_is_split_on_unescaped_commas() {
+        assert_eq!(
+            split_command_prefix("strace,-o,/tmp/trace.log"),
+            ["strace", "-o", "/tmp/trace.log"]
+        );
+        assert_eq!(
+            split_command_prefix(r"sh,-c,echo a\,b,,x\y\"),
+            ["sh", "-c", "echo a,b", r"x\y\"]
+        );
+        assert!(split_command_prefix("").is_empty());
+    }
+
+    #[test]
+    fn signals_are_parsed_by_name_and_number() {
+        assert_eq!(parse_signal("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal(" hup "), Some(libc::SIGHUP));
+        assert_eq!(parse_signal("10"), Some(libc::SIGUSR1));
+        assert_eq!(parse_signal("SIGNOPE"), None);
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("1000"), None);
+    }
+
+    #[test]
+    fn cpu_sets_are_parsed() {
+        assert_eq!(parse_cpu_set("0,1,2"), Some(vec![0, 1, 2]));
+        assert_eq!(parse_cpu_set("0-2, 5"), Some(vec![0, 1, 2, 5]));
+        assert_eq!(parse_cpu_set("3-1"), None);
+        assert_eq!(parse_cpu_set("0,,1"), None);
+        assert_eq!(parse_cpu_set("one"), None);
+    }
+
+    #[test]
+    fn send_latency_is_not_hashed() {
+        let msg = |rss: u64, latency: u64| StateMessage {
+            owl_pid: 1,
+            pid: 2,
+            name: "job".to_owned(),
+            state: "S".to_owned(),
+            fields: vec![
+                ("rss_bytes", Value::UInt(rss)),
+                ("send_latency_us", Value::UInt(latency)),
+            ],
+            labels: Vec::new(),
+            alerts: Vec::new(),
+            self_metrics: Vec::new(),
+            envelope: None,
+        };
+        assert_eq!(
+            messages_hash(&[msg(4096, 30)]),
+            messages_hash(&[msg(4096, 50)])
+        );
+        assert_ne!(
+            messages_hash(&[msg(4096, 30)]),
+            messages_hash(&[msg(8192, 30)])
+        );
     }
 }
@@ -0,0 +1,497 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The state message delivered to the remote listener and its encodings.
+//!
+
+use crate::platform::{FdTypeCounts, ProcessStats};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use crate::versions::MESSAGE_VERSION as VERSION;
+
+///
+/// The value of the message field.
+///
+#[derive(Clone, Debug)]
+pub enum Value {
+    Bool(bool),
+    UInt(u64),
+    Float(f64),
+    Text(String),
+    Floats(Vec<f32>),
+    Texts(Vec<String>),
+}
+
+impl Value {
+    ///
+    /// Encode the value as JSON.
+    ///
+    fn to_json(&self) -> String {
+        match self {
+            Value::Bool(v) => format!("{}", v),
+            Value::UInt(v) => format!("{}", v),
+            Value::Float(v) if v.is_finite() => format!("{:.2}", v),
+            Value::Float(_) => "null".to_owned(),
+            Value::Text(v) => json_string(v),
+            Value::Floats(v) => format!("[{}]", compact_floats(v)),
+            Value::Texts(v) => {
+                let texts: Vec<String> = v.iter().map(|v| json_string(v)).collect();
+                format!("[{}]", texts.join(","))
+            }
+        }
+    }
+
+    ///
+    /// Encode the value as plain text.
+    ///
+    fn to_plain(&self) -> String {
+        match self {
+            Value::Bool(v) => format!("{}", v),
+            Value::UInt(v) => format!("{}", v),
+            Value::Float(v) => format!("{:.2}", v),
+            Value::Text(v) => v.replace('\n', "\\n").replace('\r', "\\r"),
+            Value::Floats(v) => compact_floats(v),
+            Value::Texts(v) => v.join(","),
+        }
+    }
+}
+
+///
+/// The state of the command process delivered on every heartbeat.
+///
+#[derive(Clone, Debug)]
+pub struct StateMessage {
+    /// The id of the owl watcher process.
+    pub owl_pid: u32,
+
+    /// The id of the command process.
+    pub pid: u32,
+
+    /// The name of the command or the name from the `Name` option.
+    pub name: String,
+
+    /// The state of the command process.
+    pub state: String,
+
+    /// The optional fields in the order they are encoded.
+    pub fields: Vec<(&'static str, Value)>,
+
+    /// The labels attached to the message.
+    pub labels: Vec<(String, String)>,
+
+    /// The alerts raised since the previous message.
+    pub alerts: Vec<String>,
+
+    /// The resource usage of the owl watcher process.
+    pub self_metrics: Vec<(&'static str, Value)>,
+
+    /// The delivery metadata of the message.
+    pub envelope: Option<Envelope>,
+}
+
+///
+/// The delivery metadata which relays and receivers use to trace the message.
+///
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    /// The time the message is sent at in milliseconds since the UNIX epoch.
+    pub sent_ts_ms: u64,
+
+    /// The relays the message passed, empty on the first hop.
+    pub relay_chain: Vec<String>,
+}
+
+impl Envelope {
+    ///
+    /// Make the envelope of the message sent now.
+    ///
+    pub fn now() -> Envelope {
+        let sent_ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Envelope {
+            sent_ts_ms,
+            relay_chain: Vec::new(),
+        }
+    }
+}
+
+impl StateMessage {
+    ///
+    /// Make the message from `stats` of the process named `name`.
+    /// Metrics the platform does not provide are not included.
+    ///
+    pub fn from_stats(owl_pid: u32, name: String, stats: &ProcessStats) -> StateMessage {
+        let metrics = [
+            ("pgrp", stats.pgrp.map(u64::from)),
+            ("session", stats.session.map(u64::from)),
+            ("cpu_time_ms", stats.cpu_time_ms),
+            ("rss_bytes", stats.rss_bytes),
+            ("threads", stats.threads.map(u64::from)),
+            ("io_read_bytes", stats.io_read_bytes),
+            ("io_write_bytes", stats.io_write_bytes),
+            ("voluntary_ctxt_switches", stats.voluntary_ctxt_switches),
+            (
+                "nonvoluntary_ctxt_switches",
+                stats.nonvoluntary_ctxt_switches,
+            ),
+            ("zombie_children", stats.zombie_children),
+            ("vm_peak_bytes", stats.vm_peak_bytes),
+            ("rss_hwm_bytes", stats.rss_hwm_bytes),
+            ("anon_maps_count", stats.anon_maps_count),
+            ("anon_maps_bytes", stats.anon_maps_bytes),
+            ("file_maps_count", stats.file_maps_count),
+        ];
+        let fd_types = fd_type_metrics(stats);
+
+        let mut fields: Vec<(&'static str, Value)> = metrics
+            .iter()
+            .chain(fd_types.iter())
+            .filter_map(|(name, value)| value.map(|v| (*name, Value::UInt(v))))
+            .collect();
+        if let Some(truncated) = stats.maps_truncated {
+            fields.push(("maps_truncated", Value::Bool(truncated)));
+        }
+        if let Some(fifos) = stats.monitored_fifos.as_ref() {
+            fields.push(("monitored_fifos", Value::Texts(fifos.clone())));
+        }
+        if let Some(cwd) = stats.cwd.as_ref() {
+            fields.push(("cwd", Value::Text(cwd.clone())));
+        }
+
+        StateMessage {
+            owl_pid,
+            pid: stats.pid,
+            name,
+            state: stats.state.clone(),
+            fields,
+            labels: Vec::new(),
+            alerts: Vec::new(),
+            self_metrics: Vec::new(),
+            envelope: None,
+        }
+    }
+
+    ///
+    /// Encode the message with _SSDPD_ (_Simply Stupid Double Pipe Delimited_)
+    /// where the optional fields follow the mandatory ones as `name=value`.
+    ///
+    pub fn to_ssdpd(&self) -> String {
+        let mut msg = format!(
+            "{}||{}||{}||{}||version={}",
+            self.owl_pid, self.pid, self.name, self.state, VERSION
+        );
+        for (name, value) in self.fields.iter() {
+            let _ = write!(msg, "||{}={}", name, value.to_plain());
+        }
+        for (key, value) in self.labels.iter() {
+            let _ = write!(msg, "||label.{}={}", key, value);
+        }
+        for alert in self.alerts.iter() {
+            let _ = write!(msg, "||alert={}", alert);
+        }
+        for (name, value) in self.self_metrics.iter() {
+            let _ = write!(msg, "||{}={}", name, value.to_plain());
+        }
+        if let Some(envelope) = self.envelope.as_ref() {
+            let _ = write!(msg, "||envelope.sent_ts_ms={}", envelope.sent_ts_ms);
+            for relay in envelope.relay_chain.iter() {
+                let _ = write!(msg, "||envelope.relay={}", relay);
+            }
+        }
+
+        msg
+    }
+
+    ///
+    /// Encode the message as the JSON object.
+    ///
+    pub fn to_json(&self) -> String {
+        let mut msg = format!(
+            "{{\"version\":{},\"owl_pid\":{},\"pid\":{},\"name\":{},\"state\":{}",
+            VERSION,
+            self.owl_pid,
+            self.pid,
+            json_string(&self.name),
+            json_string(&self.state)
+        );
+        for (name, value) in self.fields.iter() {
+            let _ = write!(msg, ",{}:{}", json_string(name), value.to_json());
+        }
+        if !self.labels.is_empty() {
+            let labels: Vec<String> = self
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+                .collect();
+            let _ = write!(msg, ",\"labels\":{{{}}}", labels.join(","));
+        }
+        if !self.alerts.is_empty() {
+            let alerts: Vec<String> = self.alerts.iter().map(|v| json_string(v)).collect();
+            let _ = write!(msg, ",\"alerts\":[{}]", alerts.join(","));
+        }
+        if !self.self_metrics.is_empty() {
+            let metrics: Vec<String> = self
+                .self_metrics
+                .iter()
+                .map(|(name, value)| format!("{}:{}", json_string(name), value.to_json()))
+                .collect();
+            let _ = write!(msg, ",\"self_metrics\":{{{}}}", metrics.join(","));
+        }
+        if let Some(envelope) = self.envelope.as_ref() {
+            let relays: Vec<String> = envelope
+                .relay_chain
+                .iter()
+                .map(|v| json_string(v))
+                .collect();
+            let _ = write!(
+                msg,
+                ",\"envelope\":{{\"sent_ts_ms\":{},\"relay_chain\":[{}]}}",
+                envelope.sent_ts_ms,
+                relays.join(",")
+            );
+        }
+        msg.push('}');
+
+        msg
+    }
+
+    ///
+    /// Make Zabbix sender data items of the message. The state is sent
+    /// as the item `key` of the Zabbix `host` and every numeric field is sent
+    /// as the item `key[field]`.
+    ///
+    pub fn zabbix_items(&self, host: &str, key: &str) -> Vec<String> {
+        let mut items = vec![zabbix_item(host, key, &self.state)];
+        for (name, value) in self.fields.iter() {
+            match value {
+                Value::UInt(_) | Value::Float(_) => items.push(zabbix_item(
+                    host,
+                    &format!("{}[{}]", key, name),
+                    &value.to_plain(),
+                )),
+                Value::Bool(_) | Value::Text(_) | Value::Floats(_) | Value::Texts(_) => {}
+            }
+        }
+
+        items
+    }
+}
+
+///
+/// Encode `values` as the comma separated list rounded to hundredths
+/// without trailing zeros.
+///
+fn compact_floats(values: &[f32]) -> String {
+    let values: Vec<String> = values
+        .iter()
+        .map(|v| {
+            if v.is_finite() {
+                format!("{}", (v * 100.0).round() / 100.0)
+            } else {
+                "null".to_owned()
+            }
+        })
+        .collect();
+    values.join(",")
+}
+
+///
+/// Encode all `stats` of the process as the JSON object. Metrics the platform
+/// does not provide are `null`.
+///
+pub fn stats_json(stats: &ProcessStats) -> String {
+    let metrics = [
+        ("pgrp", stats.pgrp.map(u64::from)),
+        ("session", stats.session.map(u64::from)),
+        ("cpu_time_ms", stats.cpu_time_ms),
+        ("rss_bytes", stats.rss_bytes),
+        ("threads", stats.threads.map(u64::from)),
+        ("io_read_bytes", stats.io_read_bytes),
+        ("io_write_bytes", stats.io_write_bytes),
+        ("voluntary_ctxt_switches", stats.voluntary_ctxt_switches),
+        (
+            "nonvoluntary_ctxt_switches",
+            stats.nonvoluntary_ctxt_switches,
+        ),
+        ("zombie_children", stats.zombie_children),
+        ("vm_peak_bytes", stats.vm_peak_bytes),
+        ("rss_hwm_bytes", stats.rss_hwm_bytes),
+        ("anon_maps_count", stats.anon_maps_count),
+        ("anon_maps_bytes", stats.anon_maps_bytes),
+        ("file_maps_count", stats.file_maps_count),
+    ];
+
+    let mut json = format!(
+        "{{\"pid\":{},\"command\":{},\"state\":{}",
+        stats.pid,
+        json_string(&stats.command),
+        json_string(&stats.state)
+    );
+    for (name, value) in metrics.iter().chain(fd_type_metrics(stats).iter()) {
+        let _ = match value {
+            Some(v) => write!(json, ",{}:{}", json_string(name), v),
+            None => write!(json, ",{}:null", json_string(name)),
+        };
+    }
+    if let Some(truncated) = stats.maps_truncated {
+        let _ = write!(json, ",\"maps_truncated\":{}", truncated);
+    }
+    if let Some(fifos) = stats.monitored_fifos.as_ref() {
+        let fifos: Vec<String> = fifos.iter().map(|v| json_string(v)).collect();
+        let _ = write!(json, ",\"monitored_fifos\":[{}]", fifos.join(","));
+    }
+    if let Some(cwd) = stats.cwd.as_ref() {
+        let _ = write!(json, ",\"cwd\":{}", json_string(cwd));
+    }
+    json.push('}');
+
+    json
+}
+
+///
+/// Get the number of open file descriptors of the process in total and by type.
+///
+fn fd_type_metrics(stats: &ProcessStats) -> [(&'static str, Option<u64>); 9] {
+    let fd = stats.fd_types.as_ref();
+    let count = |f: fn(&FdTypeCounts) -> u32| fd.map(|fd| u64::from(f(fd)));
+    [
+        ("fd_count", count(FdTypeCounts::total)),
+        ("fd_sockets", count(|fd| fd.socket)),
+        ("fd_pipes", count(|fd| fd.pipe)),
+        ("fd_anon_inodes", count(|fd| fd.anon_inode)),
+        ("fd_regular", count(|fd| fd.regular)),
+        ("fd_directories", count(|fd| fd.directory)),
+        ("fd_character", count(|fd| fd.character)),
+        ("fd_block", count(|fd| fd.block)),
+        ("fd_other", count(|fd| fd.other)),
+    ]
+}
+
+///
+/// The header of the Zabbix protocol frame.
+///
+pub const ZABBIX_HEADER: &[u8] = b"ZBXD\x01";
+
+///
+/// Encode Zabbix sender data `items` as the Zabbix sender protocol request
+/// framed with the `ZBXD\x01` header and 8-byte little-endian length.
+///
+pub fn zabbix_frame(items: &[String]) -> Vec<u8> {
+    let body = format!(
+        "{{\"request\":\"sender data\",\"data\":[{}]}}",
+        items.join(",")
+    );
+    let mut frame = Vec::with_capacity(ZABBIX_HEADER.len() + 8 + body.len());
+    frame.extend_from_slice(ZABBIX_HEADER);
+    frame.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    frame.extend_from_slice(body.as_bytes());
+
+    frame
+}
+
+///
+/// Encode the Zabbix sender data item.
+///
+fn zabbix_item(host: &str, key: &str, value: &str) -> String {
+    format!(
+        "{{\"host\":{},\"key\":{},\"value\":{}}}",
+        json_string(host),
+        json_string(key),
+        json_string(value)
+    )
+}
+
+///
+/// Encode the string `s` as JSON string with quotes and escapes.
+///
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zabbix_header_length_matches_payload() {
+        let msg = StateMessage {
+            owl_pid: 1,
+            pid: 2,
+            name: "job".to_owned(),
+            state: "S".to_owned(),
+            fields: vec![
+                ("rss_bytes", Value::UInt(4096)),
+                ("cpu_pct", Value::Float(12.5)),
+                ("cwd", Value::Text("/".to_owned())),
+            ],
+            labels: Vec::new(),
+            alerts: Vec::new(),
+            self_metrics: Vec::new(),
+            envelope: None,
+        };
+        let items = msg.zabbix_items("host-1", "owl.state");
+        assert_eq!(items.len(), 3);
+        assert!(items[1].contains("\"key\":\"owl.state[rss_bytes]\""));
+
+        let frame = zabbix_frame(&items);
+        let header_len = ZABBIX_HEADER.len() + 8;
+        assert_eq!(&frame[..ZABBIX_HEADER.len()], ZABBIX_HEADER);
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&frame[ZABBIX_HEADER.len()..header_len]);
+        assert_eq!(u64::from_le_bytes(len) as usize, frame.len() - header_len);
+        assert!(frame[header_len..].starts_with(b"{\"request\":\"sender data\""));
+    }
+
+    #[test]
+    fn messages_carry_version() {
+        let msg = StateMessage {
+            owl_pid: 1,
+            pid: 2,
+            name: "job".to_owned(),
+            state: "S".to_owned(),
+            fields: Vec::new(),
+            labels: Vec::new(),
+            alerts: Vec::new(),
+            self_metrics: Vec::new(),
+            envelope: None,
+        };
+        assert_eq!(msg.to_ssdpd(), format!("1||2||job||S||version={}", VERSION));
+        assert!(msg
+            .to_json()
+            .starts_with(&format!("{{\"version\":{},\"owl_pid\":1,", VERSION)));
+    }
+}
@@ -0,0 +1,183 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//!
+//! The registry of gauges exposed to Prometheus in the text exposition format
+//! or the OpenMetrics format.
+//!
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+const METRIC_PREFIX: &str = "owl_";
+const OPENMETRICS_EOF: &str = "# EOF";
+
+///
+/// The format gauges are rendered in.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposition {
+    /// The Prometheus text exposition format.
+    Text,
+
+    /// The OpenMetrics text format which ends with `# EOF`.
+    OpenMetrics,
+}
+
+///
+/// The last heartbeat of the sender.
+///
+struct Sample {
+    /// The time the heartbeat is received at.
+    at: SystemTime,
+
+    /// The state of the process.
+    state: String,
+
+    /// The numeric fields of the heartbeat.
+    values: Vec<(String, f64)>,
+}
+
+///
+/// The registry keeps the last heartbeat of every sender identified by the name
+/// and the instance. Senders silent longer than the TTL are expired.
+///
+pub struct Metrics {
+    ttl: Duration,
+    samples: HashMap<(String, String), Sample>,
+}
+
+impl Metrics {
+    pub fn new(ttl: Duration) -> Metrics {
+        Metrics {
+            ttl,
+            samples: HashMap::new(),
+        }
+    }
+
+    ///
+    /// Record the heartbeat of the sender `name` and `instance` received `at`.
+    ///
+    pub fn update(
+        &mut self,
+        name: &str,
+        instance: &str,
+        state: &str,
+        values: Vec<(String, f64)>,
+        at: SystemTime,
+    ) {
+        self.samples.insert(
+            (name.to_owned(), instance.to_owned()),
+            Sample {
+                at,
+                state: state.to_owned(),
+                values,
+            },
+        );
+    }
+
+    ///
+    /// Expire senders silent at `now` longer than the TTL and render gauges
+    /// of others. Every field becomes the gauge `owl_<field>` where `_pct` is
+    /// spelled `_percent`, e.g. `owl_cpu_percent{name="rsync",instance="1280"} 42.1`,
+    /// and the state becomes `owl_state{...,state="Sleeping"} 1`.
+    /// Gauges have neither the `_total` suffix nor `created` timestamps
+    /// so OpenMetrics differs only by the terminator.
+    ///
+    pub fn render(&mut self, now: SystemTime, exposition: Exposition) -> String {
+        let ttl = self.ttl;
+        self.samples
+            .retain(|_, sample| now.duration_since(sample.at).unwrap_or_default() <= ttl);
+
+        // Group samples by the metric so every metric is declared once
+        let mut keys: Vec<&(String, String)> = self.samples.keys().collect();
+        keys.sort();
+        let mut gauges: Vec<(String, Vec<String>)> = Vec::new();
+        for key in keys {
+            let sample = &self.samples[key];
+            let labels = format!(
+                "name=\"{}\",instance=\"{}\"",
+                escape_label(&key.0),
+                escape_label(&key.1)
+            );
+            let mut lines = vec![(
+                "state".to_owned(),
+                format!("{{{},state=\"{}\"}} 1", labels, escape_label(&sample.state)),
+            )];
+            for (field, value) in sample.values.iter() {
+                lines.push((
+                    field.replace("_pct", "_percent"),
+                    format!("{{{}}} {}", labels, value),
+                ));
+            }
+
+            for (metric, line) in lines {
+                match gauges.iter_mut().find(|(name, _)| *name == metric) {
+                    Some((_, samples)) => samples.push(line),
+                    None => gauges.push((metric, vec![line])),
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for (metric, lines) in gauges {
+            let _ = writeln!(out, "# TYPE {}{} gauge", METRIC_PREFIX, metric);
+            for line in lines {
+                let _ = writeln!(out, "{}{}{}", METRIC_PREFIX, metric, line);
+            }
+        }
+        if exposition == Exposition::OpenMetrics {
+            let _ = writeln!(out, "{}", OPENMETRICS_EOF);
+        }
+
+        out
+    }
+}
+
+///
+/// Escape the label value for the text exposition format.
+///
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_senders_expire() {
+        let mut metrics = Metrics::new(Duration::from_secs(60));
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        metrics.update(
+            "rsync",
+            "1280",
+            "Running",
+            vec![("cpu_pct".to_owned(), 42.1)],
+            at,
+        );
+
+        let out = metrics.render(at + Duration::from_secs(10), Exposition::OpenMetrics);
+        assert!(out.contains("owl_cpu_percent{name=\"rsync\",instance=\"1280\"} 42.1\n"));
+        assert!(out.ends_with("# EOF\n"));
+
+        let out = metrics.render(at + Duration::from_secs(61), Exposition::Text);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The monitor of the process which derives metrics from consecutive stats
+//! samples and checks alerts.
+//!
+
+use crate::alert::{Alert, Threshold};
+use crate::message::{StateMessage, Value};
+use crate::platform::{self, ProcessStats};
+use crate::OPT;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Instant;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const OPT_ALERT_CPU_EFFICIENCY: &str = "AlertCpuEfficiency";
+const OPT_ALERT_RSS_HWM: &str = "AlertRssHwm";
+const OPT_ALERT_RSS_GROWTH_RATE: &str = "AlertRssGrowthRate";
+const OPT_RSS_GROWTH_EMA: &str = "RssGrowthEma";
+const DEFAULT_RSS_GROWTH_EMA: f64 = 0.1;
+const RATE_SUFFIX: &str = "/s";
+const OPT_ALERT_ORPHAN: &str = "AlertOrphan";
+const OPT_ALERT_FD_SOCKETS: &str = "AlertFdSockets";
+const OPT_ALERT_CWD_CHANGE: &str = "AlertCwdChange";
+const OPT_SPARKLINE_SIZE: &str = "SparklineSize";
+const STATE_ZOMBIE: &str = "Zombie";
+
+///
+/// The state kept between samples of the process.
+///
+pub struct Monitor {
+    /// The id of the process monitored.
+    pid: u32,
+
+    /// The time the process was first seen at.
+    started_at: Instant,
+
+    /// The time of the previous sample and the CPU time then.
+    prev_sample: Option<(Instant, u64)>,
+
+    /// Alerts on the CPU efficiency.
+    cpu_efficiency_alert: Option<Alert>,
+
+    /// Alerts on the peak resident set size.
+    rss_hwm_alert: Option<Alert>,
+
+    /// The time of the previous sample and the resident set size then.
+    prev_rss: Option<(Instant, u64)>,
+
+    /// The smoothing factor of the exponential moving average of the RSS growth rate.
+    rss_growth_alpha: f64,
+
+    /// The RSS growth rate in bytes per second smoothed.
+    rss_growth_rate: Option<f64>,
+
+    /// Alerts on the RSS growth rate.
+    rss_growth_alert: Option<Alert>,
+
+    /// Alerts on the number of open sockets.
+    fd_sockets_alert: Option<Alert>,
+
+    /// The flag is raised when the process is reported to be zombie.
+    zombie: bool,
+
+    /// The process group of the tool when the orphan alert is enabled.
+    owl_pgrp: Option<u32>,
+
+    /// The flag is raised when the process is reported to leave its process group.
+    orphan: bool,
+
+    /// The flag is raised when the cwd change alert is enabled.
+    cwd_alert: bool,
+
+    /// The working directory of the previous sample.
+    cwd: Option<String>,
+
+    /// The number of latest samples sparklines hold when they are enabled.
+    sparkline_size: Option<usize>,
+
+    /// The latest CPU usage samples in percents.
+    cpu_samples: VecDeque<f32>,
+
+    /// The latest RSS samples in bytes.
+    rss_samples: VecDeque<f32>,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            pid: 0,
+            started_at: Instant::now(),
+            prev_sample: None,
+            cpu_efficiency_alert: OPT
+                .get(OPT_ALERT_CPU_EFFICIENCY)
+                .and_then(|v| Threshold::parse(v))
+                .map(|t| Alert::new("cpu_efficiency", t)),
+            rss_hwm_alert: OPT
+                .get(OPT_ALERT_RSS_HWM)
+                .and_then(|v| crate::parse_size(v))
+                .map(|limit| Alert::new("rss_hwm_bytes", Threshold::Above(limit as f64))),
+            prev_rss: None,
+            rss_growth_alpha: OPT
+                .get(OPT_RSS_GROWTH_EMA)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(DEFAULT_RSS_GROWTH_EMA),
+            rss_growth_rate: None,
+            rss_growth_alert: OPT
+                .get(OPT_ALERT_RSS_GROWTH_RATE)
+                .and_then(|v| parse_growth_rate(v))
+                .map(|limit| Alert::new("rss_growth_rate_bps", Threshold::Above(limit as f64))),
+            fd_sockets_alert: OPT
+                .get(OPT_ALERT_FD_SOCKETS)
+                .and_then(|v| v.parse::<u32>().ok())
+                .map(|limit| Alert::new("fd_sockets", Threshold::Above(f64::from(limit)))),
+            zombie: false,
+            owl_pgrp: if crate::opt_enabled(OPT_ALERT_ORPHAN) {
+                Some(nix::unistd::getpgrp().as_raw() as u32)
+            } else {
+                None
+            },
+            orphan: false,
+            cwd_alert: crate::opt_enabled(OPT_ALERT_CWD_CHANGE),
+            cwd: None,
+            sparkline_size: OPT
+                .get(OPT_SPARKLINE_SIZE)
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|size| *size > 0),
+            cpu_samples: VecDeque::new(),
+            rss_samples: VecDeque::new(),
+        }
+    }
+
+    ///
+    /// Observe the sample `stats` of the process with `pid` and add derived metrics
+    /// and alerts to `msg`. The monitor starts over when `pid` changes.
+    ///
+    pub fn observe(&mut self, pid: u32, stats: &ProcessStats, msg: &mut StateMessage) {
+        let now = Instant::now();
+        if pid != self.pid {
+            self.pid = pid;
+            self.started_at = now;
+            self.prev_sample = None;
+            self.prev_rss = None;
+            self.rss_growth_rate = None;
+            self.zombie = false;
+            self.orphan = false;
+            self.cwd = None;
+            self.cpu_samples.clear();
+            self.rss_samples.clear();
+        }
+
+        // The process exited but is not reaped by its parent
+        let zombie = stats.state == STATE_ZOMBIE;
+        if zombie && !self.zombie {
+            msg.alerts.push(format!("process {} is zombie", pid));
+        }
+        self.zombie = zombie;
+
+        // The process moved to the process group of neither its own nor the tool
+        if let (Some(owl_pgrp), Some(pgrp)) = (self.owl_pgrp, stats.pgrp) {
+            let orphan = pgrp != pid && pgrp != owl_pgrp;
+            if orphan && !self.orphan {
+                msg.alerts
+                    .push(format!("process {} moved to process group {}", pid, pgrp));
+            }
+            self.orphan = orphan;
+        }
+
+        // The process changed the working directory since the previous sample
+        if let (true, Some(cwd)) = (self.cwd_alert, stats.cwd.as_ref()) {
+            if let Some(prev) = self.cwd.as_ref().filter(|prev| *prev != cwd) {
+                msg.alerts.push(format!(
+                    "process {} changed working directory from {} to {}",
+                    pid, prev, cwd
+                ));
+            }
+            self.cwd = Some(cwd.clone());
+        }
+
+        // Compute the CPU usage since the previous heartbeat
+        let cpu_pct = match (self.prev_sample, stats.cpu_time_ms) {
+            (Some((at, prev_ms)), Some(cpu_ms)) if now > at && cpu_ms >= prev_ms => {
+                let wall_ms = now.duration_since(at).as_secs_f64() * 1000.0;
+                Some((cpu_ms - prev_ms) as f64 / wall_ms * 100.0)
+            }
+            _ => None,
+        };
+        self.prev_sample = stats.cpu_time_ms.map(|ms| (now, ms));
+        if let Some(cpu_pct) = cpu_pct {
+            msg.fields.push(("cpu_pct", Value::Float(cpu_pct)));
+        }
+
+        // Compute the CPU time to the wall time ratio over the process lifetime
+        let wall_ms = now.duration_since(self.started_at).as_secs_f64() * 1000.0;
+        if let (Some(cpu_ms), true) = (stats.cpu_time_ms, wall_ms > 0.0) {
+            let efficiency = cpu_ms as f64 / wall_ms * 100.0;
+            msg.fields
+                .push(("cpu_efficiency", Value::Float(efficiency)));
+            if let Some(alert) = self.cpu_efficiency_alert.as_mut() {
+                msg.alerts.extend(alert.check(efficiency));
+            }
+        }
+
+        // The peak RSS only grows so the alert fires once per process
+        if let (Some(hwm), Some(alert)) = (stats.rss_hwm_bytes, self.rss_hwm_alert.as_mut()) {
+            msg.alerts.extend(alert.check(hwm as f64));
+        }
+
+        // Smooth the RSS growth rate so allocation spikes do not raise alerts
+        if let Some(rss) = stats.rss_bytes {
+            if let Some((at, prev)) = self.prev_rss.filter(|(at, _)| now > *at) {
+                let rate = (rss as f64 - prev as f64) / now.duration_since(at).as_secs_f64();
+                let alpha = self.rss_growth_alpha;
+                let smoothed = self
+                    .rss_growth_rate
+                    .map_or(rate, |ema| alpha * rate + (1.0 - alpha) * ema);
+                self.rss_growth_rate = Some(smoothed);
+                msg.fields
+                    .push(("rss_growth_rate_bps", Value::Float(smoothed)));
+                if let Some(alert) = self.rss_growth_alert.as_mut() {
+                    msg.alerts.extend(alert.check(smoothed));
+                }
+            }
+            self.prev_rss = Some((now, rss));
+        }
+
+        // Keep the latest samples for sparklines
+        if let Some(size) = self.sparkline_size {
+            let samples = [
+                (&mut self.cpu_samples, cpu_pct),
+                (&mut self.rss_samples, stats.rss_bytes.map(|v| v as f64)),
+            ];
+            for (samples, value) in samples {
+                if let Some(value) = value {
+                    if samples.len() == size {
+                        samples.pop_front();
+                    }
+                    samples.push_back(value as f32);
+                }
+            }
+            let cpu = self.cpu_samples.iter().cloned().collect();
+            let rss = self.rss_samples.iter().cloned().collect();
+            msg.fields.push(("sparkline_cpu", Value::Floats(cpu)));
+            msg.fields.push(("sparkline_rss", Value::Floats(rss)));
+        }
+
+        // Sockets which pile up are leaking
+        if let (Some(fd), Some(alert)) = (stats.fd_types.as_ref(), self.fd_sockets_alert.as_mut()) {
+            msg.alerts.extend(alert.check(f64::from(fd.socket)));
+        }
+
+        // Put the usage in context of cgroup limits
+        if let Some(limits) = platform::read_cgroup_limits(Path::new(CGROUP_ROOT), pid) {
+            if let (Some(rss), Some(max)) = (stats.rss_bytes, limits.memory_max) {
+                let pct = rss as f64 / max as f64 * 100.0;
+                msg.fields.push(("mem_pct_of_limit", Value::Float(pct)));
+            }
+            if let (Some(cpu_pct), Some(cpus)) = (cpu_pct, limits.cpu_max) {
+                let pct = cpu_pct / cpus;
+                msg.fields.push(("cpu_pct_of_limit", Value::Float(pct)));
+            }
+        }
+
+        for alert in msg.alerts.iter() {
+            eprintln!("alert: {}", alert);
+        }
+    }
+}
+
+///
+/// Parse the rate of growth in bytes per second from `value` which is the size
+/// optionally followed with `/s`, e.g. `1M/s`.
+///
+pub fn parse_growth_rate(value: &str) -> Option<u64> {
+    let value = value.trim();
+    crate::parse_size(value.strip_suffix(RATE_SUFFIX).unwrap_or(value))
+}
+
+///
+/// Test the stats `curr` differ from `prev` significantly, i.e. the state changed
+/// or any metric changed by more than `pct` percents. The metric which appears
+/// or disappears is the change too.
+///
+pub fn stats_changed(prev: &ProcessStats, curr: &ProcessStats, pct: f32) -> bool {
+    let fd_count = |stats: &ProcessStats| stats.fd_types.as_ref().map(|fd| u64::from(fd.total()));
+    let fd_sockets = |stats: &ProcessStats| stats.fd_types.as_ref().map(|fd| u64::from(fd.socket));
+    let metrics = [
+        (prev.cpu_time_ms, curr.cpu_time_ms),
+        (prev.rss_bytes, curr.rss_bytes),
+        (prev.threads.map(u64::from), curr.threads.map(u64::from)),
+        (prev.io_read_bytes, curr.io_read_bytes),
+        (prev.io_write_bytes, curr.io_write_bytes),
+        (prev.voluntary_ctxt_switches, curr.voluntary_ctxt_switches),
+        (
+            prev.nonvoluntary_ctxt_switches,
+            curr.nonvoluntary_ctxt_switches,
+        ),
+        (prev.zombie_children, curr.zombie_children),
+        (prev.vm_peak_bytes, curr.vm_peak_bytes),
+        (prev.rss_hwm_bytes, curr.rss_hwm_bytes),
+        (prev.anon_maps_count, curr.anon_maps_count),
+        (prev.anon_maps_bytes, curr.anon_maps_bytes),
+        (prev.file_maps_count, curr.file_maps_count),
+        (fd_count(prev), fd_count(curr)),
+        (fd_sockets(prev), fd_sockets(curr)),
+    ];
+
+    prev.state != curr.state
+        || prev.pgrp != curr.pgrp
+        || prev.session != curr.session
+        || metrics.iter().any(|pair| match *pair {
+            (Some(0), Some(c)) => c != 0,
+            (Some(p), Some(c)) => (c as f64 - p as f64).abs() / p as f64 * 100.0 > f64::from(pct),
+            (None, None) => false,
+            _ => true,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growth_rates_are_sizes_per_second() {
+        assert_eq!(parse_growth_rate("1M/s"), Some(1 << 20));
+        assert_eq!(parse_growth_rate(" 512K/s "), Some(512 << 10));
+        assert_eq!(parse_growth_rate("4096"), Some(4096));
+        assert_eq!(parse_growth_rate("fast/s"), None);
+        assert_eq!(parse_growth_rate("1M/m"), None);
+    }
+}
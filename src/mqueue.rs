@@ -0,0 +1,166 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The POSIX message queue the state is sent to for receivers on the same host.
+//!
+//! Every message of the queue carries the messages of one heartbeat encoded
+//! as the payload of other transports. The queue is opened non-blocking
+//! so the tool does not stall when the receiver falls behind and the queue
+//! is full, the heartbeat is reported as failed instead.
+//!
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+use nix::mqueue::{mq_close, mq_open, mq_send, mq_unlink, MQ_OFlag, MqAttr};
+#[cfg(target_os = "linux")]
+use nix::sys::stat::Mode;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+
+///
+/// The message queue opened by the tool.
+///
+pub struct MessageQueue {
+    name: String,
+    #[cfg(target_os = "linux")]
+    mqd: libc::mqd_t,
+    #[cfg(target_os = "linux")]
+    owner: bool,
+}
+
+impl MessageQueue {
+    ///
+    /// Open the queue `name`, e.g. `/owl-rsync`, creating it with room for
+    /// `max_msgs` messages of `msg_size` bytes if it does not exist.
+    /// The queue is removed when the value returned is dropped.
+    ///
+    pub fn create(name: &str, max_msgs: i64, msg_size: i64) -> io::Result<MessageQueue> {
+        open_queue(name, max_msgs, msg_size, true)
+    }
+
+    ///
+    /// Open the queue `name` like `create` does but keep the queue when
+    /// the value returned is dropped.
+    ///
+    pub fn open(name: &str, max_msgs: i64, msg_size: i64) -> io::Result<MessageQueue> {
+        open_queue(name, max_msgs, msg_size, false)
+    }
+
+    ///
+    /// Get the name of the queue.
+    ///
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///
+    /// Send the `payload` as one message of the queue.
+    ///
+    #[cfg(target_os = "linux")]
+    pub fn send(&self, payload: &[u8]) -> io::Result<()> {
+        mq_send(self.mqd, payload, 0).map_err(nix_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn send(&self, _payload: &[u8]) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        let _ = mq_close(self.mqd);
+        if self.owner {
+            if let Ok(c_name) = CString::new(self.name.as_str()) {
+                let _ = mq_unlink(&c_name);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_queue(name: &str, max_msgs: i64, msg_size: i64, owner: bool) -> io::Result<MessageQueue> {
+    let c_name =
+        CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let attr = MqAttr::new(0, max_msgs, msg_size, 0);
+    let mqd = mq_open(
+        &c_name,
+        MQ_OFlag::O_CREAT | MQ_OFlag::O_WRONLY | MQ_OFlag::O_NONBLOCK,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+        Some(&attr),
+    )
+    .map_err(nix_error)?;
+
+    Ok(MessageQueue {
+        name: name.to_owned(),
+        mqd,
+        owner,
+    })
+}
+
+///
+/// Message queues are not supported on the platform so the error is always returned.
+///
+#[cfg(not(target_os = "linux"))]
+fn open_queue(
+    _name: &str,
+    _max_msgs: i64,
+    _msg_size: i64,
+    _owner: bool,
+) -> io::Result<MessageQueue> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(target_os = "linux")]
+fn nix_error(err: nix::Error) -> io::Error {
+    match err {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        err => io::Error::new(io::ErrorKind::InvalidInput, err),
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use nix::mqueue::mq_receive;
+
+    // Open the queue `name` for reading if it exists.
+    fn open_reader(name: &str) -> nix::Result<libc::mqd_t> {
+        let c_name = CString::new(name).unwrap();
+        mq_open(&c_name, MQ_OFlag::O_RDONLY, Mode::empty(), None)
+    }
+
+    #[test]
+    fn messages_are_received_until_owner_drops_queue() {
+        let name = format!("/owl-test-{}-mqueue", std::process::id());
+        let queue = MessageQueue::create(&name, 4, 128).unwrap();
+        queue.send(b"1||2||job||S").unwrap();
+        drop(MessageQueue::open(&name, 4, 128).unwrap());
+
+        let mqd = open_reader(&name).unwrap();
+        let mut buf = [0; 128];
+        let mut prio = 0;
+        let len = mq_receive(mqd, &mut buf, &mut prio).unwrap();
+        assert_eq!(&buf[..len], b"1||2||job||S");
+        mq_close(mqd).unwrap();
+
+        drop(queue);
+        assert!(open_reader(&name).is_err());
+    }
+}
@@ -0,0 +1,908 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The platform abstraction layer which reads process stats with the facility
+//! the operating system provides.
+//!
+//! - On Linux the stats are read from the `/proc` filesystem.
+//! - On macOS the stats are read with `proc_pidinfo` from `libproc`.
+//!
+//! Stats which the platform does not provide are `None`.
+//!
+
+#[cfg(target_os = "linux")]
+use nix::sched::{sched_setaffinity, setns, CloneFlags, CpuSet};
+use nix::sys::signal::Signal;
+#[cfg(target_os = "linux")]
+use nix::unistd::Pid;
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+///
+/// The stats of the process.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ProcessStats {
+    /// The id of the process.
+    pub pid: u32,
+
+    /// The name of the command the process runs.
+    pub command: String,
+
+    /// The state of the process, e.g. `Running` or `Sleeping`.
+    pub state: String,
+
+    /// The id of the process group.
+    pub pgrp: Option<u32>,
+
+    /// The id of the session.
+    pub session: Option<u32>,
+
+    /// The time the process spent in user and kernel mode in milliseconds.
+    pub cpu_time_ms: Option<u64>,
+
+    /// The resident set size in bytes.
+    pub rss_bytes: Option<u64>,
+
+    /// The number of threads.
+    pub threads: Option<u32>,
+
+    /// The number of bytes the process read from the storage.
+    pub io_read_bytes: Option<u64>,
+
+    /// The number of bytes the process wrote to the storage.
+    pub io_write_bytes: Option<u64>,
+
+    /// The number of voluntary context switches.
+    pub voluntary_ctxt_switches: Option<u64>,
+
+    /// The number of involuntary context switches.
+    pub nonvoluntary_ctxt_switches: Option<u64>,
+
+    /// The number of children which exited but are not reaped by the process.
+    pub zombie_children: Option<u64>,
+
+    /// The peak virtual memory size in bytes.
+    pub vm_peak_bytes: Option<u64>,
+
+    /// The peak resident set size in bytes.
+    pub rss_hwm_bytes: Option<u64>,
+
+    /// The number of anonymous memory mappings.
+    pub anon_maps_count: Option<u64>,
+
+    /// The size of anonymous memory mappings in bytes.
+    pub anon_maps_bytes: Option<u64>,
+
+    /// The number of file-backed memory mappings.
+    pub file_maps_count: Option<u64>,
+
+    /// The flag is raised when only the part of memory mappings is counted.
+    pub maps_truncated: Option<bool>,
+
+    /// The number of open file descriptors by the type of the file.
+    pub fd_types: Option<FdTypeCounts>,
+
+    /// The named pipes the process has open which are watched.
+    pub monitored_fifos: Option<Vec<String>>,
+
+    /// The current working directory of the process.
+    pub cwd: Option<String>,
+}
+
+///
+/// The number of open file descriptors of the process by the type of the file.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FdTypeCounts {
+    pub socket: u32,
+    pub pipe: u32,
+    pub anon_inode: u32,
+    pub regular: u32,
+    pub directory: u32,
+    pub character: u32,
+    pub block: u32,
+    pub other: u32,
+}
+
+impl FdTypeCounts {
+    ///
+    /// Get the number of all file descriptors.
+    ///
+    pub fn total(&self) -> u32 {
+        self.socket
+            + self.pipe
+            + self.anon_inode
+            + self.regular
+            + self.directory
+            + self.character
+            + self.block
+            + self.other
+    }
+}
+
+///
+/// The resources used by children of the tool which were waited for.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ResourceUsage {
+    /// The CPU time spent in the user mode in milliseconds.
+    pub utime_ms: u64,
+
+    /// The CPU time spent in the kernel mode in milliseconds.
+    pub stime_ms: u64,
+
+    /// The largest peak RSS of children in bytes.
+    pub maxrss_bytes: u64,
+
+    /// The number of page faults served without I/O.
+    pub minflt: u64,
+
+    /// The number of page faults which required I/O.
+    pub majflt: u64,
+
+    /// The number of block input operations.
+    pub inblock: u64,
+
+    /// The number of block output operations.
+    pub oublock: u64,
+
+    /// The number of voluntary context switches.
+    pub nvcsw: u64,
+
+    /// The number of involuntary context switches.
+    pub nivcsw: u64,
+}
+
+impl ResourceUsage {
+    ///
+    /// Get resources used since the `before` usage was read. The peak RSS
+    /// is not a counter so it is kept as is.
+    ///
+    pub fn since(&self, before: &ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            utime_ms: self.utime_ms.saturating_sub(before.utime_ms),
+            stime_ms: self.stime_ms.saturating_sub(before.stime_ms),
+            maxrss_bytes: self.maxrss_bytes,
+            minflt: self.minflt.saturating_sub(before.minflt),
+            majflt: self.majflt.saturating_sub(before.majflt),
+            inblock: self.inblock.saturating_sub(before.inblock),
+            oublock: self.oublock.saturating_sub(before.oublock),
+            nvcsw: self.nvcsw.saturating_sub(before.nvcsw),
+            nivcsw: self.nivcsw.saturating_sub(before.nivcsw),
+        }
+    }
+}
+
+///
+/// The maximum number of memory mappings counted.
+///
+#[cfg(target_os = "linux")]
+const MAX_MAPS_LINES: usize = 10_000;
+
+///
+/// Read stats of the process with `pid`. On success stats returned or `None`
+/// otherwise whatever the reason is, see `try_read_process_stats`.
+///
+pub fn read_process_stats(pid: u32) -> Option<ProcessStats> {
+    try_read_process_stats(pid).ok().flatten()
+}
+
+///
+/// Read stats of the process with `pid` from `/proc/<pid>/stat`, `/proc/<pid>/status`,
+/// and `/proc/<pid>/io`. `None` is returned if the process does not exist and
+/// the error is returned if stats cannot be read for another reason.
+///
+#[cfg(target_os = "linux")]
+pub fn try_read_process_stats(pid: u32) -> io::Result<Option<ProcessStats>> {
+    let stat = match procinfo::pid::stat(pid as i32) {
+        Ok(stat) => stat,
+        Err(ref err) if process_missing(err) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let status = read_proc_fields(pid, "status").unwrap_or_default();
+    let io = read_proc_fields(pid, "io").unwrap_or_default();
+
+    let ticks = stat.utime as u64 + stat.stime as u64;
+    let page_size = sysconf(libc::_SC_PAGESIZE);
+
+    Ok(Some(ProcessStats {
+        pid: stat.pid as u32,
+        command: stat.command,
+        state: format!("{:?}", stat.state),
+        pgrp: Some(stat.pgrp as u32),
+        session: Some(stat.session as u32),
+        cpu_time_ms: Some(ticks * 1000 / sysconf(libc::_SC_CLK_TCK).max(1)),
+        rss_bytes: Some(stat.rss as u64 * page_size),
+        threads: Some(stat.num_threads as u32),
+        io_read_bytes: io.get("read_bytes").cloned(),
+        io_write_bytes: io.get("write_bytes").cloned(),
+        voluntary_ctxt_switches: status.get("voluntary_ctxt_switches").cloned(),
+        nonvoluntary_ctxt_switches: status.get("nonvoluntary_ctxt_switches").cloned(),
+        zombie_children: count_zombie_children(pid),
+        vm_peak_bytes: status.get("VmPeak").map(|kb| kb * 1024),
+        rss_hwm_bytes: status.get("VmHWM").map(|kb| kb * 1024),
+        fd_types: read_fd_types(pid),
+        cwd: read_cwd(pid).map(|path| path.to_string_lossy().into_owned()),
+        ..ProcessStats::default()
+    }))
+}
+
+///
+/// Test the error of the read from `/proc/<pid>` means the process does not exist.
+///
+#[cfg(target_os = "linux")]
+fn process_missing(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::NotFound || err.raw_os_error() == Some(libc::ESRCH)
+}
+
+///
+/// Count zombie children of the process with `pid` scanning `/proc` for processes
+/// which parent is `pid`. `None` is returned when `/proc` cannot be listed.
+///
+#[cfg(target_os = "linux")]
+fn count_zombie_children(pid: u32) -> Option<u64> {
+    let zombies = std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(|child| procinfo::pid::stat(child).ok())
+        .filter(|stat| stat.ppid == pid as i32 && stat.state == procinfo::pid::State::Zombie)
+        .count();
+    Some(zombies as u64)
+}
+
+///
+/// Count open file descriptors of the process with `pid` by the type of the file
+/// from `/proc/<pid>/fd`. Sockets, pipes, and anonymous inodes are told by
+/// the link target, other files by the file they link to. `None` is returned
+/// when the directory cannot be listed, e.g. the process belongs to another user.
+///
+#[cfg(target_os = "linux")]
+pub fn read_fd_types(pid: u32) -> Option<FdTypeCounts> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let mut counts = FdTypeCounts::default();
+    for entry in std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue,
+        };
+        let target = std::fs::read_link(&path).unwrap_or_default();
+        let target = target.to_string_lossy();
+        let count = if target.starts_with("socket:") {
+            &mut counts.socket
+        } else if target.starts_with("pipe:") {
+            &mut counts.pipe
+        } else if target.starts_with("anon_inode:") {
+            &mut counts.anon_inode
+        } else {
+            match std::fs::metadata(&path).map(|meta| meta.file_type()) {
+                Ok(t) if t.is_file() => &mut counts.regular,
+                Ok(t) if t.is_dir() => &mut counts.directory,
+                Ok(t) if t.is_char_device() => &mut counts.character,
+                Ok(t) if t.is_block_device() => &mut counts.block,
+                Ok(t) if t.is_fifo() => &mut counts.pipe,
+                Ok(t) if t.is_socket() => &mut counts.socket,
+                _ => &mut counts.other,
+            }
+        };
+        *count += 1;
+    }
+
+    Some(counts)
+}
+
+///
+/// Get the current working directory of the process with `pid` from `/proc/<pid>/cwd`.
+///
+#[cfg(target_os = "linux")]
+pub fn read_cwd(pid: u32) -> Option<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+///
+/// Get paths of named pipes the process with `pid` has open from `/proc/<pid>/fd`.
+/// Paths are sorted and every path is listed once even if it is open more than once.
+///
+#[cfg(target_os = "linux")]
+pub fn read_fifo_paths(pid: u32) -> Option<Vec<String>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let mut paths: Vec<String> = std::fs::read_dir(format!("/proc/{}/fd", pid))
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let target = std::fs::read_link(&path).ok()?;
+            let is_fifo = std::fs::metadata(&path).ok()?.file_type().is_fifo();
+            if is_fifo && target.is_absolute() {
+                Some(target.to_string_lossy().into_owned())
+            } else {
+                None
+            }
+        })
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    Some(paths)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_fifo_paths(_pid: u32) -> Option<Vec<String>> {
+    None
+}
+
+///
+/// Count memory mappings of the process with `pid` from `/proc/<pid>/maps`
+/// and put them into `stats`. Mappings without the path, the heap, and stacks
+/// are anonymous, mappings of paths are file-backed. Only the first 10000
+/// mappings are counted.
+///
+#[cfg(target_os = "linux")]
+pub fn read_memory_maps(pid: u32, stats: &mut ProcessStats) {
+    use std::io::{BufRead, BufReader};
+
+    let file = match std::fs::File::open(format!("/proc/{}/maps", pid)) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    let (mut anon_count, mut anon_bytes, mut file_count) = (0, 0, 0);
+    let mut lines = BufReader::new(file).lines();
+    for line in lines.by_ref().take(MAX_MAPS_LINES) {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+
+        // The line is `start-end perms offset dev inode [path]`
+        let mut fields = line.split_whitespace();
+        let range = fields.next().unwrap_or_default();
+        let path = fields.nth(4).unwrap_or_default();
+        if path.starts_with('/') {
+            file_count += 1;
+        } else if path.is_empty()
+            || path.starts_with("[heap")
+            || path.starts_with("[stack")
+            || path.starts_with("[anon")
+        {
+            let size = range.split_once('-').and_then(|(start, end)| {
+                let start = u64::from_str_radix(start, 16).ok()?;
+                let end = u64::from_str_radix(end, 16).ok()?;
+                end.checked_sub(start)
+            });
+            anon_count += 1;
+            anon_bytes += size.unwrap_or(0);
+        }
+    }
+
+    stats.anon_maps_count = Some(anon_count);
+    stats.anon_maps_bytes = Some(anon_bytes);
+    stats.file_maps_count = Some(file_count);
+    stats.maps_truncated = Some(lines.next().is_some());
+}
+
+///
+/// Memory mappings are supported on Linux only so `stats` are left as-is.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn read_memory_maps(_pid: u32, _stats: &mut ProcessStats) {}
+
+///
+/// Read the `/proc/<pid>/<file>` which has `name: value` per line, e.g. `status` or `io`,
+/// and collect the numeric values. Units like `kB` are stripped and values are
+/// returned as-is. `None` is returned when the file cannot be read, e.g. `io` is
+/// readable only by the owner of the process.
+///
+#[cfg(target_os = "linux")]
+fn read_proc_fields(pid: u32, file: &str) -> Option<HashMap<String, u64>> {
+    let contents = std::fs::read_to_string(format!("/proc/{}/{}", pid, file)).ok()?;
+    Some(
+        contents
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                let name = parts.next()?.trim();
+                let value = parts
+                    .next()?
+                    .split_whitespace()
+                    .next()?
+                    .parse::<u64>()
+                    .ok()?;
+                Some((name.to_owned(), value))
+            })
+            .collect(),
+    )
+}
+
+///
+/// Get the value of the system configuration variable `name`.
+///
+#[cfg(target_os = "linux")]
+fn sysconf(name: libc::c_int) -> u64 {
+    let value = unsafe { libc::sysconf(name) };
+    if value > 0 {
+        value as u64
+    } else {
+        0
+    }
+}
+
+///
+/// The limits of the cgroup v2 the process belongs to.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CgroupLimits {
+    /// The memory limit in bytes from `memory.max`.
+    pub memory_max: Option<u64>,
+
+    /// The number of CPUs the cgroup may use from `cpu.max`.
+    pub cpu_max: Option<f64>,
+}
+
+///
+/// Read limits of the cgroup v2 the process with `pid` belongs to. The cgroup
+/// hierarchy is expected to be mounted at `root`, e.g. `/sys/fs/cgroup`.
+/// `None` is returned when the hierarchy is not cgroup v2. Limits which are
+/// `max` (unlimited) are `None`.
+///
+#[cfg(target_os = "linux")]
+pub fn read_cgroup_limits(root: &Path, pid: u32) -> Option<CgroupLimits> {
+    // The unified hierarchy has cgroup.controllers in the root
+    if !root.join("cgroup.controllers").exists() {
+        return None;
+    }
+
+    // The cgroup v2 entry has form of `0::/path`
+    let dir = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        .ok()
+        .and_then(|contents| {
+            contents
+                .lines()
+                .find_map(|line| line.strip_prefix("0::").map(|p| p.trim().to_owned()))
+        })
+        .map(|path| root.join(path.trim_start_matches('/')))
+        .filter(|dir| dir.join("memory.max").exists() || dir.join("cpu.max").exists())
+        .unwrap_or_else(|| root.to_path_buf());
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+    let memory_max = read("memory.max").and_then(|v| v.trim().parse::<u64>().ok());
+    let cpu_max = read("cpu.max").and_then(|v| {
+        let mut parts = v.split_whitespace();
+        let quota = parts.next()?.parse::<f64>().ok()?;
+        let period = parts.next()?.parse::<f64>().ok()?;
+        if period > 0.0 {
+            Some(quota / period)
+        } else {
+            None
+        }
+    });
+
+    Some(CgroupLimits {
+        memory_max,
+        cpu_max,
+    })
+}
+
+///
+/// Cgroups are supported on Linux only so `None` is always returned.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn read_cgroup_limits(_root: &Path, _pid: u32) -> Option<CgroupLimits> {
+    None
+}
+
+///
+/// Read ids of processes of the cgroup at `dir` from its `cgroup.procs`.
+/// `None` is returned when the file cannot be read.
+///
+#[cfg(target_os = "linux")]
+pub fn read_cgroup_procs(dir: &Path) -> Option<Vec<u32>> {
+    let contents = std::fs::read_to_string(dir.join("cgroup.procs")).ok()?;
+    Some(
+        contents
+            .lines()
+            .filter_map(|line| line.trim().parse::<u32>().ok())
+            .collect(),
+    )
+}
+
+///
+/// Cgroups are supported on Linux only so `None` is always returned.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn read_cgroup_procs(_dir: &Path) -> Option<Vec<u32>> {
+    None
+}
+
+///
+/// The longest `comm` of the process the kernel keeps.
+///
+#[cfg(target_os = "linux")]
+const COMM_MAX_LEN: usize = 15;
+
+///
+/// Find processes which `comm` is `name` scanning `/proc`. The name is cut
+/// to 15 bytes like the kernel cuts `comm`. Ids of processes found are returned
+/// with the time they started at in clock ticks since the boot.
+///
+#[cfg(target_os = "linux")]
+pub fn find_processes_by_name(name: &str) -> Vec<(u32, u64)> {
+    let mut end = name.len().min(COMM_MAX_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let comm = &name[..end];
+    let mut processes: Vec<(u32, u64)> = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+            .filter_map(|pid| procinfo::pid::stat(pid).ok())
+            .filter(|stat| stat.command == comm)
+            .map(|stat| (stat.pid as u32, stat.start_time))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    processes.sort();
+
+    processes
+}
+
+///
+/// Process names are looked up on Linux only so nothing is found.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn find_processes_by_name(_name: &str) -> Vec<(u32, u64)> {
+    Vec::new()
+}
+
+///
+/// Move the calling thread into the namespace of type `nstype` referred
+/// by the file descriptor `fd`, e.g. opened `/proc/<pid>/ns/pid`.
+///
+#[cfg(target_os = "linux")]
+pub fn enter_namespace(fd: RawFd, nstype: CloneFlags) -> nix::Result<()> {
+    setns(fd, nstype)
+}
+
+///
+/// Pin the process or the thread `pid` to CPUs with numbers from `cpus`.
+///
+#[cfg(target_os = "linux")]
+pub fn set_cpu_affinity(pid: u32, cpus: &[usize]) -> nix::Result<()> {
+    let mut set = CpuSet::new();
+    for cpu in cpus.iter() {
+        set.set(*cpu)?;
+    }
+    sched_setaffinity(Pid::from_raw(pid as i32), &set)
+}
+
+///
+/// CPU affinity is set on Linux only.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn set_cpu_affinity(_pid: u32, _cpus: &[usize]) -> nix::Result<()> {
+    Err(nix::Error::UnsupportedOperation)
+}
+
+///
+/// Pin all threads of the tool to CPUs with numbers from `cpus`. Threads
+/// started later inherit the affinity of the thread which starts them.
+///
+#[cfg(target_os = "linux")]
+pub fn set_own_cpu_affinity(cpus: &[usize]) -> nix::Result<()> {
+    let tids: Vec<u32> = match std::fs::read_dir("/proc/self/task") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .collect(),
+        Err(_) => vec![std::process::id()],
+    };
+    for tid in tids {
+        match set_cpu_affinity(tid, cpus) {
+            // The thread exited meanwhile
+            Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => {}
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// CPU affinity is set on Linux only.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn set_own_cpu_affinity(_cpus: &[usize]) -> nix::Result<()> {
+    Err(nix::Error::UnsupportedOperation)
+}
+
+///
+/// The head of the Linux `struct tcp_info` up to the smoothed RTT.
+///
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct TcpInfo {
+    state: [u8; 8],
+    counters: [u32; 15],
+    rtt: u32,
+    rttvar: u32,
+}
+
+///
+/// Read resources used by children of the tool which were waited for
+/// with `getrusage(RUSAGE_CHILDREN)`. Linux reports the peak RSS in kilobytes
+/// and macOS in bytes.
+///
+pub fn read_children_usage() -> ResourceUsage {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return ResourceUsage::default();
+    }
+
+    let millis = |tv: &libc::timeval| tv.tv_sec as u64 * 1000 + tv.tv_usec as u64 / 1000;
+    let maxrss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    ResourceUsage {
+        utime_ms: millis(&usage.ru_utime),
+        stime_ms: millis(&usage.ru_stime),
+        maxrss_bytes: usage.ru_maxrss as u64 * maxrss_unit,
+        minflt: usage.ru_minflt as u64,
+        majflt: usage.ru_majflt as u64,
+        inblock: usage.ru_inblock as u64,
+        oublock: usage.ru_oublock as u64,
+        nvcsw: usage.ru_nvcsw as u64,
+        nivcsw: usage.ru_nivcsw as u64,
+    }
+}
+
+///
+/// Read the smoothed round-trip time the kernel measured for the TCP socket `fd`
+/// with `getsockopt(TCP_INFO)`. `None` is returned if it cannot be read.
+///
+#[cfg(target_os = "linux")]
+pub fn read_tcp_rtt(fd: RawFd) -> Option<std::time::Duration> {
+    let mut info = TcpInfo::default();
+    let mut len = std::mem::size_of::<TcpInfo>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == 0 && len as usize >= std::mem::size_of::<TcpInfo>() {
+        Some(std::time::Duration::from_micros(u64::from(info.rtt)))
+    } else {
+        None
+    }
+}
+
+///
+/// The TCP round-trip time is read on Linux only so `None` is always returned.
+///
+#[cfg(not(target_os = "linux"))]
+pub fn read_tcp_rtt(_fd: std::os::unix::io::RawFd) -> Option<std::time::Duration> {
+    None
+}
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn proc_pidinfo(
+        pid: libc::c_int,
+        flavor: libc::c_int,
+        arg: u64,
+        buffer: *mut libc::c_void,
+        buffersize: libc::c_int,
+    ) -> libc::c_int;
+}
+
+///
+/// Read stats of the process with `pid` with `proc_pidinfo(PROC_PIDTASKALLINFO)`.
+/// `None` is returned if the process does not exist and the error is returned
+/// if stats cannot be read for another reason.
+///
+#[cfg(target_os = "macos")]
+pub fn try_read_process_stats(pid: u32) -> io::Result<Option<ProcessStats>> {
+    use std::ffi::CStr;
+    use std::mem;
+
+    let mut info: libc::proc_taskallinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_taskallinfo>() as libc::c_int;
+    let read = unsafe {
+        proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTASKALLINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if read != size {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ESRCH) => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let command = unsafe { CStr::from_ptr(info.pbsd.pbi_comm.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+    let state = match info.pbsd.pbi_status {
+        1 => "Idle",
+        2 => "Running",
+        3 => "Sleeping",
+        4 => "Stopped",
+        5 => "Zombie",
+        _ => "Unknown",
+    };
+
+    Ok(Some(ProcessStats {
+        pid: info.pbsd.pbi_pid,
+        command,
+        state: state.to_owned(),
+        pgrp: Some(info.pbsd.pbi_pgid),
+        session: None,
+        cpu_time_ms: Some((info.ptinfo.pti_total_user + info.ptinfo.pti_total_system) / 1_000_000),
+        rss_bytes: Some(info.ptinfo.pti_resident_size),
+        threads: Some(info.ptinfo.pti_threadnum as u32),
+        io_read_bytes: None,
+        io_write_bytes: None,
+        voluntary_ctxt_switches: None,
+        nonvoluntary_ctxt_switches: None,
+        zombie_children: None,
+        vm_peak_bytes: None,
+        rss_hwm_bytes: None,
+        ..ProcessStats::default()
+    }))
+}
+
+///
+/// Process stats are not supported on the platform so the error is always returned.
+///
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn try_read_process_stats(_pid: u32) -> io::Result<Option<ProcessStats>> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+///
+/// Make the list of signals the platform has which the tool can subscribe for,
+/// i.e. all signals except ones `signal_hook` forbids to handle.
+/// Terminal stop signals keep their default action so the tool stops
+/// along with the command on `Ctrl+Z`.
+///
+pub fn allowed_signals() -> Vec<i32> {
+    let stops = [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+    Signal::iterator()
+        .filter(|s| !stops.contains(s))
+        .map(|s| s as i32)
+        .filter(|s| !signal_hook::FORBIDDEN.contains(s))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_of_own_process_are_read() {
+        let stats = read_process_stats(std::process::id()).unwrap();
+        assert_eq!(stats.pid, std::process::id());
+        assert!(!stats.command.is_empty());
+        assert!(stats.rss_bytes.unwrap() > 0);
+        assert!(stats.threads.unwrap() >= 1);
+        assert!(stats.cpu_time_ms.is_some());
+    }
+
+    #[test]
+    fn reaped_process_does_not_exist() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        assert!(try_read_process_stats(child.id()).is_ok());
+        child.wait().unwrap();
+        assert!(try_read_process_stats(child.id()).unwrap().is_none());
+    }
+
+    #[test]
+    fn children_usage_grows_after_child_exits() {
+        let before = read_children_usage();
+        std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done"])
+            .status()
+            .unwrap();
+        let usage = read_children_usage().since(&before);
+        assert!(usage.maxrss_bytes > 0);
+        assert!(usage.minflt > 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn memory_maps_of_own_process_are_counted() {
+        let mut stats = ProcessStats::default();
+        read_memory_maps(std::process::id(), &mut stats);
+        assert!(stats.anon_maps_count.unwrap() >= 1);
+        assert!(stats.anon_maps_bytes.unwrap() > 0);
+        assert!(stats.file_maps_count.unwrap() >= 1);
+        assert_eq!(stats.maps_truncated, Some(false));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bound_socket_is_counted() {
+        let _socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let counts = read_fd_types(std::process::id()).unwrap();
+        assert!(counts.socket >= 1);
+        assert!(counts.pipe >= 2);
+        nix::unistd::close(read).unwrap();
+        nix::unistd::close(write).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cgroup_limits_are_read() {
+        let root = std::env::temp_dir().join(format!("owl-test-{}-cgroup", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let pid = std::process::id();
+
+        // Not the unified hierarchy
+        assert!(read_cgroup_limits(&root, pid).is_none());
+
+        std::fs::write(root.join("cgroup.controllers"), "cpu memory\n").unwrap();
+        std::fs::write(root.join("memory.max"), "1073741824\n").unwrap();
+        std::fs::write(root.join("cpu.max"), "50000 100000\n").unwrap();
+        let limits = read_cgroup_limits(&root, pid).unwrap();
+        assert_eq!(limits.memory_max, Some(1 << 30));
+        assert_eq!(limits.cpu_max, Some(0.5));
+
+        std::fs::write(root.join("memory.max"), "max\n").unwrap();
+        std::fs::write(root.join("cpu.max"), "max 100000\n").unwrap();
+        let limits = read_cgroup_limits(&root, pid).unwrap();
+        assert_eq!(limits.memory_max, None);
+        assert_eq!(limits.cpu_max, None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn allowed_signals_exclude_forbidden_and_stops() {
+        let signals = allowed_signals();
+        for sig in [
+            libc::SIGKILL,
+            libc::SIGSTOP,
+            libc::SIGTSTP,
+            libc::SIGTTIN,
+            libc::SIGTTOU,
+        ] {
+            assert!(!signals.contains(&sig), "{} is allowed", sig);
+        }
+        for sig in [
+            libc::SIGHUP,
+            libc::SIGINT,
+            libc::SIGTERM,
+            libc::SIGUSR1,
+            libc::SIGCHLD,
+        ] {
+            assert!(signals.contains(&sig), "{} is not allowed", sig);
+        }
+    }
+}
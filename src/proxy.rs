@@ -0,0 +1,184 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The proxy which re-emits records received to the upstream receiver in the order
+//! they arrive, so records of every sender keep their sequence, batching several
+//! records in one datagram.
+//!
+
+use crate::envelope;
+use std::io;
+use std::net::UdpSocket;
+
+///
+/// The largest payload of the UDP datagram.
+///
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+///
+/// The field of records the receiver which proxied them is stored in.
+///
+const RELAY_HOST_FIELD: &str = "relay_host";
+
+///
+/// The upstream receiver and records waiting to be sent to it.
+///
+pub struct Proxy {
+    target: String,
+    batch: usize,
+    host: String,
+    socket: UdpSocket,
+    pending: Vec<String>,
+    pending_size: usize,
+}
+
+impl Proxy {
+    ///
+    /// Make the proxy to the UDP receiver at `target` which sends up to `batch`
+    /// records in one datagram and marks records with the receiver `host`.
+    ///
+    pub fn new(target: &str, batch: usize, host: &str, bind_addr: &str) -> io::Result<Proxy> {
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(target)?;
+        Ok(Proxy {
+            target: target.to_owned(),
+            batch: batch.max(1),
+            host: host.to_owned(),
+            socket,
+            pending: Vec::new(),
+            pending_size: 0,
+        })
+    }
+
+    ///
+    /// The address of the upstream receiver.
+    ///
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    ///
+    /// Queue the `record` and send the batch once it is full. The batch is sent
+    /// earlier if the record does not fit into the datagram with it.
+    ///
+    pub fn push(&mut self, record: &str) -> io::Result<()> {
+        let record = mark_relay_host(record, &self.host);
+        if !self.pending.is_empty() && self.pending_size + record.len() + 2 > MAX_DATAGRAM_SIZE {
+            self.flush()?;
+        }
+        self.pending_size += record.len() + 1;
+        self.pending.push(record);
+        if self.pending.len() >= self.batch {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Send records queued in one datagram. Records of the batch failed
+    /// to be sent are dropped.
+    ///
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let payload = if self.pending.len() == 1 {
+            self.pending[0].clone()
+        } else if self.pending.iter().all(|record| record.starts_with('{')) {
+            format!("[{}]", self.pending.join(","))
+        } else {
+            self.pending.join("\n")
+        };
+        self.pending.clear();
+        self.pending_size = 0;
+
+        self.socket.send(payload.as_bytes()).map(|_| ())
+    }
+}
+
+///
+/// Add the `relay_host` field with the `host` to the SSDPD or JSON `record`.
+/// Records proxied before keep the host of the first proxy.
+///
+fn mark_relay_host(record: &str, host: &str) -> String {
+    if let Some(rest) = record.strip_prefix('{') {
+        if record.contains(&format!("\"{}\":", RELAY_HOST_FIELD)) {
+            return record.to_owned();
+        }
+        let delimiter = if rest.trim_start().starts_with('}') {
+            ""
+        } else {
+            ","
+        };
+        format!(
+            "{{\"{}\":{}{}{}",
+            RELAY_HOST_FIELD,
+            envelope::json_string(host),
+            delimiter,
+            rest
+        )
+    } else {
+        let field = format!("{}=", RELAY_HOST_FIELD);
+        if record.split("||").any(|part| part.starts_with(&field)) {
+            return record.to_owned();
+        }
+        format!("{}||{}{}", record, field, host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn records_are_batched_in_order() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        upstream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let target = upstream.local_addr().unwrap().to_string();
+        let mut proxy = Proxy::new(&target, 2, "edge", "127.0.0.1:0").unwrap();
+
+        proxy.push("1||2||rsync||Running").unwrap();
+        proxy.push("1||3||cron||Sleeping||relay_host=core").unwrap();
+        assert_eq!(
+            receive(&upstream),
+            "1||2||rsync||Running||relay_host=edge\n1||3||cron||Sleeping||relay_host=core"
+        );
+
+        proxy.push(r#"{"pid":2}"#).unwrap();
+        proxy.push("{}").unwrap();
+        assert_eq!(
+            receive(&upstream),
+            r#"[{"relay_host":"edge","pid":2},{"relay_host":"edge"}]"#
+        );
+
+        proxy.push(r#"{"pid":4}"#).unwrap();
+        proxy.flush().unwrap();
+        assert_eq!(receive(&upstream), r#"{"relay_host":"edge","pid":4}"#);
+        proxy.flush().unwrap();
+    }
+}
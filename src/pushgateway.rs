@@ -0,0 +1,237 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The client of the Prometheus push gateway the state is pushed to as metrics.
+//!
+//! Metrics are rendered in the Prometheus text exposition format and replace
+//! the group of the job with HTTP `PUT` on every heartbeat. The group is removed
+//! with HTTP `DELETE`. Only plain `http://` URLs are supported, the connection
+//! is made for every request and closed after the response.
+//!
+
+use crate::message::{StateMessage, Value};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const URL_SCHEME: &str = "http://";
+const JOB_PATH: &str = "/metrics/job/";
+const INSTANCE_PATH: &str = "/instance/";
+const DEFAULT_PORT: u16 = 9091;
+const METRIC_PREFIX: &str = "owl_";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+
+///
+/// The push gateway group metrics are pushed to.
+///
+#[derive(Clone, Debug)]
+pub struct Pushgateway {
+    /// The address of the gateway as `host:port`.
+    addr: String,
+
+    /// The path of the group, e.g. `/metrics/job/rsync/instance/host1`.
+    path: String,
+}
+
+impl Pushgateway {
+    ///
+    /// Make the client of the group in the `url`, e.g. `http://localhost:9091/metrics/job/rsync`,
+    /// with the `instance` label added to the grouping key if given.
+    ///
+    pub fn new(url: &str, instance: Option<&str>) -> Result<Pushgateway, String> {
+        let rest = url
+            .strip_prefix(URL_SCHEME)
+            .ok_or_else(|| format!("push gateway URL '{}' is not the http:// URL", url))?;
+        let (host, path) = match rest.find('/') {
+            Some(pos) => rest.split_at(pos),
+            None => (rest, ""),
+        };
+        if host.is_empty() || !path.starts_with(JOB_PATH) || path.len() == JOB_PATH.len() {
+            return Err(format!(
+                "push gateway URL '{}' has no {}<job> path",
+                url, JOB_PATH
+            ));
+        }
+
+        let addr = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        let mut path = path.trim_end_matches('/').to_owned();
+        if let Some(instance) = instance {
+            path.push_str(INSTANCE_PATH);
+            path.push_str(&escape_path(instance));
+        }
+
+        Ok(Pushgateway { addr, path })
+    }
+
+    ///
+    /// Get the URL of the group.
+    ///
+    pub fn url(&self) -> String {
+        format!("{}{}{}", URL_SCHEME, self.addr, self.path)
+    }
+
+    ///
+    /// Replace metrics of the group with metrics of `msgs`.
+    ///
+    pub fn push(&self, msgs: &[StateMessage]) -> io::Result<()> {
+        self.request("PUT", &render(msgs))
+    }
+
+    ///
+    /// Delete metrics of the group.
+    ///
+    pub fn delete(&self) -> io::Result<()> {
+        self.request("DELETE", "")
+    }
+
+    ///
+    /// Send the request with `method` and `body` and check the gateway replies
+    /// with the success status.
+    ///
+    fn request(&self, method: &str, body: &str) -> io::Result<()> {
+        let timeout = Duration::from_secs(REQUEST_TIMEOUT_SECS);
+        let addr = self
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            self.path,
+            self.addr,
+            CONTENT_TYPE,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        if code.starts_with('2') {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("push gateway replied '{}'", status.trim()),
+            ))
+        }
+    }
+}
+
+///
+/// Render numeric fields of `msgs` in the text exposition format. Every field
+/// becomes the gauge `owl_<field>` where `_pct` is spelled `_percent`, e.g.
+/// `owl_cpu_percent{name="rsync",pid="1281"} 42.1`, and the state becomes
+/// `owl_state{...,state="Sleeping"} 1`, the same as `hollow --metrics-port` serves.
+///
+pub fn render(msgs: &[StateMessage]) -> String {
+    let mut gauges: Vec<(String, Vec<String>)> = Vec::new();
+    for msg in msgs.iter() {
+        let labels = format!("name=\"{}\",pid=\"{}\"", escape_label(&msg.name), msg.pid);
+        let mut lines = vec![(
+            "state".to_owned(),
+            format!("{{{},state=\"{}\"}} 1", labels, escape_label(&msg.state)),
+        )];
+        for (field, value) in msg.fields.iter() {
+            let value = match value {
+                Value::UInt(v) => *v as f64,
+                Value::Float(v) if v.is_finite() => *v,
+                _ => continue,
+            };
+            lines.push((
+                field.replace("_pct", "_percent"),
+                format!("{{{}}} {}", labels, value),
+            ));
+        }
+
+        for (metric, line) in lines {
+            match gauges.iter_mut().find(|(name, _)| *name == metric) {
+                Some((_, samples)) => samples.push(line),
+                None => gauges.push((metric, vec![line])),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (metric, lines) in gauges {
+        let _ = writeln!(out, "# TYPE {}{} gauge", METRIC_PREFIX, metric);
+        for line in lines {
+            let _ = writeln!(out, "{}{}{}", METRIC_PREFIX, metric, line);
+        }
+    }
+
+    out
+}
+
+///
+/// Escape the label value for the text exposition format.
+///
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+///
+/// Percent-encode the path segment except unreserved characters.
+///
+fn escape_path(value: &str) -> String {
+    let mut escaped = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            let _ = write!(escaped, "%{:02X}", byte);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_is_made_of_url_and_instance() {
+        let gateway =
+            Pushgateway::new("http://localhost/metrics/job/rsync/", Some("web 1")).unwrap();
+        assert_eq!(
+            gateway.url(),
+            "http://localhost:9091/metrics/job/rsync/instance/web%201"
+        );
+
+        let gateway = Pushgateway::new("http://10.0.0.1:9000/metrics/job/rsync", None).unwrap();
+        assert_eq!(gateway.url(), "http://10.0.0.1:9000/metrics/job/rsync");
+
+        assert!(Pushgateway::new("https://localhost/metrics/job/rsync", None).is_err());
+        assert!(Pushgateway::new("http://localhost/metrics/job/", None).is_err());
+        assert!(Pushgateway::new("http:///metrics/job/rsync", None).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The bounded queue of deliveries which the producer never blocks on.
+//!
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+///
+/// The bounded queue where pushing into the full queue drops the oldest item.
+///
+pub struct SendQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl<T> SendQueue<T> {
+    pub fn new(capacity: usize) -> SendQueue<T> {
+        SendQueue {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    ///
+    /// Push the `item` to the end of the queue. If the queue is full its oldest
+    /// item is dropped and returned.
+    ///
+    pub fn push(&self, item: T) -> Option<T> {
+        let mut items = self.items.lock().unwrap_or_else(|err| err.into_inner());
+        let dropped = if items.len() >= self.capacity {
+            items.pop_front()
+        } else {
+            None
+        };
+        items.push_back(item);
+        self.ready.notify_one();
+
+        dropped
+    }
+
+    ///
+    /// Pop the oldest item of the queue waiting until there is one.
+    ///
+    pub fn pop(&self) -> T {
+        let mut items = self.items.lock().unwrap_or_else(|err| err.into_inner());
+        loop {
+            match items.pop_front() {
+                Some(item) => return item,
+                None => {
+                    items = self
+                        .ready
+                        .wait(items)
+                        .unwrap_or_else(|err| err.into_inner())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn oldest_items_are_dropped_for_slow_consumer() {
+        let queue = Arc::new(SendQueue::new(3));
+        let consumer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                let mut items = Vec::new();
+                for _ in 0..3 {
+                    thread::sleep(Duration::from_millis(100));
+                    items.push(queue.pop());
+                }
+                items
+            })
+        };
+
+        let mut dropped = 0;
+        for item in 0..10 {
+            if queue.push(item).is_some() {
+                dropped += 1;
+            }
+        }
+        assert_eq!(dropped, 7);
+        assert_eq!(consumer.join().unwrap(), [7, 8, 9]);
+    }
+}
@@ -0,0 +1,291 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The interactive control of the replay speed from the keyboard.
+//!
+//! - `+` doubles and `-` halves the speed.
+//! - `0` pauses and `1` resumes in real time.
+//! - `space` handles the next message and pauses again.
+//! - `q` or `Ctrl+C` stops the replay.
+//!
+
+use crate::clock;
+use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+use nix::unistd::isatty;
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::SystemTime;
+
+const STDIN: RawFd = 0;
+const CTRL_C: u8 = 3;
+
+///
+/// The command which changes the replay.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Faster,
+    Slower,
+    Pause,
+    Realtime,
+    Step,
+    Quit,
+}
+
+impl Command {
+    ///
+    /// Get the command bound to the `key` pressed.
+    ///
+    pub fn from_key(key: u8) -> Option<Command> {
+        match key {
+            b'+' => Some(Command::Faster),
+            b'-' => Some(Command::Slower),
+            b'0' => Some(Command::Pause),
+            b'1' => Some(Command::Realtime),
+            b' ' => Some(Command::Step),
+            b'q' | CTRL_C => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+///
+/// The player keeps the replay in time of the log changing the speed
+/// with commands received.
+///
+pub struct Player {
+    speed: f64,
+    paused: bool,
+    step: bool,
+    commands: Receiver<Command>,
+}
+
+impl Player {
+    ///
+    /// Make the player with the initial `speed` where 0 starts it paused.
+    ///
+    pub fn new(speed: f64, commands: Receiver<Command>) -> Player {
+        Player {
+            speed: if speed > 0.0 { speed } else { 1.0 },
+            paused: speed <= 0.0,
+            step: false,
+            commands,
+        }
+    }
+
+    ///
+    /// Get the speed the clock runs with, 0 while paused.
+    ///
+    pub fn speed(&self) -> f64 {
+        if self.paused {
+            0.0
+        } else {
+            self.speed
+        }
+    }
+
+    ///
+    /// Wait until the clock reaches the time of the log `at` applying commands
+    /// received meanwhile. `false` is returned if the replay should stop.
+    /// Without the keyboard the player only waits and resumes if paused.
+    ///
+    pub fn wait_until(&mut self, at: SystemTime) -> bool {
+        loop {
+            if self.paused {
+                if self.step {
+                    self.step = false;
+                    clock::start_replay(at, 0.0);
+                    return true;
+                }
+                match self.commands.recv() {
+                    Ok(command) => {
+                        if !self.apply(command) {
+                            return false;
+                        }
+                    }
+                    Err(_) => {
+                        self.paused = false;
+                        clock::set_speed(self.speed);
+                    }
+                }
+                continue;
+            }
+
+            let left = clock::real_time_until(at);
+            if left.as_nanos() == 0 {
+                return true;
+            }
+            match self.commands.recv_timeout(left) {
+                Ok(command) => {
+                    if !self.apply(command) {
+                        return false;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(clock::real_time_until(at));
+                    return true;
+                }
+            }
+        }
+    }
+
+    ///
+    /// Apply the `command` and report the replay speed.
+    /// `false` is returned if the replay should stop.
+    ///
+    fn apply(&mut self, command: Command) -> bool {
+        match command {
+            Command::Faster => self.speed *= 2.0,
+            Command::Slower => self.speed /= 2.0,
+            Command::Pause => self.paused = true,
+            Command::Realtime => {
+                self.speed = 1.0;
+                self.paused = false;
+            }
+            Command::Step => {
+                self.paused = true;
+                self.step = true;
+            }
+            Command::Quit => return false,
+        }
+
+        clock::set_speed(self.speed());
+        if self.paused {
+            eprintln!("replay: paused at {}x", self.speed);
+        } else {
+            eprintln!("replay: {}x", self.speed);
+        }
+        true
+    }
+}
+
+///
+/// The keyboard which sends commands for keys pressed. The terminal reads keys
+/// without echo and line buffering until the keyboard is dropped.
+///
+pub struct Keyboard {
+    saved: Option<Termios>,
+}
+
+impl Keyboard {
+    ///
+    /// Start reading keys into `commands` if STDIN is the terminal.
+    /// Otherwise no commands are sent at all.
+    ///
+    pub fn open(commands: Sender<Command>) -> Keyboard {
+        if !isatty(STDIN).unwrap_or(false) {
+            return Keyboard { saved: None };
+        }
+
+        let saved = termios::tcgetattr(STDIN).ok();
+        if let Some(saved) = saved.as_ref() {
+            let mut raw = saved.clone();
+            raw.local_flags
+                .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+            let _ = termios::tcsetattr(STDIN, SetArg::TCSANOW, &raw);
+        }
+
+        thread::spawn(move || read_keys(&commands));
+        Keyboard { saved }
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.as_ref() {
+            let _ = termios::tcsetattr(STDIN, SetArg::TCSANOW, saved);
+        }
+    }
+}
+
+///
+/// Read keys from STDIN and send commands bound to them until
+/// STDIN is closed or the player is gone.
+///
+fn read_keys(commands: &Sender<Command>) {
+    let mut key = [0u8; 1];
+    let mut stdin = io::stdin();
+    while let Ok(1) = stdin.read(&mut key) {
+        if let Some(command) = Command::from_key(key[0]) {
+            if commands.send(command).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    ///
+    /// Test the real time `elapsed` is `millis` within 20% tolerance.
+    ///
+    fn near(elapsed: Duration, millis: u64) -> bool {
+        let elapsed = elapsed.as_secs_f64() * 1000.0;
+        (elapsed - millis as f64).abs() <= millis as f64 * 0.2
+    }
+
+    #[test]
+    fn keys_are_bound_to_commands() {
+        assert_eq!(Command::from_key(b'+'), Some(Command::Faster));
+        assert_eq!(Command::from_key(b'0'), Some(Command::Pause));
+        assert_eq!(Command::from_key(CTRL_C), Some(Command::Quit));
+        assert_eq!(Command::from_key(b'x'), None);
+    }
+
+    #[test]
+    fn commands_change_replay_timing() {
+        let (keys, commands) = mpsc::channel();
+        let mut player = Player::new(1.0, commands);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        clock::start_replay(start, player.speed());
+
+        // Twice faster replays 400ms of the log in 200ms
+        keys.send(Command::Faster).unwrap();
+        let started = Instant::now();
+        assert!(player.wait_until(start + Duration::from_millis(400)));
+        assert!(near(started.elapsed(), 200), "{:?}", started.elapsed());
+
+        // The pause stops the clock until the replay resumes in real time
+        keys.send(Command::Pause).unwrap();
+        let resume = keys.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            resume.send(Command::Realtime).unwrap();
+        });
+        let started = Instant::now();
+        assert!(player.wait_until(start + Duration::from_millis(600)));
+        assert!(near(started.elapsed(), 400), "{:?}", started.elapsed());
+        assert_eq!(player.speed(), 1.0);
+
+        // The step jumps to the next message and pauses again
+        keys.send(Command::Step).unwrap();
+        let started = Instant::now();
+        assert!(player.wait_until(start + Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(player.speed(), 0.0);
+
+        keys.send(Command::Quit).unwrap();
+        assert!(!player.wait_until(start + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The samples of process stats collected between deliveries which are
+//! counted and summarized when stats are collected more often than delivered.
+//!
+
+use crate::message::Value;
+use crate::platform::ProcessStats;
+use std::time::Instant;
+
+///
+/// The smallest, the largest, and the sum of values sampled.
+///
+#[derive(Clone, Copy, Default)]
+struct Range {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+impl Range {
+    ///
+    /// Account the `value` sampled.
+    ///
+    fn add(&mut self, value: f64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    ///
+    /// Push the smallest, the largest, and the average value as `names` fields.
+    /// The smallest and the largest values of `integral` metrics are integers.
+    ///
+    fn push_fields(
+        &self,
+        names: [&'static str; 3],
+        integral: bool,
+        fields: &mut Vec<(&'static str, Value)>,
+    ) {
+        let value = |v: f64| {
+            if integral {
+                Value::UInt(v as u64)
+            } else {
+                Value::Float(v)
+            }
+        };
+        if self.count > 0 {
+            fields.push((names[0], value(self.min)));
+            fields.push((names[1], value(self.max)));
+            fields.push((names[2], Value::Float(self.sum / self.count as f64)));
+        }
+    }
+}
+
+///
+/// The samples of the process since the last delivery.
+///
+#[derive(Default)]
+pub struct Samples {
+    count: u64,
+    cpu_pct: Range,
+    rss_bytes: Range,
+    threads: Range,
+    prev_cpu: Option<(Instant, u64)>,
+}
+
+impl Samples {
+    ///
+    /// Account `stats` of the process sampled at `now`. The CPU usage
+    /// is computed since the previous sample.
+    ///
+    pub fn add(&mut self, stats: &ProcessStats, now: Instant) {
+        self.count += 1;
+        if let (Some((at, prev_ms)), Some(cpu_ms)) = (self.prev_cpu, stats.cpu_time_ms) {
+            let wall_ms = now.duration_since(at).as_secs_f64() * 1000.0;
+            if wall_ms > 0.0 && cpu_ms >= prev_ms {
+                self.cpu_pct
+                    .add((cpu_ms - prev_ms) as f64 / wall_ms * 100.0);
+            }
+        }
+        self.prev_cpu = stats.cpu_time_ms.map(|ms| (now, ms));
+        if let Some(rss) = stats.rss_bytes {
+            self.rss_bytes.add(rss as f64);
+        }
+        if let Some(threads) = stats.threads {
+            self.threads.add(threads as f64);
+        }
+    }
+
+    ///
+    /// Make fields of samples, the number of samples and the smallest,
+    /// the largest, and the average metrics if `aggregated`.
+    ///
+    pub fn fields(&self, aggregated: bool) -> Vec<(&'static str, Value)> {
+        let mut fields = vec![("sample_count", Value::UInt(self.count))];
+        if aggregated {
+            self.cpu_pct.push_fields(
+                ["cpu_pct_min", "cpu_pct_max", "cpu_pct_avg"],
+                false,
+                &mut fields,
+            );
+            self.rss_bytes.push_fields(
+                ["rss_bytes_min", "rss_bytes_max", "rss_bytes_avg"],
+                true,
+                &mut fields,
+            );
+            self.threads.push_fields(
+                ["threads_min", "threads_max", "threads_avg"],
+                true,
+                &mut fields,
+            );
+        }
+
+        fields
+    }
+
+    ///
+    /// Forget samples delivered keeping the last CPU sample the usage
+    /// is computed from.
+    ///
+    pub fn reset(&mut self) {
+        *self = Samples {
+            prev_cpu: self.prev_cpu,
+            ..Samples::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn field(fields: &[(&'static str, Value)], name: &str) -> String {
+        fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| format!("{:?}", value))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn samples_are_summarized_until_reset() {
+        let start = Instant::now();
+        let mut samples = Samples::default();
+        for (i, (rss, cpu_ms)) in [(100, 0), (300, 50), (200, 150)].iter().enumerate() {
+            let stats = ProcessStats {
+                rss_bytes: Some(*rss),
+                threads: Some(i as u32 + 1),
+                cpu_time_ms: Some(*cpu_ms),
+                ..ProcessStats::default()
+            };
+            samples.add(&stats, start + Duration::from_millis(100 * i as u64));
+        }
+
+        let fields = samples.fields(false);
+        assert_eq!(fields.len(), 1);
+        assert_eq!(field(&fields, "sample_count"), "UInt(3)");
+
+        let fields = samples.fields(true);
+        assert_eq!(field(&fields, "rss_bytes_min"), "UInt(100)");
+        assert_eq!(field(&fields, "rss_bytes_max"), "UInt(300)");
+        assert_eq!(field(&fields, "rss_bytes_avg"), "Float(200.0)");
+        assert_eq!(field(&fields, "threads_max"), "UInt(3)");
+        assert_eq!(field(&fields, "cpu_pct_min"), "Float(50.0)");
+        assert_eq!(field(&fields, "cpu_pct_max"), "Float(100.0)");
+
+        // The CPU usage of the next sample is computed since the last one
+        samples.reset();
+        assert_eq!(field(&samples.fields(true), "sample_count"), "UInt(0)");
+        let stats = ProcessStats {
+            cpu_time_ms: Some(175),
+            ..ProcessStats::default()
+        };
+        samples.add(&stats, start + Duration::from_millis(300));
+        assert_eq!(field(&samples.fields(true), "cpu_pct_avg"), "Float(25.0)");
+    }
+}
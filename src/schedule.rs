@@ -0,0 +1,143 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The time-of-day windows the state is delivered within, e.g. business hours
+//! when backends charge per event ingested. Windows are in the local time zone
+//! of the system and the window which ends before it starts, e.g. `22:00-06:00`,
+//! lasts over midnight.
+//!
+
+const HOURS_PER_DAY: u32 = 24;
+const MINUTES_PER_HOUR: u32 = 60;
+const MINUTES_PER_DAY: u32 = HOURS_PER_DAY * MINUTES_PER_HOUR;
+
+///
+/// The windows of minutes of the day, the start is inclusive and the end is exclusive.
+///
+pub struct ActiveHours {
+    windows: Vec<(u32, u32)>,
+}
+
+impl ActiveHours {
+    ///
+    /// Parse the comma separated list of windows `HH:MM-HH:MM`,
+    /// e.g. `09:00-12:00,13:00-17:00`.
+    ///
+    pub fn parse(value: &str) -> Result<ActiveHours, String> {
+        let windows = value
+            .split(',')
+            .map(str::trim)
+            .map(|window| {
+                let (start, end) = window.split_once('-').ok_or_else(|| {
+                    format!("invalid active hours '{}', expected HH:MM-HH:MM", window)
+                })?;
+                match (parse_time(start), parse_time(end)) {
+                    (Some(start), Some(end)) if start != end => Ok((start, end)),
+                    _ => Err(format!(
+                        "invalid active hours '{}', expected HH:MM-HH:MM",
+                        window
+                    )),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(ActiveHours { windows })
+    }
+
+    ///
+    /// Test the local time now is within one of windows.
+    ///
+    pub fn is_active_now(&self) -> bool {
+        match local_minute_of_day() {
+            Some(minute) => self.is_active(minute),
+            // Unknown time must not silence the delivery
+            None => true,
+        }
+    }
+
+    ///
+    /// Test the `minute` of the day is within one of windows.
+    ///
+    fn is_active(&self, minute: u32) -> bool {
+        self.windows.iter().any(|(start, end)| {
+            if start < end {
+                minute >= *start && minute < *end
+            } else {
+                minute >= *start || minute < *end
+            }
+        })
+    }
+}
+
+///
+/// Parse the time of the day `HH:MM` into minutes since the midnight.
+/// `24:00` is the end of the day.
+///
+fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let hours = hours.parse::<u32>().ok()?;
+    let minutes = minutes.parse::<u32>().ok()?;
+    if hours > HOURS_PER_DAY || minutes >= MINUTES_PER_HOUR {
+        return None;
+    }
+    Some(hours * MINUTES_PER_HOUR + minutes).filter(|v| *v <= MINUTES_PER_DAY)
+}
+
+///
+/// Get minutes since the local midnight.
+///
+fn local_minute_of_day() -> Option<u32> {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+        Some(tm.tm_hour as u32 * MINUTES_PER_HOUR + tm.tm_min as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_are_parsed() {
+        let hours = ActiveHours::parse("09:00-12:00, 13:00-24:00").unwrap();
+        assert_eq!(hours.windows, vec![(540, 720), (780, 1440)]);
+
+        for value in &["", "09:00", "09:00-09:00", "25:00-26:00", "09:60-10:00"] {
+            assert!(ActiveHours::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn minutes_within_windows_are_active() {
+        let hours = ActiveHours::parse("09:00-12:00,13:00-17:00").unwrap();
+        assert!(!hours.is_active(8 * 60 + 59));
+        assert!(hours.is_active(9 * 60));
+        assert!(!hours.is_active(12 * 60));
+        assert!(hours.is_active(16 * 60 + 59));
+        assert!(!hours.is_active(17 * 60));
+
+        let night = ActiveHours::parse("22:00-06:00").unwrap();
+        assert!(night.is_active(23 * 60));
+        assert!(night.is_active(0));
+        assert!(!night.is_active(6 * 60));
+        assert!(!night.is_active(12 * 60));
+    }
+}
@@ -0,0 +1,477 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The JSON Schema of the state message owl sends in the `json` format.
+//!
+//! The schema is written by hand after `StateMessage::to_json` and is shared
+//! by owl and hollow so both print the same document with `--json-schema`.
+//! Every field the tool emits is listed with its type, other fields are allowed
+//! so receivers validating with the schema accept messages of newer versions.
+//!
+
+use std::fmt::Write;
+
+///
+/// The JSON Schema draft the document conforms to.
+///
+const SCHEMA_DRAFT: &str = "http://json-schema.org/draft-07/schema#";
+
+///
+/// Types of field values as they are encoded in JSON, floats are `null`
+/// when they are not finite numbers.
+///
+const FIELD_UINT: &str = r#""type": "integer", "minimum": 0"#;
+const FIELD_FLOAT: &str = r#""type": ["number", "null"]"#;
+const FIELD_BOOL: &str = r#""type": "boolean""#;
+const FIELD_TEXT: &str = r#""type": "string""#;
+const FIELD_TEXTS: &str = r#""type": "array", "items": { "type": "string" }"#;
+const FIELD_FLOATS: &str = r#""type": "array", "items": { "type": ["number", "null"] }"#;
+
+///
+/// The optional fields of the message which depend on options and the platform
+/// with their types and descriptions.
+///
+const FIELDS: [(&str, &str, &str); 73] = [
+    ("pgrp", FIELD_UINT, "The id of the process group."),
+    ("session", FIELD_UINT, "The id of the session."),
+    (
+        "cpu_time_ms",
+        FIELD_UINT,
+        "The time spent in user and kernel mode in milliseconds.",
+    ),
+    ("rss_bytes", FIELD_UINT, "The resident set size in bytes."),
+    ("threads", FIELD_UINT, "The number of threads."),
+    (
+        "io_read_bytes",
+        FIELD_UINT,
+        "The number of bytes read from the storage.",
+    ),
+    (
+        "io_write_bytes",
+        FIELD_UINT,
+        "The number of bytes written to the storage.",
+    ),
+    (
+        "voluntary_ctxt_switches",
+        FIELD_UINT,
+        "The number of voluntary context switches.",
+    ),
+    (
+        "nonvoluntary_ctxt_switches",
+        FIELD_UINT,
+        "The number of involuntary context switches.",
+    ),
+    (
+        "zombie_children",
+        FIELD_UINT,
+        "The number of children which exited but are not reaped by the process.",
+    ),
+    (
+        "vm_peak_bytes",
+        FIELD_UINT,
+        "The peak virtual memory size in bytes.",
+    ),
+    (
+        "rss_hwm_bytes",
+        FIELD_UINT,
+        "The peak resident set size in bytes.",
+    ),
+    (
+        "anon_maps_count",
+        FIELD_UINT,
+        "The number of anonymous memory mappings.",
+    ),
+    (
+        "anon_maps_bytes",
+        FIELD_UINT,
+        "The size of anonymous memory mappings in bytes.",
+    ),
+    (
+        "file_maps_count",
+        FIELD_UINT,
+        "The number of file-backed memory mappings.",
+    ),
+    (
+        "maps_truncated",
+        FIELD_BOOL,
+        "Only the first memory mappings are counted.",
+    ),
+    (
+        "fd_count",
+        FIELD_UINT,
+        "The number of open file descriptors.",
+    ),
+    ("fd_sockets", FIELD_UINT, "The number of open sockets."),
+    (
+        "fd_pipes",
+        FIELD_UINT,
+        "The number of open pipes and FIFOs.",
+    ),
+    (
+        "fd_anon_inodes",
+        FIELD_UINT,
+        "The number of open anonymous inodes.",
+    ),
+    (
+        "fd_regular",
+        FIELD_UINT,
+        "The number of open regular files.",
+    ),
+    (
+        "fd_directories",
+        FIELD_UINT,
+        "The number of open directories.",
+    ),
+    (
+        "fd_character",
+        FIELD_UINT,
+        "The number of open character devices.",
+    ),
+    ("fd_block", FIELD_UINT, "The number of open block devices."),
+    (
+        "fd_other",
+        FIELD_UINT,
+        "The number of open file descriptors of other types.",
+    ),
+    (
+        "cwd",
+        FIELD_TEXT,
+        "The current working directory of the process.",
+    ),
+    (
+        "monitored_fifos",
+        FIELD_TEXTS,
+        "The paths of named pipes open which match FifoPattern.",
+    ),
+    (
+        "cpu_pct",
+        FIELD_FLOAT,
+        "The CPU usage in percents since the previous heartbeat.",
+    ),
+    (
+        "rss_growth_rate_bps",
+        FIELD_FLOAT,
+        "The smoothed RSS growth rate in bytes per second.",
+    ),
+    (
+        "cpu_efficiency",
+        FIELD_FLOAT,
+        "The CPU time in percents of the wall time since the command started.",
+    ),
+    (
+        "mem_pct_of_limit",
+        FIELD_FLOAT,
+        "The rss_bytes in percents of the cgroup memory limit.",
+    ),
+    (
+        "cpu_pct_of_limit",
+        FIELD_FLOAT,
+        "The cpu_pct in percents of the cgroup CPU quota.",
+    ),
+    (
+        "sparkline_cpu",
+        FIELD_FLOATS,
+        "The latest cpu_pct samples oldest first.",
+    ),
+    (
+        "sparkline_rss",
+        FIELD_FLOATS,
+        "The latest rss_bytes samples oldest first.",
+    ),
+    (
+        "cpu_budget_remaining_secs",
+        FIELD_FLOAT,
+        "The CPU time left of CpuBudget in seconds.",
+    ),
+    (
+        "sample_count",
+        FIELD_UINT,
+        "The number of stats samples taken since the previous heartbeat.",
+    ),
+    (
+        "cpu_pct_min",
+        FIELD_FLOAT,
+        "The smallest cpu_pct sampled since the previous heartbeat.",
+    ),
+    (
+        "cpu_pct_max",
+        FIELD_FLOAT,
+        "The largest cpu_pct sampled since the previous heartbeat.",
+    ),
+    (
+        "cpu_pct_avg",
+        FIELD_FLOAT,
+        "The average cpu_pct sampled since the previous heartbeat.",
+    ),
+    (
+        "rss_bytes_min",
+        FIELD_UINT,
+        "The smallest rss_bytes sampled since the previous heartbeat.",
+    ),
+    (
+        "rss_bytes_max",
+        FIELD_UINT,
+        "The largest rss_bytes sampled since the previous heartbeat.",
+    ),
+    (
+        "rss_bytes_avg",
+        FIELD_FLOAT,
+        "The average rss_bytes sampled since the previous heartbeat.",
+    ),
+    (
+        "threads_min",
+        FIELD_UINT,
+        "The smallest threads sampled since the previous heartbeat.",
+    ),
+    (
+        "threads_max",
+        FIELD_UINT,
+        "The largest threads sampled since the previous heartbeat.",
+    ),
+    (
+        "threads_avg",
+        FIELD_FLOAT,
+        "The average threads sampled since the previous heartbeat.",
+    ),
+    (
+        "stdout_lines",
+        FIELD_UINT,
+        "The number of lines the command wrote to STDOUT since it started.",
+    ),
+    (
+        "stdout_lines_delta",
+        FIELD_UINT,
+        "The number of lines the command wrote to STDOUT since the previous heartbeat.",
+    ),
+    (
+        "stderr_lines",
+        FIELD_UINT,
+        "The number of lines the command wrote to STDERR since it started.",
+    ),
+    (
+        "stderr_lines_delta",
+        FIELD_UINT,
+        "The number of lines the command wrote to STDERR since the previous heartbeat.",
+    ),
+    (
+        "paused",
+        FIELD_BOOL,
+        "The command is paused with PauseSignal.",
+    ),
+    (
+        "status",
+        FIELD_TEXT,
+        "The status of the command which start is delayed, pending or running.",
+    ),
+    (
+        "event",
+        FIELD_TEXT,
+        "The event the message reports, accounting, test, pid_changed, or pid_vanished.",
+    ),
+    (
+        "previous_pid",
+        FIELD_UINT,
+        "The id of the process followed before the pid_changed event.",
+    ),
+    (
+        "deduplicated_count",
+        FIELD_UINT,
+        "The number of identical heartbeats suppressed before the heartbeat.",
+    ),
+    (
+        "send_dropped",
+        FIELD_UINT,
+        "The number of heartbeats dropped from the full send buffer.",
+    ),
+    (
+        "send_latency_us",
+        FIELD_UINT,
+        "The time the previous heartbeat took to send in microseconds.",
+    ),
+    (
+        "avg_send_latency_us",
+        FIELD_UINT,
+        "The average send_latency_us of the latest heartbeats.",
+    ),
+    (
+        "last_rtt_ms",
+        FIELD_FLOAT,
+        "The round-trip time of the tcp transport connection.",
+    ),
+    (
+        "send_failures",
+        FIELD_UINT,
+        "The number of heartbeats failed to be delivered since the last successful one.",
+    ),
+    ("exit_code", FIELD_UINT, "The exit code of the tool."),
+    (
+        "exit_reason",
+        FIELD_TEXT,
+        "The signal the command is killed with.",
+    ),
+    (
+        "output_limit_exceeded",
+        FIELD_BOOL,
+        "The command is terminated for outputting more lines than MaxOutputLines.",
+    ),
+    (
+        "last_output",
+        FIELD_TEXT,
+        "The last bytes of STDOUT and STDERR of the command.",
+    ),
+    (
+        "coredump_path",
+        FIELD_TEXT,
+        "The path of the core dump of the command.",
+    ),
+    (
+        "ru_utime_ms",
+        FIELD_UINT,
+        "The CPU time spent in the user mode in milliseconds.",
+    ),
+    (
+        "ru_stime_ms",
+        FIELD_UINT,
+        "The CPU time spent in the kernel mode in milliseconds.",
+    ),
+    ("ru_maxrss_bytes", FIELD_UINT, "The peak RSS in bytes."),
+    (
+        "ru_minflt",
+        FIELD_UINT,
+        "The number of page faults served without I/O.",
+    ),
+    (
+        "ru_majflt",
+        FIELD_UINT,
+        "The number of page faults which required I/O.",
+    ),
+    (
+        "ru_inblock",
+        FIELD_UINT,
+        "The number of block input operations.",
+    ),
+    (
+        "ru_oublock",
+        FIELD_UINT,
+        "The number of block output operations.",
+    ),
+    (
+        "ru_nvcsw",
+        FIELD_UINT,
+        "The number of voluntary context switches.",
+    ),
+    (
+        "ru_nivcsw",
+        FIELD_UINT,
+        "The number of involuntary context switches.",
+    ),
+];
+
+///
+/// Get the JSON Schema document of the state message. The version of the document
+/// is the version of the crate.
+///
+pub fn state_message_schema() -> String {
+    let mut fields = String::new();
+    for (name, kind, description) in FIELDS.iter() {
+        let _ = write!(
+            fields,
+            "\n    \"{}\": {{\n      \"description\": \"{}\",\n      {}\n    }},",
+            name, description, kind
+        );
+    }
+
+    format!(
+        r#"{{
+  "$schema": "{draft}",
+  "title": "StateMessage",
+  "description": "The state of the command process watched by owl.",
+  "version": "{version}",
+  "type": "object",
+  "required": ["owl_pid", "pid", "name", "state"],
+  "properties": {{
+    "version": {{
+      "description": "The version of the message format, absent in messages before versioning.",
+      "type": "integer",
+      "minimum": 1
+    }},
+    "owl_pid": {{
+      "description": "The id of the owl watcher process.",
+      "type": "integer",
+      "minimum": 0
+    }},
+    "pid": {{
+      "description": "The id of the command process, 0 while the command is pending.",
+      "type": "integer",
+      "minimum": 0
+    }},
+    "name": {{
+      "description": "The name of the command or the name from the Name option.",
+      "type": "string"
+    }},
+    "state": {{
+      "description": "The state of the command process.",
+      "type": "string"
+    }},{fields}
+    "labels": {{
+      "description": "The labels attached to the message.",
+      "type": "object",
+      "additionalProperties": {{ "type": "string" }}
+    }},
+    "alerts": {{
+      "description": "The alerts raised since the previous message.",
+      "type": "array",
+      "items": {{ "type": "string" }}
+    }},
+    "self_metrics": {{
+      "description": "The resource usage of the owl watcher process.",
+      "type": "object",
+      "properties": {{
+        "owl_cpu_pct": {{ "type": ["number", "null"] }},
+        "owl_rss_bytes": {{ "type": "integer", "minimum": 0 }},
+        "owl_threads": {{ "type": "integer", "minimum": 0 }}
+      }},
+      "additionalProperties": false
+    }},
+    "envelope": {{
+      "description": "The delivery metadata of the message.",
+      "type": "object",
+      "required": ["sent_ts_ms", "relay_chain"],
+      "properties": {{
+        "sent_ts_ms": {{
+          "description": "The time the message is sent at in milliseconds since the UNIX epoch.",
+          "type": "integer",
+          "minimum": 0
+        }},
+        "relay_chain": {{
+          "description": "The relays the message passed, empty on the first hop.",
+          "type": "array",
+          "items": {{ "type": "string" }}
+        }}
+      }},
+      "additionalProperties": false
+    }}
+  }},
+  "additionalProperties": {{
+    "description": "The field added in newer versions of the message format."
+  }}
+}}"#,
+        draft = SCHEMA_DRAFT,
+        version = env!("CARGO_PKG_VERSION"),
+        fields = fields
+    )
+}
@@ -0,0 +1,212 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The resolver of the secret values of variables set for the command so
+//! secrets never appear in the command line or the configuration file.
+//!
+//! - `file://<PATH>` reads the file, the trailing line break is trimmed.
+//! - `env://<NAME>` reads the variable of the tool environment.
+//! - `vault://<PATH>#<FIELD>` reads the field of the Vault secret at `PATH`
+//!   with the token from `VAULT_TOKEN` from the server at `VAULT_ADDR`. Only
+//!   plain `http://` addresses are supported.
+//!
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const SCHEME_FILE: &str = "file://";
+const SCHEME_ENV: &str = "env://";
+const SCHEME_VAULT: &str = "vault://";
+const VAULT_ADDR_VAR: &str = "VAULT_ADDR";
+const VAULT_TOKEN_VAR: &str = "VAULT_TOKEN";
+const DEFAULT_VAULT_ADDR: &str = "http://127.0.0.1:8200";
+const URL_SCHEME: &str = "http://";
+const API_PATH: &str = "/v1/";
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+
+///
+/// Resolve the value of the secret `reference`.
+///
+pub fn resolve(reference: &str) -> Result<String, String> {
+    if let Some(path) = reference.strip_prefix(SCHEME_FILE) {
+        let mut value = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read '{}': {}", path, err))?;
+        if value.ends_with('\n') {
+            value.pop();
+            if value.ends_with('\r') {
+                value.pop();
+            }
+        }
+        Ok(value)
+    } else if let Some(name) = reference.strip_prefix(SCHEME_ENV) {
+        env::var(name).map_err(|_| format!("variable '{}' is not set", name))
+    } else if let Some(secret) = reference.strip_prefix(SCHEME_VAULT) {
+        let (path, field) = match secret.find('#') {
+            Some(pos) if pos + 1 < secret.len() => (&secret[..pos], &secret[pos + 1..]),
+            _ => return Err(format!("no field in '{}', expected PATH#FIELD", reference)),
+        };
+        let body = read_vault(path)
+            .map_err(|err| format!("failed to read Vault secret '{}': {}", path, err))?;
+        find_string(&body, field)
+            .ok_or_else(|| format!("no field '{}' in Vault secret '{}'", field, path))
+    } else {
+        Err(format!("unsupported secret reference '{}'", reference))
+    }
+}
+
+///
+/// Read the secret at `path` from Vault and return the JSON body of the response.
+///
+fn read_vault(path: &str) -> io::Result<String> {
+    let url = env::var(VAULT_ADDR_VAR).unwrap_or_else(|_| DEFAULT_VAULT_ADDR.to_string());
+    let token = env::var(VAULT_TOKEN_VAR).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("variable '{}' is not set", VAULT_TOKEN_VAR),
+        )
+    })?;
+    let addr = url
+        .strip_prefix(URL_SCHEME)
+        .map(|rest| rest.trim_end_matches('/'))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported Vault address '{}'", url),
+            )
+        })?;
+
+    let timeout = Duration::from_secs(REQUEST_TIMEOUT_SECS);
+    let sock_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+    let mut stream = TcpStream::connect_timeout(&sock_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // HTTP/1.0 makes the server close the connection after the body so
+    // it's read with no chunked encoding
+    let request = format!(
+        "GET {}{} HTTP/1.0\r\nHost: {}\r\nX-Vault-Token: {}\r\n\r\n",
+        API_PATH,
+        path.trim_start_matches('/'),
+        addr,
+        token
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.lines().next().unwrap_or_default();
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if !code.starts_with('2') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Vault replied '{}'", status.trim()),
+        ));
+    }
+
+    match response.find("\r\n\r\n") {
+        Some(pos) => Ok(response[pos + 4..].to_string()),
+        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+    }
+}
+
+///
+/// Find the first string value of `field` in the JSON `body` and decode it.
+/// Both the KV version 1 body `{"data":{"<FIELD>":"..."}}` and the version 2
+/// body `{"data":{"data":{"<FIELD>":"..."}}}` are supported.
+///
+fn find_string(body: &str, field: &str) -> Option<String> {
+    let key = format!("\"{}\"", field);
+    let mut rest = body;
+    while let Some(pos) = rest.find(&key) {
+        rest = &rest[pos + key.len()..];
+        let value = rest.trim_start();
+        if let Some(value) = value.strip_prefix(':') {
+            if let Some(value) = value.trim_start().strip_prefix('"') {
+                return decode_string(value);
+            }
+        }
+    }
+
+    None
+}
+
+///
+/// Decode the JSON string which starts right after the opening quote.
+///
+fn decode_string(s: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&code, 16).ok()?;
+                    value.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                }
+                other => value.push(other),
+            },
+            _ => value.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_and_env_references_are_resolved() {
+        let path = env::temp_dir().join(format!("owl-test-{}-secret", std::process::id()));
+        fs::write(&path, "s3cr3t\r\n").unwrap();
+        let reference = format!("file://{}", path.display());
+        assert_eq!(resolve(&reference).unwrap(), "s3cr3t");
+        fs::remove_file(&path).unwrap();
+        assert!(resolve(&reference).is_err());
+
+        env::set_var("OWL_TEST_SECRET_SOURCE", "from env");
+        assert_eq!(resolve("env://OWL_TEST_SECRET_SOURCE").unwrap(), "from env");
+        assert!(resolve("env://OWL_TEST_NO_SUCH_VARIABLE").is_err());
+
+        assert!(resolve("vault://secret/data/myapp").is_err());
+        assert!(resolve("s3://bucket/key").is_err());
+    }
+
+    #[test]
+    fn vault_fields_are_found_in_body() {
+        let v1 = r#"{"data":{"user":"app","password":"p\"w!\n"}}"#;
+        assert_eq!(find_string(v1, "password").unwrap(), "p\"w!\n");
+        let v2 = r#"{"data":{"data":{"password" : "pw"},"metadata":{"version":3}}}"#;
+        assert_eq!(find_string(v2, "password").unwrap(), "pw");
+        assert_eq!(find_string(v2, "version"), None);
+        assert_eq!(find_string(v2, "token"), None);
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The shared memory segment the state is written to for readers on the same host.
+//!
+//! The segment is the POSIX shared memory object and the named semaphore
+//! of the same name which is posted after every write. The segment starts
+//! with the header of the layout version, the payload size, the time
+//! of the write in microseconds since the UNIX epoch, and the sequence number,
+//! all native endian, and the payload follows. The sequence number is odd
+//! while the write is in progress and even once it is done, so readers copy
+//! the payload again when the number is odd or changes during the copy.
+//!
+
+use std::ffi::CString;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use crate::versions::SHM_LAYOUT_VERSION as LAYOUT_VERSION;
+
+///
+/// The size of the header which precedes the payload.
+///
+pub const HEADER_SIZE: usize = 24;
+
+///
+/// The largest payload the segment holds.
+///
+pub const MAX_PAYLOAD_SIZE: usize = 65536;
+
+const SEGMENT_MODE: libc::mode_t = 0o600;
+
+///
+/// The shared memory segment mapped into the tool.
+///
+pub struct SharedMemory {
+    name: String,
+    data: *mut u8,
+    sem: *mut libc::sem_t,
+}
+
+// The segment is written from the delivery thread only.
+unsafe impl Send for SharedMemory {}
+
+impl SharedMemory {
+    ///
+    /// Open the segment and the semaphore `name`, e.g. `/owl-rsync`,
+    /// creating them if they do not exist.
+    ///
+    pub fn open(name: &str) -> io::Result<SharedMemory> {
+        let c_name =
+            CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let size = HEADER_SIZE + MAX_PAYLOAD_SIZE;
+        unsafe {
+            let fd = libc::shm_open(c_name.as_ptr(), libc::O_CREAT | libc::O_RDWR, SEGMENT_MODE);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ftruncate(fd, size as libc::off_t) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(err);
+            }
+            let data = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            libc::close(fd);
+            if data == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let sem = libc::sem_open(
+                c_name.as_ptr(),
+                libc::O_CREAT,
+                libc::c_uint::from(SEGMENT_MODE),
+                0 as libc::c_uint,
+            );
+            if sem == libc::SEM_FAILED {
+                let err = io::Error::last_os_error();
+                libc::munmap(data, size);
+                return Err(err);
+            }
+
+            Ok(SharedMemory {
+                name: name.to_owned(),
+                data: data as *mut u8,
+                sem,
+            })
+        }
+    }
+
+    ///
+    /// Get the name of the segment.
+    ///
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///
+    /// Write the `payload` with the header and notify readers.
+    ///
+    pub fn write(&self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("message of {} bytes is too large", payload.len()),
+            ));
+        }
+
+        let ts_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+        unsafe {
+            // The odd sequence number marks the write in progress, the number left
+            // odd by the writer which died in the middle of the write is reused
+            let sequence = &*(self.data.add(16) as *const AtomicU64);
+            let seq = sequence.load(Ordering::Relaxed) | 1;
+            sequence.store(seq, Ordering::Relaxed);
+            fence(Ordering::Release);
+
+            ptr::copy_nonoverlapping(payload.as_ptr(), self.data.add(HEADER_SIZE), payload.len());
+            ptr::write_volatile(self.data as *mut u32, LAYOUT_VERSION);
+            ptr::write_volatile(self.data.add(4) as *mut u32, payload.len() as u32);
+            ptr::write_volatile(self.data.add(8) as *mut u64, ts_us);
+            sequence.store(seq.wrapping_add(1), Ordering::Release);
+            if libc::sem_post(self.sem) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(
+                self.data as *mut libc::c_void,
+                HEADER_SIZE + MAX_PAYLOAD_SIZE,
+            );
+            libc::sem_close(self.sem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Read the header and the payload the way readers do.
+    fn read(shm: &SharedMemory) -> (u32, u64, Vec<u8>) {
+        unsafe {
+            libc::sem_wait(shm.sem);
+            let sequence = &*(shm.data.add(16) as *const AtomicU64);
+            let seq = sequence.load(Ordering::Acquire);
+            let version = ptr::read_volatile(shm.data as *const u32);
+            let len = ptr::read_volatile(shm.data.add(4) as *const u32) as usize;
+            let payload = std::slice::from_raw_parts(shm.data.add(HEADER_SIZE), len).to_vec();
+            (version, seq, payload)
+        }
+    }
+
+    #[test]
+    fn writes_are_read_back_quickly() {
+        let name = format!("/owl-test-{}-shm", std::process::id());
+        let writer = SharedMemory::open(&name).unwrap();
+        let reader = SharedMemory::open(&name).unwrap();
+
+        let mut last_seq = None;
+        let mut fastest = Duration::from_secs(1);
+        for i in 0..200 {
+            let payload = format!("{{\"state\":\"running\",\"n\":{}}}", "x".repeat(i % 7));
+            let started = Instant::now();
+            writer.write(payload.as_bytes()).unwrap();
+            let (version, seq, read) = read(&reader);
+            fastest = fastest.min(started.elapsed());
+
+            assert_eq!(version, LAYOUT_VERSION);
+            assert_eq!(read, payload.as_bytes());
+            assert_eq!(seq % 2, 0);
+            if let Some(last) = last_seq {
+                assert_eq!(seq, last + 2);
+            }
+            last_seq = Some(seq);
+        }
+        assert!(fastest < Duration::from_micros(100), "{:?}", fastest);
+        assert!(writer.write(&vec![0; MAX_PAYLOAD_SIZE + 1]).is_err());
+
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            libc::shm_unlink(c_name.as_ptr());
+            libc::sem_unlink(c_name.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,100 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The Server-Sent Events stream of messages received which browsers
+//! subscribe to with `EventSource`.
+//!
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+///
+/// The time the write to the slow client may take before it is dropped.
+///
+const CLIENT_WRITE_TIMEOUT_MILLIS: u64 = 100;
+
+///
+/// The comment clients get periodically so proxies keep the connection open.
+///
+const KEEP_ALIVE_COMMENT: &str = ": heartbeat\n\n";
+
+///
+/// The clients messages are pushed to as events.
+///
+#[derive(Default)]
+pub struct Events {
+    clients: Vec<TcpStream>,
+}
+
+impl Events {
+    ///
+    /// Push the message `data` to clients as the event. Clients which fail
+    /// to receive it are dropped.
+    ///
+    pub fn send(&mut self, data: &str) {
+        let event = format!("data: {}\n\n", data);
+        self.write(&event);
+    }
+
+    ///
+    /// Send the comment to clients so idle connections are kept open.
+    ///
+    pub fn keep_alive(&mut self) {
+        self.write(KEEP_ALIVE_COMMENT);
+    }
+
+    ///
+    /// Push further events to the `client` which got the response head.
+    ///
+    pub fn attach(&mut self, client: TcpStream) {
+        let _ = client.set_write_timeout(Some(Duration::from_millis(CLIENT_WRITE_TIMEOUT_MILLIS)));
+        self.clients.push(client);
+    }
+
+    ///
+    /// Write the `text` to every client dropping clients which fail.
+    ///
+    fn write(&mut self, text: &str) {
+        self.clients
+            .retain_mut(|client| client.write_all(text.as_bytes()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn events_and_comments_are_framed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let mut events = Events::default();
+        events.attach(server);
+        events.send("1||2||rsync||Running");
+        events.keep_alive();
+        drop(events);
+
+        let mut stream = String::new();
+        client.read_to_string(&mut stream).unwrap();
+        assert_eq!(stream, "data: 1||2||rsync||Running\n\n: heartbeat\n\n");
+    }
+}
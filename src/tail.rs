@@ -0,0 +1,111 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The tail of messages received which HTTP streaming clients get on connect
+//! before live messages, so late joiners see the recent history.
+//!
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+///
+/// The time the write to the slow client may take before it is dropped.
+///
+const CLIENT_WRITE_TIMEOUT_MILLIS: u64 = 100;
+
+///
+/// The last messages received and clients messages are streamed to.
+///
+pub struct Tail {
+    capacity: usize,
+    lines: VecDeque<String>,
+    clients: Vec<TcpStream>,
+}
+
+impl Tail {
+    ///
+    /// Make the tail which keeps up to `capacity` last messages.
+    ///
+    pub fn new(capacity: usize) -> Tail {
+        Tail {
+            capacity,
+            lines: VecDeque::with_capacity(capacity),
+            clients: Vec::new(),
+        }
+    }
+
+    ///
+    /// Keep the message `line` and send it to clients. Clients which fail
+    /// to receive it are dropped.
+    ///
+    pub fn push(&mut self, line: &str) {
+        if self.capacity > 0 {
+            if self.lines.len() == self.capacity {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line.to_owned());
+        }
+        self.clients
+            .retain_mut(|client| writeln!(client, "{}", line).is_ok());
+    }
+
+    ///
+    /// Send messages kept to the `client` and stream further messages to it.
+    ///
+    pub fn attach(&mut self, mut client: TcpStream) {
+        let _ = client.set_write_timeout(Some(Duration::from_millis(CLIENT_WRITE_TIMEOUT_MILLIS)));
+        if self
+            .lines
+            .iter()
+            .all(|line| writeln!(client, "{}", line).is_ok())
+        {
+            self.clients.push(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn only_last_messages_are_kept() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let mut tail = Tail::new(3);
+        for i in 0..10 {
+            tail.push(&i.to_string());
+        }
+        assert_eq!(tail.lines, ["7", "8", "9"]);
+        tail.attach(server);
+        tail.push("10");
+        assert_eq!(tail.lines, ["8", "9", "10"]);
+
+        let lines: Vec<String> = BufReader::new(client)
+            .lines()
+            .take(4)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, ["7", "8", "9", "10"]);
+    }
+}
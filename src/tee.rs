@@ -0,0 +1,130 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The tee which sends the copy of every message to secondary destinations.
+//!
+//! Copies are always encoded as plain text SSDPD and sent over UDP whatever
+//! the format and the transport of the primary delivery are. Copies are
+//! handed over to the writer thread so the tee never slows the primary
+//! delivery down, copies which fail to be sent are dropped silently.
+//!
+
+use crate::message::StateMessage;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+pub struct Tee {
+    sender: Mutex<Option<Sender<Vec<u8>>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Tee {
+    ///
+    /// Make the tee and start the writer which sends copies to the comma
+    /// separated `host:port` addresses of `targets`.
+    ///
+    pub fn new(targets: &str) -> Result<Tee, String> {
+        let mut sockets = Vec::new();
+        for addr in parse_targets(targets)? {
+            let local_addr = if addr.is_ipv6() {
+                SocketAddr::from(([0u16; 8], 0))
+            } else {
+                SocketAddr::from(([0, 0, 0, 0], 0))
+            };
+            let socket = UdpSocket::bind(local_addr)
+                .map_err(|err| format!("failed to bind tee socket for {}: {}", addr, err))?;
+            sockets.push((addr, socket));
+        }
+
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let writer = thread::spawn(move || {
+            for data in receiver.iter() {
+                for (addr, socket) in sockets.iter() {
+                    let _ = socket.send_to(&data, addr);
+                }
+            }
+        });
+
+        Ok(Tee {
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    ///
+    /// Queue the copy of `msgs` which is sent in one datagram.
+    ///
+    pub fn send(&self, msgs: &[StateMessage]) {
+        let lines: Vec<String> = msgs.iter().map(StateMessage::to_ssdpd).collect();
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                let _ = sender.send(lines.join("\n").into_bytes());
+            }
+        }
+    }
+
+    ///
+    /// Stop accepting copies and wait until the writer sends queued ones.
+    ///
+    pub fn finish(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let writer = self.writer.lock().ok().and_then(|mut writer| writer.take());
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+    }
+}
+
+///
+/// Resolve the comma separated `host:port` addresses of `targets`.
+///
+pub fn parse_targets(targets: &str) -> Result<Vec<SocketAddr>, String> {
+    targets
+        .split(',')
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .map(|target| {
+            target
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| format!("invalid tee address '{}'", target))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_resolved() {
+        let addrs = parse_targets("127.0.0.1:9090, [::1]:9091,").unwrap();
+        assert_eq!(
+            addrs,
+            [
+                "127.0.0.1:9090".parse::<SocketAddr>().unwrap(),
+                "[::1]:9091".parse::<SocketAddr>().unwrap(),
+            ]
+        );
+        assert!(parse_targets("127.0.0.1").is_err());
+    }
+}
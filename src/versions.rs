@@ -0,0 +1,33 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Versions of the formats owl produces and hollow reads.
+//!
+//! Both binaries build the module so the sender and the receiver agree on
+//! the versions without keeping their own copies.
+//!
+
+///
+/// The version of the message format. It is bumped every time fields
+/// are added so receivers can tell messages newer than they know.
+///
+pub const MESSAGE_VERSION: u32 = 8;
+
+///
+/// The version of the shared memory segment layout.
+///
+pub const SHM_LAYOUT_VERSION: u32 = 2;
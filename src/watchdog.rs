@@ -0,0 +1,125 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! The watchdog which detects senders which stopped sending heartbeats.
+//!
+
+use crate::clock::format_time;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+///
+/// The sender of heartbeats tracked.
+///
+struct Sender {
+    /// The time the last heartbeat is received at.
+    last_seen: SystemTime,
+
+    /// The flag is raised when the missed heartbeat is reported.
+    missed: bool,
+}
+
+///
+/// The watchdog tracks the last heartbeat of every sender identified
+/// by the name and the instance.
+///
+pub struct Watchdog {
+    timeout: Duration,
+    senders: HashMap<(String, String), Sender>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Watchdog {
+        Watchdog {
+            timeout,
+            senders: HashMap::new(),
+        }
+    }
+
+    ///
+    /// Record the heartbeat of the sender `name` and `instance` received `at`.
+    /// If the sender missed heartbeats before the recovery notice is returned.
+    ///
+    pub fn seen(&mut self, name: &str, instance: &str, at: SystemTime) -> Option<String> {
+        let key = (name.to_owned(), instance.to_owned());
+        let prev = self.senders.insert(
+            key,
+            Sender {
+                last_seen: at,
+                missed: false,
+            },
+        );
+        match prev {
+            Some(prev) if prev.missed => {
+                let gap = at.duration_since(prev.last_seen).unwrap_or_default();
+                Some(format!(
+                    "[RECOVERED] {} heartbeat resumed after {}s",
+                    name,
+                    gap.as_secs()
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    ///
+    /// Check senders at `now` and return alerts on senders which heartbeats
+    /// are missing longer than the timeout. Every sender is reported once
+    /// until it recovers.
+    ///
+    pub fn check(&mut self, now: SystemTime) -> Vec<String> {
+        let mut alerts = Vec::new();
+        for ((name, _), sender) in self.senders.iter_mut() {
+            let silence = now.duration_since(sender.last_seen).unwrap_or_default();
+            if !sender.missed && silence > self.timeout {
+                sender.missed = true;
+                alerts.push(format!(
+                    "[ALERT] {} missed heartbeat (last seen {})",
+                    name,
+                    format_time(sender.last_seen, false)
+                ));
+            }
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missed_heartbeat_is_reported_once() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(30));
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert!(watchdog.seen("rsync", "host-1", start).is_none());
+
+        assert!(watchdog.check(start + Duration::from_secs(20)).is_empty());
+        let alerts = watchdog.check(start + Duration::from_secs(31));
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].starts_with("[ALERT] rsync missed heartbeat"));
+        assert!(watchdog.check(start + Duration::from_secs(60)).is_empty());
+
+        let recovered = watchdog.seen("rsync", "host-1", start + Duration::from_secs(90));
+        assert_eq!(
+            recovered.as_deref(),
+            Some("[RECOVERED] rsync heartbeat resumed after 90s")
+        );
+        assert!(watchdog.check(start + Duration::from_secs(100)).is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Helpers shared by tests which run the tool and the receiver binaries.
+//!
+
+use std::fs;
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RECEIVE_TIMEOUT_MILLIS: u64 = 50;
+
+///
+/// Make the command running the tool with `args`.
+///
+pub fn owl(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_owl"));
+    command.args(args);
+    command
+}
+
+///
+/// Make the command running the receiver with `args`.
+///
+pub fn hollow(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hollow"));
+    command.args(args);
+    command
+}
+
+///
+/// Make the empty directory `name` unique to the test process.
+///
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("owl-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+///
+/// Get the value of the field `name` of the SSDPD `record`.
+///
+pub fn field<'a>(record: &'a str, name: &str) -> Option<&'a str> {
+    record
+        .split("||")
+        .find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+}
+
+///
+/// The UDP listener of messages the tool delivers.
+///
+pub struct Receiver {
+    socket: UdpSocket,
+}
+
+impl Receiver {
+    ///
+    /// Listen on the loopback address with the port the OS assigns.
+    ///
+    pub fn bind() -> Receiver {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(RECEIVE_TIMEOUT_MILLIS)))
+            .unwrap();
+        Receiver { socket }
+    }
+
+    ///
+    /// Get the port the receiver listens on.
+    ///
+    pub fn port(&self) -> u16 {
+        self.socket.local_addr().unwrap().port()
+    }
+
+    ///
+    /// Get the tool options which deliver messages to the receiver.
+    ///
+    pub fn opts(&self) -> Vec<String> {
+        vec![
+            "+Host:127.0.0.1".to_owned(),
+            format!("+Port:{}", self.port()),
+        ]
+    }
+
+    ///
+    /// Collect records received within `timeout`.
+    ///
+    pub fn records(&self, timeout: Duration) -> Vec<String> {
+        let mut records = Vec::new();
+        let until = Instant::now() + timeout;
+        while Instant::now() < until {
+            records.extend(self.receive());
+        }
+        records
+    }
+
+    ///
+    /// Wait up to `timeout` for the record `accept` returns `true` for.
+    ///
+    pub fn wait_for<F: Fn(&str) -> bool>(&self, timeout: Duration, accept: F) -> Option<String> {
+        let until = Instant::now() + timeout;
+        while Instant::now() < until {
+            if let Some(record) = self.receive().into_iter().find(|r| accept(r)) {
+                return Some(record);
+            }
+        }
+        None
+    }
+
+    ///
+    /// Wait up to `timeout` for the datagram and get its payload as-is.
+    ///
+    pub fn datagram(&self, timeout: Duration) -> Option<Vec<u8>> {
+        let until = Instant::now() + timeout;
+        while Instant::now() < until {
+            if let Some(payload) = self.receive_payload() {
+                return Some(payload);
+            }
+        }
+        None
+    }
+
+    fn receive(&self) -> Vec<String> {
+        match self.receive_payload() {
+            Some(payload) => String::from_utf8_lossy(&payload)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn receive_payload(&self) -> Option<Vec<u8>> {
+        let mut buf = [0; 65536];
+        let len = self.socket.recv(&mut buf).ok()?;
+        Some(buf[..len].to_vec())
+    }
+}
+
+///
+/// Test the `text` is the complete JSON object or array, i.e. braces and brackets
+/// outside strings are balanced and nothing follows the top level value.
+///
+pub fn complete_json(text: &str) -> bool {
+    let text = text.trim();
+    if !text.starts_with('{') && !text.starts_with('[') {
+        return false;
+    }
+    let (mut depth, mut in_string, mut escaped) = (0i32, false, false);
+    for (pos, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' | '[' if !in_string => depth += 1,
+            '}' | ']' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return pos == text.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+///
+/// Get the UDP port of the loopback address no one listens on at the moment.
+///
+pub fn free_port() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap().port()
+}
+
+///
+/// Get ids of child processes of the process with `pid`.
+///
+pub fn children(pid: u32) -> Vec<u32> {
+    fs::read_to_string(format!("/proc/{0}/task/{0}/children", pid))
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|id| id.parse().ok())
+        .collect()
+}
+
+///
+/// Get the CPU time the process with `pid` spent in user and system modes
+/// in clock ticks.
+///
+pub fn cpu_ticks(pid: u32) -> u64 {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    // Fields after the command name, which is in parentheses, start with the state
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+    fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap()
+}
+
+///
+/// Get the TCP port of the loopback address no one listens on at the moment.
+///
+pub fn free_tcp_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+///
+/// Connect to the loopback `port` retrying until the listener is started
+/// within `timeout`.
+///
+pub fn connect(port: u16, timeout: Duration) -> TcpStream {
+    let until = Instant::now() + timeout;
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => return stream,
+            Err(err) if Instant::now() >= until => panic!("failed to connect: {}", err),
+            Err(_) => thread::sleep(Duration::from_millis(RECEIVE_TIMEOUT_MILLIS)),
+        }
+    }
+}
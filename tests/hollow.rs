@@ -0,0 +1,638 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Tests which run the receiver and check messages it prints.
+//!
+
+pub mod common;
+#[allow(dead_code)]
+#[path = "../src/versions.rs"]
+mod versions;
+
+use common::{hollow, Receiver};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///
+/// The lock of the fixed UDP address the receiver listens on without `--tcp-port`.
+///
+static FIXED_ADDRESS: Mutex<()> = Mutex::new(());
+
+///
+/// Send `payload` framed with 4-byte big-endian length over `stream`.
+///
+fn send_frame(stream: &mut TcpStream, payload: &str) {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload.as_bytes());
+    stream.write_all(&frame).unwrap();
+    stream.flush().unwrap();
+}
+
+///
+/// Run the receiver with `flags` accepting TCP connections, send `records`
+/// over one connection, and get what the receiver prints.
+///
+fn tcp_session(flags: &[&str], records: &[&str]) -> String {
+    let port = common::free_tcp_port().to_string();
+    let child = hollow(&["--tcp-port", &port])
+        .args(flags)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = common::connect(port.parse().unwrap(), Duration::from_secs(2));
+    for record in records {
+        send_frame(&mut client, record);
+    }
+    thread::sleep(Duration::from_millis(200));
+    drop(client);
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+///
+/// Get process ids of records printed in `stdout`.
+///
+fn printed_pids(stdout: &str) -> Vec<&str> {
+    stdout
+        .lines()
+        .filter_map(|line| line.split("||").nth(1))
+        .collect()
+}
+
+#[test]
+fn tcp_clients_are_printed_interleaved() {
+    let port = common::free_tcp_port();
+    let child = hollow(&["--tcp-port", &port.to_string()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut clients = [
+        common::connect(port, Duration::from_secs(2)),
+        common::connect(port, Duration::from_secs(2)),
+    ];
+    for (client, name) in [(0, "alpha-1"), (1, "beta-1"), (0, "alpha-2"), (1, "beta-2")] {
+        send_frame(&mut clients[client], &format!("1||2||{}||S", name));
+        thread::sleep(Duration::from_millis(100));
+    }
+    drop(clients);
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let names: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.split("||").nth(2))
+        .collect();
+    assert_eq!(names, ["alpha-1", "beta-1", "alpha-2", "beta-2"]);
+}
+
+const FILTERED_RECORDS: [&str; 3] = [
+    "1||2||rsync||Running",
+    "1||3||rsync||Sleeping",
+    "1||4||cron||Running",
+];
+
+#[test]
+fn messages_are_filtered_by_state_and_name() {
+    let stdout = tcp_session(&["--filter-state", "R"], &FILTERED_RECORDS);
+    assert_eq!(printed_pids(&stdout), ["2", "4"]);
+
+    let stdout = tcp_session(
+        &["--filter-state", "R", "--filter-name", "rsync"],
+        &FILTERED_RECORDS,
+    );
+    assert_eq!(printed_pids(&stdout), ["2"]);
+
+    let stdout = tcp_session(&["--filter-name", "rsync", "--invert"], &FILTERED_RECORDS);
+    assert_eq!(printed_pids(&stdout), ["4"]);
+}
+
+#[test]
+fn messages_are_routed_by_name() {
+    let dir = common::temp_dir("route");
+    let daily = dir.join("daily.log");
+    let weekly = dir.join("weekly.log");
+    let stdout = tcp_session(
+        &[
+            "--route",
+            &format!("*-daily:{}", daily.display()),
+            "--route",
+            &format!("backup-*:{}", weekly.display()),
+        ],
+        &[
+            "1||2||rsync-daily||Running",
+            "1||3||backup-weekly||Running",
+            "1||4||cron||Running",
+            "1||5||backup-daily||Running",
+        ],
+    );
+    assert_eq!(printed_pids(&stdout), ["4"]);
+    // The first matching route takes the message
+    let daily = fs::read_to_string(daily).unwrap();
+    assert_eq!(printed_pids(&daily), ["2", "5"]);
+    let weekly = fs::read_to_string(weekly).unwrap();
+    assert_eq!(printed_pids(&weekly), ["3"]);
+}
+
+#[test]
+fn replay_keeps_gaps_between_messages() {
+    let dir = common::temp_dir("replay");
+    let log = dir.join("owl.log");
+    fs::write(
+        &log,
+        "2019-11-05T14:00:00Z 10.0.0.1:4000 -> 1||2||rsync||Running\n\
+         2019-11-05T14:00:01Z 10.0.0.1:4000 -> 1||2||rsync||Sleeping\n\
+         2019-11-05T14:00:03Z 10.0.0.1:4000 -> 1||2||rsync||Running\n",
+    )
+    .unwrap();
+    let mut child = hollow(&["--replay", &log.to_string_lossy()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let printed: Vec<Instant> = stdout.lines().map(|_| Instant::now()).collect();
+    assert!(child.wait().unwrap().success());
+
+    assert_eq!(printed.len(), 3);
+    for (gap, expected) in printed.windows(2).zip([1000.0, 2000.0]) {
+        let gap = gap[1].duration_since(gap[0]).as_secs_f64() * 1000.0;
+        assert!(
+            (gap - expected).abs() <= expected * 0.1,
+            "the gap of {}ms is not {}ms",
+            gap,
+            expected
+        );
+    }
+}
+
+///
+/// Get the headers and the body of the HTTP response to `GET path` with
+/// the `accept` media type from the loopback `port`.
+///
+fn http_get(port: u16, path: &str, accept: &str) -> (String, String) {
+    let mut stream = common::connect(port, Duration::from_secs(2));
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: localhost\r\nAccept: {}\r\n\r\n",
+        path, accept
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(
+        head.starts_with("HTTP/1.1 200"),
+        "unexpected response: {}",
+        head
+    );
+    (head.to_owned(), body.to_owned())
+}
+
+#[test]
+fn metrics_are_gauges_of_last_heartbeat() {
+    let port = common::free_tcp_port();
+    let metrics_port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--metrics-port",
+        &metrics_port.to_string(),
+    ])
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    send_frame(
+        &mut client,
+        "1||2||rsync||Running||cpu_pct=10||rss_bytes=100",
+    );
+    send_frame(
+        &mut client,
+        "1||2||rsync||Sleeping||cpu_pct=42.5||rss_bytes=4096",
+    );
+    thread::sleep(Duration::from_millis(200));
+    let (_, body) = http_get(metrics_port, "/metrics", "*/*");
+    drop(client);
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    child.wait_with_output().unwrap();
+
+    let gauge = |metric: &str| -> Vec<(String, f64)> {
+        body.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter(|(series, _)| series.split('{').next() == Some(metric))
+            .map(|(series, value)| (series.to_owned(), value.parse().unwrap()))
+            .collect()
+    };
+    assert!(body.contains("# TYPE owl_cpu_percent gauge"));
+    let cpu = gauge("owl_cpu_percent");
+    assert_eq!(cpu.len(), 1);
+    assert!(cpu[0].0.contains("name=\"rsync\""));
+    assert_eq!(cpu[0].1, 42.5);
+    assert_eq!(gauge("owl_rss_bytes")[0].1, 4096.0);
+    let state = gauge("owl_state");
+    assert!(state[0].0.contains("state=\"Sleeping\""));
+}
+
+#[test]
+fn relays_are_added_to_relay_chain() {
+    let ports: Vec<u16> = (0..3).map(|_| common::free_tcp_port()).collect();
+    let mut receivers = Vec::new();
+    for (i, port) in ports.iter().enumerate().rev() {
+        let mut command = hollow(&["--tcp-port", &port.to_string()]);
+        if let Some(next) = ports.get(i + 1) {
+            command.args(["--relay", &format!("tcp://127.0.0.1:{}", next)]);
+        }
+        receivers.push(command.stdout(Stdio::piped()).spawn().unwrap());
+        drop(common::connect(*port, Duration::from_secs(2)));
+    }
+
+    let mut client = common::connect(ports[0], Duration::from_secs(2));
+    send_frame(
+        &mut client,
+        "1||2||job||S||envelope.sent_ts_ms=1572962592345",
+    );
+    thread::sleep(Duration::from_millis(300));
+    drop(client);
+
+    let mut outputs: Vec<String> = receivers
+        .into_iter()
+        .map(|child| {
+            kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+            let output = child.wait_with_output().unwrap();
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        })
+        .collect();
+    // Receivers are started from the last one
+    outputs.reverse();
+    let relays = |stdout: &str| stdout.matches("||envelope.relay=").count();
+    assert_eq!(relays(&outputs[0]), 0);
+    assert_eq!(relays(&outputs[1]), 1);
+    assert_eq!(relays(&outputs[2]), 2, "unexpected chain: {}", outputs[2]);
+}
+
+#[test]
+fn messages_are_printed_and_teed() {
+    let tee = Receiver::bind();
+    let target = format!("udp://127.0.0.1:{}", tee.port());
+    let stdout = tcp_session(&["--tee", &target], &FILTERED_RECORDS);
+    assert_eq!(printed_pids(&stdout), ["2", "3", "4"]);
+    let teed = tee.records(Duration::from_millis(200));
+    assert_eq!(teed, FILTERED_RECORDS);
+}
+
+#[test]
+fn metrics_format_is_negotiated() {
+    let port = common::free_tcp_port();
+    let metrics_port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--metrics-port",
+        &metrics_port.to_string(),
+    ])
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    send_frame(&mut client, "1||2||rsync||Running||cpu_pct=10");
+    thread::sleep(Duration::from_millis(200));
+    let text = http_get(metrics_port, "/metrics", "text/plain");
+    let openmetrics = http_get(
+        metrics_port,
+        "/metrics",
+        "application/openmetrics-text; version=1.0.0",
+    );
+    drop(client);
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    child.wait_with_output().unwrap();
+
+    assert!(text.0.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(!text.1.contains("# EOF"));
+    assert!(openmetrics
+        .0
+        .contains("Content-Type: application/openmetrics-text; version=1.0.0"));
+    assert!(openmetrics.1.ends_with("# EOF\n"));
+    assert_eq!(openmetrics.1.trim_end_matches("# EOF\n"), text.1);
+}
+
+#[test]
+fn late_stream_clients_get_tail_then_live_messages() {
+    let port = common::free_tcp_port();
+    let metrics_port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--metrics-port",
+        &metrics_port.to_string(),
+        "--tail-log",
+        "100",
+    ])
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    for pid in 0..50 {
+        send_frame(&mut client, &format!("1||{}||rsync||Running", pid));
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    let mut stream = common::connect(metrics_port, Duration::from_secs(2));
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    write!(stream, "GET /stream HTTP/1.0\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap() > 2 {
+        line.clear();
+    }
+    let mut read_pid = || {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        line.split("||").nth(1).unwrap_or_default().to_owned()
+    };
+    for pid in 0..50 {
+        assert_eq!(read_pid(), pid.to_string());
+    }
+    send_frame(&mut client, "1||50||rsync||Running");
+    assert_eq!(read_pid(), "50");
+
+    drop(client);
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    child.wait_with_output().unwrap();
+}
+
+#[test]
+fn proxied_messages_reach_final_receiver() {
+    // The final receiver listens on the fixed UDP address
+    let _fixed = FIXED_ADDRESS.lock().unwrap_or_else(|err| err.into_inner());
+    if UdpSocket::bind("127.0.0.1:9090").is_err() {
+        eprintln!("127.0.0.1:9090 is in use, the proxy chain is not tested");
+        return;
+    }
+    let central = hollow(&[]).stdout(Stdio::piped()).spawn().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let port = common::free_tcp_port();
+    let sidecar = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--proxy-to",
+        "udp://127.0.0.1:9090",
+        "--proxy-batch",
+        "2",
+    ])
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    for pid in 2..5 {
+        send_frame(&mut client, &format!("1||{}||rsync||Running", pid));
+    }
+    // The last record waits for the batch to fill until the periodic flush
+    thread::sleep(Duration::from_millis(1500));
+    drop(client);
+
+    let stop = |child: std::process::Child| {
+        kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+        let output = child.wait_with_output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    stop(sidecar);
+    let stdout = stop(central);
+    assert_eq!(printed_pids(&stdout), ["2", "3", "4"]);
+    assert_eq!(stdout.matches("||relay_host=").count(), 3, "{}", stdout);
+}
+
+#[test]
+fn newer_message_versions_are_handled() {
+    let port = common::free_tcp_port();
+    let metrics_port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--metrics-port",
+        &metrics_port.to_string(),
+    ])
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    let newer = versions::MESSAGE_VERSION + 1;
+    send_frame(&mut client, "1||2||rsync||Running||cpu_pct=10");
+    send_frame(
+        &mut client,
+        &format!(
+            "1||3||rsync||Running||version={}||cpu_pct=20||future_field=x",
+            newer
+        ),
+    );
+    send_frame(
+        &mut client,
+        &format!(
+            r#"{{"version":{},"owl_pid":1,"pid":4,"name":"cron","state":"Sleeping","future":{{"a":[1]}}}}"#,
+            newer
+        ),
+    );
+    send_frame(
+        &mut client,
+        &format!("1||5||backup||Running||version={}", newer + 1),
+    );
+    thread::sleep(Duration::from_millis(200));
+    let (_, body) = http_get(metrics_port, "/metrics", "*/*");
+    drop(client);
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 4, "{}", stdout);
+    assert!(
+        body.contains("owl_cpu_percent{name=\"rsync\",instance=\"1\"} 20"),
+        "{}",
+        body
+    );
+    assert!(!body.contains("owl_version"), "{}", body);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let warning = format!(
+        "sends messages of version {} newer than {}",
+        newer,
+        versions::MESSAGE_VERSION
+    );
+    assert_eq!(stderr.matches(&warning).count(), 1, "{}", stderr);
+    assert_eq!(stderr.matches("newer than").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn address_in_use_is_reported() {
+    let _fixed = FIXED_ADDRESS.lock().unwrap_or_else(|err| err.into_inner());
+    let _taken = UdpSocket::bind("127.0.0.1:9090");
+    let output = hollow(&[]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error: failed to listen on 127.0.0.1:9090"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn messages_are_pushed_as_events() {
+    let port = common::free_tcp_port();
+    let sse_port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--sse-port",
+        &sse_port.to_string(),
+    ])
+    .stdout(Stdio::null())
+    .spawn()
+    .unwrap();
+
+    let subscribe = || {
+        let mut stream = common::connect(sse_port, Duration::from_secs(2));
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        write!(
+            stream,
+            "GET /events HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        while reader.read_line(&mut head).unwrap() > 0 && !head.ends_with("\r\n\r\n") {}
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(
+            head.to_ascii_lowercase()
+                .contains("content-type: text/event-stream"),
+            "{}",
+            head
+        );
+        reader
+    };
+    let mut browsers = [subscribe(), subscribe()];
+    thread::sleep(Duration::from_millis(100));
+
+    let mut client = common::connect(port, Duration::from_secs(2));
+    send_frame(&mut client, "1||2||rsync||Running");
+    send_frame(
+        &mut client,
+        r#"{"owl_pid":1,"pid":3,"name":"cron","state":"Sleeping"}"#,
+    );
+    for browser in browsers.iter_mut() {
+        let mut events = String::new();
+        while events.matches("\n\n").count() < 2 {
+            browser.read_line(&mut events).unwrap();
+        }
+        assert_eq!(
+            events,
+            "data: 1||2||rsync||Running\n\n\
+             data: {\"owl_pid\":1,\"pid\":3,\"name\":\"cron\",\"state\":\"Sleeping\"}\n\n"
+        );
+    }
+
+    drop(client);
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    child.wait_with_output().unwrap();
+}
+
+#[test]
+fn dry_run_counts_messages_without_printing() {
+    let port = common::free_tcp_port();
+    let child = hollow(&[
+        "--tcp-port",
+        &port.to_string(),
+        "--dry-run",
+        "--duration",
+        "3s",
+    ])
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap();
+    let mut client = common::connect(port, Duration::from_secs(2));
+    let mut bytes = 0;
+    for pid in 0..10_000 {
+        let record = format!("1||{}||rsync||Running||cpu_pct=1", pid);
+        bytes += record.len();
+        send_frame(&mut client, &record);
+    }
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    assert!(
+        stdout.starts_with(&format!(
+            "received 10000 messages (0 parse errors, {} bytes) in ",
+            bytes
+        )),
+        "{}",
+        stdout
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn messages_are_received_from_message_queue() {
+    let name = format!("/owl-test-{}-mq", std::process::id());
+    let mut receiver = hollow(&["--mq", &name])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let status = common::owl(&["+Heartbeat:100ms", "+Transport:posix_mq"])
+        .arg(format!("+MqName:{}", name))
+        .args(["sleep", "0.35"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut lines = BufReader::new(receiver.stdout.take().unwrap()).lines();
+    let mut names = Vec::new();
+    loop {
+        let line = lines.next().unwrap().unwrap();
+        let record = line
+            .strip_prefix(&format!("{} -> ", name))
+            .unwrap()
+            .to_owned();
+        names.push(record.split("||").nth(2).unwrap().to_owned());
+        if common::field(&record, "event") == Some("accounting") {
+            break;
+        }
+    }
+    assert!(names.len() >= 3, "{:?}", names);
+    assert!(names.iter().all(|n| n == "sleep"), "{:?}", names);
+
+    kill(Pid::from_raw(receiver.id() as i32), Signal::SIGINT).unwrap();
+    receiver.wait().unwrap();
+}
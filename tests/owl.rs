@@ -0,0 +1,2396 @@
+/*
+ * Copyright 2019 Andrew "workanator" Bashkatov
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//!
+//! Tests which run the tool with the command and check its exit code,
+//! output, and messages delivered.
+//!
+
+pub mod common;
+
+use common::{owl, Receiver};
+use nix::sys::signal::{kill, sigprocmask, SigSet, SigmaskHow, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn missing_command_exits_127() {
+    let output = owl(&["+Host:127.0.0.1", "owl-test-no-such-command"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not found"));
+}
+
+#[test]
+fn not_executable_command_exits_127() {
+    let dir = common::temp_dir("not-executable");
+    let path = dir.join("script.sh");
+    fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+    let output = owl(&["+Host:127.0.0.1", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+}
+
+#[test]
+fn failed_pre_exec_hook_skips_command() {
+    let dir = common::temp_dir("pre-exec-failure");
+    let marker = dir.join("command-ran");
+    let status = owl(&["+Host:127.0.0.1", "+PreExec:exit 3", "touch"])
+        .arg(&marker)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert!(!marker.exists());
+}
+
+#[test]
+fn post_exec_hook_runs_after_success_and_failure() {
+    for (command, code) in [("true", 0), ("false", 1)].iter() {
+        let dir = common::temp_dir(&format!("post-exec-{}", command));
+        let marker = dir.join("hook-ran");
+        let hook = format!("+PostExec:touch {}", marker.display());
+        let status = owl(&["+Host:127.0.0.1", &hook, command]).status().unwrap();
+        assert_eq!(status.code(), Some(*code));
+        assert!(marker.exists(), "post-exec did not run after '{}'", command);
+    }
+}
+
+#[test]
+fn hooks_share_command_environment() {
+    let status = owl(&[
+        "+Host:127.0.0.1",
+        "+Env:OWL_TEST_HOOK=1",
+        "+PreExec:test \"$OWL_TEST_HOOK\" = 1",
+        "true",
+    ])
+    .status()
+    .unwrap();
+    assert_eq!(status.code(), Some(0));
+}
+
+#[test]
+fn post_exec_hook_runs_when_command_fails_to_start() {
+    let dir = common::temp_dir("post-exec-no-start");
+    let marker = dir.join("hook-ran");
+    let hook = format!("+PostExec:touch {}", marker.display());
+    let log = format!("+LogFile:{}", dir.join("missing/owl.log").display());
+    let status = owl(&["+Host:127.0.0.1", &hook, &log, "true"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+    assert!(marker.exists());
+}
+
+#[test]
+fn log_file_is_reopened_on_rotate_signal() {
+    let dir = common::temp_dir("log-rotate");
+    let log = dir.join("job.log");
+    let mut child = owl(&["+Host:127.0.0.1", "+LogRotateSignal:SIGUSR2"])
+        .arg(format!("+LogFile:{}", log.display()))
+        .args(["sh", "-c", "echo before; sleep 1; echo after"])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    // Rotate the log like logrotate does and tell the tool about it
+    let rotated = dir.join("job.log.1");
+    fs::rename(&log, &rotated).unwrap();
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGUSR2).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), "before\n");
+    assert_eq!(fs::read_to_string(&log).unwrap(), "after\n");
+}
+
+#[test]
+fn log_file_is_rotated_over_size_limit() {
+    let dir = common::temp_dir("log-rotate-size");
+    let log = dir.join("job.log");
+    let status = owl(&["+Host:127.0.0.1", "+LogRotateSize:8"])
+        .arg(format!("+LogFile:{}", log.display()))
+        .args(["sh", "-c", "echo 0123456789; sleep 0.2; echo next"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        fs::read_to_string(dir.join("job.log.1")).unwrap(),
+        "0123456789\n"
+    );
+    assert_eq!(fs::read_to_string(&log).unwrap(), "next\n");
+}
+
+#[test]
+fn unsupported_namespace_is_reported() {
+    let output = owl(&["+Host:127.0.0.1", "+Namespace:net", "sleep", "0.3"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsupported namespace type 'net'"));
+}
+
+#[cfg(feature = "test_namespaces")]
+#[test]
+fn stats_are_read_in_pid_namespace() {
+    let mut namespace = std::process::Command::new("unshare")
+        .args(["--user", "--map-root-user", "--pid", "--fork", "sleep", "5"])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let children = format!("/proc/{0}/task/{0}/children", namespace.id());
+    let pid = fs::read_to_string(children).unwrap().trim().to_owned();
+
+    let receiver = Receiver::bind();
+    let mut child = owl(&["+Namespace:pid", "+HeartbeatCount:1"])
+        .args(receiver.opts())
+        .arg(format!("+NsPid:/proc/{}/ns/pid", pid))
+        .arg(format!("+Pids:{}", pid))
+        .spawn()
+        .unwrap();
+    let record = receiver.wait_for(Duration::from_secs(3), |r| r.contains("||sleep||"));
+    kill(Pid::from_raw(pid.parse().unwrap()), Signal::SIGKILL).unwrap();
+    let _ = namespace.wait();
+    let _ = child.wait();
+    assert!(record.is_some());
+}
+
+#[test]
+fn state_file_is_always_complete_json() {
+    let dir = common::temp_dir("state-file");
+    let path = dir.join("state.json");
+    let mut child = owl(&["+Transport:file", "+Heartbeat:5ms"])
+        .arg(format!("+StateFile:{}", path.display()))
+        .args(["sleep", "1"])
+        .spawn()
+        .unwrap();
+
+    let mut reads = 0;
+    while child.try_wait().unwrap().is_none() {
+        if let Ok(text) = fs::read_to_string(&path) {
+            assert!(common::complete_json(&text), "partial state: {}", text);
+            reads += 1;
+        }
+    }
+    assert!(reads > 0);
+    let text = fs::read_to_string(&path).unwrap();
+    assert!(text.contains("\"event\":\"accounting\""));
+}
+
+#[test]
+fn port_service_name_is_resolved() {
+    let output = owl(&["+Host:127.0.0.1", "+Port:ntp", "+Banner:true", "true"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("127.0.0.1:123"));
+}
+
+#[test]
+fn unknown_port_service_is_reported() {
+    let output = owl(&["+Host:127.0.0.1", "+Port:owl-no-such-service", "true"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("neither a port number nor a known service name"));
+}
+
+#[test]
+fn received_state_is_printed() {
+    let port = format!("+Port:{}", common::free_port());
+    let output = owl(&[
+        "+Host:127.0.0.1",
+        &port,
+        "+Receive:true",
+        "+Heartbeat:100ms",
+        "sleep",
+        "0.5",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("||sleep||"), "nothing received: {}", stdout);
+}
+
+#[test]
+fn downward_api_labels_are_attached() {
+    let receiver = Receiver::bind();
+    let status = owl(&[
+        "+Format:json",
+        "+K8sLabels:true",
+        "+Label:pod_name=web-2,team=infra",
+        "+HeartbeatCount:1",
+    ])
+    .args(receiver.opts())
+    .args(["sleep", "0.5"])
+    .env("MY_POD_NAME", "web-1")
+    .env("MY_NODE_NAME", "node-a")
+    .status()
+    .unwrap();
+    assert!(status.success());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| r.contains("\"labels\""))
+        .expect("no labels are delivered");
+    assert!(
+        record.contains(r#""labels":{"node_name":"node-a","pod_name":"web-2","team":"infra"}"#),
+        "unexpected labels: {}",
+        record
+    );
+}
+
+#[test]
+fn paused_command_does_not_run() {
+    let receiver = Receiver::bind();
+    let mut child = owl(&[
+        "+PauseSignal:SIGUSR1",
+        "+ResumeSignal:SIGUSR2",
+        "+Heartbeat:100ms",
+    ])
+    .args(receiver.opts())
+    .args(["sh", "-c", "while :; do :; done"])
+    .spawn()
+    .unwrap();
+    let owl_pid = Pid::from_raw(child.id() as i32);
+    thread::sleep(Duration::from_millis(300));
+    let pid = common::children(child.id())[0];
+
+    kill(owl_pid, Signal::SIGUSR1).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let paused_ticks = common::cpu_ticks(pid);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(common::cpu_ticks(pid), paused_ticks);
+    assert!(receiver
+        .wait_for(Duration::from_secs(1), |r| common::field(r, "paused")
+            == Some("true"))
+        .is_some());
+
+    kill(owl_pid, Signal::SIGUSR2).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert!(common::cpu_ticks(pid) > paused_ticks);
+    assert!(receiver
+        .wait_for(Duration::from_secs(1), |r| common::field(r, "paused")
+            == Some("false"))
+        .is_some());
+
+    kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap();
+    child.wait().unwrap();
+}
+
+///
+/// Get the `cpu_efficiency` of the record.
+///
+fn cpu_efficiency(record: &str) -> Option<f64> {
+    common::field(record, "cpu_efficiency")?.parse().ok()
+}
+
+#[test]
+fn cpu_efficiency_of_busy_command_is_high() {
+    let receiver = Receiver::bind();
+    let mut child = owl(&["+Heartbeat:200ms"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "while :; do :; done"])
+        .spawn()
+        .unwrap();
+    let record = receiver.wait_for(Duration::from_secs(3), |r| {
+        cpu_efficiency(r).filter(|e| *e > 70.0).is_some()
+    });
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    child.wait().unwrap();
+    assert!(record.is_some(), "the efficiency is not high");
+}
+
+#[test]
+fn cpu_efficiency_of_sleeping_command_is_low() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:200ms"])
+        .args(receiver.opts())
+        .args(["sleep", "1"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let efficiencies: Vec<f64> = receiver
+        .records(Duration::from_millis(200))
+        .iter()
+        .filter_map(|r| cpu_efficiency(r))
+        .collect();
+    assert!(!efficiencies.is_empty());
+    assert!(
+        efficiencies.iter().all(|e| *e < 5.0),
+        "the efficiency is not low: {:?}",
+        efficiencies
+    );
+}
+
+#[test]
+fn dot_env_port_is_used() {
+    let receiver = Receiver::bind();
+    let dir = common::temp_dir("dot-env");
+    fs::write(
+        dir.join(".env"),
+        format!("OWL_HOST=127.0.0.1\nOWL_PORT={}\n", receiver.port()),
+    )
+    .unwrap();
+    let status = owl(&["+HeartbeatCount:1", "true"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(receiver
+        .wait_for(Duration::from_secs(1), |r| r.contains("||true||"))
+        .is_some());
+}
+
+///
+/// Count heartbeats of `records` which are not events.
+///
+fn heartbeats(records: &[String]) -> usize {
+    records
+        .iter()
+        .filter(|r| common::field(r, "event").is_none())
+        .count()
+}
+
+#[test]
+fn unchanged_heartbeats_are_skipped() {
+    let receiver = Receiver::bind();
+    let status = owl(&[
+        "+Heartbeat:100ms",
+        "+HeartbeatOnChange:true",
+        "+MaxSilence:10s",
+    ])
+    .args(receiver.opts())
+    .args(["sleep", "1.5"])
+    .status()
+    .unwrap();
+    assert!(status.success());
+    // The first heartbeat may catch the command loading so the second one is sent too
+    assert_eq!(heartbeats(&receiver.records(Duration::from_millis(200))), 2);
+}
+
+#[test]
+fn unchanged_heartbeat_is_sent_after_max_silence() {
+    let receiver = Receiver::bind();
+    let status = owl(&[
+        "+Heartbeat:100ms",
+        "+HeartbeatOnChange:true",
+        "+MaxSilence:400ms",
+    ])
+    .args(receiver.opts())
+    .args(["sleep", "1.8"])
+    .status()
+    .unwrap();
+    assert!(status.success());
+    // Two heartbeats on start and one every 400ms of silence
+    let count = heartbeats(&receiver.records(Duration::from_millis(200)));
+    assert!((4..=6).contains(&count), "{} heartbeats are sent", count);
+}
+
+#[test]
+fn running_processes_are_batched() {
+    let receiver = Receiver::bind();
+    let mut first = Command::new("sleep").arg("1").spawn().unwrap();
+    let mut second = Command::new("sleep").arg("1").spawn().unwrap();
+    let pids = format!("+Pids:{},{}", first.id(), second.id());
+    let mut child = owl(&[&pids, "+HeartbeatCount:1"])
+        .args(receiver.opts())
+        .spawn()
+        .unwrap();
+    let payload = receiver
+        .datagram(Duration::from_secs(1))
+        .expect("no batch is delivered");
+    let payload = String::from_utf8_lossy(&payload);
+    let lines: Vec<&str> = payload.lines().collect();
+    assert_eq!(lines.len(), 2, "unexpected batch: {}", payload);
+    let pids: Vec<String> = [&first, &second]
+        .iter()
+        .map(|c| c.id().to_string())
+        .collect();
+    for (line, pid) in lines.iter().zip(pids.iter()) {
+        assert_eq!(line.split("||").nth(1), Some(pid.as_str()));
+    }
+
+    first.wait().unwrap();
+    second.wait().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn command_over_rss_limit_is_killed() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:100ms", "+MaxRssBytes:64M"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "s=x; while :; do s=\"$s$s\"; sleep 0.05; done"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(137));
+}
+
+#[test]
+fn command_reads_stdin_file() {
+    let dir = common::temp_dir("stdin-file");
+    let path = dir.join("input.txt");
+    fs::write(&path, "first line\nsecond line\n").unwrap();
+    let stdin = format!("+ChildStdinFile:{}", path.display());
+    let output = owl(&[&stdin, "+HeartbeatCount:0", "cat"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"first line\nsecond line\n");
+}
+
+#[test]
+fn state_file_survives_killed_writer() {
+    let dir = common::temp_dir("state-file-killed");
+    let path = dir.join("state.json");
+    for attempt in 0..10 {
+        let mut child = owl(&["+Transport:file", "+Heartbeat:1ms", "+FsyncState:true"])
+            .arg(format!("+StateFile:{}", path.display()))
+            .args(["sleep", "10"])
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(50 + attempt * 7));
+        let commands = common::children(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        for pid in commands {
+            kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap();
+        }
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(common::complete_json(&text), "partial state: {}", text);
+    }
+}
+
+///
+/// Get names of variables the command gets with the `Environment` option `mode`.
+///
+fn child_environment(mode: &str) -> Vec<String> {
+    let output = owl(&[
+        &format!("+Environment:{}", mode),
+        "+Env:OWL_TEST_EXTRA=extra",
+        "+HeartbeatCount:0",
+        "/usr/bin/env",
+    ])
+    .env("OWL_TEST_SENTINEL", "sentinel")
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split('=').next())
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn environment_modes_pass_variables() {
+    let has = |vars: &[String], name: &str| vars.iter().any(|v| v == name);
+
+    let vars = child_environment("inherit");
+    assert!(has(&vars, "OWL_TEST_SENTINEL") && has(&vars, "OWL_TEST_EXTRA"));
+    assert!(has(&vars, "PATH"));
+
+    let vars = child_environment("minimal");
+    assert!(!has(&vars, "OWL_TEST_SENTINEL") && has(&vars, "OWL_TEST_EXTRA"));
+    assert!(has(&vars, "PATH"));
+
+    let vars = child_environment("clean");
+    assert_eq!(vars, ["OWL_TEST_EXTRA"]);
+}
+
+#[test]
+fn zombie_children_are_counted() {
+    let receiver = Receiver::bind();
+    let mut child = owl(&["+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "sleep 0.3 & exec sleep 5"])
+        .spawn()
+        .unwrap();
+    let zombies = |r: &str| common::field(r, "zombie_children").map(String::from);
+    let before = receiver.wait_for(Duration::from_secs(1), |r| zombies(r).is_some());
+    let after = receiver.wait_for(Duration::from_secs(2), |r| {
+        zombies(r).as_deref() == Some("1")
+    });
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    child.wait().unwrap();
+    assert_eq!(before.as_deref().and_then(zombies).as_deref(), Some("0"));
+    assert!(after.is_some(), "the zombie is not counted");
+}
+
+#[test]
+fn cgroup_main_process_is_picked() {
+    let mut first = Command::new("sleep").arg("5").spawn().unwrap();
+    let mut second = Command::new("sleep").arg("5").spawn().unwrap();
+    for (mode, expected) in [("first", first.id()), ("last", second.id())] {
+        let receiver = Receiver::bind();
+        let dir = common::temp_dir(&format!("cgroup-{}", mode));
+        fs::write(
+            dir.join("cgroup.procs"),
+            format!("{}\n{}\n", first.id(), second.id()),
+        )
+        .unwrap();
+        let mut child = owl(&["+Heartbeat:100ms", &format!("+CgroupMainPid:{}", mode)])
+            .arg(format!("+CgroupPath:{}", dir.display()))
+            .args(receiver.opts())
+            .spawn()
+            .unwrap();
+        let record = receiver.wait_for(Duration::from_secs(1), |_| true);
+        fs::remove_dir_all(&dir).unwrap();
+        child.wait().unwrap();
+        let record = record.expect("no heartbeat is delivered");
+        assert_eq!(
+            record.split("||").nth(1),
+            Some(expected.to_string().as_str())
+        );
+    }
+    first.kill().unwrap();
+    second.kill().unwrap();
+    first.wait().unwrap();
+    second.wait().unwrap();
+}
+
+///
+/// Test the signal `sig` is pending for the process with `pid`.
+///
+fn signal_pending(pid: u32, sig: Signal) -> bool {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("ShdPnd:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .is_some_and(|mask| mask & (1 << (sig as i32 - 1)) != 0)
+}
+
+#[test]
+fn signal_reaches_all_processes_at_once() {
+    // Processes block the signal so it stays pending and the time it arrives at is seen
+    let mut processes: Vec<_> = (0..100)
+        .map(|_| {
+            let mut command = Command::new("sleep");
+            command.arg("10");
+            unsafe {
+                command.pre_exec(|| {
+                    let mut set = SigSet::empty();
+                    set.add(Signal::SIGUSR2);
+                    sigprocmask(SigmaskHow::SIG_BLOCK, Some(&set), None)
+                        .map_err(|_| io::Error::last_os_error())
+                });
+            }
+            command.spawn().unwrap()
+        })
+        .collect();
+    let pids: Vec<u32> = processes.iter().map(|p| p.id()).collect();
+    let list: Vec<String> = pids.iter().map(u32::to_string).collect();
+    let mut child = owl(&[&format!("+Pids:{}", list.join(",")), "+HeartbeatCount:0"])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGUSR2).unwrap();
+    let mut arrived: Vec<Option<Instant>> = vec![None; pids.len()];
+    let until = Instant::now() + Duration::from_secs(2);
+    while arrived.iter().any(Option::is_none) && Instant::now() < until {
+        for (pid, at) in pids.iter().zip(arrived.iter_mut()) {
+            if at.is_none() && signal_pending(*pid, Signal::SIGUSR2) {
+                *at = Some(Instant::now());
+            }
+        }
+    }
+    for process in processes.iter_mut() {
+        process.kill().unwrap();
+        process.wait().unwrap();
+    }
+    child.wait().unwrap();
+
+    let arrived: Vec<Instant> = arrived.into_iter().flatten().collect();
+    assert_eq!(
+        arrived.len(),
+        pids.len(),
+        "not every process gets the signal"
+    );
+    let spread = arrived
+        .iter()
+        .max()
+        .unwrap()
+        .duration_since(*arrived.iter().min().unwrap());
+    assert!(
+        spread <= Duration::from_millis(10),
+        "the spread is {:?}",
+        spread
+    );
+}
+
+#[test]
+fn locked_job_runs_once() {
+    let dir = common::temp_dir("lock-file");
+    let lock = format!("+LockFile:{}", dir.join("job.lock").display());
+    let mut first = owl(&[&lock, "+HeartbeatCount:0", "sleep", "1"])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let second = owl(&[&lock, "+HeartbeatCount:0", "true"]).status().unwrap();
+    assert_eq!(second.code(), Some(75));
+    let contents = fs::read_to_string(dir.join("job.lock")).unwrap();
+    assert!(contents.contains(&first.id().to_string()));
+    assert!(first.wait().unwrap().success());
+
+    // The lock is released when the instance exits
+    let third = owl(&[&lock, "+HeartbeatCount:0", "true"]).status().unwrap();
+    assert!(third.success());
+}
+
+// Run `sleep` with `opts`, send `signal` to the tool, and get the exit code
+// along with the id of the tool.
+fn signal_session(opts: &[&str], signal: Signal) -> (Option<i32>, u32) {
+    let mut child = owl(&["+HeartbeatCount:0"])
+        .args(opts)
+        .args(["sleep", "1"])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    kill(Pid::from_raw(child.id() as i32), signal).unwrap();
+    (child.wait().unwrap().code(), child.id())
+}
+
+#[test]
+fn stats_are_dumped_on_signal() {
+    let dir = common::temp_dir("dump");
+    let path = dir.join("owl.json");
+    let dump_file = format!("+DumpFile:{}", path.display());
+    let (code, pid) = signal_session(&[&dump_file, "+AuthToken:hidden-value"], Signal::SIGUSR1);
+    assert_eq!(code, Some(0), "the signal is propagated");
+    let text = fs::read_to_string(&path).expect("no dump is written");
+    assert!(common::complete_json(&text), "invalid dump: {}", text);
+    assert!(text.contains(&format!("{}", pid)));
+    assert!(text.contains("\"sleep\""));
+    assert!(!text.contains("hidden-value"));
+
+    fs::remove_file(&path).unwrap();
+    let (code, _) = signal_session(&[&dump_file, "+DumpSignal:SIGUSR2"], Signal::SIGUSR2);
+    assert_eq!(code, Some(0), "the signal is propagated");
+    assert!(path.exists(), "no dump is written");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dump_signal_is_propagated_without_dump_options() {
+    let (code, pid) = signal_session(&[], Signal::SIGUSR1);
+    assert_eq!(code, Some(128 + libc::SIGUSR1));
+    assert!(!Path::new(&format!("/tmp/owl-{}.json", pid)).exists());
+}
+
+#[test]
+fn invalid_heartbeat_exits_before_command() {
+    let dir = common::temp_dir("invalid-heartbeat");
+    let marker = dir.join("started");
+    let output = owl(&["+Heartbeat:abc", "touch"])
+        .arg(&marker)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("abc"));
+    assert!(!marker.exists(), "the command is started");
+}
+
+#[test]
+fn connection_is_retried_until_receiver_starts() {
+    let port = common::free_tcp_port();
+    let child = owl(&[
+        "+Transport:tcp",
+        "+Host:127.0.0.1",
+        &format!("+Port:{}", port),
+        "+ConnectRetry:20",
+        "+ConnectRetryDelay:100ms",
+        "+Heartbeat:100ms",
+        "sleep",
+        "1.5",
+    ])
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    assert!(String::from_utf8_lossy(&payload).contains("||sleep||"));
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("connection attempt 1 of 21"));
+    assert!(!stderr.contains("error:"), "heartbeats failed: {}", stderr);
+}
+
+#[test]
+fn script_header_options_are_applied() {
+    let receiver = Receiver::bind();
+    let dir = common::temp_dir("script-header");
+    let script = dir.join("job.sh");
+    fs::write(
+        &script,
+        format!(
+            "#!{}\n# owl: +Name:scripted_job {} +HeartbeatCount:1 sh\necho \"$0 $1\"\nsleep 0.3\n",
+            env!("CARGO_BIN_EXE_owl"),
+            receiver.opts().join(" ")
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = Command::new(&script).arg("first").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("{} first", script.display())
+    );
+    assert!(receiver
+        .wait_for(Duration::from_secs(1), |r| r.contains("||scripted_job||"))
+        .is_some());
+}
+
+#[test]
+fn high_water_marks_never_decrease() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:50ms"])
+        .args(receiver.opts())
+        .args([
+            "sh",
+            "-c",
+            "s=x; for i in $(seq 22); do s=\"$s$s\"; sleep 0.02; done; s=; sleep 0.5",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let records = receiver.records(Duration::from_millis(200));
+    let values = |name: &str| -> Vec<u64> {
+        records
+            .iter()
+            .filter_map(|r| common::field(r, name)?.parse().ok())
+            .collect()
+    };
+    let rss = values("rss_bytes");
+    let hwm = values("rss_hwm_bytes");
+    let peak = values("vm_peak_bytes");
+    assert!(hwm.len() > 5, "too few samples: {:?}", hwm);
+    assert!(hwm.windows(2).all(|w| w[0] <= w[1]), "{:?}", hwm);
+    assert!(peak.windows(2).all(|w| w[0] <= w[1]), "{:?}", peak);
+    assert!(hwm.last() >= rss.iter().max());
+}
+
+///
+/// Run the command which reports SIGTERM with `TermPropagation` set to `mode`
+/// sending SIGTERM to the tool while the command runs.
+///
+fn terminated_command_output(mode: &str) -> (Option<i32>, String) {
+    let child = owl(&[&format!("+TermPropagation:{}", mode), "+HeartbeatCount:0"])
+        .args([
+            "sh",
+            "-c",
+            "trap 'echo terminated; exit 3' TERM; sleep 1 & wait",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    (output.status.code(), stdout)
+}
+
+#[test]
+fn sigterm_is_not_propagated_with_none() {
+    let (code, stdout) = terminated_command_output("none");
+    assert_eq!(code, Some(0));
+    assert!(stdout.is_empty(), "the command is terminated");
+
+    let (code, stdout) = terminated_command_output("immediate");
+    assert_eq!(code, Some(3));
+    assert_eq!(stdout.trim(), "terminated");
+}
+
+#[test]
+fn send_latency_to_loopback_is_low() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .args(["sleep", "0.5"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(200));
+    let latencies: Vec<u64> = records
+        .iter()
+        .filter_map(|r| common::field(r, "send_latency_us")?.parse().ok())
+        .collect();
+    // The first heartbeat has no previous send to report
+    assert!(!latencies.is_empty());
+    assert!(latencies.iter().all(|us| *us < 10_000), "{:?}", latencies);
+    assert!(records
+        .iter()
+        .any(|r| common::field(r, "avg_send_latency_us").is_some()));
+}
+
+#[test]
+fn command_leads_own_group_with_signal_group() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+SignalGroup:true", "+HeartbeatCount:1"])
+        .args(receiver.opts())
+        .args(["sleep", "0.3"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "pgrp").is_some()
+        })
+        .expect("no heartbeat is delivered");
+    let pid = record.split("||").nth(1);
+    assert_eq!(common::field(&record, "pgrp"), pid);
+    assert!(common::field(&record, "session").is_some());
+}
+
+#[test]
+fn pending_heartbeats_precede_delayed_command() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+ExecDelayStart:400ms", "+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .args(["sleep", "0.4"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records: Vec<String> = receiver
+        .records(Duration::from_millis(200))
+        .into_iter()
+        .filter(|r| common::field(r, "event").is_none())
+        .collect();
+    let statuses: Vec<&str> = records
+        .iter()
+        .filter_map(|r| common::field(r, "status"))
+        .collect();
+    let pending = statuses.iter().take_while(|s| **s == "pending").count();
+    assert!(pending >= 2, "unexpected statuses: {:?}", statuses);
+    assert!(statuses[pending..].iter().all(|s| *s == "running"));
+    assert!(statuses.len() > pending, "no heartbeat of the command");
+    for record in &records[..pending] {
+        assert_eq!(record.split("||").nth(1), Some("0"));
+        assert_eq!(record.split("||").nth(3), Some("Pending"));
+    }
+}
+
+#[test]
+fn command_gets_signals_blocked_in_tool() {
+    let mut command = owl(&["+HeartbeatCount:0", "sleep", "5"]);
+    unsafe {
+        command.pre_exec(|| {
+            let mut set = SigSet::empty();
+            set.add(Signal::SIGTERM);
+            sigprocmask(SigmaskHow::SIG_BLOCK, Some(&set), None)
+                .map_err(|_| io::Error::last_os_error())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let pid = common::children(child.id())[0];
+    kill(Pid::from_raw(pid as i32), Signal::SIGTERM).unwrap();
+
+    let started = Instant::now();
+    let status = child.wait().unwrap();
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "SIGTERM is blocked"
+    );
+    assert!(!status.success());
+}
+
+#[test]
+fn accounting_reports_cpu_time_of_busy_command() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+HeartbeatCount:0"])
+        .args(receiver.opts())
+        .args([
+            "sh",
+            "-c",
+            "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("accounting")
+        })
+        .expect("no accounting message is delivered");
+    assert_eq!(common::field(&record, "exit_code"), Some("0"));
+    let utime: u64 = common::field(&record, "ru_utime_ms")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(utime > 0);
+}
+
+#[test]
+fn smoke_check_confirms_tcp_receiver() {
+    let port = common::free_tcp_port();
+    let opts = [
+        "+Host:127.0.0.1".to_owned(),
+        format!("+Port:{}", port),
+        "+Transport:tcp".to_owned(),
+    ];
+    let status = owl(&[]).args(&opts).arg("--test").status().unwrap();
+    assert_eq!(status.code(), Some(1), "no receiver is listening");
+
+    let mut receiver = common::hollow(&["--tcp-port", &port.to_string()])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    drop(common::connect(port, Duration::from_secs(2)));
+    let status = owl(&[]).args(&opts).arg("--test").status().unwrap();
+    kill(Pid::from_raw(receiver.id() as i32), Signal::SIGINT).unwrap();
+    receiver.wait().unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn core_dump_path_is_reported() {
+    let receiver = Receiver::bind();
+    let dir = common::temp_dir("core-dump");
+    let status = owl(&["+HeartbeatCount:0"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "ulimit -c unlimited; kill -ABRT $$"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("accounting")
+        })
+        .expect("no accounting message is delivered");
+
+    // The dump is written only where the kernel core pattern allows it
+    let dumps: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("core")
+        })
+        .collect();
+    if let [dump] = dumps.as_slice() {
+        assert_eq!(
+            common::field(&record, "coredump_path"),
+            Some(dump.to_string_lossy().as_ref())
+        );
+    }
+}
+
+#[test]
+fn sparkline_grows_and_slides() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+SparklineSize:3", "+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .args([
+            "sh",
+            "-c",
+            "s=x; for i in $(seq 18); do s=\"$s$s\"; sleep 0.05; done",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let sparklines: Vec<Vec<String>> = receiver
+        .records(Duration::from_millis(200))
+        .iter()
+        .filter_map(|r| common::field(r, "sparkline_rss"))
+        .map(|v| v.split(',').map(String::from).collect())
+        .collect();
+    assert!(sparklines.len() > 4, "too few heartbeats: {:?}", sparklines);
+
+    let lengths: Vec<usize> = sparklines.iter().map(Vec::len).collect();
+    assert_eq!(lengths[..3], [1, 2, 3]);
+    assert!(lengths[3..].iter().all(|len| *len == 3), "{:?}", lengths);
+    for pair in sparklines.windows(2) {
+        let kept = pair[0].len().min(2);
+        assert_eq!(
+            pair[0][pair[0].len() - kept..],
+            pair[1][pair[1].len() - 1 - kept..pair[1].len() - 1]
+        );
+    }
+}
+
+// Validate the schema against the draft-07 meta-schema and the `message`
+// against the schema, get `None` if the validator is not installed.
+// Every field of the message must be listed in the schema.
+fn validate_schema(schema: &[u8], message: &str) -> Option<Result<(), String>> {
+    const SCRIPT: &str = "import json, sys, jsonschema
+schema = json.loads(sys.argv[1])
+message = json.loads(sys.argv[2])
+jsonschema.Draft7Validator.check_schema(schema)
+jsonschema.Draft7Validator(schema).validate(message)
+unlisted = set(message) - set(schema['properties'])
+assert not unlisted, 'fields are not listed: %s' % sorted(unlisted)";
+
+    let available = Command::new("python3")
+        .args(["-c", "import jsonschema"])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !available {
+        return None;
+    }
+
+    let output = Command::new("python3")
+        .args(["-c", SCRIPT, &String::from_utf8_lossy(schema), message])
+        .output()
+        .unwrap();
+    Some(if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    })
+}
+
+#[test]
+fn json_schema_is_valid_and_matches_messages() {
+    let schema = owl(&["--json-schema"]).output().unwrap();
+    assert!(schema.status.success());
+    let text = String::from_utf8_lossy(&schema.stdout);
+    assert!(common::complete_json(&text), "invalid schema: {}", text);
+    assert!(text.contains(r#""$schema": "http://json-schema.org/draft-07/schema#""#));
+    assert!(text.contains(&format!(r#""version": "{}""#, env!("CARGO_PKG_VERSION"))));
+
+    let hollow = common::hollow(&["--json-schema"]).output().unwrap();
+    assert!(hollow.status.success());
+    assert_eq!(hollow.stdout, schema.stdout);
+
+    // Options add most of optional fields to messages
+    let receiver = Receiver::bind();
+    let status = owl(&[
+        "+Format:json",
+        "+Heartbeat:100ms",
+        "+StatsInterval:50ms",
+        "+DeliverAggregated:true",
+        "+SparklineSize:5",
+        "+CountOutputLines:true",
+        "+SelfMetrics:true",
+        "+Label:env=test",
+        "+Envelope:true",
+        "+MemMaps:true",
+        "+CpuBudget:10s",
+        "+AlertCwdChange:true",
+        "+OutputMode:capture",
+    ])
+    .args(receiver.opts())
+    .args(["sh", "-c", "echo hello; sleep 0.3; cd /; sleep 0.3"])
+    .stdout(Stdio::null())
+    .status()
+    .unwrap();
+    assert!(status.success());
+    let messages = receiver.records(Duration::from_millis(300));
+    assert!(messages.len() >= 5, "{:?}", messages);
+    assert!(messages.iter().any(|m| m.contains("\"alerts\"")));
+
+    for message in messages.iter() {
+        match validate_schema(&schema.stdout, message) {
+            Some(result) => result.unwrap(),
+            None => {
+                eprintln!("jsonschema is not installed, the schema is not validated");
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn open_named_pipes_are_watched() {
+    let dir = common::temp_dir("fifos");
+    let fifo = dir.join("jobs.fifo");
+    let other = dir.join("jobs.other");
+    for path in [&fifo, &other] {
+        let status = Command::new("mkfifo").arg(path).status().unwrap();
+        assert!(status.success());
+    }
+
+    let receiver = Receiver::bind();
+    let pattern = format!("+FifoPattern:{}/*.fifo", dir.display());
+    let script = format!("exec 3<>{} 4<>{}; sleep 1", fifo.display(), other.display());
+    let status = owl(&[
+        "+Format:json",
+        "+Heartbeat:100ms",
+        "+WatchFifos:true",
+        &pattern,
+    ])
+    .args(receiver.opts())
+    .args(["sh", "-c", &script])
+    .status()
+    .unwrap();
+    assert!(status.success());
+    let watched = format!(r#""monitored_fifos":["{}"]"#, fifo.display());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            r.contains("monitored_fifos\":[\"")
+        })
+        .expect("no named pipes are reported");
+    assert!(record.contains(&watched), "unexpected pipes: {}", record);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Accept requests to the mock push gateway until the DELETE request
+// and get request lines with bodies.
+fn gateway_requests(listener: TcpListener) -> Vec<(String, String)> {
+    let mut requests = Vec::new();
+    for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        let done = line.starts_with("DELETE");
+        requests.push((line.trim().to_owned(), String::from_utf8(body).unwrap()));
+        if done {
+            break;
+        }
+    }
+    requests
+}
+
+#[test]
+fn metrics_are_pushed_and_deleted() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "+PushgatewayUrl:http://{}/metrics/job/rsync",
+        listener.local_addr().unwrap()
+    );
+    let gateway = thread::spawn(move || gateway_requests(listener));
+
+    let status = owl(&[
+        "+Transport:pushgateway",
+        &url,
+        "+PushgatewayInstance:host1",
+        "+PushgatewayAutoDelete:true",
+        "+Heartbeat:100ms",
+        "sleep",
+        "0.5",
+    ])
+    .status()
+    .unwrap();
+    assert!(status.success());
+
+    let requests = gateway.join().unwrap();
+    let (last, puts) = requests.split_last().unwrap();
+    assert_eq!(last.0, "DELETE /metrics/job/rsync/instance/host1 HTTP/1.1");
+    assert!(!puts.is_empty());
+    for (line, body) in puts {
+        assert_eq!(line, "PUT /metrics/job/rsync/instance/host1 HTTP/1.1");
+        assert!(body.contains("# TYPE owl_state gauge\n"), "{}", body);
+        assert!(body.contains("owl_state{name=\"sleep\""), "{}", body);
+    }
+}
+
+#[test]
+fn output_lines_are_counted_and_forwarded() {
+    let receiver = Receiver::bind();
+    let output = owl(&["+Heartbeat:100ms", "+CountOutputLines:true"])
+        .args(receiver.opts())
+        .args([
+            "sh",
+            "-c",
+            "i=0; while [ $i -lt 100 ]; do i=$((i+1)); echo line $i; done; echo oops >&2; sleep 0.5",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().filter(|l| l.starts_with("line ")).count(),
+        100
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("oops"));
+
+    let records: Vec<String> = receiver
+        .records(Duration::from_millis(300))
+        .into_iter()
+        .filter(|r| common::field(r, "stdout_lines").is_some())
+        .collect();
+    let count =
+        |record: &String, name| -> u64 { common::field(record, name).unwrap().parse().unwrap() };
+    let last = records.last().expect("no output lines are reported");
+    assert_eq!(count(last, "stdout_lines"), 100);
+    assert_eq!(count(last, "stderr_lines"), 1);
+    let deltas: u64 = records.iter().map(|r| count(r, "stdout_lines_delta")).sum();
+    assert_eq!(deltas, 100);
+}
+
+#[test]
+fn command_is_run_with_prefix() {
+    let output = owl(&["+Host:127.0.0.1", "+CmdPrefix:echo,prefix", "ls", "-a"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "prefix ls -a\n");
+
+    // The command is named after itself while the prefix runs it
+    let receiver = Receiver::bind();
+    let output = owl(&["+CmdPrefix:sh,-c,sleep 0.3; echo prefix $0 \"$@\""])
+        .args(receiver.opts())
+        .args(["true", "a,b"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "prefix true a,b\n");
+
+    // The record starts with the tool pid, the command pid, and the name
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            r.split("||").nth(1) != Some("0")
+        })
+        .expect("no state is delivered");
+    assert_eq!(record.split("||").nth(2), Some("true"), "{}", record);
+}
+
+#[test]
+fn cloudwatch_transport_is_unsupported() {
+    let output = owl(&["+Transport:cloudwatch", "true"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unsupported transport 'cloudwatch'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn suid_command_is_reported() {
+    let dir = common::temp_dir("suid");
+    let binary = dir.join("suid-true");
+    fs::copy("/bin/true", &binary).unwrap();
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o6755)).unwrap();
+    let binary = binary.to_str().unwrap();
+
+    let output = owl(&["+Host:127.0.0.1", binary]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning: binary"), "{}", stderr);
+    assert!(stderr.contains("has the SUID bit set"), "{}", stderr);
+    assert!(stderr.contains("has the SGID bit set"), "{}", stderr);
+
+    let output = owl(&["+Host:127.0.0.1", "+StrictBinaryCheck:true", binary])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error: binary"));
+
+    let output = owl(&["+Host:127.0.0.1", "+SkipBinaryCheck:true", binary])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // Changing the owner needs root and clears the bits
+    if nix::unistd::geteuid().is_root() {
+        let nobody = nix::unistd::Uid::from_raw(65534);
+        nix::unistd::chown(binary, Some(nobody), None).unwrap();
+        fs::set_permissions(binary, fs::Permissions::from_mode(0o4755)).unwrap();
+        let output = owl(&["+Host:127.0.0.1", binary]).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("is owned by uid 65534, not root"),
+            "{}",
+            stderr
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn delivery_stops_after_heartbeat_count() {
+    let receiver = Receiver::bind();
+    let started = Instant::now();
+    let status = owl(&["+Heartbeat:50ms", "+HeartbeatCount:10"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "sleep 1.5; exit 3"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+    let records = receiver.records(Duration::from_millis(300));
+    assert_eq!(heartbeats(&records), 10, "{:?}", records);
+
+    let status = owl(&["+Heartbeat:50ms", "+HeartbeatCount:0"])
+        .args(receiver.opts())
+        .args(["sleep", "0.3"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Only the accounting event of the exit is delivered
+    let records = receiver.records(Duration::from_millis(200));
+    assert_eq!(heartbeats(&records), 0, "{:?}", records);
+}
+
+#[test]
+fn working_directory_change_is_alerted() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:100ms", "+AlertCwdChange:true"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "sleep 0.4; cd /tmp; sleep 0.4"])
+        .current_dir("/")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(300));
+    let alerts: Vec<&String> = records.iter().filter(|r| r.contains("||alert=")).collect();
+    assert_eq!(alerts.len(), 1, "{:?}", records);
+    assert!(
+        alerts[0].contains("changed working directory from / to /tmp"),
+        "{}",
+        alerts[0]
+    );
+    assert_eq!(common::field(alerts[0], "cwd"), Some("/tmp"));
+}
+
+#[test]
+fn options_are_loaded_from_project_conf() {
+    let dir = common::temp_dir("project-conf");
+    let cargo = dir.join("Cargo.toml");
+    let receiver = Receiver::bind();
+    fs::write(
+        &cargo,
+        format!(
+            "[package]\nname = \"job\"\n\n[owl]\nHost = \"127.0.0.1\"\nPort = {}\nName = \"job\"\nHeartbeatCount = 1\n",
+            receiver.port()
+        ),
+    )
+    .unwrap();
+    let conf = format!("+ProjectConf:{}", cargo.display());
+    let status = owl(&[&conf, "sleep", "0.3"]).status().unwrap();
+    assert!(status.success());
+    let named = receiver.wait_for(Duration::from_secs(1), |r| {
+        r.split("||").nth(2) == Some("job")
+    });
+    assert!(
+        named.is_some(),
+        "options of the project configuration are not used"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_reads_output_of_stdin_command() {
+    let output = owl(&["+Host:127.0.0.1", "+ChildStdinCmd:echo hello", "cat"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+
+    let output = owl(&[
+        "+Host:127.0.0.1",
+        "+ChildStdinCmd:echo denied >&2; false",
+        "cat",
+    ])
+    .output()
+    .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("denied"), "{}", stderr);
+    assert!(stderr.contains("exited with code 1"), "{}", stderr);
+}
+
+// Run the tool with `args`, send SIGHUP to it once the command starts,
+// and get the exit code and the time the tool took.
+fn sighup_session(args: &[&str]) -> (Option<i32>, Duration) {
+    let started = Instant::now();
+    let child = owl(args).spawn().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGHUP).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (output.status.code(), started.elapsed())
+}
+
+#[test]
+fn sighup_is_forwarded_or_ignored() {
+    let (code, elapsed) = sighup_session(&["+Host:127.0.0.1", "sleep", "5"]);
+    assert_eq!(code, Some(128 + libc::SIGHUP));
+    assert!(elapsed < Duration::from_secs(3));
+
+    let (code, elapsed) = sighup_session(&["+Host:127.0.0.1", "+OnSighup:forward", "sleep", "5"]);
+    assert_eq!(code, Some(128 + libc::SIGHUP));
+    assert!(elapsed < Duration::from_secs(3));
+
+    let (code, elapsed) = sighup_session(&["+Host:127.0.0.1", "+OnSighup:ignore", "sleep", "0.8"]);
+    assert_eq!(code, Some(0));
+    assert!(elapsed >= Duration::from_millis(800));
+}
+
+#[test]
+fn sighup_restarts_command() {
+    let dir = common::temp_dir("sighup-restart");
+    let runs = dir.join("runs");
+    let script = format!(
+        "echo run >> {0}; [ $(wc -l < {0}) -ge 2 ] || sleep 5",
+        runs.display()
+    );
+    let (code, elapsed) =
+        sighup_session(&["+Host:127.0.0.1", "+OnSighup:restart", "sh", "-c", &script]);
+    assert_eq!(code, Some(0));
+    assert!(elapsed < Duration::from_secs(3));
+    assert_eq!(fs::read_to_string(&runs).unwrap(), "run\nrun\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sighup_reloads_configuration() {
+    let dir = common::temp_dir("sighup-reload");
+    let conf = dir.join("owl.toml");
+    let before = Receiver::bind();
+    let after = Receiver::bind();
+    let watch = |receiver: &Receiver| {
+        format!(
+            "[watch]\nHost = \"127.0.0.1\"\nPort = {}\nHeartbeat = \"100ms\"\n",
+            receiver.port()
+        )
+    };
+    fs::write(&conf, watch(&before)).unwrap();
+
+    let started = Instant::now();
+    let child = owl(&[&format!("+Conf:{}", conf.display()), "+OnSighup:reload"])
+        .args(["sleep", "1.2"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(before.wait_for(Duration::from_secs(1), |_| true).is_some());
+    fs::write(&conf, watch(&after)).unwrap();
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGHUP).unwrap();
+    assert!(
+        after.wait_for(Duration::from_secs(1), |_| true).is_some(),
+        "heartbeats do not follow the reloaded port"
+    );
+
+    // The command does not get the signal and runs to the end
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(started.elapsed() >= Duration::from_millis(1200));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("delivery options reloaded"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_is_terminated_when_cpu_budget_is_spent() {
+    let receiver = Receiver::bind();
+    let started = Instant::now();
+    let output = owl(&["+Heartbeat:100ms", "+CpuBudget:500ms"])
+        .args(receiver.opts())
+        .args(["sh", "-c", "while :; do :; done"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(128 + libc::SIGTERM));
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let remaining: Vec<f64> = receiver
+        .records(Duration::from_millis(300))
+        .iter()
+        .filter_map(|r| common::field(r, "cpu_budget_remaining_secs")?.parse().ok())
+        .collect();
+    assert!(remaining.len() >= 2, "{:?}", remaining);
+    assert!(
+        remaining.windows(2).all(|w| w[1] <= w[0]),
+        "{:?}",
+        remaining
+    );
+    assert!(remaining[0] <= 0.5);
+    assert_eq!(*remaining.last().unwrap(), 0.0);
+}
+
+#[test]
+fn selected_env_set_is_passed_to_command() {
+    let dir = common::temp_dir("envsets");
+    let conf = dir.join("owl.toml");
+    fs::write(
+        &conf,
+        "[watch]\nHost = \"127.0.0.1\"\n\n\
+         [watch.envsets.production]\nOWL_TEST_DB = \"prod-db\"\nOWL_TEST_WORKERS = 8\n\n\
+         [watch.envsets.staging]\nOWL_TEST_DB = \"stage-db\"\nOWL_TEST_DEBUG = true\n",
+    )
+    .unwrap();
+    let output = owl(&[
+        &format!("+Conf:{}", conf.display()),
+        "+EnvSet:production",
+        "+Env:OWL_TEST_WORKERS=2",
+        "/usr/bin/env",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let mut vars: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("OWL_TEST_"))
+        .map(String::from)
+        .collect();
+    vars.sort();
+    assert_eq!(vars, ["OWL_TEST_DB=prod-db", "OWL_TEST_WORKERS=2"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn self_metrics_are_plausible() {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:100ms", "+SelfMetrics:true"])
+        .args(receiver.opts())
+        .args(["sleep", "0.5"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(300));
+    let record = records
+        .iter()
+        .find(|r| common::field(r, "owl_cpu_pct").is_some())
+        .expect("no self metrics are delivered");
+    let rss: u64 = common::field(record, "owl_rss_bytes")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(rss > 1 << 20 && rss < 500 << 20, "{}", rss);
+    let threads: u64 = common::field(record, "owl_threads")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(threads >= 2);
+
+    let status = owl(&["+Format:json", "+HeartbeatCount:1", "+SelfMetrics:true"])
+        .args(receiver.opts())
+        .args(["sleep", "0.3"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let record = receiver
+        .wait_for(Duration::from_secs(1), |r| r.contains("\"self_metrics\":{"))
+        .expect("self metrics are not nested in JSON");
+    assert!(record.contains("\"owl_rss_bytes\":"), "{}", record);
+}
+
+#[test]
+fn replaced_process_is_followed_by_name() {
+    let dir = common::temp_dir("by-name");
+    let name = format!("owl{}", std::process::id());
+    let binary = dir.join(&name);
+    fs::copy("/bin/sleep", &binary).unwrap();
+    let start = || Command::new(&binary).arg("30").spawn().unwrap();
+
+    let mut first = start();
+    let receiver = Receiver::bind();
+    let monitor = owl(&["+Heartbeat:100ms", &format!("+MonitorByName:{}", name)])
+        .args(receiver.opts())
+        .spawn()
+        .unwrap();
+    let pid_of = |record: &str| record.split("||").nth(1).map(String::from);
+    let first_pid = first.id().to_string();
+    assert!(receiver
+        .wait_for(Duration::from_secs(2), |r| pid_of(r).as_ref()
+            == Some(&first_pid))
+        .is_some());
+
+    first.kill().unwrap();
+    first.wait().unwrap();
+    let mut second = start();
+    let changed = receiver
+        .wait_for(Duration::from_secs(2), |r| {
+            common::field(r, "event") == Some("pid_changed")
+        })
+        .expect("the new process is not picked");
+    assert_eq!(pid_of(&changed), Some(second.id().to_string()));
+    assert_eq!(
+        common::field(&changed, "previous_pid"),
+        Some(first_pid.as_str())
+    );
+
+    kill(Pid::from_raw(monitor.id() as i32), Signal::SIGINT).unwrap();
+    monitor.wait_with_output().unwrap();
+    second.kill().unwrap();
+    second.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_failing_to_start_is_reported() {
+    let dir = common::temp_dir("failing-start");
+    let no_interpreter = dir.join("no-interpreter.sh");
+    fs::write(&no_interpreter, "#!/owl-test-no-such-shell\nexit 0\n").unwrap();
+    // The interpreter which is the directory cannot be executed
+    let dir_interpreter = dir.join("dir-interpreter.sh");
+    fs::write(&dir_interpreter, format!("#!{}\nexit 0\n", dir.display())).unwrap();
+    for path in [&no_interpreter, &dir_interpreter] {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    for (path, code) in [(&no_interpreter, 127), (&dir_interpreter, 126)] {
+        let output = owl(&["+Host:127.0.0.1", path.to_str().unwrap()])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(code));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("error: failed to execute command"),
+            "{}",
+            stderr
+        );
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_not_found_error_is_reported() {
+    let output = owl(&["+Host:127.0.0.1", "owl-test-no-such-command"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr,
+        "error: command 'owl-test-no-such-command' not found in PATH\n"
+    );
+}
+
+#[test]
+fn signal_setup_failure_is_reported() {
+    // The listener cannot make its pipe with descriptors the loader leaves
+    let mut command = owl(&["+Host:127.0.0.1", "true"]);
+    unsafe {
+        command.pre_exec(|| {
+            let limit = libc::rlimit {
+                rlim_cur: 4,
+                rlim_max: 4,
+            };
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+    let output = command.output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: failed to set up signal listener: "),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn socket_bind_failure_is_reported() {
+    let dir = common::temp_dir("bind-failure");
+    let marker = dir.join("started");
+    let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let output = owl(&[
+        "+Host:127.0.0.1",
+        &format!("+Port:{}", port),
+        "+Receive:true",
+        "touch",
+    ])
+    .arg(&marker)
+    .output()
+    .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!("error: failed to listen on 127.0.0.1:{}: ", port)),
+        "{}",
+        stderr
+    );
+    assert!(!marker.exists(), "the command is started");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_parse_error_is_reported() {
+    let dir = common::temp_dir("config-parse-error");
+    let conf = dir.join("owl.conf");
+    let marker = dir.join("started");
+    fs::write(&conf, "[watch]\nHost = \"127.0.0.1\n").unwrap();
+    let output = owl(&[&format!("+Conf:{}", conf.display()), "touch"])
+        .arg(&marker)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!(
+            "error: failed to parse configuration file '{}': ",
+            conf.display()
+        )),
+        "{}",
+        stderr
+    );
+    assert!(!marker.exists(), "the command is started");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn delivery_setup_failure_stops_before_command() {
+    // The delivery thread reports its failures back to the main thread
+    let dir = common::temp_dir("delivery-setup-failure");
+    let marker = dir.join("started");
+    // The name of the shared memory segment is longer than names can be
+    let long_name = format!("+Name:{}", "x".repeat(300));
+    for (opts, code, error) in [
+        (
+            vec!["+Transport:cloudwatch"],
+            2,
+            "error: unsupported transport 'cloudwatch'",
+        ),
+        (
+            vec!["+Transport:shm", &long_name],
+            1,
+            "error: failed to open shared memory '/owl-xxx",
+        ),
+    ] {
+        let output = owl(&opts).arg("touch").arg(&marker).output().unwrap();
+        assert_eq!(output.status.code(), Some(code));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with(error), "{}", stderr);
+        assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+        assert!(!marker.exists(), "the command is started");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stats_are_sampled_between_heartbeats() {
+    let receiver = Receiver::bind();
+    let status = owl(&[
+        "+StatsInterval:50ms",
+        "+Heartbeat:1000ms",
+        "+DeliverAggregated:true",
+    ])
+    .args(receiver.opts())
+    .args(["sleep", "2.3"])
+    .status()
+    .unwrap();
+    assert!(status.success());
+    let counts: Vec<u64> = receiver
+        .records(Duration::from_millis(300))
+        .iter()
+        .filter(|r| common::field(r, "rss_bytes_avg").is_some())
+        .filter_map(|r| common::field(r, "sample_count")?.parse().ok())
+        .collect();
+    // The first heartbeat goes out right after the start with few samples
+    assert!(counts.len() >= 2, "{:?}", counts);
+    assert!(
+        counts[1..].iter().all(|count| (15..=21).contains(count)),
+        "{:?}",
+        counts
+    );
+}
+
+// Run `sh -c script` with the output `mode` and extra `opts`, and get what the tool
+// prints along with `last_output` of the accounting event.
+fn output_mode_session(mode: &str, opts: &[&str], script: &str) -> (String, Option<String>) {
+    let receiver = Receiver::bind();
+    let output = owl(&[&format!("+OutputMode:{}", mode)])
+        .args(opts)
+        .args(receiver.opts())
+        .args(["sh", "-c", script])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let accounting = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("accounting")
+        })
+        .expect("no accounting is delivered");
+    let printed = String::from_utf8_lossy(&output.stdout).into_owned()
+        + &String::from_utf8_lossy(&output.stderr);
+    (
+        printed,
+        common::field(&accounting, "last_output").map(String::from),
+    )
+}
+
+#[test]
+fn output_is_captured_or_discarded() {
+    let (printed, last) = output_mode_session("capture", &[], "echo hello");
+    assert_eq!(printed, "");
+    assert_eq!(last.as_deref(), Some("hello\\n"));
+
+    let (printed, last) = output_mode_session("silent", &[], "echo hello; echo world >&2");
+    assert_eq!(printed, "");
+    assert_eq!(last, None);
+
+    let (printed, last) = output_mode_session("pipe", &[], "echo hello");
+    assert_eq!(printed, "hello\n");
+    assert_eq!(last, None);
+}
+
+#[test]
+fn output_is_captured_and_forwarded() {
+    let (printed, last) = output_mode_session(
+        "capture+forward",
+        &[],
+        "for i in 1 2 3 4 5 6 7 8 9 10; do echo line $i >&2; done",
+    );
+    let lines: Vec<String> = (1..=10).map(|i| format!("line {}", i)).collect();
+    assert_eq!(printed, lines.join("\n") + "\n");
+    assert_eq!(last, Some(lines.join("\\n") + "\\n"));
+
+    let (printed, last) = output_mode_session(
+        "capture+forward",
+        &["+CaptureMaxBytes:6"],
+        "echo hello; sleep 0.1; echo world >&2",
+    );
+    assert_eq!(printed, "hello\nworld\n");
+    assert_eq!(last.as_deref(), Some("world\\n"));
+}
+
+#[test]
+fn killing_signal_is_named() {
+    // The core the command may dump is left in the directory removed after
+    let dir = common::temp_dir("killed-by-signal");
+    let receiver = Receiver::bind();
+    let child = owl(&["+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .args(["sleep", "5"])
+        .current_dir(&dir)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let pid = common::children(child.id())[0];
+    kill(Pid::from_raw(pid as i32), Signal::SIGSEGV).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(128 + libc::SIGSEGV));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("process {} killed by signal SIGSEGV (11)", pid)),
+        "{}",
+        stderr
+    );
+    let accounting = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("accounting")
+        })
+        .expect("no accounting is delivered");
+    assert_eq!(
+        common::field(&accounting, "exit_reason"),
+        Some("killed by signal SIGSEGV (11)")
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn memory_limit_is_skipped_without_cgroup() {
+    let dir = common::temp_dir("no-cgroup");
+    let base = format!("+CgroupBase:{}", dir.display());
+    let output = owl(&["+Host:127.0.0.1", "+CgroupMemoryLimit:16M", &base, "true"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the memory limit is skipped"), "{}", stderr);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn memory_limit_is_enforced_by_kernel() {
+    // Creating cgroups needs the writable unified hierarchy
+    let base = std::path::Path::new("/sys/fs/cgroup");
+    let writable = base.join("cgroup.controllers").is_file()
+        && nix::unistd::access(base, nix::unistd::AccessFlags::W_OK).is_ok();
+    if !writable {
+        eprintln!("cgroup v2 is not writable, the memory limit is not tested");
+        return;
+    }
+
+    // The tail keeps the whole line of zeroes in memory
+    let output = owl(&[
+        "+Host:127.0.0.1",
+        "+Name:owl-test-limit",
+        "+CgroupMemoryLimit:16M",
+        "sh",
+        "-c",
+        "head -c 256M /dev/zero | tail -n 1 > /dev/null",
+    ])
+    .output()
+    .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(128 + libc::SIGKILL),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!base.join("owl-owl-test-limit.scope").exists());
+}
+
+#[test]
+fn secret_env_is_resolved_for_command() {
+    let dir = common::temp_dir("secret-env");
+    let secret = dir.join("db-password");
+    fs::write(&secret, "hunter2\n").unwrap();
+
+    // The mock Vault answers one request with the KV version 2 body
+    let vault = TcpListener::bind("127.0.0.1:0").unwrap();
+    let vault_addr = format!("http://{}", vault.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = vault.accept().unwrap();
+        let mut request = String::new();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        while reader.read_line(&mut request).unwrap() > 2 && !request.ends_with("\r\n\r\n") {}
+        let body = r#"{"data":{"data":{"token":"from-vault"}}}"#;
+        write!(
+            stream,
+            "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        request
+    });
+
+    let output = owl(&[
+        "+Host:127.0.0.1",
+        &format!(
+            "+SecretEnv:OWL_TEST_DB_PASSWORD=file://{},OWL_TEST_API_TOKEN=vault://secret/data/myapp#token",
+            secret.display()
+        ),
+        "/usr/bin/env",
+    ])
+    .env("VAULT_ADDR", &vault_addr)
+    .env("VAULT_TOKEN", "t0ken")
+    .output()
+    .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut vars: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("OWL_TEST_"))
+        .map(String::from)
+        .collect();
+    vars.sort();
+    assert_eq!(
+        vars,
+        [
+            "OWL_TEST_API_TOKEN=from-vault",
+            "OWL_TEST_DB_PASSWORD=hunter2"
+        ]
+    );
+
+    let request = server.join().unwrap();
+    assert!(
+        request.starts_with("GET /v1/secret/data/myapp HTTP/1.0\r\n"),
+        "{}",
+        request
+    );
+    assert!(request.contains("X-Vault-Token: t0ken\r\n"), "{}", request);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tee_copies_arrive_while_receiver_is_down() {
+    let tees = [Receiver::bind(), Receiver::bind()];
+    let closed = common::free_tcp_port().to_string();
+    let output = owl(&[
+        "+Transport:tcp",
+        "+Format:json",
+        "+Host:127.0.0.1",
+        &format!("+Port:{}", closed),
+        "+Heartbeat:100ms",
+        &format!("+Tee:127.0.0.1:{}", tees[0].port()),
+        &format!("+Tee:127.0.0.1:{}", tees[1].port()),
+        "sleep",
+        "0.5",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    for tee in tees.iter() {
+        let records = tee.records(Duration::from_millis(300));
+        assert!(heartbeats(&records) >= 3, "{:?}", records);
+        assert!(
+            records.iter().all(|r| r.contains("||sleep||")),
+            "{:?}",
+            records
+        );
+    }
+}
+
+// Get the list of CPUs the process with `pid` may run on from its status.
+fn cpus_allowed(pid: u32) -> String {
+    fs::read_to_string(format!("/proc/{}/status", pid))
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+#[test]
+fn command_and_tool_are_pinned_to_cpus() {
+    // Pin to the last CPU the test may use so the set differs from all CPUs
+    let cpu = cpus_allowed(std::process::id())
+        .rsplit(&[',', '-'][..])
+        .next()
+        .unwrap()
+        .to_owned();
+    let child = owl(&[
+        "+Host:127.0.0.1",
+        &format!("+CpuSet:{}", cpu),
+        "+CpuSetInherit:true",
+        "sleep",
+        "1",
+    ])
+    .spawn()
+    .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let pid = common::children(child.id())[0];
+    assert_eq!(cpus_allowed(pid), cpu);
+    assert_eq!(cpus_allowed(child.id()), cpu);
+    assert!(child.wait_with_output().unwrap().status.success());
+}
+
+// Run the tool which wraps another instance of it running `sleep` and
+// count distinct instances which delivered heartbeats.
+fn nested_session(inner: &[&str]) -> usize {
+    let receiver = Receiver::bind();
+    let status = owl(&["+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .arg(env!("CARGO_BIN_EXE_owl"))
+        .args(["+Heartbeat:100ms"])
+        .args(inner)
+        .args(receiver.opts())
+        .args(["sleep", "0.5"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(300));
+    let mut instances: Vec<&str> = records
+        .iter()
+        .filter(|r| common::field(r, "event").is_none())
+        .map(|r| r.split("||").next().unwrap())
+        .collect();
+    instances.sort_unstable();
+    instances.dedup();
+    instances.len()
+}
+
+#[test]
+fn nested_instance_does_not_deliver() {
+    assert_eq!(nested_session(&[]), 1);
+    assert_eq!(nested_session(&["+AllowNested:true"]), 2);
+}
+
+// Format the local time `offset` from now as `HH:MM`.
+fn local_time(offset: &str) -> String {
+    let output = Command::new("date")
+        .args(["-d", offset, "+%H:%M"])
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+#[test]
+fn heartbeats_are_delivered_within_active_hours() {
+    let receiver = Receiver::bind();
+    let later = format!(
+        "+ActiveHours:{}-{}",
+        local_time("+2 min"),
+        local_time("+3 min")
+    );
+    let status = owl(&["+Heartbeat:50ms", &later])
+        .args(receiver.opts())
+        .args(["sleep", "0.5"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(200));
+    assert_eq!(heartbeats(&records), 0, "{:?}", records);
+
+    let now = format!(
+        "+ActiveHours:{}-{}",
+        local_time("-1 min"),
+        local_time("+2 min")
+    );
+    let status = owl(&["+Heartbeat:50ms", &now])
+        .args(receiver.opts())
+        .args(["sleep", "0.5"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let records = receiver.records(Duration::from_millis(200));
+    assert!(heartbeats(&records) >= 5, "{:?}", records);
+}
+
+#[test]
+fn command_outputting_too_many_lines_is_terminated() {
+    let receiver = Receiver::bind();
+    let output = owl(&["+Heartbeat:100ms", "+MaxOutputLines:100"])
+        .args(receiver.opts())
+        .arg("yes")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(128 + libc::SIGTERM));
+    assert_eq!(output.stdout, "y\n".repeat(100).into_bytes());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("output exceeds the limit of 100 lines"),
+        "{}",
+        stderr
+    );
+    let accounting = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("accounting")
+        })
+        .expect("no accounting is delivered");
+    assert_eq!(
+        common::field(&accounting, "output_limit_exceeded"),
+        Some("true")
+    );
+}
+
+#[test]
+fn vanished_processes_are_reported_once() {
+    let receiver = Receiver::bind();
+    let mut first = Command::new("sleep").arg("0.3").spawn().unwrap();
+    let mut second = Command::new("sleep").arg("1").spawn().unwrap();
+    let pids = format!("+Pids:{},{}", first.id(), second.id());
+    let child = owl(&[&pids, "+Heartbeat:100ms"])
+        .args(receiver.opts())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    first.wait().unwrap();
+    let vanished = receiver
+        .wait_for(Duration::from_secs(1), |r| {
+            common::field(r, "event") == Some("pid_vanished")
+        })
+        .expect("no pid_vanished is delivered");
+    assert_eq!(
+        vanished.split("||").nth(1),
+        Some(first.id().to_string().as_str())
+    );
+    assert_eq!(vanished.split("||").nth(3), Some("Vanished"));
+    second.wait().unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let records = receiver.records(Duration::from_millis(300));
+    let vanished: Vec<&str> = records
+        .iter()
+        .filter(|r| common::field(r, "event") == Some("pid_vanished"))
+        .filter_map(|r| r.split("||").nth(1))
+        .collect();
+    assert!(
+        vanished.iter().all(|pid| *pid == second.id().to_string()),
+        "{:?}",
+        records
+    );
+    // The tool may exit before it reports the last process vanished
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let alerts = |process: &Child| {
+        stderr
+            .matches(&format!("alert: process {} vanished", process.id()))
+            .count()
+    };
+    assert_eq!(alerts(&first), 1, "{}", stderr);
+    assert!(alerts(&second) <= 1, "{}", stderr);
+}
+
+#[test]
+fn banner_shows_destination_before_command_starts() {
+    let dir = common::temp_dir("banner");
+    let secret = dir.join("password");
+    fs::write(&secret, "hunter2\n").unwrap();
+    let receiver = Receiver::bind();
+    let output = owl(&[
+        "+Banner:true",
+        "+Heartbeat:250ms",
+        &format!("+SecretEnv:OWL_TEST_PASSWORD=file://{}", secret.display()),
+    ])
+    .args(receiver.opts())
+    .arg("owl-test-missing-command")
+    .output()
+    .unwrap();
+    assert_eq!(output.status.code(), Some(127));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let destination = format!("127.0.0.1:{}", receiver.port());
+    for row in [
+        "  monitoring   owl-test-missing-command\n".to_owned(),
+        "  heartbeat    250ms\n".to_owned(),
+        "  transport    udp\n".to_owned(),
+        format!("  destination  {}\n", destination),
+    ] {
+        assert!(stderr.contains(&row), "{}", stderr);
+    }
+    assert!(stderr.contains(" SecretEnv:***\n"), "{}", stderr);
+    assert!(!stderr.contains(&*secret.to_string_lossy()), "{}", stderr);
+
+    let output = owl(&["+Banner:true", "+BannerColor:true"])
+        .args(receiver.opts())
+        .arg("true")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("\x1b[1;32m{}\x1b[0m", destination)),
+        "{}",
+        stderr
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Monitor `sleep` running already with deduplicated heartbeats and extra `opts`,
+// and get the number of heartbeats sent before the first identical one is
+// suppressed along with values of `deduplicated_count` of heartbeats sent after.
+// The command is not run by the tool so its stats do not change on exec, and
+// heartbeats of the exited command are not counted.
+fn deduplicated_session(opts: &[&str]) -> (usize, Vec<Option<String>>) {
+    let receiver = Receiver::bind();
+    let mut sleep = Command::new("sleep").arg("1.6").spawn().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let status = owl(&["+Heartbeat:50ms", "+DeduplicateSends:true"])
+        .arg(format!("+Pids:{}", sleep.id()))
+        .args(opts)
+        .args(receiver.opts())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    sleep.wait().unwrap();
+    let mut counts: Vec<Option<String>> = receiver
+        .records(Duration::from_millis(300))
+        .iter()
+        .filter(|r| r.split("||").nth(3) == Some("Sleeping"))
+        .map(|r| common::field(r, "deduplicated_count").map(String::from))
+        .collect();
+    let changed = counts.iter().take_while(|count| count.is_none()).count();
+    (changed, counts.split_off(changed))
+}
+
+#[test]
+fn identical_heartbeats_are_deduplicated() {
+    // Rates are known from the second heartbeat, then the first
+    // of every 10 identical heartbeats is sent
+    let (changed, counts) = deduplicated_session(&[]);
+    assert_eq!(changed, 2, "{:?}", counts);
+    assert!(counts.len() >= 2, "{:?}", counts);
+    assert!(
+        counts.iter().all(|count| count.as_deref() == Some("9")),
+        "{:?}",
+        counts
+    );
+
+    let (_, counts) = deduplicated_session(&["+KeepAliveSends:5"]);
+    assert!(counts.len() >= 4, "{:?}", counts);
+    assert!(
+        counts.iter().all(|count| count.as_deref() == Some("4")),
+        "{:?}",
+        counts
+    );
+}
+
+// Monitor the shell running `script` already with the RSS growth rate alert
+// and get growth rate alerts raised. The script runs the loop `step` 30 times.
+fn rss_growth_alerts(step: &str) -> Vec<String> {
+    let receiver = Receiver::bind();
+    let script = format!(
+        "i=0; while [ $i -lt 30 ]; do {}; sleep 0.05; i=$((i+1)); done",
+        step
+    );
+    let mut shell = Command::new("sh").args(["-c", &script]).spawn().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let status = owl(&["+Heartbeat:100ms", "+AlertRssGrowthRate:256K/s"])
+        .arg(format!("+Pids:{}", shell.id()))
+        .args(receiver.opts())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    shell.wait().unwrap();
+    receiver
+        .records(Duration::from_millis(300))
+        .iter()
+        .filter_map(|r| common::field(r, "alert"))
+        .filter(|alert| alert.starts_with("rss_growth_rate_bps "))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn rss_growth_rate_is_alerted() {
+    let alerts = rss_growth_alerts("s=\"$s$(head -c 65536 /dev/zero | tr '\\0' x)\"");
+    assert!(!alerts.is_empty());
+    assert!(
+        alerts
+            .iter()
+            .all(|alert| alert.ends_with(" is above 262144")),
+        "{:?}",
+        alerts
+    );
+
+    // Pages the shell touches once are smoothed out
+    let alerts = rss_growth_alerts("s=x");
+    assert!(alerts.is_empty(), "{:?}", alerts);
+}